@@ -0,0 +1,93 @@
+use criterion::{criterion_group, criterion_main, Criterion};
+use hyper::{header::HOST, Request};
+use local_http_proxy::config::{ProxyMode, Route};
+use local_http_proxy::server::proxy::{build_upstream_uri, get_destination};
+use local_http_proxy::server::router::Router;
+use std::hint::black_box;
+
+/// A route table roughly the size of a busy local setup.
+fn route_map() -> Router {
+    Router::new(
+        (0..200)
+            .map(|i| {
+                let target = format!("http://localhost:{}", 3000 + i);
+                (format!("service-{i}"), Route::new(target))
+            })
+            .collect(),
+    )
+}
+
+fn bench_get_destination(c: &mut Criterion) {
+    let routes = route_map();
+    let mut group = c.benchmark_group("get_destination");
+
+    let path_match = Request::builder()
+        .uri("/service-42/v1/users?limit=10")
+        .body(())
+        .unwrap();
+    let path_miss = Request::builder()
+        .uri("/unknown/v1/users?limit=10")
+        .body(())
+        .unwrap();
+    let domain_match = Request::builder()
+        .uri("/v1/users?limit=10")
+        .header(HOST, "service-42.localhost:8000")
+        .body(())
+        .unwrap();
+    let domain_miss = Request::builder()
+        .uri("/v1/users?limit=10")
+        .header(HOST, "unknown.localhost:8000")
+        .body(())
+        .unwrap();
+
+    for (name, req, mode) in [
+        ("path/match", &path_match, ProxyMode::Path),
+        ("path/no_match", &path_miss, ProxyMode::Path),
+        ("domain/match", &domain_match, ProxyMode::Domain),
+        ("domain/no_match", &domain_miss, ProxyMode::Domain),
+    ] {
+        group.bench_function(name, |b| {
+            b.iter(|| get_destination(black_box(req), &mode, &routes, false, None))
+        });
+    }
+    group.finish();
+}
+
+/// Path mode requests that can't carry a key, rejected before the path regex runs.
+/// Compare against an earlier build with `--save-baseline`/`--baseline`.
+fn bench_path_rejects(c: &mut Criterion) {
+    let routes = route_map();
+    let mut group = c.benchmark_group("get_destination/path_rejects");
+
+    for (name, uri) in [
+        ("root", "/"),
+        ("dot_segment", "/.well-known/acme-challenge/token"),
+        ("double_slash", "//service-42/v1/users"),
+        ("leading_hyphen", "/-service/v1/users"),
+    ] {
+        let req = Request::builder().uri(uri).body(()).unwrap();
+        group.bench_function(name, |b| {
+            b.iter(|| get_destination(black_box(&req), &ProxyMode::Path, &routes, false, None))
+        });
+    }
+    group.finish();
+}
+
+fn bench_build_upstream_uri(c: &mut Criterion) {
+    c.bench_function("build_upstream_uri", |b| {
+        b.iter(|| {
+            build_upstream_uri(
+                black_box("http://localhost:3042"),
+                black_box("/v1/users?limit=10"),
+            )
+        })
+    });
+}
+
+criterion_group!(
+    benches,
+    bench_get_destination,
+    bench_path_rejects,
+    bench_build_upstream_uri
+);
+criterion_main!(benches);
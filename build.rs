@@ -0,0 +1,45 @@
+use std::env;
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Passes what `version --verbose` reports about the build to the compiler as `LHP_BUILD_*`
+/// variables.
+fn main() {
+    let commit = Command::new("git")
+        .args(["rev-parse", "--short=12", "HEAD"])
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .and_then(|output| String::from_utf8(output.stdout).ok())
+        .map(|commit| commit.trim().to_string())
+        .unwrap_or_default();
+    // reproducible builds pin the date with SOURCE_DATE_EPOCH
+    let timestamp = env::var("SOURCE_DATE_EPOCH").unwrap_or_else(|_| {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |since| since.as_secs())
+            .to_string()
+    });
+    // cargo sets CARGO_FEATURE_<NAME> for every enabled feature of this package
+    let mut features: Vec<String> = env::vars()
+        .filter_map(|(name, _)| name.strip_prefix("CARGO_FEATURE_").map(str::to_string))
+        .map(|name| name.to_ascii_lowercase().replace('_', "-"))
+        .collect();
+    features.sort();
+
+    println!("cargo:rustc-env=LHP_BUILD_COMMIT={}", commit);
+    println!("cargo:rustc-env=LHP_BUILD_TIMESTAMP={}", timestamp);
+    println!("cargo:rustc-env=LHP_BUILD_FEATURES={}", features.join(","));
+    println!(
+        "cargo:rustc-env=LHP_BUILD_TARGET={}",
+        env::var("TARGET").unwrap_or_default()
+    );
+    println!(
+        "cargo:rustc-env=LHP_BUILD_PROFILE={}",
+        env::var("PROFILE").unwrap_or_default()
+    );
+    // a new commit moves the branch ref, not HEAD itself
+    println!("cargo:rerun-if-changed=.git/HEAD");
+    println!("cargo:rerun-if-changed=.git/refs/heads");
+    println!("cargo:rerun-if-env-changed=SOURCE_DATE_EPOCH");
+}
@@ -1,6 +1,17 @@
 mod config;
+mod daemon;
+mod doctor;
+mod error;
+mod init;
+mod pidfile;
 mod start;
-mod util;
+mod stats;
+pub(crate) mod util;
+mod version;
 
 pub use config::handle_config_command;
+pub use doctor::handle_doctor_command;
+pub use init::handle_init_command;
 pub use start::handle_start_command;
+pub use stats::handle_stats_command;
+pub use version::handle_version_command;
@@ -1,112 +1,1293 @@
-use super::util::{normalize_source_key, normalize_target};
+use super::error::CommandError;
+use super::util::{
+    infer_source_key, normalize_domain_key, normalize_glob_key, normalize_pattern_key,
+    normalize_prefix_key, normalize_source_key, normalize_target, parse_weighted_targets,
+    targets_proxy,
+};
 use crate::config::{
-    util::{read_config_file, write_config_file}, AppConfig, Command, ConfigFile,
-    ProxyMode,
+    util::{read_config_file, write_config_file, FileWatcher},
+    AppConfig, Command, ConfigFile, ProxyMode, Route, Switch, DEFAULT_PROFILE,
 };
-use std::error::Error;
+use crate::server::FIRST_UNPRIVILEGED_PORT;
+use crate::status;
+use indexmap::map::Entry;
+use std::io::{self, BufRead, IsTerminal, Write};
+use std::net::SocketAddr;
 use std::path::Path;
 use std::process;
+use std::thread;
+use std::time::Duration;
+
+/// The `add` target that means "read the target from stdin".
+const STDIN_TARGET: &str = "-";
+
+/// How often `list --watch` checks the config file for changes.
+const WATCH_INTERVAL: Duration = Duration::from_millis(500);
 
-/// Handles non-server commands (`list`, `add`, `remove`, `set-mode`).
+/// Clears the terminal and moves the cursor to the top left.
+const CLEAR_SCREEN: &str = "\x1b[2J\x1b[H";
+
+/// Handles non-server commands (`list`, `add`, `remove`, `set-mode`, `set-port`, `maintenance`,
+/// `use-profile`).
 /// This function will exit the process after handling the command.
-pub fn handle_config_command(command: &Command) -> ! {
-    let config = AppConfig::instance();
-    match handle_config_command_with_error_capture(command, &config.path) {
+pub fn handle_config_command(command: &Command, config: &AppConfig) -> ! {
+    let listen = SocketAddr::new(config.bind, config.port);
+    match handle_config_command_with_error_capture(command, &config.path, &config.profile, listen) {
         Ok(..) => {
             process::exit(0);
         }
         Err(e) => {
             eprintln!("\nError:\n{}\n", e);
-            process::exit(1);
+            process::exit(e.exit_code());
         }
     }
 }
 
-/// Handles non-server commands (`list`, `add`, `remove`, `set-mode`).
+/// Handles non-server commands (`list`, `add`, `remove`, `set-mode`, `set-port`, `maintenance`,
+/// `use-profile`) against the given profile, for a proxy that listens on `listen`.
 /// Returns a Result to capture errors without exiting the process.
 fn handle_config_command_with_error_capture(
     command: &Command,
     path: &Path,
-) -> Result<(), Box<dyn Error>> {
+    profile: &str,
+    listen: SocketAddr,
+) -> Result<(), CommandError> {
     let mut config = read_config_file(path).unwrap_or_default();
 
     match command {
-        Command::List => {
-            handle_list_command(&mut config);
+        Command::List { watch: false, tag } => {
+            handle_list_command(&config, profile, tag.as_deref(), &mut io::stdout())?;
+        }
+        Command::List { watch: true, tag } => {
+            handle_list_watch_command(path, profile, tag.as_deref())?;
+        }
+        Command::Add {
+            source,
+            target,
+            from_target,
+            rewrite,
+            description,
+            mode,
+            tags,
+            yes,
+        } => {
+            let Some(target) = from_target.as_ref().or(target.as_ref()) else {
+                unreachable!("clap requires a target or --from-target");
+            };
+            let target = match target.as_str() {
+                STDIN_TARGET => read_target(io::stdin().lock())?,
+                _ => target.clone(),
+            };
+            let source = match source {
+                Some(source) => source.clone(),
+                None => inferred_source(&target)?,
+            };
+            // scripts and piped input never get a prompt
+            let interactive = !*yes && io::stdin().is_terminal();
+            handle_add_command(
+                path,
+                &mut config,
+                profile,
+                &source,
+                &target,
+                listen,
+                &RouteSettings {
+                    rewrite: rewrite.as_deref(),
+                    description: description.as_deref(),
+                    mode: *mode,
+                    tags,
+                },
+                |question| !interactive || ask_yes_no(question, io::stdin().lock(), io::stdout()),
+            )?;
         }
-        Command::Add { source, target } => {
-            handle_add_command(path, &mut config, source, target)?;
+        Command::Remove { sources, .. } if !sources.is_empty() => {
+            handle_remove_command(path, &mut config, profile, sources)?;
         }
-        Command::Remove { source } => {
-            handle_remove_command(path, &mut config, source)?;
+        Command::Remove {
+            tag: Some(tag),
+            yes,
+            ..
+        } => {
+            let interactive = !*yes && io::stdin().is_terminal();
+            handle_remove_tag_command(path, &mut config, profile, tag, |question| {
+                !interactive || ask_yes_no(question, io::stdin().lock(), io::stdout())
+            })?;
         }
+        Command::Remove { tag: None, .. } => unreachable!("clap requires a source or --tag"),
         Command::SetMode { mode } => {
-            handle_set_mode_command(path, &mut config, mode)?;
+            handle_set_mode_command(path, &mut config, profile, mode)?;
         }
-        Command::Start { .. } => unreachable!(),
+        Command::SetPort { port } => {
+            handle_set_port_command(path, &mut config, *port)?;
+        }
+        Command::Maintenance { state, message } => {
+            handle_maintenance_command(path, &mut config, *state, message.as_deref())?;
+        }
+        Command::UseProfile { name } => {
+            handle_use_profile_command(path, &mut config, name)?;
+        }
+        Command::Start { .. }
+        | Command::Stats
+        | Command::Doctor
+        | Command::Init { .. }
+        | Command::Version { .. } => unreachable!(),
     }
     Ok(())
 }
 
-fn handle_list_command(config: &mut ConfigFile) {
-    println!("Mode: {}", config.mode);
-    println!("Routes:");
-    if config.routes.is_empty() {
-        println!("  (No routes configured. Use the `add` command to create one.)");
+/// Lists the routes of `profile`, or only those tagged `tag`.
+fn handle_list_command(
+    config: &ConfigFile,
+    profile: &str,
+    tag: Option<&str>,
+    out: &mut impl Write,
+) -> Result<(), CommandError> {
+    let profile_config = config.profile(profile).ok_or_else(|| {
+        CommandError::NotFound(format!(
+            "Profile '{profile}' does not exist in the config file."
+        ))
+    })?;
+
+    writeln!(out, "Profile: {}", profile)?;
+    writeln!(out, "Mode: {}", profile_config.mode)?;
+    let mut sorted_routes: Vec<_> = profile_config
+        .routes
+        .iter()
+        .filter(|(_, route)| tag.is_none_or(|tag| has_tag(route, tag)))
+        .collect();
+    match tag {
+        Some(tag) => writeln!(out, "Routes tagged '{}':", tag.trim())?,
+        None => writeln!(out, "Routes:")?,
+    }
+    if profile_config.routes.is_empty() {
+        writeln!(
+            out,
+            "  (No routes configured. Use the `add` command to create one.)"
+        )?;
+    } else if sorted_routes.is_empty() {
+        writeln!(out, "  (No routes have this tag.)")?;
     } else {
-        let mut sorted_routes: Vec<_> = config.routes.iter().collect();
-        sorted_routes.sort_by(|a, b| a.0.cmp(b.0));
-        for (source, target) in sorted_routes {
-            println!("  {} → {}", source, target);
+        // patterns are tried in config order, so keep that order for regex mode
+        if !matches!(profile_config.mode, ProxyMode::Regex) {
+            sorted_routes.sort_by(|a, b| a.0.cmp(b.0));
+        }
+        for (source, route) in sorted_routes {
+            let mut line = format!("  {} → {}", source, describe_instances(route));
+            if !route.enabled {
+                line.push_str(" (disabled)");
+            }
+            if route.maintenance {
+                line.push_str(" (maintenance)");
+            }
+            if !route.tags.is_empty() {
+                line.push_str(&format!(" [{}]", route.tags.join(", ")));
+            }
+            if let Some(description) = &route.description {
+                line.push_str(&format!(" — {}", description));
+            }
+            writeln!(out, "{}", line)?;
+        }
+    }
+    Ok(())
+}
+
+/// The route's instances, with their weights if it has any: `http://a=3, http://b=1`.
+fn describe_instances(route: &Route) -> String {
+    let instances = route.instances();
+    if route.weights.is_empty() {
+        return instances.collect::<Vec<_>>().join(", ");
+    }
+    instances
+        .zip(&route.weights)
+        .map(|(instance, weight)| format!("{}={}", instance, weight))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Handles `list --watch`: shows the routes again whenever the config file changes, until
+/// the process is interrupted with Ctrl-C.
+fn handle_list_watch_command(
+    path: &Path,
+    profile: &str,
+    tag: Option<&str>,
+) -> Result<(), CommandError> {
+    let mut watcher = FileWatcher::new(path);
+    loop {
+        refresh_list(&mut watcher, profile, tag, &mut io::stdout())?;
+        thread::sleep(WATCH_INTERVAL);
+    }
+}
+
+/// Clears the screen and lists the routes if the watched file changed since the last call.
+/// Returns whether it did. A file that can't be read (e.g. half-saved) shows the error
+/// instead, and the watch goes on.
+fn refresh_list(
+    watcher: &mut FileWatcher,
+    profile: &str,
+    tag: Option<&str>,
+    out: &mut impl Write,
+) -> Result<bool, CommandError> {
+    if !watcher.changed() {
+        return Ok(false);
+    }
+    write!(out, "{}", CLEAR_SCREEN)?;
+    let listed = match read_config_file(watcher.path()) {
+        Ok(config) => handle_list_command(&config, profile, tag, out),
+        // like `list`, a missing file lists the defaults
+        Err(_) if !watcher.path().exists() => {
+            handle_list_command(&ConfigFile::default(), profile, tag, out)
+        }
+        Err(e) => Err(CommandError::io(e)),
+    };
+    if let Err(e) = listed {
+        writeln!(out, "Error:\n{}", e)?;
+    }
+    writeln!(
+        out,
+        "\nWatching {} for changes. Press Ctrl-C to stop.",
+        watcher.path().display()
+    )?;
+    out.flush()?;
+    Ok(true)
+}
+
+/// Reads an `add` target from the first line of `reader` (stdin for `add <source> -`),
+/// so targets never have to survive shell quoting.
+fn read_target(mut reader: impl BufRead) -> Result<String, CommandError> {
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    let target = line.trim();
+    if target.is_empty() {
+        return Err(CommandError::InvalidInput(
+            "No target was given on stdin.".to_string(),
+        ));
+    }
+    Ok(target.to_string())
+}
+
+/// The source `add --from-target` names a route after: the host of its (first) target.
+fn inferred_source(target: &str) -> Result<String, CommandError> {
+    let instances = parse_weighted_targets(target).map_err(CommandError::InvalidInput)?;
+    infer_source_key(instances[0].0).map_err(CommandError::InvalidInput)
+}
+
+/// Asks a yes/no question, treating anything but `y`/`yes` as no.
+fn ask_yes_no(question: &str, mut input: impl BufRead, mut output: impl Write) -> bool {
+    let _ = write!(output, "{question} [y/N] ");
+    let _ = output.flush();
+    let mut answer = String::new();
+    if input.read_line(&mut answer).is_err() {
+        return false;
+    }
+    matches!(answer.trim().to_ascii_lowercase().as_str(), "y" | "yes")
+}
+
+/// The settings `add` can give a route besides its target. Unset ones are left as they are
+/// on an existing route.
+#[derive(Debug, Default)]
+struct RouteSettings<'a> {
+    rewrite: Option<&'a str>,
+    description: Option<&'a str>,
+    // the route's own mode, in place of the profile's
+    mode: Option<ProxyMode>,
+    // added to the route's tags
+    tags: &'a [String],
+}
+
+impl RouteSettings<'_> {
+    fn apply(&self, route: &mut Route) {
+        if let Some(rewrite) = self.rewrite {
+            route.rewrite = Some(rewrite.to_string());
+        }
+        if let Some(description) = self.description {
+            route.description = Some(description.to_string());
+        }
+        if let Some(mode) = self.mode {
+            route.mode = Some(mode);
+        }
+        for tag in self.tags {
+            if !has_tag(route, tag) {
+                route.tags.push(tag.trim().to_string());
+            }
         }
     }
 }
 
+/// Whether the route has `tag`, compared case-insensitively.
+fn has_tag(route: &Route, tag: &str) -> bool {
+    route
+        .tags
+        .iter()
+        .any(|t| t.eq_ignore_ascii_case(tag.trim()))
+}
+
+/// Adds or updates a route. `target` may list several instances, as in
+/// `localhost:3000=3,localhost:3001=1`; they replace those of an existing route.
+/// `confirm_overwrite` is asked before an existing route's instances are replaced with
+/// different ones. Targets that are the proxy itself, listening on `listen`, are refused.
+#[allow(clippy::too_many_arguments)]
 fn handle_add_command(
     path: &Path,
     config: &mut ConfigFile,
-    source: &String,
-    target: &String,
-) -> Result<(), Box<dyn Error>> {
-    let source = normalize_source_key(source).map_err(|e| Box::<dyn Error>::from(e))?;
-    let target = normalize_target(target).map_err(|e| Box::<dyn Error>::from(e))?;
-
-    let source_to_target = format!("{source} → {target}");
-    if let Some(old) = config.routes.insert(source, target) {
-        println!("✅ Updated route: {source_to_target} (was → {old})");
-    } else {
-        println!("✅ Added route: {source_to_target}");
+    profile: &str,
+    source: &str,
+    target: &str,
+    listen: SocketAddr,
+    settings: &RouteSettings,
+    confirm_overwrite: impl FnOnce(&str) -> bool,
+) -> Result<(), CommandError> {
+    let profile_mode = config.profile(profile).map_or(ProxyMode::Path, |p| p.mode);
+    let mode = settings.mode.unwrap_or(profile_mode);
+    if mode != profile_mode && !(mode.mixes() && profile_mode.mixes()) {
+        return Err(CommandError::InvalidInput(format!(
+            "A {} route can't be added to a {} mode profile; only domain and path routes can be mixed.",
+            mode, profile_mode
+        )));
+    }
+    let source =
+        normalize_key_for_mode(config, mode, source).map_err(CommandError::InvalidInput)?;
+    let mut instances = Vec::new();
+    for (target, weight) in parse_weighted_targets(target).map_err(CommandError::InvalidInput)? {
+        let target = normalize_target(target, config.default_upstream_port)
+            .map_err(CommandError::InvalidInput)?;
+        if targets_proxy(&target, listen.ip(), listen.port()) {
+            return Err(CommandError::InvalidInput(format!(
+                "The target {} is the proxy itself (it listens on {}), so requests would loop forever.",
+                target, listen
+            )));
+        }
+        instances.push((target, weight));
     }
-    write_config_file(path, &config)?;
+    if settings.tags.iter().any(|tag| tag.trim().is_empty()) {
+        return Err(CommandError::InvalidInput(
+            "Tags can't be empty.".to_string(),
+        ));
+    }
+
+    let weighted = instances.iter().any(|(_, weight)| *weight != 1);
+    let (weights, mut targets): (Vec<u32>, Vec<String>) = instances
+        .into_iter()
+        .map(|(target, weight)| (weight, target))
+        .unzip();
+    let mut added = Route::new(targets.remove(0));
+    added.targets = targets;
+    added.weights = if weighted { weights } else { Vec::new() };
+
+    let source_to_target = format!("{source} → {}", describe_instances(&added));
+    match config.profile_mut(profile).routes.entry(source) {
+        Entry::Occupied(mut entry) => {
+            let current = describe_instances(entry.get());
+            if current != describe_instances(&added) {
+                let question = format!(
+                    "Route '{}' already points to {}. Replace it?",
+                    entry.key(),
+                    current
+                );
+                if !confirm_overwrite(&question) {
+                    status!("Kept route: {} → {}", entry.key(), current);
+                    return Ok(());
+                }
+            }
+            // keep any other settings of the existing route
+            let route = entry.get_mut();
+            settings.apply(route);
+            route.target = added.target;
+            route.targets = added.targets;
+            route.weights = added.weights;
+            status!("✅ Updated route: {source_to_target} (was → {current})");
+        }
+        Entry::Vacant(entry) => {
+            settings.apply(&mut added);
+            entry.insert(added);
+            status!("✅ Added route: {source_to_target}");
+        }
+    }
+    write_config_file(path, config).map_err(CommandError::io)?;
     Ok(())
 }
 
+/// Removes the routes of `sources`, reporting each one that has no route, and writes the
+/// config file once if any was removed.
 fn handle_remove_command(
     path: &Path,
     config: &mut ConfigFile,
-    source: &String,
-) -> Result<(), Box<dyn Error>> {
-    let source = match normalize_source_key(source) {
-        Ok(s) => s,
-        Err(_) => source.trim().to_ascii_lowercase(),
-    };
+    profile: &str,
+    sources: &[String],
+) -> Result<(), CommandError> {
+    let keys: Vec<String> = sources
+        .iter()
+        .map(
+            |source| match normalize_route_key(config, profile, source) {
+                Ok(s) => s,
+                Err(_) => source.trim().to_ascii_lowercase(),
+            },
+        )
+        .collect();
 
-    if config.routes.remove(&source).is_some() {
-        println!("✅ Removed route for: {source}");
-        write_config_file(path, &config)?;
-    } else {
-        println!("⚠️  No route found for '{source}'. Nothing to remove.");
+    let routes = &mut config.profile_mut(profile).routes;
+    let mut removed = Vec::new();
+    for key in keys {
+        if routes.shift_remove(&key).is_some() {
+            status!("✅ Removed route for: {key}");
+            removed.push(key);
+        } else if !removed.contains(&key) {
+            status!("⚠️  No route found for '{key}'. Nothing to remove.");
+        }
+    }
+    if !removed.is_empty() {
+        write_config_file(path, config).map_err(CommandError::io)?;
+    }
+    Ok(())
+}
+
+/// Removes every route tagged `tag`, once `confirm` agrees to the list of them.
+fn handle_remove_tag_command(
+    path: &Path,
+    config: &mut ConfigFile,
+    profile: &str,
+    tag: &str,
+    confirm: impl FnOnce(&str) -> bool,
+) -> Result<(), CommandError> {
+    let routes = &mut config.profile_mut(profile).routes;
+    let tagged: Vec<String> = routes
+        .iter()
+        .filter(|(_, route)| has_tag(route, tag))
+        .map(|(key, _)| key.clone())
+        .collect();
+    let tag = tag.trim();
+    if tagged.is_empty() {
+        status!("⚠️  No routes tagged '{tag}'. Nothing to remove.");
+        return Ok(());
+    }
+    let question = format!(
+        "Remove {} route(s) tagged '{}' ({})?",
+        tagged.len(),
+        tag,
+        tagged.join(", ")
+    );
+    if !confirm(&question) {
+        status!("Kept the routes tagged '{tag}'.");
+        return Ok(());
     }
+
+    routes.retain(|key, _| !tagged.contains(key));
+    write_config_file(path, config).map_err(CommandError::io)?;
+    status!("✅ Removed {} route(s) tagged '{tag}'.", tagged.len());
     Ok(())
 }
 
+/// Normalizes a source into a route key for the profile's mode.
+pub(super) fn normalize_route_key(
+    config: &ConfigFile,
+    profile: &str,
+    source: &str,
+) -> Result<String, String> {
+    let mode = config.profile(profile).map_or(ProxyMode::Path, |p| p.mode);
+    normalize_key_for_mode(config, mode, source)
+}
+
+/// Normalizes `source` as a key of a route matched in `mode`.
+pub(super) fn normalize_key_for_mode(
+    config: &ConfigFile,
+    mode: ProxyMode,
+    source: &str,
+) -> Result<String, String> {
+    let local_suffixes = config.local_suffixes();
+    match mode {
+        ProxyMode::Prefix => normalize_prefix_key(source),
+        ProxyMode::Regex => normalize_pattern_key(source),
+        ProxyMode::Domain => normalize_domain_key(source, &local_suffixes),
+        _ if source.contains('*') => normalize_glob_key(source),
+        _ => normalize_source_key(source, &local_suffixes),
+    }
+}
+
 fn handle_set_mode_command(
     path: &Path,
     config: &mut ConfigFile,
+    profile: &str,
     mode: &ProxyMode,
-) -> Result<(), Box<dyn Error>> {
-    config.mode = *mode;
-    println!("✅ Proxy mode set to: {}", mode);
-    write_config_file(path, &config)?;
+) -> Result<(), CommandError> {
+    config.profile_mut(profile).mode = *mode;
+    status!("✅ Proxy mode set to: {}", mode);
+    write_config_file(path, config).map_err(CommandError::io)?;
     Ok(())
 }
+
+fn handle_set_port_command(
+    path: &Path,
+    config: &mut ConfigFile,
+    port: u16,
+) -> Result<(), CommandError> {
+    if port == 0 {
+        return Err(CommandError::InvalidInput(
+            "The port must be between 1 and 65535.".to_string(),
+        ));
+    }
+    config.port = port;
+    status!("✅ Proxy port set to: {}", port);
+    if port < FIRST_UNPRIVILEGED_PORT {
+        eprintln!(
+            "⚠️  Port {} is privileged: `start` needs root or the capability granted with \
+             `sudo setcap cap_net_bind_service=+ep $(which local-http-proxy)`.",
+            port
+        );
+    }
+    write_config_file(path, config).map_err(CommandError::io)?;
+    Ok(())
+}
+
+fn handle_maintenance_command(
+    path: &Path,
+    config: &mut ConfigFile,
+    state: Switch,
+    message: Option<&str>,
+) -> Result<(), CommandError> {
+    config.maintenance = state == Switch::On;
+    if let Some(message) = message {
+        config.maintenance_message = Some(message.to_string());
+    }
+    write_config_file(path, config).map_err(CommandError::io)?;
+    if config.maintenance {
+        status!("🚧 Maintenance mode on: every route answers 503.");
+    } else {
+        status!("✅ Maintenance mode off.");
+    }
+    status!("   Send SIGHUP to a running server (`kill -HUP <pid>`) to apply it.");
+    Ok(())
+}
+
+fn handle_use_profile_command(
+    path: &Path,
+    config: &mut ConfigFile,
+    name: &str,
+) -> Result<(), CommandError> {
+    let route_count = config
+        .profile(name)
+        .map(|p| p.routes.len())
+        .ok_or_else(|| {
+            CommandError::NotFound(format!(
+                "Profile '{name}' does not exist in the config file."
+            ))
+        })?;
+
+    config.active_profile = (name != DEFAULT_PROFILE).then(|| name.to_string());
+    status!("✅ Active profile set to: {name} ({route_count} routes)");
+    write_config_file(path, config).map_err(CommandError::io)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Args;
+    use clap::Parser;
+    use std::fs;
+    use std::net::{IpAddr, Ipv4Addr};
+    use tempfile::TempDir;
+
+    /// Where the proxy the commands are run for listens.
+    const LISTEN: SocketAddr = SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 8000);
+
+    fn config_with_profile(dir: &TempDir) -> std::path::PathBuf {
+        let path = dir.path().join("config.json");
+        let mut config = ConfigFile::default();
+        config
+            .profile_mut("integration")
+            .routes
+            .insert("api".into(), Route::new("http://integration:8080"));
+        write_config_file(&path, &config).unwrap();
+        path
+    }
+
+    #[test]
+    fn list_watch_redraws_when_the_file_changes() {
+        let dir = TempDir::new().unwrap();
+        let path = config_with_profile(&dir);
+        let mut watcher = FileWatcher::new(&path);
+        let mut out = Vec::new();
+
+        assert!(refresh_list(&mut watcher, "integration", None, &mut out).unwrap());
+        let shown = String::from_utf8(std::mem::take(&mut out)).unwrap();
+        assert!(shown.starts_with(CLEAR_SCREEN));
+        assert!(shown.contains("  api → http://integration:8080"));
+        assert!(!refresh_list(&mut watcher, "integration", None, &mut out).unwrap());
+        assert!(out.is_empty());
+
+        let mut config = read_config_file(&path).unwrap();
+        config
+            .profile_mut("integration")
+            .routes
+            .insert("web".into(), Route::new("http://integration:3000"));
+        write_config_file(&path, &config).unwrap();
+        assert!(refresh_list(&mut watcher, "integration", None, &mut out).unwrap());
+        let shown = String::from_utf8(std::mem::take(&mut out)).unwrap();
+        assert!(shown.contains("  web → http://integration:3000"));
+
+        // a half-written file shows the error and keeps watching
+        fs::write(&path, "{ \"routes\": ").unwrap();
+        assert!(refresh_list(&mut watcher, "integration", None, &mut out).unwrap());
+        let shown = String::from_utf8(out).unwrap();
+        assert!(shown.contains("Error:"));
+        assert!(shown.contains("Watching"));
+    }
+
+    #[test]
+    fn use_profile_switches_to_existing_profile() {
+        let dir = TempDir::new().unwrap();
+        let path = config_with_profile(&dir);
+        let command = Command::UseProfile {
+            name: "integration".into(),
+        };
+
+        handle_config_command_with_error_capture(&command, &path, DEFAULT_PROFILE, LISTEN).unwrap();
+
+        let config = read_config_file(&path).unwrap();
+        assert_eq!(config.active_profile.as_deref(), Some("integration"));
+
+        let command = Command::UseProfile {
+            name: DEFAULT_PROFILE.into(),
+        };
+        handle_config_command_with_error_capture(&command, &path, "integration", LISTEN).unwrap();
+        assert_eq!(read_config_file(&path).unwrap().active_profile, None);
+    }
+
+    #[test]
+    fn use_profile_rejects_missing_profile() {
+        let dir = TempDir::new().unwrap();
+        let path = config_with_profile(&dir);
+        let command = Command::UseProfile {
+            name: "staging".into(),
+        };
+
+        let err =
+            handle_config_command_with_error_capture(&command, &path, DEFAULT_PROFILE, LISTEN)
+                .unwrap_err();
+
+        assert!(err.to_string().contains("staging"));
+        assert_eq!(read_config_file(&path).unwrap().active_profile, None);
+    }
+
+    #[test]
+    fn set_port_writes_the_port_to_the_config() {
+        let dir = TempDir::new().unwrap();
+        let path = config_with_profile(&dir);
+
+        let command = Command::SetPort { port: 9090 };
+        handle_config_command_with_error_capture(&command, &path, DEFAULT_PROFILE, LISTEN).unwrap();
+        let config = read_config_file(&path).unwrap();
+        assert_eq!(config.port, 9090);
+        // the rest of the config is kept
+        assert!(config.profile("integration").is_some());
+
+        let command = Command::SetPort { port: 0 };
+        let err =
+            handle_config_command_with_error_capture(&command, &path, DEFAULT_PROFILE, LISTEN)
+                .unwrap_err();
+        assert_eq!(err.exit_code(), 2);
+        assert_eq!(read_config_file(&path).unwrap().port, 9090);
+    }
+
+    #[test]
+    fn maintenance_toggles_the_flag_and_keeps_the_message() {
+        let dir = TempDir::new().unwrap();
+        let path = config_with_profile(&dir);
+
+        let command = Command::Maintenance {
+            state: Switch::On,
+            message: Some("Back at 5pm".to_string()),
+        };
+        handle_config_command_with_error_capture(&command, &path, DEFAULT_PROFILE, LISTEN).unwrap();
+        let config = read_config_file(&path).unwrap();
+        assert!(config.maintenance);
+        assert_eq!(config.maintenance_message.as_deref(), Some("Back at 5pm"));
+
+        let command = Command::Maintenance {
+            state: Switch::Off,
+            message: None,
+        };
+        handle_config_command_with_error_capture(&command, &path, DEFAULT_PROFILE, LISTEN).unwrap();
+        let config = read_config_file(&path).unwrap();
+        assert!(!config.maintenance);
+        assert_eq!(config.maintenance_message.as_deref(), Some("Back at 5pm"));
+    }
+
+    #[test]
+    fn add_target_can_come_from_a_reader() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("config.json");
+        let mut config = ConfigFile::default();
+
+        let stdin = io::Cursor::new("  localhost:3000/$weird&path \nignored\n");
+        let target = read_target(stdin).unwrap();
+        handle_add_command(
+            &path,
+            &mut config,
+            DEFAULT_PROFILE,
+            "api",
+            &target,
+            LISTEN,
+            &RouteSettings::default(),
+            |_| true,
+        )
+        .unwrap();
+
+        let saved = read_config_file(&path).unwrap();
+        assert_eq!(
+            saved.default_profile.routes["api"].target,
+            normalize_target("localhost:3000/$weird&path", None).unwrap()
+        );
+    }
+
+    #[test]
+    fn descriptions_are_saved_and_listed() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("config.json");
+        let command = Command::Add {
+            source: Some("api".into()),
+            target: Some("8080".into()),
+            from_target: None,
+            rewrite: None,
+            description: Some("Orders API".into()),
+            mode: None,
+            tags: Vec::new(),
+            yes: true,
+        };
+        handle_config_command_with_error_capture(&command, &path, DEFAULT_PROFILE, LISTEN).unwrap();
+        // updating the target keeps the description
+        handle_config_command_with_error_capture(
+            &add("api", "8081", true),
+            &path,
+            DEFAULT_PROFILE,
+            LISTEN,
+        )
+        .unwrap();
+        handle_config_command_with_error_capture(
+            &add("web", "3000", true),
+            &path,
+            DEFAULT_PROFILE,
+            LISTEN,
+        )
+        .unwrap();
+
+        let saved = read_config_file(&path).unwrap();
+        let api = &saved.default_profile.routes["api"];
+        assert_eq!(api.description.as_deref(), Some("Orders API"));
+        assert_eq!(api.target, "http://localhost:8081");
+
+        let mut out = Vec::new();
+        handle_list_command(&saved, DEFAULT_PROFILE, None, &mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.contains("  api → http://localhost:8081 — Orders API\n"));
+        assert!(out.contains("  web → http://localhost:3000\n"));
+    }
+
+    #[test]
+    fn list_marks_disabled_routes_and_enabled_is_saved_only_when_off() {
+        let mut config: ConfigFile = serde_json::from_str(
+            r#"{ "routes": {
+                "api": { "target": "http://localhost:8080", "enabled": false },
+                "web": "http://localhost:3000"
+            } }"#,
+        )
+        .unwrap();
+        assert!(!config.default_profile.routes["api"].enabled);
+        assert!(config.default_profile.routes["web"].enabled);
+
+        let mut out = Vec::new();
+        handle_list_command(&config, DEFAULT_PROFILE, None, &mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.contains("  api → http://localhost:8080 (disabled)\n"));
+        assert!(out.contains("  web → http://localhost:3000\n"));
+
+        config.default_profile.routes["api"].enabled = true;
+        let json = serde_json::to_string(&config).unwrap();
+        assert!(!json.contains("enabled"), "{json}");
+    }
+
+    fn tagged_config(dir: &TempDir) -> (std::path::PathBuf, ConfigFile) {
+        let path = dir.path().join("config.json");
+        let mut config = ConfigFile::default();
+        for (source, target, tags) in [
+            ("web", "3000", &["frontend"][..]),
+            ("admin", "3001", &["Frontend", "internal"][..]),
+            ("api", "8080", &["backend"][..]),
+        ] {
+            let tags: Vec<String> = tags.iter().map(|t| t.to_string()).collect();
+            let settings = RouteSettings {
+                tags: &tags,
+                ..RouteSettings::default()
+            };
+            handle_add_command(
+                &path,
+                &mut config,
+                DEFAULT_PROFILE,
+                source,
+                target,
+                LISTEN,
+                &settings,
+                |_| true,
+            )
+            .unwrap();
+        }
+        (path, config)
+    }
+
+    #[test]
+    fn list_marks_routes_in_maintenance() {
+        let mut config = ConfigFile::default();
+        let routes = &mut config.default_profile.routes;
+        routes.insert("api".into(), Route::new("http://localhost:8080"));
+        routes.insert("web".into(), Route::new("http://localhost:3000"));
+        routes["api"].maintenance = true;
+
+        let mut out = Vec::new();
+        handle_list_command(&config, DEFAULT_PROFILE, None, &mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.contains("  api → http://localhost:8080 (maintenance)\n"));
+        assert!(out.contains("  web → http://localhost:3000\n"));
+    }
+
+    #[test]
+    fn list_can_filter_by_tag() {
+        let dir = TempDir::new().unwrap();
+        let (path, _) = tagged_config(&dir);
+        let saved = read_config_file(&path).unwrap();
+        assert_eq!(
+            saved.default_profile.routes["admin"].tags,
+            ["Frontend", "internal"]
+        );
+
+        let mut out = Vec::new();
+        handle_list_command(&saved, DEFAULT_PROFILE, Some("frontend"), &mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.contains("Routes tagged 'frontend':\n"));
+        assert!(out.contains("  admin → http://localhost:3001 [Frontend, internal]\n"));
+        assert!(out.contains("  web → http://localhost:3000 [frontend]\n"));
+        assert!(!out.contains("api"));
+
+        let mut out = Vec::new();
+        handle_list_command(&saved, DEFAULT_PROFILE, Some("mobile"), &mut out).unwrap();
+        assert!(String::from_utf8(out)
+            .unwrap()
+            .contains("(No routes have this tag.)"));
+    }
+
+    #[test]
+    fn remove_by_tag_removes_the_group_once_confirmed() {
+        let dir = TempDir::new().unwrap();
+        let (path, mut config) = tagged_config(&dir);
+
+        let mut asked = None;
+        handle_remove_tag_command(&path, &mut config, DEFAULT_PROFILE, "frontend", |q| {
+            asked = Some(q.to_string());
+            false
+        })
+        .unwrap();
+        assert_eq!(
+            asked.as_deref(),
+            Some("Remove 2 route(s) tagged 'frontend' (web, admin)?")
+        );
+        assert_eq!(
+            read_config_file(&path)
+                .unwrap()
+                .default_profile
+                .routes
+                .len(),
+            3
+        );
+
+        handle_remove_tag_command(&path, &mut config, DEFAULT_PROFILE, "frontend", |_| true)
+            .unwrap();
+        let saved = read_config_file(&path).unwrap();
+        let keys: Vec<_> = saved.default_profile.routes.keys().collect();
+        assert_eq!(keys, ["api"]);
+
+        // `remove` needs a source or a tag, but not both
+        assert!(Args::try_parse_from(["lhp", "remove"]).is_err());
+        assert!(Args::try_parse_from(["lhp", "remove", "api", "--tag", "backend"]).is_err());
+        assert!(Args::try_parse_from(["lhp", "remove", "--tag", "backend", "--yes"]).is_ok());
+    }
+
+    #[test]
+    fn remove_takes_several_sources_and_skips_missing_ones() {
+        let dir = TempDir::new().unwrap();
+        let (path, mut config) = tagged_config(&dir);
+        let sources = ["/WEB", "missing", "api", "api"].map(String::from);
+
+        handle_remove_command(&path, &mut config, DEFAULT_PROFILE, &sources).unwrap();
+        let saved = read_config_file(&path).unwrap();
+        let keys: Vec<_> = saved.default_profile.routes.keys().collect();
+        assert_eq!(keys, ["admin"]);
+
+        // nothing is written when no source has a route
+        fs::remove_file(&path).unwrap();
+        let sources = ["missing", "web"].map(String::from);
+        handle_remove_command(&path, &mut config, DEFAULT_PROFILE, &sources).unwrap();
+        assert!(!path.exists());
+
+        let args = Args::try_parse_from(["lhp", "remove", "web", "api"]).unwrap();
+        let Command::Remove { sources, .. } = args.command else {
+            panic!("not a remove command");
+        };
+        assert_eq!(sources, ["web", "api"]);
+    }
+
+    #[test]
+    fn add_accepts_weighted_instances() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("config.json");
+        let mut config = ConfigFile::default();
+        let add = |config: &mut ConfigFile, target: &str| {
+            handle_add_command(
+                &path,
+                config,
+                DEFAULT_PROFILE,
+                "api",
+                target,
+                LISTEN,
+                &RouteSettings::default(),
+                |_| true,
+            )
+        };
+
+        add(&mut config, "localhost:3000=3,localhost:3001=1").unwrap();
+        let route = &read_config_file(&path).unwrap().default_profile.routes["api"];
+        assert_eq!(route.target, "http://localhost:3000");
+        assert_eq!(route.targets, ["http://localhost:3001"]);
+        assert_eq!(route.weights, [3, 1]);
+        assert_eq!(
+            describe_instances(route),
+            "http://localhost:3000=3, http://localhost:3001=1"
+        );
+
+        // a new list replaces the instances, and unweighted lists store no weights
+        add(&mut config, "localhost:4000,localhost:4001").unwrap();
+        let route = &read_config_file(&path).unwrap().default_profile.routes["api"];
+        assert_eq!(route.targets, ["http://localhost:4001"]);
+        assert!(route.weights.is_empty());
+
+        let err = add(&mut config, "localhost:3000=0").unwrap_err();
+        assert_eq!(err.exit_code(), 2);
+    }
+
+    #[test]
+    fn targets_pointing_back_at_the_proxy_are_refused() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("config.json");
+        let mut config = ConfigFile::default();
+        let listen = SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 8100);
+        let settings = RouteSettings::default();
+        for target in ["8100", "127.0.0.1:8100", "http://localhost:8100/api"] {
+            let err = handle_add_command(
+                &path,
+                &mut config,
+                DEFAULT_PROFILE,
+                "api",
+                target,
+                listen,
+                &settings,
+                |_| true,
+            )
+            .unwrap_err();
+            assert_eq!(err.exit_code(), 2);
+            assert!(err.to_string().contains("is the proxy itself"), "{err}");
+        }
+        assert!(config.default_profile.routes.is_empty());
+        assert!(!path.exists());
+
+        handle_add_command(
+            &path,
+            &mut config,
+            DEFAULT_PROFILE,
+            "api",
+            "8000",
+            listen,
+            &settings,
+            |_| true,
+        )
+        .unwrap();
+
+        // a proxy bound to another address is found there too
+        let listen = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(192, 168, 1, 20)), 8100);
+        let err = handle_add_command(
+            &path,
+            &mut config,
+            DEFAULT_PROFILE,
+            "web",
+            "http://192.168.1.20:8100",
+            listen,
+            &settings,
+            |_| true,
+        )
+        .unwrap_err();
+        assert!(
+            err.to_string().contains("listens on 192.168.1.20:8100"),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn add_gives_host_only_targets_the_default_upstream_port() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("config.json");
+        let mut config = ConfigFile::default();
+        let settings = RouteSettings::default();
+        handle_add_command(
+            &path,
+            &mut config,
+            DEFAULT_PROFILE,
+            "api",
+            "myhost",
+            LISTEN,
+            &settings,
+            |_| true,
+        )
+        .unwrap();
+        assert_eq!(config.default_profile.routes["api"].target, "http://myhost");
+
+        config.default_upstream_port = Some(80);
+        handle_add_command(
+            &path,
+            &mut config,
+            DEFAULT_PROFILE,
+            "api",
+            "myhost",
+            LISTEN,
+            &settings,
+            |_| true,
+        )
+        .unwrap();
+        let saved = read_config_file(&path).unwrap();
+        assert_eq!(
+            saved.default_profile.routes["api"].target,
+            "http://myhost:80"
+        );
+        assert_eq!(saved.default_upstream_port, Some(80));
+    }
+
+    #[test]
+    fn add_with_a_mode_normalizes_the_key_that_way_and_saves_it() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("config.json");
+        let mut config = ConfigFile::default();
+        let settings = RouteSettings {
+            mode: Some(ProxyMode::Domain),
+            ..RouteSettings::default()
+        };
+        handle_add_command(
+            &path,
+            &mut config,
+            DEFAULT_PROFILE,
+            "Docs.localhost",
+            "4000",
+            LISTEN,
+            &settings,
+            |_| true,
+        )
+        .unwrap();
+        let saved = read_config_file(&path).unwrap();
+        assert_eq!(
+            saved.default_profile.routes["docs"].mode,
+            Some(ProxyMode::Domain)
+        );
+
+        config.default_profile.mode = ProxyMode::Prefix;
+        let err = handle_add_command(
+            &path,
+            &mut config,
+            DEFAULT_PROFILE,
+            "web",
+            "3000",
+            LISTEN,
+            &settings,
+            |_| true,
+        )
+        .unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("only domain and path routes can be mixed"));
+    }
+
+    #[test]
+    fn empty_stdin_target_is_rejected() {
+        let err = read_target(io::Cursor::new("\n")).unwrap_err();
+        assert!(err.to_string().contains("stdin"));
+        assert!(read_target(io::empty()).is_err());
+    }
+
+    fn add(source: &str, target: &str, yes: bool) -> Command {
+        Command::Add {
+            source: Some(source.into()),
+            target: Some(target.into()),
+            from_target: None,
+            rewrite: None,
+            description: None,
+            mode: None,
+            tags: Vec::new(),
+            yes,
+        }
+    }
+
+    #[test]
+    fn add_with_yes_overwrites_existing_route() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("config.json");
+        handle_config_command_with_error_capture(
+            &add("api", "3000", false),
+            &path,
+            DEFAULT_PROFILE,
+            LISTEN,
+        )
+        .unwrap();
+        handle_config_command_with_error_capture(
+            &add("api", "4000", true),
+            &path,
+            DEFAULT_PROFILE,
+            LISTEN,
+        )
+        .unwrap();
+
+        let saved = read_config_file(&path).unwrap();
+        assert_eq!(
+            saved.default_profile.routes["api"].target,
+            "http://localhost:4000"
+        );
+    }
+
+    #[test]
+    fn add_from_target_names_the_route_after_its_host() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("config.json");
+        let args = Args::try_parse_from(["lhp", "add", "--from-target", "http://my-app:3000"]);
+        handle_config_command_with_error_capture(
+            &args.unwrap().command,
+            &path,
+            DEFAULT_PROFILE,
+            LISTEN,
+        )
+        .unwrap();
+        let saved = read_config_file(&path).unwrap();
+        assert_eq!(
+            saved.default_profile.routes["my-app"].target,
+            "http://my-app:3000"
+        );
+
+        let args = Args::try_parse_from(["lhp", "add", "--from-target", "127.0.0.1:3000"]);
+        let err = handle_config_command_with_error_capture(
+            &args.unwrap().command,
+            &path,
+            DEFAULT_PROFILE,
+            LISTEN,
+        )
+        .unwrap_err();
+        assert!(matches!(err, CommandError::InvalidInput(_)));
+        assert!(err.to_string().contains("Pass the source explicitly"));
+
+        // a source and a target are needed without it, and can't be combined with it
+        assert!(Args::try_parse_from(["lhp", "add", "api"]).is_err());
+        assert!(
+            Args::try_parse_from(["lhp", "add", "api", "--from-target", "http://a:1"]).is_err()
+        );
+    }
+
+    #[test]
+    fn declined_overwrite_keeps_existing_route() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("config.json");
+        let mut config = ConfigFile::default();
+        let no_prompt = |_: &str| -> bool { panic!("a new route needs no confirmation") };
+        handle_add_command(
+            &path,
+            &mut config,
+            DEFAULT_PROFILE,
+            "api",
+            "3000",
+            LISTEN,
+            &RouteSettings::default(),
+            no_prompt,
+        )
+        .unwrap();
+
+        let mut asked = None;
+        handle_add_command(
+            &path,
+            &mut config,
+            DEFAULT_PROFILE,
+            "api",
+            "4000",
+            LISTEN,
+            &RouteSettings::default(),
+            |q| {
+                asked = Some(q.to_string());
+                false
+            },
+        )
+        .unwrap();
+
+        assert!(asked
+            .unwrap()
+            .contains("already points to http://localhost:3000"));
+        let saved = read_config_file(&path).unwrap();
+        assert_eq!(
+            saved.default_profile.routes["api"].target,
+            "http://localhost:3000"
+        );
+    }
+
+    #[test]
+    fn exit_code_depends_on_failure_kind() {
+        let dir = TempDir::new().unwrap();
+        let path = config_with_profile(&dir);
+        let exit_code = |command: &Command, path: &Path, profile: &str| {
+            handle_config_command_with_error_capture(command, path, profile, LISTEN)
+                .unwrap_err()
+                .exit_code()
+        };
+
+        assert_eq!(
+            exit_code(&add("bad key", "3000", true), &path, DEFAULT_PROFILE),
+            2
+        );
+        assert_eq!(exit_code(&add("api", "", true), &path, DEFAULT_PROFILE), 2);
+        assert_eq!(
+            exit_code(
+                &Command::List {
+                    watch: false,
+                    tag: None
+                },
+                &path,
+                "staging"
+            ),
+            3
+        );
+        let command = Command::UseProfile {
+            name: "staging".into(),
+        };
+        assert_eq!(exit_code(&command, &path, DEFAULT_PROFILE), 3);
+
+        // the config "directory" is a file, so the write fails
+        let blocked = path.join("config.json");
+        assert_eq!(
+            exit_code(&add("api", "3000", true), &blocked, DEFAULT_PROFILE),
+            4
+        );
+    }
+
+    #[test]
+    fn ask_yes_no_accepts_only_yes() {
+        for (answer, expected) in [
+            ("y\n", true),
+            ("YES\n", true),
+            ("n\n", false),
+            ("\n", false),
+        ] {
+            let mut output = Vec::new();
+            assert_eq!(
+                ask_yes_no("Replace?", answer.as_bytes(), &mut output),
+                expected
+            );
+            assert_eq!(String::from_utf8(output).unwrap(), "Replace? [y/N] ");
+        }
+    }
+}
@@ -0,0 +1,85 @@
+use std::ffi::OsString;
+use std::path::Path;
+
+/// The flag that sends `start` to the background; the background copy runs without it.
+const DAEMON_FLAG: &str = "--daemon";
+
+/// Starts this program again in the background with the same arguments, minus `--daemon`,
+/// detached from the terminal. Its output goes to `log_file`, or nowhere when unset.
+/// Returns the PID of the background server.
+#[cfg(unix)]
+pub fn spawn_in_background(log_file: Option<&Path>) -> Result<u32, String> {
+    use std::fs::OpenOptions;
+    use std::os::unix::process::CommandExt;
+    use std::process::{Command, Stdio};
+
+    let exe = std::env::current_exe()
+        .map_err(|e| format!("Could not find the running executable.\n  Cause: {}", e))?;
+    let (stdout, stderr) = match log_file {
+        Some(path) => {
+            let file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .map_err(|e| {
+                    format!(
+                        "Could not open the log file '{}'.\n  Cause: {}",
+                        path.display(),
+                        e
+                    )
+                })?;
+            let copy = file
+                .try_clone()
+                .map_err(|e| format!("Could not open the log file twice.\n  Cause: {}", e))?;
+            (Stdio::from(file), Stdio::from(copy))
+        }
+        None => (Stdio::null(), Stdio::null()),
+    };
+
+    let mut command = Command::new(exe);
+    command
+        .args(child_args(std::env::args_os().skip(1)))
+        .stdin(Stdio::null())
+        .stdout(stdout)
+        .stderr(stderr);
+    // SAFETY: `setsid` is async-signal-safe, so it may run between fork and exec. A new
+    // session has no controlling terminal, so closing the terminal doesn't stop the server.
+    unsafe {
+        command.pre_exec(|| {
+            if libc::setsid() == -1 {
+                return Err(std::io::Error::last_os_error());
+            }
+            Ok(())
+        });
+    }
+    let child = command.spawn().map_err(|e| {
+        format!(
+            "Could not start the server in the background.\n  Cause: {}",
+            e
+        )
+    })?;
+    Ok(child.id())
+}
+
+#[cfg(not(unix))]
+pub fn spawn_in_background(_log_file: Option<&Path>) -> Result<u32, String> {
+    Err("--daemon is only available on Unix.".into())
+}
+
+fn child_args(args: impl Iterator<Item = OsString>) -> Vec<OsString> {
+    args.filter(|arg| arg != DAEMON_FLAG).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn background_copy_runs_without_daemon_flag() {
+        let args = ["--profile", "dev", "start", "--daemon", "--port", "8080"].map(OsString::from);
+        assert_eq!(
+            child_args(args.into_iter()),
+            ["--profile", "dev", "start", "--port", "8080"].map(OsString::from)
+        );
+    }
+}
@@ -0,0 +1,374 @@
+use super::config::normalize_key_for_mode;
+use super::util::normalize_target;
+use crate::config::{read_merged_config, AppConfig, ConfigFile, ProxyMode, Route};
+use crate::server::static_files;
+use hyper::Uri;
+use std::env;
+use std::fmt::{self, Display, Formatter};
+use std::fs::OpenOptions;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::time::Duration;
+use tokio::net::TcpStream;
+
+/// How long a backend gets to accept a connection before it counts as unreachable.
+const PROBE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Handles the `doctor` command.
+/// This function will exit the process, with code 1 if a critical problem was found.
+pub async fn handle_doctor_command(config: &AppConfig) -> ! {
+    let findings = diagnose(&config.config_paths, &config.profile, env::vars()).await;
+    print!("{}", format_report(&findings));
+    let critical = findings.iter().any(|f| f.severity == Severity::Critical);
+    process::exit(if critical { 1 } else { 0 });
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Severity {
+    /// Requests (or commands) fail until it is fixed.
+    Critical,
+    /// Likely to surprise, but the proxy works.
+    Warning,
+}
+
+/// One problem found by `doctor`, with how to fix it.
+#[derive(Debug)]
+struct Finding {
+    severity: Severity,
+    problem: String,
+    fix: String,
+}
+
+impl Finding {
+    fn critical(problem: impl Into<String>, fix: impl Into<String>) -> Self {
+        Finding {
+            severity: Severity::Critical,
+            problem: problem.into(),
+            fix: fix.into(),
+        }
+    }
+
+    fn warning(problem: impl Into<String>, fix: impl Into<String>) -> Self {
+        Finding {
+            severity: Severity::Warning,
+            problem: problem.into(),
+            fix: fix.into(),
+        }
+    }
+}
+
+impl Display for Finding {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let icon = match self.severity {
+            Severity::Critical => "❌",
+            Severity::Warning => "⚠️ ",
+        };
+        write!(f, "{} {}\n   Fix: {}", icon, self.problem, self.fix)
+    }
+}
+
+/// Runs every check for `profile` against the config `start` would serve: the config files at
+/// `paths` merged, with the `LHP_ROUTE_*` routes among `vars`. Most severe first.
+async fn diagnose(
+    paths: &[PathBuf],
+    profile: &str,
+    vars: impl IntoIterator<Item = (String, String)>,
+) -> Vec<Finding> {
+    let mut findings = Vec::new();
+    // commands write to the last file
+    if let Some(path) = paths.last() {
+        check_writable(path, &mut findings);
+    }
+    check_config(paths, profile, vars, &mut findings).await;
+    findings.sort_by_key(|f| f.severity);
+    findings
+}
+
+async fn check_config(
+    paths: &[PathBuf],
+    profile: &str,
+    vars: impl IntoIterator<Item = (String, String)>,
+    findings: &mut Vec<Finding>,
+) {
+    let config = match read_merged_config(paths, profile, vars) {
+        Ok(config) => config,
+        Err(e) => {
+            findings.push(Finding::critical(
+                format!("The config file can't be read: {}", e),
+                "Fix the file or pass another one with --config-file.",
+            ));
+            return;
+        }
+    };
+    let Some(selected) = config.profile(profile) else {
+        findings.push(Finding::critical(
+            format!("Profile '{}' does not exist in the config file.", profile),
+            "Create it with `add --profile`, or pick another one with `use-profile`.",
+        ));
+        return;
+    };
+    if selected.routes.is_empty() {
+        findings.push(Finding::warning(
+            format!("Profile '{}' has no routes.", profile),
+            "Add one with `local-http-proxy add <name> <target>`.",
+        ));
+    }
+
+    for (key, route) in &selected.routes {
+        check_key(&config, route.mode.unwrap_or(selected.mode), key, findings);
+        for target in targets(route) {
+            check_target(key, target, config.default_upstream_port, findings).await;
+        }
+    }
+}
+
+/// Every target a route can send requests to.
+fn targets(route: &Route) -> impl Iterator<Item = &str> {
+    route
+        .instances()
+        .chain(route.header_routes.iter().map(|rule| rule.target.as_str()))
+}
+
+fn check_writable(path: &Path, findings: &mut Vec<Finding>) {
+    // append never truncates, so opening is a safe stand-in for writing
+    let result = OpenOptions::new().append(true).open(path);
+    match result {
+        Ok(_) => {}
+        Err(e) if e.kind() == io::ErrorKind::NotFound => findings.push(Finding::warning(
+            format!("The config file '{}' does not exist yet.", path.display()),
+            "Add a route to create it, or pass an existing file with --config-file.",
+        )),
+        Err(e) => findings.push(Finding::critical(
+            format!(
+                "The config file '{}' can't be written ({}), so `add` and `remove` will fail.",
+                path.display(),
+                e
+            ),
+            "Make the file and its directory writable by your user, or pass another file with --config-file.",
+        )),
+    }
+}
+
+/// Checks that the key is one requests can match in `mode`, the route's own or its profile's.
+fn check_key(config: &ConfigFile, mode: ProxyMode, key: &str, findings: &mut Vec<Finding>) {
+    match normalize_key_for_mode(config, mode, key) {
+        Err(e) => findings.push(Finding::critical(
+            format!("Route '{}' can never match in {} mode: {}", key, mode, e),
+            "Rename the route, or switch the mode with `set-mode` if it was written for another one.",
+        )),
+        Ok(normalized) if normalized != key => findings.push(Finding::warning(
+            format!("Route '{}' is matched as '{}'.", key, normalized),
+            format!("Rename the route to '{}' in the config file.", normalized),
+        )),
+        Ok(_) => {}
+    }
+}
+
+async fn check_target(
+    key: &str,
+    target: &str,
+    default_port: Option<u16>,
+    findings: &mut Vec<Finding>,
+) {
+    let normalized = match normalize_target(target, default_port) {
+        Ok(normalized) => normalized,
+        Err(e) => {
+            findings.push(Finding::critical(
+                format!("Route '{}' has an invalid target '{}': {}", key, target, e),
+                "Use a port, host:port or an http:// or https:// URL.",
+            ));
+            return;
+        }
+    };
+    let has_scheme = target.contains("://") || static_files::root(target).is_some();
+    if !has_scheme {
+        // e.g. `localhost:3000`: `add` writes the scheme, but hand-edited files may not have it
+        findings.push(Finding::critical(
+            format!(
+                "Route '{}' has the target '{}' without a scheme, so requests can't be forwarded.",
+                key, target
+            ),
+            format!("Change the target to '{}'.", normalized),
+        ));
+        return;
+    }
+
+    if let Some(root) = static_files::root(&normalized) {
+        if !root.is_dir() {
+            findings.push(Finding::warning(
+                format!(
+                    "Route '{}' serves the directory '{}', which does not exist.",
+                    key,
+                    root.display()
+                ),
+                "Create the directory or point the route at another one.",
+            ));
+        }
+        return;
+    }
+    if let Err(e) = probe(&normalized).await {
+        findings.push(Finding::warning(
+            format!(
+                "Route '{}' can't reach its backend at {}: {}",
+                key, normalized, e
+            ),
+            "Start the backend, or check the host and port.",
+        ));
+    }
+}
+
+/// Opens (and drops) a TCP connection to the target's host and port.
+async fn probe(target: &str) -> Result<(), String> {
+    let uri: Uri = target.parse().map_err(|_| "invalid URI".to_string())?;
+    let host = uri.host().ok_or("no host")?;
+    let port = uri
+        .port_u16()
+        .unwrap_or(if uri.scheme_str() == Some("https") {
+            443
+        } else {
+            80
+        });
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    match tokio::time::timeout(PROBE_TIMEOUT, TcpStream::connect((host, port))).await {
+        Ok(Ok(_)) => Ok(()),
+        Ok(Err(e)) => Err(e.to_string()),
+        Err(_) => Err(format!("no answer within {}s", PROBE_TIMEOUT.as_secs())),
+    }
+}
+
+fn format_report(findings: &[Finding]) -> String {
+    if findings.is_empty() {
+        return "✅ No problems found.\n".to_string();
+    }
+    let mut out = String::new();
+    for finding in findings {
+        out.push_str(&format!("{}\n", finding));
+    }
+    let critical = findings
+        .iter()
+        .filter(|f| f.severity == Severity::Critical)
+        .count();
+    out.push_str(&format!(
+        "\n{} critical, {} warning(s).\n",
+        critical,
+        findings.len() - critical
+    ));
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::TempDir;
+    use tokio::net::TcpListener;
+
+    #[tokio::test]
+    async fn planted_problems_are_reported_critical_first() {
+        let backend = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let up = backend.local_addr().unwrap();
+        let down = {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            listener.local_addr().unwrap()
+        };
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("config.json");
+        fs::write(
+            &path,
+            format!(
+                r#"{{ "mode": "path", "routes": {{
+                    "api": "http://{up}",
+                    "web": "localhost:3000",
+                    "docs.localhost": "http://{up}",
+                    "Admin": "http://{down}"
+                }} }}"#
+            ),
+        )
+        .unwrap();
+
+        let findings = diagnose(&[path], "default", []).await;
+        let problems: Vec<_> = findings.iter().map(|f| (f.severity, &f.problem)).collect();
+        assert_eq!(findings.len(), 4, "{problems:#?}");
+
+        assert_eq!(findings[0].severity, Severity::Critical);
+        assert!(findings[0]
+            .problem
+            .contains("'web' has the target 'localhost:3000' without a scheme"));
+        assert_eq!(
+            findings[0].fix,
+            "Change the target to 'http://localhost:3000'."
+        );
+
+        let warnings: Vec<_> = findings[1..].iter().map(|f| f.problem.as_str()).collect();
+        assert!(findings[1..]
+            .iter()
+            .all(|f| f.severity == Severity::Warning));
+        assert!(warnings[0].contains("'docs.localhost' is matched as 'docs'"));
+        assert!(warnings[1].contains("'Admin' is matched as 'admin'"));
+        assert!(warnings[2].contains("'Admin' can't reach its backend"));
+
+        let report = format_report(&findings);
+        assert!(report.starts_with("❌ Route 'web'"));
+        assert!(report.ends_with("\n1 critical, 3 warning(s).\n"));
+    }
+
+    #[tokio::test]
+    async fn layered_files_and_env_routes_are_checked_too() {
+        let down = {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            listener.local_addr().unwrap()
+        };
+        let dir = TempDir::new().unwrap();
+        let base = dir.path().join("base.json");
+        let primary = dir.path().join("config.json");
+        fs::write(
+            &base,
+            r#"{ "mode": "path", "routes": { "web": "localhost:3000" } }"#,
+        )
+        .unwrap();
+        fs::write(&primary, r#"{ "routes": {} }"#).unwrap();
+        let vars = [("LHP_ROUTE_API".to_string(), down.to_string())];
+
+        let findings = diagnose(&[base, primary], "default", vars).await;
+        let problems: Vec<_> = findings.iter().map(|f| f.problem.as_str()).collect();
+        assert_eq!(findings.len(), 2, "{problems:#?}");
+        assert!(problems[0].contains("'web' has the target 'localhost:3000' without a scheme"));
+        assert!(problems[1].contains("'api' can't reach its backend"));
+    }
+
+    #[tokio::test]
+    async fn keys_that_cannot_match_the_mode_are_critical() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("config.json");
+        fs::write(
+            &path,
+            r#"{ "mode": "domain", "routes": { "api/v2": "dir:/" } }"#,
+        )
+        .unwrap();
+
+        let findings = diagnose(std::slice::from_ref(&path), "default", []).await;
+        assert_eq!(findings.len(), 1);
+        assert_eq!(findings[0].severity, Severity::Critical);
+        assert!(findings[0]
+            .problem
+            .contains("'api/v2' can never match in domain mode"));
+
+        // a route of its own mode is checked in that mode
+        fs::write(
+            &path,
+            r#"{ "mode": "path", "routes": { "*": { "target": "dir:/", "mode": "domain" } } }"#,
+        )
+        .unwrap();
+        let findings = diagnose(&[path], "default", []).await;
+        assert!(findings.is_empty(), "{findings:#?}");
+
+        let findings = diagnose(&[dir.path().join("missing.json")], "default", []).await;
+        assert_eq!(findings.len(), 2);
+        assert_eq!(findings[0].severity, Severity::Critical);
+        assert!(findings[0]
+            .problem
+            .starts_with("The config file can't be read"));
+        assert!(findings[1].problem.contains("does not exist yet"));
+    }
+}
@@ -0,0 +1,46 @@
+use std::error::Error;
+use std::fmt::{Display, Formatter, Result};
+use std::io;
+
+/// Why a config command failed. Each kind exits with its own code so scripts can branch on it.
+#[derive(Debug)]
+pub enum CommandError {
+    /// The arguments can't be used (e.g. an invalid source or target). Exit code 2.
+    InvalidInput(String),
+    /// The profile the command names doesn't exist. Exit code 3.
+    NotFound(String),
+    /// Reading or writing a file failed. Exit code 4.
+    Io(String),
+}
+
+impl CommandError {
+    pub fn io(e: impl Display) -> Self {
+        CommandError::Io(e.to_string())
+    }
+
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            CommandError::InvalidInput(_) => 2,
+            CommandError::NotFound(_) => 3,
+            CommandError::Io(_) => 4,
+        }
+    }
+}
+
+impl Display for CommandError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        match self {
+            CommandError::InvalidInput(message)
+            | CommandError::NotFound(message)
+            | CommandError::Io(message) => f.write_str(message),
+        }
+    }
+}
+
+impl Error for CommandError {}
+
+impl From<io::Error> for CommandError {
+    fn from(e: io::Error) -> Self {
+        CommandError::io(e)
+    }
+}
@@ -0,0 +1,176 @@
+use super::error::CommandError;
+use crate::config::{util::write_config_file, ConfigFile, ProxyMode, Route};
+use crate::status;
+use std::path::Path;
+use std::process;
+
+/// An example route of the starter config: its key, target and the description that tells
+/// what it does, since JSON has no comments.
+struct Example {
+    key: &'static str,
+    target: &'static str,
+    rewrite: Option<&'static str>,
+    description: &'static str,
+}
+
+/// Handles the `init` command: writes a starter config file for `mode` to `path`.
+/// This function will exit the process after handling the command.
+pub fn handle_init_command(path: &Path, mode: ProxyMode, force: bool) -> ! {
+    match init(path, mode, force) {
+        Ok(..) => process::exit(0),
+        Err(e) => {
+            eprintln!("\nError:\n{}\n", e);
+            process::exit(e.exit_code());
+        }
+    }
+}
+
+/// Writes the starter config, refusing to replace an existing file unless `force` is set.
+fn init(path: &Path, mode: ProxyMode, force: bool) -> Result<(), CommandError> {
+    if path.exists() && !force {
+        return Err(CommandError::InvalidInput(format!(
+            "A config file already exists at {}.\n\n  Pass --force to replace it with the starter config.",
+            path.display()
+        )));
+    }
+    write_config_file(path, &starter_config(mode)).map_err(CommandError::io)?;
+    status!(
+        "✅ Wrote a starter config for {} mode to {}",
+        mode,
+        path.display()
+    );
+    status!(
+        "Each example route says what it does in its \"description\". Point the targets at your \
+         own servers, or replace the routes with `add` and `remove`, then run `start`."
+    );
+    Ok(())
+}
+
+/// The default config with `mode` and a couple of example routes for it.
+fn starter_config(mode: ProxyMode) -> ConfigFile {
+    let mut config = ConfigFile::default();
+    config.default_profile.mode = mode;
+    for example in examples(mode) {
+        let mut route = Route::new(example.target);
+        route.rewrite = example.rewrite.map(String::from);
+        route.description = Some(example.description.to_string());
+        config
+            .default_profile
+            .routes
+            .insert(example.key.to_string(), route);
+    }
+    config
+}
+
+fn examples(mode: ProxyMode) -> [Example; 2] {
+    let example = |key, target, description| Example {
+        key,
+        target,
+        rewrite: None,
+        description,
+    };
+    match mode {
+        ProxyMode::Domain => [
+            example(
+                "api",
+                "http://localhost:3000",
+                "Example: http://api.localhost:8000/users is forwarded to http://localhost:3000/users",
+            ),
+            example(
+                "web",
+                "http://localhost:5173",
+                "Example: http://web.localhost:8000/ is forwarded to the dev server on port 5173",
+            ),
+        ],
+        ProxyMode::Path => [
+            example(
+                "api",
+                "http://localhost:3000",
+                "Example: /api/users is forwarded to http://localhost:3000/users",
+            ),
+            example(
+                "web",
+                "http://localhost:5173",
+                "Example: /web/ is forwarded to the dev server on port 5173",
+            ),
+        ],
+        ProxyMode::Prefix => [
+            example(
+                "api/v2",
+                "http://localhost:3002",
+                "Example: /api/v2/users is forwarded to http://localhost:3002/users",
+            ),
+            example(
+                "api",
+                "http://localhost:3000",
+                "Example: other /api paths, as /api/v1/users, go to http://localhost:3000/v1/users",
+            ),
+        ],
+        ProxyMode::Regex => [
+            Example {
+                key: r"^/users/(?P<id>\d+)$",
+                target: "http://localhost:3000",
+                rewrite: Some("/api/users/${id}"),
+                description: "Example: /users/42 is forwarded to http://localhost:3000/api/users/42",
+            },
+            example(
+                "^/",
+                "http://localhost:5173",
+                "Example: every other path goes to the dev server on port 5173, unchanged",
+            ),
+        ],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::util::read_config_file;
+    use crate::config::{AppConfig, Args};
+    use clap::Parser;
+    use std::fs;
+    use tempfile::TempDir;
+
+    #[test]
+    fn init_writes_a_loadable_config_for_every_mode() {
+        let dir = TempDir::new().unwrap();
+        let args = Args::try_parse_from(["local-http-proxy", "list"]).unwrap();
+        for mode in [
+            ProxyMode::Domain,
+            ProxyMode::Path,
+            ProxyMode::Prefix,
+            ProxyMode::Regex,
+        ] {
+            let path = dir.path().join(format!("{mode}/config.json"));
+            init(&path, mode, false).unwrap();
+
+            let file = read_config_file(&path).unwrap();
+            assert_eq!(file.default_profile.mode, mode);
+            assert_eq!(file.default_profile.routes.len(), 2);
+            assert!(file
+                .default_profile
+                .routes
+                .values()
+                .all(|route| route.description.is_some()));
+            // the example keys are valid for the mode
+            AppConfig::from_file(path, file, &args).unwrap();
+        }
+    }
+
+    #[test]
+    fn init_refuses_to_replace_a_config_without_force() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("config.json");
+        fs::write(&path, r#"{ "port": 9000 }"#).unwrap();
+
+        let err = init(&path, ProxyMode::Path, false).unwrap_err();
+        assert_eq!(err.exit_code(), 2);
+        assert!(err.to_string().contains("Pass --force"), "{err}");
+        assert_eq!(fs::read_to_string(&path).unwrap(), r#"{ "port": 9000 }"#);
+
+        init(&path, ProxyMode::Domain, true).unwrap();
+        let file = read_config_file(&path).unwrap();
+        assert_eq!(file.port, 8000);
+        assert_eq!(file.default_profile.mode, ProxyMode::Domain);
+    }
+}
@@ -0,0 +1,110 @@
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+
+/// A file holding the server's PID, removed again when the server shuts down.
+pub struct Pidfile {
+    path: PathBuf,
+}
+
+impl Pidfile {
+    /// Writes the current PID to `path`. A pidfile left over by a process that has exited
+    /// is replaced; one naming a running process is an error.
+    pub fn create(path: &Path) -> Result<Self, String> {
+        if let Some(pid) = read_pid(path)
+            && pid != process::id()
+            && is_running(pid)
+        {
+            return Err(format!(
+                "The pidfile '{}' belongs to a running process (PID {}).\n  \
+                 Stop that process first, or pass another path with --pidfile.",
+                path.display(),
+                pid
+            ));
+        }
+
+        let write = || -> io::Result<()> {
+            let mut file = fs::File::create(path)?;
+            writeln!(file, "{}", process::id())
+        };
+        write().map_err(|e| {
+            format!(
+                "Could not write the pidfile '{}'.\n  Cause: {}",
+                path.display(),
+                e
+            )
+        })?;
+        Ok(Pidfile {
+            path: path.to_path_buf(),
+        })
+    }
+}
+
+impl Drop for Pidfile {
+    fn drop(&mut self) {
+        if let Err(e) = fs::remove_file(&self.path) {
+            eprintln!(
+                "Could not remove the pidfile '{}'.\n  Cause: {}",
+                self.path.display(),
+                e
+            );
+        }
+    }
+}
+
+fn read_pid(path: &Path) -> Option<u32> {
+    fs::read_to_string(path).ok()?.trim().parse().ok()
+}
+
+#[cfg(unix)]
+fn is_running(pid: u32) -> bool {
+    let Ok(pid) = libc::pid_t::try_from(pid) else {
+        return false;
+    };
+    // signal 0 only checks that the process exists; EPERM means it does, under another user
+    // SAFETY: `kill` takes no pointers, and signal 0 sends nothing.
+    let result = unsafe { libc::kill(pid, 0) };
+    result == 0 || io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+// without a portable liveness check, a leftover pidfile is treated as stale
+#[cfg(not(unix))]
+fn is_running(_pid: u32) -> bool {
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn pidfile_is_written_and_removed() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("lhp.pid");
+
+        let pidfile = Pidfile::create(&path).unwrap();
+        assert_eq!(read_pid(&path), Some(process::id()));
+        drop(pidfile);
+        assert!(!path.exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn live_pid_is_rejected_and_stale_pid_replaced() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("lhp.pid");
+
+        let mut child = process::Command::new("sleep").arg("5").spawn().unwrap();
+        fs::write(&path, format!("{}\n", child.id())).unwrap();
+        let err = Pidfile::create(&path).err().unwrap();
+        assert!(err.contains(&format!("running process (PID {})", child.id())));
+
+        child.kill().unwrap();
+        child.wait().unwrap();
+        let pidfile = Pidfile::create(&path).unwrap();
+        assert_eq!(read_pid(&path), Some(process::id()));
+        drop(pidfile);
+    }
+}
@@ -1,11 +1,282 @@
+use super::daemon;
+use super::pidfile::Pidfile;
+use super::util::targets_proxy;
 use crate::config::AppConfig;
-use crate::server;
+use crate::server::router::Router;
+use crate::server::telemetry;
+use crate::{output, server, status};
+use serde_json::json;
+use std::error::Error;
+use std::io::{self, Write};
+use std::net::IpAddr;
+use std::process;
+use std::sync::Arc;
 
-pub async fn handle_start_command() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    let config = AppConfig::instance();
-    println!("🚀 Starting proxy server on port {}...", config.port);
-    println!("   Mode: {}", config.mode);
-    println!("   Routes loaded: {}", config.routes.len());
+/// Handles the `start` command.
+/// This function will exit the process if the server cannot start or stops with an error.
+pub async fn handle_start_command(config: Arc<AppConfig>) -> ! {
+    if config.daemon {
+        if cfg!(unix) {
+            match daemon::spawn_in_background(config.log_file.as_deref()) {
+                Ok(pid) => {
+                    println!("🚀 Proxy server running in the background (PID {}).", pid);
+                    process::exit(0);
+                }
+                Err(e) => {
+                    eprintln!("\nError:\n{}\n", e);
+                    process::exit(1);
+                }
+            }
+        }
+        eprintln!("⚠️  --daemon is only available on Unix; running in the foreground.");
+    }
 
-    server::start_server().await
+    match start(config).await {
+        Ok(..) => process::exit(0),
+        Err(e) => {
+            eprintln!("\nError:\n{}\n", e);
+            process::exit(1);
+        }
+    }
+}
+
+async fn start(config: Arc<AppConfig>) -> Result<(), Box<dyn Error + Send + Sync>> {
+    check_routes(&config)?;
+    // dropped (and removed) when `start` returns, before the process exits
+    let _pidfile = config.pidfile.as_deref().map(Pidfile::create).transpose()?;
+    let routes = config.routes.load();
+    for (key, route) in routes.iter() {
+        if route.insecure_skip_verify {
+            eprintln!(
+                "⚠️  Route '{}' has insecure_skip_verify set: its upstream's TLS certificate is not checked.",
+                key
+            );
+        }
+    }
+    for warning in self_targets(&routes, config.bind, config.port) {
+        eprintln!("⚠️  {}", warning);
+    }
+    if let Some(key) = config.missing_host.route()
+        && routes
+            .resolve(&config.mode, &key.to_ascii_lowercase())
+            .is_none()
+    {
+        eprintln!(
+            "⚠️  missing_host names the route '{}', which does not exist; requests without a Host get 404.",
+            key
+        );
+    }
+    if !config.bind.is_loopback() {
+        eprintln!(
+            "⚠️  Listening on {}: other machines can reach the proxy and every route.",
+            config.bind
+        );
+    }
+    let tracer_provider = match &config.otlp_endpoint {
+        // the exporter's blocking HTTP client can't be created on an async worker
+        Some(endpoint) => {
+            let owned = endpoint.clone();
+            Some(tokio::task::spawn_blocking(move || telemetry::init(&owned)).await??)
+        }
+        None => None,
+    };
+    announce(
+        &config,
+        routes.len(),
+        output::is_quiet(),
+        &mut io::stdout(),
+        &mut io::stderr(),
+    )?;
+
+    tokio::select! {
+        result = server::start_server(config) => result,
+        _ = shutdown_signal() => {
+            status!("👋 Shutting down.");
+            if let Some(provider) = tracer_provider {
+                // sends the spans still batched in memory; it blocks until the export is done
+                let flushed = tokio::task::spawn_blocking(move || provider.shutdown()).await?;
+                if let Err(e) = flushed {
+                    eprintln!("⚠️  Could not send the last request spans: {}", e);
+                }
+            }
+            Ok(())
+        }
+    }
+}
+
+/// Tells what the server is about to serve, once: as the 🚀 banner on stdout, or under
+/// `--quiet` as a single JSON line on stderr that log pipelines can parse.
+fn announce(
+    config: &AppConfig,
+    route_count: usize,
+    quiet: bool,
+    stdout: &mut impl Write,
+    stderr: &mut impl Write,
+) -> io::Result<()> {
+    if quiet {
+        let line = json!({
+            "event": "start",
+            "port": config.port,
+            "profile": config.profile,
+            "config": config.path,
+            "config_source": config.config_source.to_string(),
+            "mode": config.mode.to_string(),
+            "routes": route_count,
+            "otlp_endpoint": config.otlp_endpoint,
+        });
+        return writeln!(stderr, "{}", line);
+    }
+
+    writeln!(
+        stdout,
+        "🚀 Starting proxy server on port {}...",
+        config.port
+    )?;
+    writeln!(stdout, "   Profile: {}", config.profile)?;
+    writeln!(
+        stdout,
+        "   Config: {} ({})",
+        config.path.display(),
+        config.config_source
+    )?;
+    writeln!(stdout, "   Mode: {}", config.mode)?;
+    writeln!(stdout, "   Routes loaded: {}", route_count)?;
+    if let Some(endpoint) = &config.otlp_endpoint {
+        writeln!(stdout, "   Tracing to: {}", endpoint)?;
+    }
+    Ok(())
+}
+
+/// Resolves on Ctrl-C, or on SIGTERM on Unix.
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                tokio::select! {
+                    _ = tokio::signal::ctrl_c() => {}
+                    _ = terminate.recv() => {}
+                }
+            }
+            Err(_) => {
+                let _ = tokio::signal::ctrl_c().await;
+            }
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = tokio::signal::ctrl_c().await;
+    }
+}
+
+/// Describes each route target that is the proxy itself on `bind` and `port`, whose requests
+/// would go around in a loop.
+fn self_targets(routes: &Router, bind: IpAddr, port: u16) -> Vec<String> {
+    let mut found = Vec::new();
+    for (key, route) in routes.iter() {
+        let targets = route
+            .instances()
+            .chain(route.header_routes.iter().map(|rule| rule.target.as_str()));
+        for target in targets.filter(|target| targets_proxy(target, bind, port)) {
+            found.push(format!(
+                "Route '{}' targets {}, which is this proxy (port {}); its requests would loop.",
+                key, target, port
+            ));
+        }
+    }
+    found
+}
+
+/// With `require_routes`, refuses to serve a profile that has no routes.
+fn check_routes(config: &AppConfig) -> Result<(), String> {
+    if config.require_routes && config.routes.load().is_empty() {
+        return Err(format!(
+            "Profile '{}' has no routes, but routes are required (--require-routes).\n  \
+             Add one with `local-http-proxy add <name> <target>` or check the config file at '{}'.",
+            config.profile,
+            config.path.display()
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{Args, ConfigFile};
+    use clap::Parser;
+
+    fn config(json: &str, argv: &[&str]) -> AppConfig {
+        let file: ConfigFile = serde_json::from_str(json).unwrap();
+        let args = Args::try_parse_from(argv).unwrap();
+        AppConfig::from_file("config.json".into(), file, &args).unwrap()
+    }
+
+    #[test]
+    fn empty_routes_fail_only_when_required() {
+        let empty = config("{}", &["lhp", "start", "--require-routes"]);
+        let err = check_routes(&empty).unwrap_err();
+        assert!(err.contains("Profile 'default' has no routes"));
+
+        let from_file = config(r#"{ "require_routes": true }"#, &["lhp", "start"]);
+        assert!(check_routes(&from_file).is_err());
+
+        assert!(check_routes(&config("{}", &["lhp", "start"])).is_ok());
+        let routed = config(
+            r#"{ "routes": { "api": "http://localhost:3000" } }"#,
+            &["lhp", "start", "--require-routes"],
+        );
+        assert!(check_routes(&routed).is_ok());
+    }
+
+    #[test]
+    fn targets_on_the_listen_port_are_flagged() {
+        let config = config(
+            r#"{ "routes": {
+                "api": "http://localhost:8100",
+                "web": { "target": "http://localhost:3000", "targets": ["http://127.0.0.1:8100"] }
+            } }"#,
+            &["lhp", "start", "--port", "8100"],
+        );
+        let found = self_targets(&config.routes.load(), config.bind, config.port);
+        assert_eq!(
+            found,
+            [
+                "Route 'api' targets http://localhost:8100, which is this proxy (port 8100); its requests would loop.",
+                "Route 'web' targets http://127.0.0.1:8100, which is this proxy (port 8100); its requests would loop.",
+            ]
+        );
+        assert!(self_targets(&config.routes.load(), config.bind, 8000).is_empty());
+    }
+
+    #[test]
+    fn quiet_start_logs_one_json_line_instead_of_the_banner() {
+        let config = config(
+            r#"{ "routes": { "api": "http://localhost:3000" } }"#,
+            &["lhp", "start", "--port", "8100"],
+        );
+
+        let (mut stdout, mut stderr) = (Vec::new(), Vec::new());
+        announce(&config, 1, false, &mut stdout, &mut stderr).unwrap();
+        let banner = String::from_utf8(stdout).unwrap();
+        assert!(banner.starts_with("🚀 Starting proxy server on port 8100..."));
+        assert!(banner.contains("   Routes loaded: 1\n"));
+        assert!(banner.contains("   Config: config.json (the default location)\n"));
+        assert!(stderr.is_empty());
+
+        let (mut stdout, mut stderr) = (Vec::new(), Vec::new());
+        announce(&config, 1, true, &mut stdout, &mut stderr).unwrap();
+        assert!(stdout.is_empty());
+        let line = String::from_utf8(stderr).unwrap();
+        assert_eq!(line.lines().count(), 1);
+        let logged: serde_json::Value = serde_json::from_str(&line).unwrap();
+        assert_eq!(logged["event"], "start");
+        assert_eq!(logged["port"], 8100);
+        assert_eq!(logged["profile"], "default");
+        assert_eq!(logged["config"], "config.json");
+        assert_eq!(logged["mode"], "path");
+        assert_eq!(logged["routes"], 1);
+    }
 }
@@ -0,0 +1,207 @@
+use crate::config::AppConfig;
+use crate::server::stats::{StatsReport, STATS_PATH};
+use http_body_util::{BodyExt, Empty};
+use hyper::body::Bytes;
+use hyper::header::PROXY_AUTHORIZATION;
+use hyper::Request;
+use hyper_util::client::legacy::Client;
+use hyper_util::rt::TokioExecutor;
+use std::error::Error;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::process;
+
+/// Handles the `stats` command.
+/// This function will exit the process after handling the command.
+pub async fn handle_stats_command(config: &AppConfig) -> ! {
+    if !config.stats_endpoint {
+        eprintln!(
+            "\nError:\nThe proxy only serves its stats when the config file turns them on.\n  \
+             Set \"stats_endpoint\": true in {} and restart the server.\n",
+            config.path.display()
+        );
+        process::exit(1);
+    }
+    let addr = server_addr(config.bind, config.port);
+    match print_stats(addr, config.proxy_auth.as_deref()).await {
+        Ok(..) => process::exit(0),
+        Err(e) => {
+            eprintln!("\nError:\n{}\n", e);
+            process::exit(1);
+        }
+    }
+}
+
+/// Where to reach a server bound to `bind`: the address itself, or loopback when it listens
+/// on every address (`0.0.0.0` or `::`).
+fn server_addr(bind: IpAddr, port: u16) -> SocketAddr {
+    let ip = match bind {
+        IpAddr::V4(ip) if ip.is_unspecified() => IpAddr::V4(Ipv4Addr::LOCALHOST),
+        IpAddr::V6(ip) if ip.is_unspecified() => IpAddr::V6(Ipv6Addr::LOCALHOST),
+        ip => ip,
+    };
+    SocketAddr::new(ip, port)
+}
+
+/// Fetches the request counters of the proxy running at `addr` and prints them, with the
+/// base64 `proxy_auth` credentials when the proxy requires them.
+/// The counters cover the server's current run only.
+async fn print_stats(
+    addr: SocketAddr,
+    proxy_auth: Option<&str>,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let port = addr.port();
+    let mut req = Request::get(format!("http://{}{}", addr, STATS_PATH));
+    if let Some(credentials) = proxy_auth {
+        req = req.header(PROXY_AUTHORIZATION, format!("Basic {}", credentials));
+    }
+    let req = req.body(Empty::<Bytes>::new())?;
+
+    let client = Client::builder(TokioExecutor::new()).build_http::<Empty<Bytes>>();
+    let res = client.request(req).await.map_err(|e| {
+        format!(
+            "Could not reach the proxy on port {}. Is it running?\n  Cause: {}",
+            port, e
+        )
+    })?;
+    if !res.status().is_success() {
+        return Err(format!(
+            "The proxy answered the stats request with {}.",
+            res.status()
+        )
+        .into());
+    }
+
+    let body = res.into_body().collect().await?.to_bytes();
+    let report: StatsReport = serde_json::from_slice(&body)?;
+    print!("{}", format_report(&report));
+    Ok(())
+}
+
+fn format_report(report: &StatsReport) -> String {
+    let connections = &report.connections;
+    let connections = format!(
+        "Connections: {} accepted, {} open, {} failed\n",
+        connections.accepted, connections.open, connections.errors
+    );
+    if report.routes.is_empty() {
+        return format!("No routes configured.\n{}", connections);
+    }
+
+    let width = report
+        .routes
+        .keys()
+        .map(|key| key.len())
+        .chain(["Route".len()])
+        .max()
+        .unwrap_or_default();
+    // the server only reports body bytes when it counts them
+    let bytes = report
+        .routes
+        .values()
+        .any(|stats| stats.request_bytes.is_some());
+    let mut out = format!(
+        "{:<width$}  {:>7}  {:>6}  {:>6}  {:>6}  {:>6}  {:>6}",
+        "Route", "Total", "1xx", "2xx", "3xx", "4xx", "5xx"
+    );
+    if bytes {
+        out.push_str(&format!("  {:>10}  {:>10}", "Bytes in", "Bytes out"));
+    }
+    out.push('\n');
+    for (key, stats) in &report.routes {
+        out.push_str(&format!(
+            "{:<width$}  {:>7}  {:>6}  {:>6}  {:>6}  {:>6}  {:>6}",
+            key,
+            stats.total,
+            stats.informational,
+            stats.success,
+            stats.redirection,
+            stats.client_error,
+            stats.server_error
+        ));
+        if bytes {
+            out.push_str(&format!(
+                "  {:>10}  {:>10}",
+                stats.request_bytes.unwrap_or_default(),
+                stats.response_bytes.unwrap_or_default()
+            ));
+        }
+        out.push('\n');
+    }
+    out.push('\n');
+    out.push_str(&connections);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::stats::{ConnectionStats, RouteStats};
+
+    #[test]
+    fn report_is_printed_as_aligned_table() {
+        let mut report = StatsReport::default();
+        report.routes.insert(
+            "api".into(),
+            RouteStats {
+                total: 12,
+                success: 10,
+                client_error: 2,
+                ..RouteStats::default()
+            },
+        );
+        report
+            .routes
+            .insert("frontend".into(), RouteStats::default());
+        report.connections = ConnectionStats {
+            accepted: 3,
+            errors: 0,
+            open: 1,
+        };
+
+        assert_eq!(
+            format_report(&report),
+            "Route       Total     1xx     2xx     3xx     4xx     5xx\n\
+             api            12       0      10       0       2       0\n\
+             frontend        0       0       0       0       0       0\n\
+             \n\
+             Connections: 3 accepted, 1 open, 0 failed\n"
+        );
+    }
+
+    #[test]
+    fn counted_bytes_get_their_own_columns() {
+        let mut report = StatsReport::default();
+        report.routes.insert(
+            "api".into(),
+            RouteStats {
+                total: 1,
+                success: 1,
+                request_bytes: Some(300),
+                response_bytes: Some(12000),
+                ..RouteStats::default()
+            },
+        );
+
+        assert!(format_report(&report).starts_with(
+            "Route    Total     1xx     2xx     3xx     4xx     5xx    Bytes in   Bytes out\n\
+             api          1       0       1       0       0       0         300       12000\n"
+        ));
+    }
+
+    #[test]
+    fn stats_are_fetched_from_the_bind_address() {
+        let addr = |bind: &str| server_addr(bind.parse().unwrap(), 8000).to_string();
+        assert_eq!(addr("127.0.0.1"), "127.0.0.1:8000");
+        assert_eq!(addr("192.168.1.20"), "192.168.1.20:8000");
+        assert_eq!(addr("0.0.0.0"), "127.0.0.1:8000");
+        assert_eq!(addr("::"), "[::1]:8000");
+    }
+
+    #[test]
+    fn empty_report_says_so() {
+        assert_eq!(
+            format_report(&StatsReport::default()),
+            "No routes configured.\nConnections: 0 accepted, 0 open, 0 failed\n"
+        );
+    }
+}
@@ -1,9 +1,11 @@
+use crate::config::validation::is_valid_label;
+use crate::config::WILDCARD_KEY;
+use crate::server::static_files::{DIR_PREFIX, FILE_PREFIX};
 use hyper::Uri;
 use regex::Regex;
-use std::sync::LazyLock;
-
-static LABEL_RE: LazyLock<Regex> =
-    LazyLock::new(|| Regex::new(r"^[a-z0-9](?:[a-z0-9-]*[a-z0-9])?$").unwrap());
+use std::env;
+use std::net::IpAddr;
+use std::path::Path;
 
 const INVALID_SOURCE_RULES: &str = "The name must be 1–63 characters long and a single segment usable in both a URL path and a domain.
 
@@ -17,27 +19,129 @@ const INVALID_SOURCE_RULES: &str = "The name must be 1–63 characters long and
 /// Normalize a user-provided source identifier into the canonical routing key used by the proxy.
 /// Rules:
 /// - Accepts forms like "/app", "app", "app.localhost", "app.localhost:3000".
-/// - Extracts the first path segment or the first host label before a dot.
+/// - Extracts the first path segment or the first host label before a dot. A host must end in
+///   one of `local_suffixes` (e.g. "localhost"), so an external host isn't taken for a key.
+/// - Keeps the port of a "app:8001" form, for Domain mode keys that match on the Host port.
 /// - Lowercases and validates against the proxy's label rules: [a-z0-9-], not starting/ending with '-'.
-pub fn normalize_source_key(input: &str) -> Result<String, String> {
+pub fn normalize_source_key(input: &str, local_suffixes: &[&str]) -> Result<String, String> {
     let s = input.trim();
     if s.is_empty() {
         return Err("Source cannot be empty".to_string());
     }
 
-    let key = parse_source_raw_key(s)?.to_ascii_lowercase();
-    validate_source_label(&key)?;
+    let key = parse_source_raw_key(s, local_suffixes)?.to_ascii_lowercase();
+    match key.split_once(':') {
+        Some((label, port)) if !s.starts_with('/') => {
+            validate_source_label(label)?;
+            if !port.parse::<u16>().is_ok_and(|p| p != 0) {
+                return Err(format!("Invalid port in source: \"{}\"", key));
+            }
+        }
+        _ => validate_source_label(&key)?,
+    }
     Ok(key)
 }
 
-/// Normalize a user-provided target into an absolute HTTP URI string acceptable by the proxy.
+/// Infers a source from a target's host name, e.g. `my-app` for `http://my-app:3000`: the
+/// first label of the host, checked by `normalize_source_key`. Targets by IP address,
+/// `localhost` or only a port have no name to use, so they are rejected.
+pub fn infer_source_key(target: &str) -> Result<String, String> {
+    let no_name = || {
+        format!(
+            "Can't infer a source from the target \"{}\": it has no host name to use.\n  \
+             Pass the source explicitly (e.g., `add my-app {}`).",
+            target.trim(),
+            target.trim()
+        )
+    };
+    let normalized = normalize_target(target, None)?;
+    if normalized.starts_with(DIR_PREFIX) || normalized.starts_with(FILE_PREFIX) {
+        return Err(no_name());
+    }
+    let uri: Uri = normalized.parse().map_err(|_| no_name())?;
+    let host = uri.host().ok_or_else(no_name)?;
+    let bare = host.trim_start_matches('[').trim_end_matches(']');
+    if bare.eq_ignore_ascii_case("localhost") || bare.parse::<IpAddr>().is_ok() {
+        return Err(no_name());
+    }
+    normalize_source_key(host.split('.').next().unwrap_or(host), &[])
+}
+
+/// Normalize a user-provided source into a key for Domain mode.
+/// Rules:
+/// - `*` or a wildcard host like `*.preview.localhost` becomes the wildcard key `*`.
+/// - Anything else follows `normalize_source_key`.
+pub fn normalize_domain_key(input: &str, local_suffixes: &[&str]) -> Result<String, String> {
+    let s = input.trim();
+    if s == WILDCARD_KEY || s.starts_with("*.") {
+        return Ok(WILDCARD_KEY.to_string());
+    }
+    normalize_source_key(s, local_suffixes)
+}
+
+/// Normalize a user-provided glob source for Path mode, like `api-*`, where each `*` stands for
+/// any characters of the key extracted from the request.
+/// Rules:
+/// - Accepts "api-*" or "/api-*"; lowercases it.
+/// - Allows only [a-z0-9-] besides `*`, at most 63 characters, and at least one of them.
+pub fn normalize_glob_key(input: &str) -> Result<String, String> {
+    let s = input.trim().trim_start_matches('/');
+    let key = s.to_ascii_lowercase();
+    let valid = key.len() <= 63
+        && key.chars().any(|c| c != '*')
+        && key
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-' || c == '*');
+    if !valid {
+        return Err(format!(
+            "Invalid source pattern: \"{}\".\n\n  A glob source is a name in which `*` stands for any \
+             characters (e.g., 'api-*'). Besides '*' it may only hold a-z, 0-9 and '-'.",
+            s
+        ));
+    }
+    Ok(key)
+}
+
+/// Normalize a user-provided source into a multi-segment key for Prefix mode.
+/// Rules:
+/// - Accepts forms like "/api/v2", "api/v2/" or a single segment like "api".
+/// - Lowercases and validates every segment against the same label rules as `normalize_source_key`.
+pub fn normalize_prefix_key(input: &str) -> Result<String, String> {
+    let s = input.trim().trim_matches('/');
+    if s.is_empty() {
+        return Err("Source cannot be empty".to_string());
+    }
+
+    let key = s.to_ascii_lowercase();
+    for segment in key.split('/') {
+        validate_source_label(segment)?;
+    }
+    Ok(key)
+}
+
+/// Normalize a user-provided source pattern for Regex mode.
+/// The pattern is kept verbatim (apart from surrounding whitespace) and must compile.
+pub fn normalize_pattern_key(input: &str) -> Result<String, String> {
+    let s = input.trim();
+    if s.is_empty() {
+        return Err("Source cannot be empty".to_string());
+    }
+    Regex::new(s).map_err(|e| format!("Invalid source pattern: \"{}\".\n\n  {}", s, e))?;
+    Ok(s.to_string())
+}
+
+/// Normalize a user-provided target into an absolute HTTP(S) URI string acceptable by the proxy.
 /// Rules:
 /// - Allow just a port (e.g., "3000" or ":3000") -> http://localhost:3000
 /// - Allow host:port or IP:port -> http://{host}:port
+/// - Allow a bare host (e.g., "myhost") -> http://myhost, or http://myhost:{default_port}
+///   when a default port is given
 /// - Allow IPv6 literals in brackets: "\[::1]:3000" -> http://\[::1]:3000
-/// - Allow explicit http://...; reject https:// (not supported by current client)
+/// - Allow explicit http://... and https://...; reject other schemes
+/// - Allow a static directory as "dir:<path>" or "file:///<path>"; a relative "dir:" path is
+///   made absolute against the current directory
 /// - Trim trailing slashes to avoid '//' when concatenating with request path
-pub fn normalize_target(input: &str) -> Result<String, String> {
+pub fn normalize_target(input: &str, default_port: Option<u16>) -> Result<String, String> {
     fn is_all_digits(s: &str) -> bool {
         !s.is_empty() && s.chars().all(|c| c.is_ascii_digit())
     }
@@ -46,8 +150,8 @@ pub fn normalize_target(input: &str) -> Result<String, String> {
         let uri: Uri = s
             .parse()
             .map_err(|_| "Target must be a valid absolute URI or host:port".to_string())?;
-        if uri.scheme_str() != Some("http") {
-            return Err("Only http:// targets are supported".into());
+        if !matches!(uri.scheme_str(), Some("http" | "https")) {
+            return Err("Only http:// and https:// targets are supported".into());
         }
         if uri.authority().is_none() {
             return Err("Target must include a host (authority)".into());
@@ -59,8 +163,18 @@ pub fn normalize_target(input: &str) -> Result<String, String> {
     if s.is_empty() {
         return Err("Target cannot be empty".into());
     }
+    if let Some(path) = s.strip_prefix(DIR_PREFIX) {
+        return normalize_dir_target(path);
+    }
+    if let Some(path) = s.strip_prefix(FILE_PREFIX) {
+        if !path.starts_with('/') {
+            return Err("A file:// target needs an absolute path, e.g. file:///srv/site".into());
+        }
+        return Ok(format!("{}{}", FILE_PREFIX, trim_dir_path(path)));
+    }
 
     // Port-only forms
+    let mut bare_host = false;
     let with_scheme = if is_all_digits(s) {
         format!("http://localhost:{}", s)
     } else if let Some(rest) = s.strip_prefix(':') {
@@ -69,23 +183,55 @@ pub fn normalize_target(input: &str) -> Result<String, String> {
         } else {
             return Err("Invalid port after ':' in target".into());
         }
-    } else if s.starts_with("http://") {
+    } else if s.starts_with("http://") || s.starts_with("https://") {
         s.to_string()
-    } else if s.starts_with("https://") {
-        return Err("https:// upstreams are not supported (TLS not enabled). Use http:// or a port like 3000".into());
     } else if s.contains("://") {
-        return Err("Unsupported URI scheme. Only http:// is supported".into());
+        return Err(
+            "Unsupported URI scheme. Only http://, https:// and file:// are supported".into(),
+        );
     } else {
+        bare_host = true;
         format!("http://{}", s)
     };
 
     // Validate and normalize trailing slash
-    let uri = parse_and_validate_http(&with_scheme)?;
+    let mut uri = parse_and_validate_http(&with_scheme)?;
+    if let Some(port) = default_port.filter(|_| bare_host && uri.port().is_none()) {
+        let host = uri.host().unwrap_or_default();
+        let path = uri.path_and_query().map_or("", |pq| pq.as_str());
+        uri = parse_and_validate_http(&format!("http://{}:{}{}", host, port, path))?;
+    }
     let out = uri.to_string().trim_end_matches('/').to_string();
     Ok(out)
 }
 
-fn parse_source_raw_key(s: &str) -> Result<String, String> {
+fn normalize_dir_target(path: &str) -> Result<String, String> {
+    let path = path.trim();
+    if path.is_empty() {
+        return Err("A dir: target needs a directory, e.g. dir:./dist".into());
+    }
+    let expanded = shellexpand::tilde(path);
+    let mut absolute = Path::new(expanded.as_ref()).to_path_buf();
+    if absolute.is_relative() {
+        let cwd = env::current_dir()
+            .map_err(|e| format!("Could not resolve the relative path '{}': {}", path, e))?;
+        absolute = cwd.join(absolute);
+    }
+    let absolute = absolute
+        .to_str()
+        .ok_or_else(|| format!("The path '{}' is not valid UTF-8", path))?;
+    Ok(format!("{}{}", DIR_PREFIX, trim_dir_path(absolute)))
+}
+
+/// Drops trailing slashes, keeping a lone `/`.
+fn trim_dir_path(path: &str) -> &str {
+    match path.trim_end_matches('/') {
+        "" => "/",
+        trimmed => trimmed,
+    }
+}
+
+fn parse_source_raw_key(s: &str, local_suffixes: &[&str]) -> Result<String, String> {
     if s.starts_with('/') {
         return s
             .trim_start_matches('/')
@@ -101,6 +247,15 @@ fn parse_source_raw_key(s: &str) -> Result<String, String> {
     if s.contains('.') {
         // strip :port if present, then take first label before '.'
         let before_port = s.split(':').next().unwrap_or(s);
+        if !has_local_suffix(before_port, local_suffixes) {
+            let suffixes: Vec<String> = local_suffixes.iter().map(|s| format!(".{s}")).collect();
+            return Err(format!(
+                "Invalid source: \"{}\" is not a local host. Host sources must end in {} \
+                 (set `local_suffixes` in the config file to change the list).",
+                before_port,
+                suffixes.join(", ")
+            ));
+        }
         return before_port
             .split('.')
             .find(|seg| !seg.is_empty())
@@ -114,14 +269,75 @@ fn parse_source_raw_key(s: &str) -> Result<String, String> {
     Ok(s.to_string())
 }
 
-fn validate_source_label(key: &str) -> Result<(), String> {
-    if key.len() > 63 {
-        return Err(format!(
-            "Invalid source name: \"{}\".\n\n  {}",
-            key, INVALID_SOURCE_RULES
-        ));
+/// Splits an `add` target like `localhost:3000=3,localhost:3001` into its instances and their
+/// weights, 1 where none is given. A trailing `=<number>` outside a query string is the weight,
+/// so `localhost:3000/?page=2` is a plain target.
+pub fn parse_weighted_targets(input: &str) -> Result<Vec<(&str, u32)>, String> {
+    input
+        .split(',')
+        .map(|part| {
+            let part = part.trim();
+            match part.rsplit_once('=') {
+                Some((target, weight))
+                    if !target.contains('?')
+                        && !weight.is_empty()
+                        && weight.bytes().all(|b| b.is_ascii_digit()) =>
+                {
+                    match weight.parse::<u32>() {
+                        Ok(weight) if weight > 0 => Ok((target, weight)),
+                        _ => Err(format!(
+                            "Invalid weight '{}' for '{}': use a whole number of at least 1.",
+                            weight, target
+                        )),
+                    }
+                }
+                _ => Ok((part, 1)),
+            }
+        })
+        .collect()
+}
+
+/// Whether `target` (as `normalize_target` returns it) is the proxy itself, listening on
+/// `bind` at `port`: a loopback or unspecified host, or `bind` itself, on that port.
+/// Forwarding to it would send every request around in a loop.
+pub fn targets_proxy(target: &str, bind: IpAddr, port: u16) -> bool {
+    let Ok(uri) = target.parse::<Uri>() else {
+        return false;
+    };
+    let Some(host) = uri.host() else {
+        return false;
+    };
+    let default_port = if uri.scheme_str() == Some("https") {
+        443
+    } else {
+        80
+    };
+    if uri.port_u16().unwrap_or(default_port) != port {
+        return false;
     }
-    if !LABEL_RE.is_match(key) {
+    let host = host
+        .trim_start_matches('[')
+        .trim_end_matches(']')
+        .to_ascii_lowercase();
+    host == "localhost"
+        || host.ends_with(".localhost")
+        || host
+            .parse::<IpAddr>()
+            .is_ok_and(|ip| ip == bind || ip.is_loopback() || ip.is_unspecified())
+}
+
+/// Whether `host` is below one of `suffixes`, e.g. `api.localhost` below `localhost`.
+fn has_local_suffix(host: &str, suffixes: &[&str]) -> bool {
+    let host = host.trim_end_matches('.').to_ascii_lowercase();
+    suffixes.iter().any(|suffix| {
+        let suffix = suffix.trim_matches('.').to_ascii_lowercase();
+        host.strip_suffix(suffix.as_str())
+            .is_some_and(|label| label.ends_with('.') && label.len() > 1)
+    })
+}
+
+fn validate_source_label(key: &str) -> Result<(), String> {
+    if !is_valid_label(key) {
         return Err(format!(
             "Invalid source name: \"{}\".\n\n  {}",
             key, INVALID_SOURCE_RULES
@@ -133,53 +349,282 @@ fn validate_source_label(key: &str) -> Result<(), String> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::config::{DEFAULT_BIND, DEFAULT_LOCAL_SUFFIXES};
+
+    #[test]
+    fn sources_are_inferred_from_target_host_names() {
+        assert_eq!(infer_source_key("http://my-app:3000").unwrap(), "my-app");
+        assert_eq!(infer_source_key("API.internal:8080").unwrap(), "api");
+        assert_eq!(infer_source_key("https://docs").unwrap(), "docs");
+
+        for ambiguous in [
+            "3000",
+            "localhost:3000",
+            "http://127.0.0.1:3000",
+            "[::1]:3000",
+        ] {
+            let err = infer_source_key(ambiguous).unwrap_err();
+            assert!(
+                err.contains("Pass the source explicitly"),
+                "{ambiguous}: {err}"
+            );
+        }
+        // the name must still be a valid source
+        assert!(infer_source_key("http://my_app:3000").is_err());
+    }
+
+    #[test]
+    fn glob_keys_keep_their_stars() {
+        assert_eq!(normalize_glob_key("/API-*").unwrap(), "api-*");
+        assert_eq!(normalize_glob_key("*-svc").unwrap(), "*-svc");
+        assert!(normalize_glob_key("*").is_err());
+        assert!(normalize_glob_key("api/*").is_err());
+        assert!(normalize_glob_key("api.*").is_err());
+    }
+
+    #[test]
+    fn weighted_targets_are_split_with_their_weights() {
+        assert_eq!(
+            parse_weighted_targets("localhost:3000=3, localhost:3001").unwrap(),
+            [("localhost:3000", 3), ("localhost:3001", 1)]
+        );
+        assert_eq!(
+            parse_weighted_targets("localhost:3000/?page=2").unwrap(),
+            [("localhost:3000/?page=2", 1)]
+        );
+        assert!(parse_weighted_targets("localhost:3000=0").is_err());
+        assert!(parse_weighted_targets("localhost:3000=99999999999").is_err());
+    }
 
     #[test]
     fn normalize_source_accepts_path_and_host_forms() {
-        assert_eq!(normalize_source_key("/Svc").unwrap(), "svc");
-        assert_eq!(normalize_source_key("API.localhost").unwrap(), "api");
-        assert_eq!(normalize_source_key("api.localhost:8080").unwrap(), "api");
-        assert_eq!(normalize_source_key("my-app").unwrap(), "my-app");
-        // dotted host inputs normalize to the first label
-        assert_eq!(normalize_source_key("has.dot").unwrap(), "has");
+        assert_eq!(
+            normalize_source_key("/Svc", &DEFAULT_LOCAL_SUFFIXES).unwrap(),
+            "svc"
+        );
+        assert_eq!(
+            normalize_source_key("API.localhost", &DEFAULT_LOCAL_SUFFIXES).unwrap(),
+            "api"
+        );
+        assert_eq!(
+            normalize_source_key("api.localhost:8080", &DEFAULT_LOCAL_SUFFIXES).unwrap(),
+            "api"
+        );
+        assert_eq!(
+            normalize_source_key("my-app", &DEFAULT_LOCAL_SUFFIXES).unwrap(),
+            "my-app"
+        );
+        assert_eq!(
+            normalize_source_key("web.preview.test", &DEFAULT_LOCAL_SUFFIXES).unwrap(),
+            "web"
+        );
+    }
+
+    #[test]
+    fn normalize_source_requires_a_local_suffix() {
+        for host in [
+            "api.github.com",
+            "has.dot",
+            "api.localhost.com",
+            ".localhost",
+        ] {
+            let err = normalize_source_key(host, &DEFAULT_LOCAL_SUFFIXES).unwrap_err();
+            assert!(err.contains("not a local host"), "{host}: {err}");
+        }
+        assert!(normalize_source_key("api.LOCAL", &DEFAULT_LOCAL_SUFFIXES).is_ok());
+        assert!(normalize_domain_key("api.github.com", &DEFAULT_LOCAL_SUFFIXES).is_err());
+
+        let custom = ["dev.internal"];
+        assert_eq!(
+            normalize_source_key("api.dev.internal:8080", &custom).unwrap(),
+            "api"
+        );
+        assert!(normalize_source_key("api.localhost", &custom).is_err());
+    }
+
+    #[test]
+    fn normalize_source_keeps_explicit_key_port() {
+        assert_eq!(
+            normalize_source_key("API:8001", &DEFAULT_LOCAL_SUFFIXES).unwrap(),
+            "api:8001"
+        );
+        assert!(normalize_source_key("api:0", &DEFAULT_LOCAL_SUFFIXES).is_err());
+        assert!(normalize_source_key("api:http", &DEFAULT_LOCAL_SUFFIXES).is_err());
+        assert!(normalize_source_key("-api:8001", &DEFAULT_LOCAL_SUFFIXES).is_err());
+        assert!(normalize_source_key("/api:8001", &DEFAULT_LOCAL_SUFFIXES).is_err());
     }
 
     #[test]
     fn normalize_source_rejects_invalid() {
-        assert!(normalize_source_key("").is_err());
-        assert!(normalize_source_key("-bad").is_err());
-        assert!(normalize_source_key("bad-").is_err());
-        assert!(normalize_source_key("has/slash").is_err());
+        assert!(normalize_source_key("", &DEFAULT_LOCAL_SUFFIXES).is_err());
+        assert!(normalize_source_key("-bad", &DEFAULT_LOCAL_SUFFIXES).is_err());
+        assert!(normalize_source_key("bad-", &DEFAULT_LOCAL_SUFFIXES).is_err());
+        assert!(normalize_source_key("has/slash", &DEFAULT_LOCAL_SUFFIXES).is_err());
         // 64-char label rejected
         let sixty_four = "a".repeat(64);
-        assert!(normalize_source_key(&sixty_four).is_err());
+        assert!(normalize_source_key(&sixty_four, &DEFAULT_LOCAL_SUFFIXES).is_err());
         // 63-char label accepted
         let sixty_three = "a".repeat(63);
-        assert_eq!(normalize_source_key(&sixty_three).unwrap(), sixty_three);
+        assert_eq!(
+            normalize_source_key(&sixty_three, &DEFAULT_LOCAL_SUFFIXES).unwrap(),
+            sixty_three
+        );
+    }
+
+    #[test]
+    fn targets_on_the_proxy_port_of_this_machine_are_the_proxy() {
+        for target in [
+            "http://localhost:8000",
+            "http://127.0.0.1:8000/api",
+            "http://[::1]:8000",
+            "http://0.0.0.0:8000",
+            "http://app.localhost:8000",
+        ] {
+            assert!(targets_proxy(target, DEFAULT_BIND, 8000), "{target}");
+        }
+        assert!(targets_proxy("http://localhost", DEFAULT_BIND, 80));
+        assert!(targets_proxy("https://localhost", DEFAULT_BIND, 443));
+
+        assert!(!targets_proxy("http://localhost:8001", DEFAULT_BIND, 8000));
+        assert!(!targets_proxy(
+            "http://example.com:8000",
+            DEFAULT_BIND,
+            8000
+        ));
+        assert!(!targets_proxy(
+            "http://192.168.1.20:8000",
+            DEFAULT_BIND,
+            8000
+        ));
+        assert!(!targets_proxy("dir:/srv/site", DEFAULT_BIND, 8000));
+
+        // the address the proxy is bound to is the proxy too
+        let bind = "192.168.1.20".parse().unwrap();
+        assert!(targets_proxy("http://192.168.1.20:8000", bind, 8000));
+        assert!(!targets_proxy("http://192.168.1.21:8000", bind, 8000));
+    }
+
+    #[test]
+    fn normalize_domain_key_accepts_wildcards() {
+        assert_eq!(
+            normalize_domain_key("*", &DEFAULT_LOCAL_SUFFIXES).unwrap(),
+            "*"
+        );
+        assert_eq!(
+            normalize_domain_key("*.preview.localhost", &DEFAULT_LOCAL_SUFFIXES).unwrap(),
+            "*"
+        );
+        assert_eq!(
+            normalize_domain_key("API.localhost", &DEFAULT_LOCAL_SUFFIXES).unwrap(),
+            "api"
+        );
+        assert!(normalize_domain_key("a*", &DEFAULT_LOCAL_SUFFIXES).is_err());
+        assert!(normalize_source_key("*", &DEFAULT_LOCAL_SUFFIXES).is_err());
+    }
+
+    #[test]
+    fn normalize_prefix_key_keeps_all_segments() {
+        assert_eq!(normalize_prefix_key("/API/v2/").unwrap(), "api/v2");
+        assert_eq!(normalize_prefix_key("api").unwrap(), "api");
+        assert!(normalize_prefix_key("/").is_err());
+        assert!(normalize_prefix_key("/api//v2").is_err());
+        assert!(normalize_prefix_key("/api/-v2").is_err());
+    }
+
+    #[test]
+    fn normalize_pattern_key_requires_valid_regex() {
+        assert_eq!(
+            normalize_pattern_key(r" ^/User-\d+/ ").unwrap(),
+            r"^/User-\d+/"
+        );
+        assert!(normalize_pattern_key(r"^/user-(\d+").is_err());
+        assert!(normalize_pattern_key("  ").is_err());
     }
 
     #[test]
     fn normalize_target_supports_port_and_hostport() {
-        assert_eq!(normalize_target("3000").unwrap(), "http://localhost:3000");
-        assert_eq!(normalize_target(":3000").unwrap(), "http://localhost:3000");
         assert_eq!(
-            normalize_target("localhost:3000").unwrap(),
+            normalize_target("3000", None).unwrap(),
             "http://localhost:3000"
         );
         assert_eq!(
-            normalize_target("127.0.0.1:8080").unwrap(),
+            normalize_target(":3000", None).unwrap(),
+            "http://localhost:3000"
+        );
+        assert_eq!(
+            normalize_target("localhost:3000", None).unwrap(),
+            "http://localhost:3000"
+        );
+        assert_eq!(
+            normalize_target("127.0.0.1:8080", None).unwrap(),
             "http://127.0.0.1:8080"
         );
-        assert_eq!(normalize_target("[::1]:8080").unwrap(), "http://[::1]:8080");
         assert_eq!(
-            normalize_target("http://svc:8080/").unwrap(),
+            normalize_target("[::1]:8080", None).unwrap(),
+            "http://[::1]:8080"
+        );
+        assert_eq!(
+            normalize_target("http://svc:8080/", None).unwrap(),
             "http://svc:8080"
         );
     }
 
     #[test]
-    fn normalize_target_rejects_https_and_bad_scheme() {
-        assert!(normalize_target("https://host").is_err());
-        assert!(normalize_target("ftp://host").is_err());
+    fn normalize_target_accepts_https_and_rejects_bad_scheme() {
+        assert_eq!(
+            normalize_target("https://svc:8443/", None).unwrap(),
+            "https://svc:8443"
+        );
+        assert!(normalize_target("ftp://host", None).is_err());
+    }
+
+    #[test]
+    fn normalize_target_accepts_static_directories() {
+        assert_eq!(
+            normalize_target("dir:/srv/site/", None).unwrap(),
+            "dir:/srv/site"
+        );
+        assert_eq!(normalize_target("dir:/", None).unwrap(), "dir:/");
+        let cwd = env::current_dir().unwrap();
+        assert_eq!(
+            normalize_target("dir:dist", None).unwrap(),
+            format!("dir:{}", cwd.join("dist").display())
+        );
+        assert_eq!(
+            normalize_target("file:///srv/site", None).unwrap(),
+            "file:///srv/site"
+        );
+        assert!(normalize_target("dir:", None).is_err());
+        assert!(normalize_target("file://site", None).is_err());
+    }
+
+    #[test]
+    fn host_only_targets_get_the_default_port_when_one_is_set() {
+        assert_eq!(normalize_target("myhost", None).unwrap(), "http://myhost");
+        assert_eq!(
+            normalize_target("myhost", Some(80)).unwrap(),
+            "http://myhost:80"
+        );
+        assert_eq!(
+            normalize_target("myhost/api", Some(8080)).unwrap(),
+            "http://myhost:8080/api"
+        );
+        assert_eq!(
+            normalize_target("[::1]", Some(80)).unwrap(),
+            "http://[::1]:80"
+        );
+        // an explicit port or scheme wins
+        assert_eq!(
+            normalize_target("myhost:3000", Some(80)).unwrap(),
+            "http://myhost:3000"
+        );
+        assert_eq!(
+            normalize_target("https://myhost", Some(80)).unwrap(),
+            "https://myhost"
+        );
+        assert_eq!(
+            normalize_target("3000", Some(80)).unwrap(),
+            "http://localhost:3000"
+        );
     }
 }
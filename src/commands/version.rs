@@ -0,0 +1,90 @@
+use crate::config::AppConfig;
+use std::time::{Duration, UNIX_EPOCH};
+
+/// What was known about the build when it was compiled, set by `build.rs`.
+struct BuildInfo {
+    version: &'static str,
+    commit: &'static str,
+    timestamp: &'static str,
+    features: &'static str,
+    target: &'static str,
+    profile: &'static str,
+}
+
+const BUILD: BuildInfo = BuildInfo {
+    version: env!("CARGO_PKG_VERSION"),
+    commit: env!("LHP_BUILD_COMMIT"),
+    timestamp: env!("LHP_BUILD_TIMESTAMP"),
+    features: env!("LHP_BUILD_FEATURES"),
+    target: env!("LHP_BUILD_TARGET"),
+    profile: env!("LHP_BUILD_PROFILE"),
+};
+
+/// Handles the `version` command. `verbose` adds what a bug report needs: the commit, the
+/// build date and target, the enabled cargo features and the config file in use.
+pub fn handle_version_command(config: &AppConfig, verbose: bool) {
+    if verbose {
+        print!("{}", verbose_report(&BUILD, config));
+    } else {
+        println!("local-http-proxy {}", BUILD.version);
+    }
+}
+
+fn verbose_report(build: &BuildInfo, config: &AppConfig) -> String {
+    let or_unknown = |value: &'static str| if value.is_empty() { "unknown" } else { value };
+    let built = build
+        .timestamp
+        .parse()
+        .map(|secs| httpdate::fmt_http_date(UNIX_EPOCH + Duration::from_secs(secs)))
+        .unwrap_or_else(|_| "unknown".to_string());
+    let features = if build.features.is_empty() {
+        "none".to_string()
+    } else {
+        build.features.replace(',', ", ")
+    };
+    format!(
+        "local-http-proxy {}\n  Commit:   {}\n  Built:    {} ({} build)\n  Target:   {}\n  \
+         Features: {}\n  Config:   {} ({})\n",
+        build.version,
+        or_unknown(build.commit),
+        built,
+        or_unknown(build.profile),
+        or_unknown(build.target),
+        features,
+        config.path.display(),
+        config.config_source
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{Args, ConfigFile};
+    use clap::Parser;
+
+    #[test]
+    fn verbose_output_lists_the_build_and_its_features() {
+        let args = Args::try_parse_from(["lhp", "version", "--verbose"]).unwrap();
+        let config =
+            AppConfig::from_file("/tmp/lhp.json".into(), ConfigFile::default(), &args).unwrap();
+        let build = BuildInfo {
+            version: "1.2.3",
+            commit: "0123456789ab",
+            timestamp: "1760434200",
+            features: "otlp,tls",
+            target: "x86_64-unknown-linux-gnu",
+            profile: "release",
+        };
+
+        let report = verbose_report(&build, &config);
+        assert!(report.starts_with("local-http-proxy 1.2.3\n"), "{report}");
+        assert!(report.contains("  Commit:   0123456789ab\n"), "{report}");
+        assert!(report.contains("  Built:    Tue, 14 Oct 2025 09:30:00 GMT (release build)\n"));
+        assert!(report.contains("  Features: otlp, tls\n"), "{report}");
+        assert!(report.contains("  Config:   /tmp/lhp.json (the default location)\n"));
+
+        let report = verbose_report(&BUILD, &config);
+        assert!(report.contains(&format!("local-http-proxy {}\n", env!("CARGO_PKG_VERSION"))));
+        assert!(report.contains("  Features: "), "{report}");
+    }
+}
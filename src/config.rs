@@ -2,7 +2,14 @@ mod app_config;
 mod args;
 mod models;
 pub mod util;
+pub mod validation;
 
-pub use app_config::AppConfig;
-pub use args::{Args,Command};
-pub use models::{ProxyMode,ConfigFile};
+pub use app_config::{read_merged_config, AppConfig, ConfigSource};
+pub use args::{Args, Command, Switch};
+pub use models::{
+    weighted_index, ClientCert, ConfigFile, HeaderRoute, HttpVersion, Latency, MissingHost,
+    OriginHeader, ProxyMode, RedirectStatus, Route, RouteKind, Routes, StickySession,
+    TrailingSlash, DEFAULT_BIND, DEFAULT_LOCAL_SUFFIXES, DEFAULT_MAINTENANCE_MESSAGE,
+    DEFAULT_MAX_HOPS, DEFAULT_MAX_URI_LENGTH, DEFAULT_PROFILE, DEFAULT_ROUTES_ENDPOINT_PATH,
+    WILDCARD_KEY,
+};
@@ -1,62 +1,947 @@
 use super::args::{Args, Command};
-use super::models::ProxyMode;
+use super::models::{
+    ConfigFile, HttpVersion, MissingHost, Profile, ProxyMode, Route, Routes, DEFAULT_BIND,
+    DEFAULT_LOCAL_SUFFIXES, DEFAULT_MAINTENANCE_MESSAGE, DEFAULT_MAX_HOPS, DEFAULT_MAX_URI_LENGTH,
+    DEFAULT_PROFILE, DEFAULT_ROUTES_ENDPOINT_PATH,
+};
 use super::util::*;
+use crate::commands::util::{normalize_source_key, normalize_target};
+use crate::server::access_log::{AccessLog, DEFAULT_ACCESS_LOG_MAX_SIZE};
+use crate::server::router::{Router, SharedRouter};
+use crate::server::stats::ConnectionCounters;
+use crate::server::DEFAULT_ACCEPT_BACKOFF_MAX;
+use base64::prelude::*;
+use clap::ValueEnum;
+use hyper::header::HeaderValue;
+use hyper::{StatusCode, Version};
 use std::collections::HashMap;
+use std::env;
+use std::fmt::{self, Display, Formatter};
+use std::net::IpAddr;
 use std::path::{Path, PathBuf};
 use std::process;
-use std::sync::OnceLock;
+use std::sync::{Arc, PoisonError, RwLock};
+use std::time::Duration;
 
-static CONFIG: OnceLock<AppConfig> = OnceLock::new();
+/// Prefix of the environment variables that define routes, as in
+/// `LHP_ROUTE_API=localhost:8080`.
+const ENV_ROUTE_PREFIX: &str = "LHP_ROUTE_";
+
+/// The environment variables `start` reads the settings of its `--port`, `--mode` and
+/// `--bind` flags from when the flags are not given.
+const PORT_ENV: &str = "PORT";
+const MODE_ENV: &str = "MODE";
+const BIND_ENV: &str = "BIND";
+
+/// The shortest `max_uri_length` accepted: enough for `/` and a short route key, so a limit
+/// can't turn away every request.
+const MIN_MAX_URI_LENGTH: usize = 16;
+
+/// The environment variables the config file path is read from when `--config-file` is not
+/// given, the first one set winning.
+const CONFIG_ENVS: [&str; 2] = ["LOCAL_HTTP_PROXY_CONFIG", "CONFIG_FILE"];
+
+/// Where the config file path came from, as `start` tells.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConfigSource {
+    Flag,
+    Env(&'static str),
+    Default,
+}
+
+impl Display for ConfigSource {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigSource::Flag => f.write_str("from --config-file"),
+            ConfigSource::Env(name) => write!(f, "from ${}", name),
+            ConfigSource::Default => f.write_str("the default location"),
+        }
+    }
+}
+
+/// The config files to read: those given with `--config-file`, else the one named by the
+/// first of `CONFIG_ENVS` that is set, else `default`.
+fn config_paths(
+    flags: &[String],
+    env: impl Fn(&str) -> Option<String>,
+    default: impl FnOnce() -> PathBuf,
+) -> (Vec<PathBuf>, ConfigSource) {
+    let expand = |file: &str| Path::new(shellexpand::tilde(file).as_ref()).to_path_buf();
+    if !flags.is_empty() {
+        let paths = flags.iter().map(|file| expand(file)).collect();
+        return (paths, ConfigSource::Flag);
+    }
+    for name in CONFIG_ENVS {
+        if let Some(file) = env(name).filter(|file| !file.trim().is_empty()) {
+            return (vec![expand(&file)], ConfigSource::Env(name));
+        }
+    }
+    (vec![default()], ConfigSource::Default)
+}
 
 /// Represents the active, in-memory configuration for the running application.
 #[derive(Debug)]
 pub struct AppConfig {
     pub path: PathBuf,
+    // every file the config was merged from, `path` last
+    pub config_paths: Vec<PathBuf>,
+    pub config_source: ConfigSource,
+    pub profile: String,
     pub port: u16,
+    pub bind: IpAddr,
     pub mode: ProxyMode,
-    pub routes: HashMap<String, String>,
+    pub routes: SharedRouter,
+    pub match_host_port: bool,
+    pub misdirected_unknown_hosts: bool,
+    pub missing_host: MissingHost,
+    pub root_index: bool,
+    // the path the route table is served on; `None` when the endpoint is off
+    pub routes_endpoint: Option<String>,
+    // whether `STATS_PATH` answers with the request counters instead of being routed
+    pub stats_endpoint: bool,
+    // the page served to every request while in maintenance mode; `None` when it is off
+    pub maintenance: RwLock<Option<String>>,
+    pub max_uri_length: usize,
+    pub max_hops: u32,
+    pub not_found_status: StatusCode,
+    pub bad_gateway_status: StatusCode,
+    pub server_header: Option<HeaderValue>,
+    // the base64 `user:password` expected in `Proxy-Authorization`, when proxy auth is on
+    pub proxy_auth: Option<String>,
+    pub otlp_endpoint: Option<String>,
+    pub h2c: bool,
+    pub connection_close: bool,
+    pub upstream_http_version: Option<Version>,
+    pub require_routes: bool,
+    pub pidfile: Option<PathBuf>,
+    pub daemon: bool,
+    pub log_file: Option<PathBuf>,
+    pub access_log: Option<AccessLog>,
+    pub accept_backoff_max: Duration,
+    pub count_bytes: bool,
+    pub connections: Arc<ConnectionCounters>,
 }
 
 impl AppConfig {
-    /// Initializes the AppConfig singleton by parsing CLI args and the config file.
-    pub fn load(args: &Args) -> &'static AppConfig {
-        let expanded_path = shellexpand::tilde(&args.config_file);
-        let config_path = Path::new(expanded_path.as_ref()).to_path_buf();
+    /// Builds the config from the CLI args and the config files, exiting the process with the
+    /// error if they can't be loaded. Commands share it through the returned `Arc`.
+    pub fn load(args: &Args) -> Arc<AppConfig> {
+        let (config_paths, config_source) = config_paths(
+            &args.config_file,
+            |name| env::var(name).ok(),
+            default_config_path,
+        );
+        let config_path = config_paths.last().cloned().unwrap_or_default();
 
-        let file_content = load_or_create_config_file(&config_path).unwrap_or_else(|e| {
+        let mut file_content = load_config_files(&config_paths).unwrap_or_else(|e| {
             eprintln!("Error: Could not load configuration file.\n  Cause: {}", e);
             process::exit(1);
         });
+        let profile = file_content
+            .active_profile_name(args.profile.as_deref())
+            .to_string();
+        let from_env =
+            env_routes(env::vars(), file_content.default_upstream_port).unwrap_or_else(|e| {
+                eprintln!("Error: {}", e);
+                process::exit(1);
+            });
+        merge_routes(&mut file_content, &profile, from_env);
+
+        let mut config =
+            AppConfig::from_file(config_path, file_content, args).unwrap_or_else(|e| {
+                eprintln!("Error: {}", e);
+                process::exit(1);
+            });
+        config.config_paths = config_paths;
+        config.config_source = config_source;
+        Arc::new(config)
+    }
+
+    /// The config file commands write to (the last one given), without reading or creating
+    /// it.
+    pub fn write_path(args: &Args) -> PathBuf {
+        let (paths, _) = config_paths(
+            &args.config_file,
+            |name| env::var(name).ok(),
+            default_config_path,
+        );
+        paths.last().cloned().unwrap_or_default()
+    }
+
+    /// Builds the config from the file contents, selecting the active profile.
+    /// `add` may name a profile that doesn't exist yet and `use-profile` replaces the selection,
+    /// so both tolerate a missing profile; every other command requires it.
+    pub(crate) fn from_file(
+        path: PathBuf,
+        file: ConfigFile,
+        args: &Args,
+    ) -> Result<AppConfig, String> {
+        Self::from_file_and_env(path, file, args, |name| env::var(name).ok())
+    }
+
+    /// Like `from_file`, reading the environment through `env`. Settings of `start` come from
+    /// its flag, else the environment, else the config file, else the default.
+    pub(crate) fn from_file_and_env(
+        path: PathBuf,
+        mut file: ConfigFile,
+        args: &Args,
+        env: impl Fn(&str) -> Option<String>,
+    ) -> Result<AppConfig, String> {
+        let profile = file
+            .active_profile_name(args.profile.as_deref())
+            .to_string();
+        let selected = if profile == DEFAULT_PROFILE {
+            file.default_profile
+        } else {
+            match file.profiles.remove(&profile) {
+                Some(p) => p,
+                None if matches!(
+                    args.command,
+                    Command::Add { .. } | Command::UseProfile { .. }
+                ) =>
+                {
+                    Profile::default()
+                }
+                None => {
+                    return Err(format!(
+                        "Profile '{}' does not exist in the config file.",
+                        profile
+                    ));
+                }
+            }
+        };
+
+        // the router depends on the mode, so a `start --mode` override has to be known here
+        let mode = match &args.command {
+            Command::Start { mode, .. } => flag_or_env(*mode, &env, MODE_ENV, |value| {
+                ProxyMode::from_str(value, true).ok()
+            })?,
+            _ => None,
+        }
+        .unwrap_or(selected.mode);
+        let mut routes = build_router(&mode, selected.routes)?;
+        // only the server connects upstream and logs requests, so other commands don't need
+        // the files
+        let mut access_log = None;
+        if let Command::Start { .. } = args.command {
+            routes.load_clients(file.client_cert.as_ref())?;
+            if let Some(path) = &file.access_log_path {
+                let path = PathBuf::from(shellexpand::tilde(path).as_ref());
+                let max_size = file
+                    .access_log_max_size
+                    .unwrap_or(DEFAULT_ACCESS_LOG_MAX_SIZE);
+                access_log = Some(AccessLog::open(&path, max_size)?);
+            }
+        }
 
         let mut config = AppConfig {
-            path: config_path,
-            port: file_content.port,
-            mode: file_content.mode,
-            routes: file_content.routes,
+            config_paths: vec![path.clone()],
+            config_source: ConfigSource::Default,
+            path,
+            profile,
+            port: file.port,
+            bind: file.bind.unwrap_or(DEFAULT_BIND),
+            mode,
+            routes: SharedRouter::new(routes),
+            match_host_port: file.match_host_port,
+            misdirected_unknown_hosts: file.misdirected_unknown_hosts,
+            missing_host: file.missing_host.clone(),
+            root_index: file.root_index,
+            routes_endpoint: routes_endpoint(file.routes_endpoint, file.routes_endpoint_path)?,
+            stats_endpoint: file.stats_endpoint,
+            maintenance: RwLock::new(maintenance_page(file.maintenance, file.maintenance_message)),
+            max_uri_length: max_uri_length(file.max_uri_length)?,
+            max_hops: max_hops(file.max_hops)?,
+            not_found_status: status_override(
+                "not_found_status",
+                file.not_found_status,
+                StatusCode::NOT_FOUND,
+            )?,
+            bad_gateway_status: status_override(
+                "bad_gateway_status",
+                file.bad_gateway_status,
+                StatusCode::BAD_GATEWAY,
+            )?,
+            server_header: file
+                .server_header
+                .map(|value| {
+                    HeaderValue::try_from(value.as_str())
+                        .map_err(|_| format!("Invalid server_header value \"{}\".", value))
+                })
+                .transpose()?,
+            proxy_auth: file.proxy_auth.map(proxy_credentials).transpose()?,
+            otlp_endpoint: file.otlp_endpoint,
+            h2c: file.h2c,
+            connection_close: file.connection_close,
+            upstream_http_version: file.upstream_http_version.map(|version| match version {
+                HttpVersion::Http10 => Version::HTTP_10,
+                HttpVersion::Http11 => Version::HTTP_11,
+            }),
+            require_routes: file.require_routes,
+            pidfile: None,
+            daemon: false,
+            log_file: None,
+            access_log,
+            accept_backoff_max: file
+                .accept_backoff_max_ms
+                .map_or(DEFAULT_ACCEPT_BACKOFF_MAX, Duration::from_millis),
+            count_bytes: file.count_bytes,
+            connections: Arc::default(),
+        };
+
+        apply_overrides(&mut config, args, &env)?;
+        Ok(config)
+    }
+
+    /// Re-reads the config files and swaps in the routes of the running profile and the
+    /// maintenance mode, keeping the current mode. The current routes stay in place if
+    /// anything is wrong with the new ones. Returns the number of routes loaded.
+    pub fn reload_routes(&self) -> Result<usize, String> {
+        let mut file = load_config_files(&self.config_paths).map_err(|e| e.to_string())?;
+        let from_env = env_routes(env::vars(), file.default_upstream_port)?;
+        merge_routes(&mut file, &self.profile, from_env);
+        let profile = if self.profile == DEFAULT_PROFILE {
+            file.default_profile
+        } else {
+            file.profiles.remove(&self.profile).ok_or_else(|| {
+                format!(
+                    "Profile '{}' no longer exists in the config file.",
+                    self.profile
+                )
+            })?
         };
 
-        apply_overrides(&mut config, args);
-        CONFIG
-            .set(config)
-            .expect("AppConfig should only be initialized once.");
-        AppConfig::instance()
+        let mut routes = build_router(&self.mode, profile.routes)?;
+        routes.load_clients(file.client_cert.as_ref())?;
+        let count = routes.len();
+        self.routes.replace(routes);
+        *self
+            .maintenance
+            .write()
+            .unwrap_or_else(PoisonError::into_inner) =
+            maintenance_page(file.maintenance, file.maintenance_message);
+        Ok(count)
     }
 
-    /// Retrieves the AppConfig singleton instance.
-    /// Panics if `load()` has not been called yet.
-    pub fn instance() -> &'static AppConfig {
-        CONFIG.get().expect("AppConfig is not initialized!")
+    /// The page to answer every request with, if maintenance mode is on.
+    pub fn maintenance_page(&self) -> Option<String> {
+        self.maintenance
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
     }
 }
 
-fn apply_overrides(config: &mut AppConfig, args: &Args) {
+fn maintenance_page(maintenance: bool, message: Option<String>) -> Option<String> {
+    maintenance.then(|| message.unwrap_or_else(|| DEFAULT_MAINTENANCE_MESSAGE.to_string()))
+}
+
+/// Reads routes from `LHP_ROUTE_<KEY>=<target>` environment variables, checked like the
+/// arguments of `add`. The key is lowercased with `_` turned into `-`, so `LHP_ROUTE_MY_APP`
+/// defines the route `my-app`.
+fn env_routes(
+    vars: impl IntoIterator<Item = (String, String)>,
+    default_port: Option<u16>,
+) -> Result<Routes, String> {
+    let mut routes: Vec<(String, Route)> = Vec::new();
+    for (name, value) in vars {
+        let Some(raw_key) = name.strip_prefix(ENV_ROUTE_PREFIX) else {
+            continue;
+        };
+        // variable names have no dots, so the local suffixes never come into play
+        let key = normalize_source_key(&raw_key.replace('_', "-"), &DEFAULT_LOCAL_SUFFIXES)
+            .map_err(|e| format!("Invalid route in {}: {}", name, e))?;
+        let target = normalize_target(&value, default_port)
+            .map_err(|e| format!("Invalid route in {}: {}", name, e))?;
+        routes.push((key, Route::new(target)));
+    }
+    // the environment has no order of its own
+    routes.sort_by(|(a, _), (b, _)| a.cmp(b));
+    Ok(routes.into_iter().collect())
+}
+
+/// The config that `AppConfig::load` builds on: the config files at `paths` merged, with the
+/// `LHP_ROUTE_*` routes among `vars` added to `profile`. Unlike `load`, a missing file is an
+/// error instead of being created, so checks can read the config without writing it.
+pub fn read_merged_config(
+    paths: &[PathBuf],
+    profile: &str,
+    vars: impl IntoIterator<Item = (String, String)>,
+) -> Result<ConfigFile, String> {
+    let mut file = read_config_files(paths).map_err(|e| e.to_string())?;
+    let from_env = env_routes(vars, file.default_upstream_port)?;
+    merge_routes(&mut file, profile, from_env);
+    Ok(file)
+}
+
+/// Adds `routes` to the named profile, replacing its routes with the same keys. Nothing is
+/// merged into a profile the config doesn't have.
+fn merge_routes(file: &mut ConfigFile, profile: &str, routes: Routes) {
+    let target = if profile == DEFAULT_PROFILE {
+        Some(&mut file.default_profile)
+    } else {
+        file.profiles.get_mut(profile)
+    };
+    if let Some(target) = target {
+        target.routes.extend(routes);
+    }
+}
+
+fn build_router(mode: &ProxyMode, routes: Routes) -> Result<Router, String> {
+    match mode {
+        // patterns are case-sensitive (`\d` vs `\D`), so they are kept verbatim
+        ProxyMode::Regex => Router::for_mode(mode, routes),
+        _ => {
+            let (routes, warnings) = normalize_route_keys(routes);
+            for warning in warnings {
+                eprintln!("⚠️  {}", warning);
+            }
+            Router::for_mode(mode, routes)
+        }
+    }
+}
+
+/// The Basic credentials for `proxy_auth`, as they appear in `Proxy-Authorization`.
+fn proxy_credentials(user_password: String) -> Result<String, String> {
+    if !user_password.contains(':') {
+        return Err("proxy_auth must be \"user:password\".".to_string());
+    }
+    Ok(BASE64_STANDARD.encode(user_password))
+}
+
+/// The path of the route table endpoint, if it is on. A path not starting with `/` could never
+/// be requested.
+fn routes_endpoint(enabled: bool, path: Option<String>) -> Result<Option<String>, String> {
+    if !enabled {
+        return Ok(None);
+    }
+    let path = path.unwrap_or_else(|| DEFAULT_ROUTES_ENDPOINT_PATH.to_string());
+    if !path.starts_with('/') {
+        return Err(format!(
+            "routes_endpoint_path must start with '/', as in \"{}\".",
+            DEFAULT_ROUTES_ENDPOINT_PATH
+        ));
+    }
+    Ok(Some(path))
+}
+
+/// The status set as `name` in the config file, or `default`. It must be a final status a
+/// response can have, 200 to 599.
+fn status_override(
+    name: &str,
+    code: Option<u16>,
+    default: StatusCode,
+) -> Result<StatusCode, String> {
+    let Some(code) = code else {
+        return Ok(default);
+    };
+    StatusCode::from_u16(code)
+        .ok()
+        .filter(|_| (200..600).contains(&code))
+        .ok_or_else(|| format!("{} must be a status from 200 to 599, not {}.", name, code))
+}
+
+/// The `max_uri_length` of the config file, or its default. Limits under
+/// `MIN_MAX_URI_LENGTH` would answer nearly every request with 414 URI Too Long, so they are
+/// refused.
+fn max_uri_length(value: Option<usize>) -> Result<usize, String> {
+    match value {
+        Some(length) if length < MIN_MAX_URI_LENGTH => Err(format!(
+            "max_uri_length must be at least {}, not {}; a shorter limit would answer nearly every request with 414 URI Too Long.",
+            MIN_MAX_URI_LENGTH, length
+        )),
+        value => Ok(value.unwrap_or(DEFAULT_MAX_URI_LENGTH)),
+    }
+}
+
+/// The `max_hops` of the config file, or its default. A limit of 0 would answer every request
+/// with 508 Loop Detected, so it is refused.
+fn max_hops(value: Option<u32>) -> Result<u32, String> {
+    match value {
+        Some(0) => Err(
+            "max_hops must be at least 1; 0 would answer every request with 508 Loop Detected."
+                .to_string(),
+        ),
+        value => Ok(value.unwrap_or(DEFAULT_MAX_HOPS)),
+    }
+}
+
+/// Lowercases route keys so hand-edited entries like `Api` match the lowercased keys
+/// extracted from requests in every key-based mode. Of keys that only differ in case, the
+/// first in the config wins. Returns a warning for every key changed or dropped, so the
+/// user can fix the config file.
+fn normalize_route_keys(routes: Routes) -> (Routes, Vec<String>) {
+    let mut normalized = Routes::with_capacity(routes.len());
+    // the key each normalized key was written as, to name both sides of a collision
+    let mut written_as: HashMap<String, String> = HashMap::new();
+    let mut warnings = Vec::new();
+    for (key, route) in routes {
+        let lower = key.to_ascii_lowercase();
+        if let Some(first) = written_as.get(&lower) {
+            warnings.push(format!(
+                "Route keys '{}' and '{}' only differ in case; '{}' is ignored. Remove one of them from the config file.",
+                first, key, key
+            ));
+            continue;
+        }
+        if lower != key {
+            warnings.push(format!(
+                "Route key '{}' is matched as '{}'; write it in lowercase in the config file to silence this warning.",
+                key, lower
+            ));
+        }
+        written_as.insert(lower.clone(), key);
+        normalized.insert(lower, route);
+    }
+    (normalized, warnings)
+}
+
+/// Applies the `start` flags, and the environment variables standing in for missing ones, over
+/// the config file. The mode is applied by `from_file` already, as the router depends on it.
+fn apply_overrides(
+    config: &mut AppConfig,
+    args: &Args,
+    env: &impl Fn(&str) -> Option<String>,
+) -> Result<(), String> {
     match &args.command {
-        Command::Start { port, mode } => {
-            config.port = port.unwrap_or(config.port);
-            config.mode = mode.unwrap_or(config.mode);
+        Command::Start {
+            port,
+            mode: _,
+            bind,
+            otlp_endpoint,
+            require_routes,
+            pidfile,
+            daemon,
+            log_file,
+        } => {
+            config.port = flag_or_env(*port, env, PORT_ENV, |value| value.parse().ok())?
+                .unwrap_or(config.port);
+            config.bind = flag_or_env(*bind, env, BIND_ENV, |value| value.parse().ok())?
+                .unwrap_or(config.bind);
+            if otlp_endpoint.is_some() {
+                config.otlp_endpoint = otlp_endpoint.clone();
+            }
+            config.require_routes |= require_routes;
+            config.pidfile = pidfile
+                .as_deref()
+                .map(|file| PathBuf::from(shellexpand::tilde(file).as_ref()));
+            config.daemon = *daemon;
+            config.log_file = log_file
+                .as_deref()
+                .map(|file| PathBuf::from(shellexpand::tilde(file).as_ref()));
+        }
+        // `stats` looks for the server where `start` put it with the same environment, and
+        // `add` checks its targets against that address
+        Command::Stats | Command::Add { .. } => {
+            config.port = flag_or_env(None, env, PORT_ENV, |value| value.parse().ok())?
+                .unwrap_or(config.port);
+            config.bind = flag_or_env(None, env, BIND_ENV, |value| value.parse().ok())?
+                .unwrap_or(config.bind);
         }
         _ => {
             // there are no overrides from the other commands yet
         }
     }
+    Ok(())
+}
+
+/// The value of a `start` flag if it was given, or else of the environment variable `name`.
+/// An empty variable counts as unset.
+fn flag_or_env<T>(
+    flag: Option<T>,
+    env: &impl Fn(&str) -> Option<String>,
+    name: &str,
+    parse: impl FnOnce(&str) -> Option<T>,
+) -> Result<Option<T>, String> {
+    if flag.is_some() {
+        return Ok(flag);
+    }
+    match env(name).filter(|value| !value.is_empty()) {
+        Some(value) => parse(value.trim())
+            .map(Some)
+            .ok_or_else(|| format!("Invalid {} environment variable \"{}\".", name, value)),
+        None => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+
+    #[test]
+    fn config_path_comes_from_the_flag_then_the_env_then_the_default() {
+        let env = |vars: &'static [(&str, &str)]| {
+            move |name: &str| {
+                vars.iter()
+                    .find(|(var, _)| *var == name)
+                    .map(|(_, value)| value.to_string())
+            }
+        };
+        let default = || PathBuf::from("default.json");
+        let both = env(&[
+            ("LOCAL_HTTP_PROXY_CONFIG", "dedicated.json"),
+            ("CONFIG_FILE", "generic.json"),
+        ]);
+
+        let flags = ["a.json".to_string(), "b.json".to_string()];
+        let (paths, source) = config_paths(&flags, both, default);
+        assert_eq!(paths, [PathBuf::from("a.json"), PathBuf::from("b.json")]);
+        assert_eq!(source, ConfigSource::Flag);
+
+        let (paths, source) = config_paths(&[], both, default);
+        assert_eq!(paths, [PathBuf::from("dedicated.json")]);
+        assert_eq!(source.to_string(), "from $LOCAL_HTTP_PROXY_CONFIG");
+
+        let generic = env(&[
+            ("LOCAL_HTTP_PROXY_CONFIG", ""),
+            ("CONFIG_FILE", "generic.json"),
+        ]);
+        let (paths, source) = config_paths(&[], generic, default);
+        assert_eq!(paths, [PathBuf::from("generic.json")]);
+        assert_eq!(source, ConfigSource::Env("CONFIG_FILE"));
+
+        let (paths, source) = config_paths(&[], env(&[]), default);
+        assert_eq!(paths, [PathBuf::from("default.json")]);
+        assert_eq!(source, ConfigSource::Default);
+    }
+
+    const PROFILES_JSON: &str = r#"{
+        "port": 9000,
+        "mode": "path",
+        "routes": { "api": "http://localhost:8080" },
+        "profiles": {
+            "integration": {
+                "mode": "domain",
+                "routes": { "api": "http://integration:8080", "web": "http://integration:3000" }
+            }
+        },
+        "active_profile": "integration"
+    }"#;
+
+    fn load(json: &str, argv: &[&str]) -> Result<AppConfig, String> {
+        let file: ConfigFile = serde_json::from_str(json).unwrap();
+        let args = Args::try_parse_from(argv).unwrap();
+        AppConfig::from_file(PathBuf::from("config.json"), file, &args)
+    }
+
+    fn target(config: &AppConfig, key: &str) -> Option<String> {
+        let routes = config.routes.load();
+        let (_, route) = routes.resolve(&config.mode, key)?;
+        Some(route.target.clone())
+    }
+
+    #[test]
+    fn overridden_statuses_must_be_final_ones() {
+        let config = load(r#"{ "not_found_status": 410 }"#, &["lhp", "list"]).unwrap();
+        assert_eq!(config.not_found_status, StatusCode::GONE);
+        assert_eq!(config.bad_gateway_status, StatusCode::BAD_GATEWAY);
+
+        for code in [99, 101, 600] {
+            let err = load(
+                &format!(r#"{{ "bad_gateway_status": {code} }}"#),
+                &["lhp", "list"],
+            )
+            .unwrap_err();
+            assert_eq!(
+                err,
+                format!("bad_gateway_status must be a status from 200 to 599, not {code}.")
+            );
+        }
+    }
+
+    #[test]
+    fn flat_config_loads_as_default_profile() {
+        let json =
+            r#"{ "port": 8000, "mode": "domain", "routes": { "api": "http://localhost:8080" } }"#;
+        let config = load(json, &["lhp", "list"]).unwrap();
+        assert_eq!(config.profile, "default");
+        assert!(matches!(config.mode, ProxyMode::Domain));
+        assert_eq!(target(&config, "api"), Some("http://localhost:8080".into()));
+    }
+
+    #[test]
+    fn active_profile_selects_its_routes_and_mode() {
+        let config = load(PROFILES_JSON, &["lhp", "list"]).unwrap();
+        assert_eq!(config.profile, "integration");
+        assert!(matches!(config.mode, ProxyMode::Domain));
+        assert_eq!(config.port, 9000);
+        assert_eq!(config.routes.load().len(), 2);
+        assert_eq!(
+            target(&config, "api"),
+            Some("http://integration:8080".into())
+        );
+    }
+
+    #[test]
+    fn profile_flag_overrides_active_profile() {
+        let config = load(PROFILES_JSON, &["lhp", "--profile", "default", "list"]).unwrap();
+        assert_eq!(config.profile, "default");
+        assert!(matches!(config.mode, ProxyMode::Path));
+        assert_eq!(target(&config, "api"), Some("http://localhost:8080".into()));
+    }
+
+    #[test]
+    fn mixed_case_route_keys_are_lowercased() {
+        let json = r#"{ "routes": { "Api": "http://localhost:8080", "WEB-App": "http://localhost:3000" } }"#;
+        let config = load(json, &["lhp", "start"]).unwrap();
+        assert_eq!(target(&config, "api"), Some("http://localhost:8080".into()));
+        assert_eq!(
+            target(&config, "web-app"),
+            Some("http://localhost:3000".into())
+        );
+        assert_eq!(target(&config, "Api"), None);
+    }
+
+    #[test]
+    fn keys_differing_only_in_case_keep_the_first_and_warn() {
+        let mut routes = Routes::new();
+        routes.insert("Api".into(), Route::new("http://localhost:8080"));
+        routes.insert("web".into(), Route::new("http://localhost:3000"));
+        routes.insert("api".into(), Route::new("http://localhost:9090"));
+
+        let (routes, warnings) = normalize_route_keys(routes);
+        assert_eq!(routes.keys().collect::<Vec<_>>(), ["api", "web"]);
+        assert_eq!(routes["api"].target, "http://localhost:8080");
+        assert_eq!(warnings.len(), 2);
+        assert!(warnings[0].contains("'Api' is matched as 'api'"));
+        assert!(warnings[1].contains("'Api' and 'api' only differ in case; 'api' is ignored"));
+
+        let mut routes = Routes::new();
+        routes.insert("api".into(), Route::new("http://localhost:8080"));
+        assert!(normalize_route_keys(routes).1.is_empty());
+    }
+
+    fn vars(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        pairs
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn routes_can_come_from_the_environment_alone() {
+        let routes = env_routes(
+            vars(&[
+                ("LHP_ROUTE_WEB", "3000"),
+                ("LHP_ROUTE_MY_API", "http://api.internal:8080/"),
+                ("PATH", "/usr/bin"),
+            ]),
+            None,
+        )
+        .unwrap();
+        let mut file = ConfigFile::default();
+        merge_routes(&mut file, DEFAULT_PROFILE, routes);
+
+        let args = Args::try_parse_from(["lhp", "start"]).unwrap();
+        let config = AppConfig::from_file("config.json".into(), file, &args).unwrap();
+        assert_eq!(config.routes.load().len(), 2);
+        assert_eq!(target(&config, "web"), Some("http://localhost:3000".into()));
+        assert_eq!(
+            target(&config, "my-api"),
+            Some("http://api.internal:8080".into())
+        );
+    }
+
+    #[test]
+    fn env_routes_override_the_file_and_are_validated() {
+        let mut file: ConfigFile = serde_json::from_str(
+            r#"{ "routes": { "api": "http://localhost:8080", "web": "http://localhost:3000" } }"#,
+        )
+        .unwrap();
+        let routes = env_routes(vars(&[("LHP_ROUTE_API", "9090")]), None).unwrap();
+        merge_routes(&mut file, DEFAULT_PROFILE, routes);
+        assert_eq!(
+            file.default_profile.routes["api"].target,
+            "http://localhost:9090"
+        );
+        assert_eq!(
+            file.default_profile.routes["web"].target,
+            "http://localhost:3000"
+        );
+
+        let err = env_routes(vars(&[("LHP_ROUTE_API", "ftp://host")]), None).unwrap_err();
+        assert!(err.contains("LHP_ROUTE_API"), "{err}");
+        assert!(env_routes(vars(&[("LHP_ROUTE_", "3000")]), None).is_err());
+    }
+
+    #[test]
+    fn start_mode_flag_overrides_the_file_for_that_run_only() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("config.json");
+        let json = r#"{ "mode": "domain", "routes": { "api": "http://localhost:8080", "^/users/(\\d+)$": "http://localhost:9000" } }"#;
+        std::fs::write(&path, json).unwrap();
+
+        let file = load_config_files(std::slice::from_ref(&path)).unwrap();
+        let args = Args::try_parse_from(["lhp", "start", "--mode", "regex"]).unwrap();
+        let config = AppConfig::from_file(path.clone(), file, &args).unwrap();
+        assert!(matches!(config.mode, ProxyMode::Regex));
+        // the router is built for the overriding mode
+        let routes = config.routes.load();
+        let (key, _, _) = routes.resolve_pattern("/users/42").unwrap();
+        assert_eq!(key, r"^/users/(\d+)$");
+
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), json);
+        let file = load_config_files(std::slice::from_ref(&path)).unwrap();
+        let config =
+            AppConfig::from_file(path, file, &Args::try_parse_from(["lhp", "start"]).unwrap())
+                .unwrap();
+        assert!(matches!(config.mode, ProxyMode::Domain));
+    }
+
+    #[test]
+    fn start_settings_prefer_flag_then_env_then_file_then_default() {
+        let load_with = |json: &str, argv: &[&str], vars: &[(&str, &str)]| {
+            let file: ConfigFile = serde_json::from_str(json).unwrap();
+            let args = Args::try_parse_from(argv).unwrap();
+            let vars: HashMap<String, String> = vars
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+                .collect();
+            AppConfig::from_file_and_env("config.json".into(), file, &args, |name| {
+                vars.get(name).cloned()
+            })
+        };
+        let file = r#"{ "port": 9000, "mode": "domain", "bind": "0.0.0.0" }"#;
+        let env = [("PORT", "9100"), ("MODE", "prefix"), ("BIND", "::1")];
+        let flags = [
+            "lhp", "start", "--port", "9200", "--mode", "regex", "--bind", "10.0.0.1",
+        ];
+
+        // defaults
+        let config = load_with("{}", &["lhp", "start"], &[]).unwrap();
+        assert_eq!(config.port, 8000);
+        assert!(matches!(config.mode, ProxyMode::Path));
+        assert_eq!(config.bind, DEFAULT_BIND);
+
+        // the file over the defaults
+        let config = load_with(file, &["lhp", "start"], &[]).unwrap();
+        assert_eq!(config.port, 9000);
+        assert!(matches!(config.mode, ProxyMode::Domain));
+        assert_eq!(config.bind.to_string(), "0.0.0.0");
+
+        // the environment over the file
+        let config = load_with(file, &["lhp", "start"], &env).unwrap();
+        assert_eq!(config.port, 9100);
+        assert!(matches!(config.mode, ProxyMode::Prefix));
+        assert_eq!(config.bind.to_string(), "::1");
+
+        // flags over the environment
+        let config = load_with(file, &flags, &env).unwrap();
+        assert_eq!(config.port, 9200);
+        assert!(matches!(config.mode, ProxyMode::Regex));
+        assert_eq!(config.bind.to_string(), "10.0.0.1");
+
+        // only `start` (and `stats`, to find it) reads the environment, and bad values are
+        // errors
+        let config = load_with(file, &["lhp", "list"], &env).unwrap();
+        assert_eq!(config.port, 9000);
+        assert!(matches!(config.mode, ProxyMode::Domain));
+        let config = load_with(file, &["lhp", "stats"], &env).unwrap();
+        assert_eq!(config.port, 9100);
+        assert_eq!(config.bind.to_string(), "::1");
+        let config = load_with(file, &["lhp", "add", "api", "3000"], &env).unwrap();
+        assert_eq!(config.port, 9100);
+        assert_eq!(config.bind.to_string(), "::1");
+        let err = load_with(file, &["lhp", "start"], &[("PORT", "http")]).unwrap_err();
+        assert_eq!(err, "Invalid PORT environment variable \"http\".");
+        let config = load_with(file, &["lhp", "start"], &[("BIND", "")]).unwrap();
+        assert_eq!(config.bind.to_string(), "0.0.0.0");
+    }
+
+    #[test]
+    fn regex_mode_keeps_patterns_and_rejects_invalid_ones() {
+        let json = r#"{ "mode": "regex", "routes": { "^/User-\\d+": "http://localhost:8080" } }"#;
+        let config = load(json, &["lhp", "start"]).unwrap();
+        let routes = config.routes.load();
+        let (_, route, _) = routes.resolve_pattern("/User-1").unwrap();
+        assert_eq!(route.target, "http://localhost:8080");
+
+        let json = r#"{ "mode": "regex", "routes": { "^/user-(\\d+": "http://localhost:8080" } }"#;
+        let err = load(json, &["lhp", "start"]).unwrap_err();
+        assert!(err.contains("Invalid route pattern"));
+    }
+
+    #[test]
+    fn missing_profile_is_an_error_except_for_add() {
+        assert!(load(PROFILES_JSON, &["lhp", "--profile", "nope", "start"]).is_err());
+
+        let config = load(
+            PROFILES_JSON,
+            &["lhp", "--profile", "nope", "add", "api", "3000"],
+        )
+        .unwrap();
+        assert_eq!(config.profile, "nope");
+        assert!(config.routes.load().is_empty());
+    }
+
+    #[test]
+    fn missing_client_cert_fails_at_startup() {
+        let json = r#"{
+            "routes": { "api": "https://localhost:8443" },
+            "client_cert": { "cert": "/nonexistent/client.pem", "key": "/nonexistent/client.key" }
+        }"#;
+
+        let err = load(json, &["lhp", "start"]).unwrap_err();
+        assert!(err.contains("Route 'api'"));
+        assert!(err.contains("/nonexistent/client.pem"));
+        // commands that don't connect upstream don't read the files
+        assert!(load(json, &["lhp", "list"]).is_ok());
+    }
+
+    #[test]
+    fn server_header_must_be_a_valid_header_value() {
+        let config = load(r#"{ "server_header": "lhp/0.1" }"#, &["lhp", "start"]).unwrap();
+        assert_eq!(config.server_header.unwrap(), "lhp/0.1");
+
+        let err = load(r#"{ "server_header": "bad\nvalue" }"#, &["lhp", "start"]).unwrap_err();
+        assert!(err.contains("server_header"));
+    }
+
+    #[test]
+    fn proxy_auth_must_name_a_user_and_password() {
+        let config = load(r#"{ "proxy_auth": "dev:secret" }"#, &["lhp", "start"]).unwrap();
+        assert_eq!(config.proxy_auth.as_deref(), Some("ZGV2OnNlY3JldA=="));
+
+        let err = load(r#"{ "proxy_auth": "dev" }"#, &["lhp", "start"]).unwrap_err();
+        assert!(err.contains("proxy_auth must be \"user:password\""));
+    }
+
+    #[test]
+    fn routes_endpoint_path_defaults_and_must_start_with_a_slash() {
+        let config = load(r#"{ "routes_endpoint": true }"#, &["lhp", "start"]).unwrap();
+        assert_eq!(config.routes_endpoint.as_deref(), Some("/__routes"));
+        let config = load(r#"{ "routes_endpoint_path": "/table" }"#, &["lhp", "start"]).unwrap();
+        assert_eq!(config.routes_endpoint, None);
+
+        let json = r#"{ "routes_endpoint": true, "routes_endpoint_path": "table" }"#;
+        let err = load(json, &["lhp", "start"]).unwrap_err();
+        assert!(err.contains("routes_endpoint_path must start with '/'"));
+    }
+
+    #[test]
+    fn max_hops_must_be_at_least_one() {
+        let config = load(r#"{ "max_hops": 1 }"#, &["lhp", "start"]).unwrap();
+        assert_eq!(config.max_hops, 1);
+        assert_eq!(
+            load("{}", &["lhp", "start"]).unwrap().max_hops,
+            DEFAULT_MAX_HOPS
+        );
+
+        let err = load(r#"{ "max_hops": 0 }"#, &["lhp", "start"]).unwrap_err();
+        assert!(err.contains("max_hops must be at least 1"), "{err}");
+    }
+
+    #[test]
+    fn max_uri_length_has_a_minimum() {
+        let json = format!(r#"{{ "max_uri_length": {MIN_MAX_URI_LENGTH} }}"#);
+        let config = load(&json, &["lhp", "start"]).unwrap();
+        assert_eq!(config.max_uri_length, MIN_MAX_URI_LENGTH);
+
+        for length in [0, MIN_MAX_URI_LENGTH - 1] {
+            let json = format!(r#"{{ "max_uri_length": {length} }}"#);
+            let err = load(&json, &["lhp", "start"]).unwrap_err();
+            assert!(err.contains("max_uri_length must be at least 16"), "{err}");
+        }
+    }
 }
@@ -1,13 +1,27 @@
 use super::models::ProxyMode;
-use clap::{Parser, Subcommand};
+use clap::{Parser, Subcommand, ValueEnum};
+use std::net::IpAddr;
 
 /// A simple local HTTP proxy for routing requests based on hostname or path.
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
 pub struct Args {
-    /// Path to the configuration file.
-    #[arg(short, long, env, default_value = "~/.local-http-proxy/config.json")]
-    pub config_file: String,
+    /// Path to the configuration file. Taken from this flag, else the `LOCAL_HTTP_PROXY_CONFIG`
+    /// or `CONFIG_FILE` environment variable, else `local-http-proxy/config.json` in the
+    /// platform's config directory (e.g. `$XDG_CONFIG_HOME` on Linux).
+    /// Repeat to layer files: later files override earlier ones, and commands write to the last.
+    #[arg(short, long)]
+    pub config_file: Vec<String>,
+
+    /// The profile to use. Defaults to the config file's `active_profile`, or `default`.
+    #[arg(long, env, global = true)]
+    pub profile: Option<String>,
+
+    /// Prints only errors and the requested output, leaving out status messages.
+    /// Config commands exit with 2 for invalid input, 3 when a profile is not found, and 4 for
+    /// file errors.
+    #[arg(short, long, global = true)]
+    pub quiet: bool,
 
     #[command(subcommand)]
     pub command: Command,
@@ -17,33 +31,99 @@ pub struct Args {
 pub enum Command {
     /// Starts the HTTP proxy server.
     Start {
-        /// The port to use. Defaults to 8000, or as defined in the config file.
-        #[arg(short, long, env, required = false)]
+        /// The port to use. Taken from this flag, else the `PORT` environment variable, else the
+        /// config file, else 8000.
+        #[arg(short, long, required = false)]
         port: Option<u16>,
 
-        /// The proxy mode to use. Defaults to path, or as defined in the config file.
-        #[arg(short, long, env, required = false)]
+        /// The proxy mode to use for this run, without saving it. Taken from this flag, else the
+        /// `MODE` environment variable, else the config file, else path.
+        #[arg(short, long, required = false)]
         mode: Option<ProxyMode>,
+
+        /// The address to listen on, e.g. 0.0.0.0 to accept connections from other machines.
+        /// Taken from this flag, else the `BIND` environment variable, else the config file,
+        /// else 127.0.0.1.
+        #[arg(long, required = false)]
+        bind: Option<IpAddr>,
+
+        /// Exports a trace span per request to this OTLP/HTTP collector
+        /// (e.g., http://localhost:4318/v1/traces). Tracing is off unless set here or in the config file.
+        #[arg(long, env, required = false)]
+        otlp_endpoint: Option<String>,
+
+        /// Exits with an error instead of serving when the profile has no routes
+        /// (e.g., to catch a missing config in CI).
+        #[arg(long)]
+        require_routes: bool,
+
+        /// Writes the server's PID to this file, and removes it when the server shuts down.
+        #[arg(long, env, required = false)]
+        pidfile: Option<String>,
+
+        /// Runs the server in the background (Unix only) and prints its PID.
+        /// Combine with --pidfile to stop it later.
+        #[arg(long)]
+        daemon: bool,
+
+        /// With --daemon, appends the server's output to this file instead of discarding it.
+        #[arg(long, env, required = false)]
+        log_file: Option<String>,
     },
 
     /// Lists all active routes and the current mode.
-    List,
+    List {
+        /// Keeps running and shows the routes again whenever the config file changes.
+        #[arg(short, long)]
+        watch: bool,
+        /// Shows only the routes with this tag.
+        #[arg(long)]
+        tag: Option<String>,
+    },
 
     /// Adds a new route to the configuration.
     Add {
         /// The source host or path to match (e.g., my-app.local or /my-app).
-        #[arg(index = 1)]
-        source: String,
-        /// The target server to forward to (e.g., localhost:3000).
-        #[arg(index = 2)]
-        target: String,
+        #[arg(index = 1, required_unless_present = "from_target")]
+        source: Option<String>,
+        /// The target server to forward to (e.g., localhost:3000). Use `-` to read it from stdin.
+        /// Separate several instances with commas, each optionally weighted as `target=weight`
+        /// (e.g., localhost:3000=3,localhost:3001=1).
+        #[arg(index = 2, required_unless_present = "from_target")]
+        target: Option<String>,
+        /// Adds a route to this target, with the source named after its host
+        /// (e.g., http://my-app:3000 is added as my-app).
+        #[arg(long, value_name = "TARGET", conflicts_with_all = ["source", "target"])]
+        from_target: Option<String>,
+        /// In regex mode, rebuilds the forwarded path from the pattern's captures (e.g., /users/$1).
+        #[arg(long)]
+        rewrite: Option<String>,
+        /// A note on what the route is for, shown by `list`.
+        #[arg(long = "desc")]
+        description: Option<String>,
+        /// Matches this route by host (`domain`) or by path (`path`) instead of the profile's
+        /// mode, so one profile can mix both.
+        #[arg(long)]
+        mode: Option<ProxyMode>,
+        /// Tags the route (e.g., frontend). Can be repeated.
+        #[arg(long = "tag")]
+        tags: Vec<String>,
+        /// Replaces an existing route without asking for confirmation.
+        #[arg(short, long)]
+        yes: bool,
     },
 
-    /// Removes an existing route from the configuration.
+    /// Removes existing routes from the configuration.
     Remove {
-        /// The source host or path of the route to remove.
-        #[arg(index = 1)]
-        source: String,
+        /// The source hosts or paths of the routes to remove.
+        #[arg(index = 1, required_unless_present = "tag", conflicts_with = "tag")]
+        sources: Vec<String>,
+        /// Removes every route with this tag instead, after asking for confirmation.
+        #[arg(long)]
+        tag: Option<String>,
+        /// Removes the tagged routes without asking for confirmation.
+        #[arg(short, long)]
+        yes: bool,
     },
 
     /// Sets the proxy mode (`domain` or `path`).
@@ -52,4 +132,62 @@ pub enum Command {
         #[arg(index = 1)]
         mode: ProxyMode,
     },
+
+    /// Sets the port the proxy server listens on, persisted in the configuration file.
+    SetPort {
+        /// The port to use (1-65535).
+        #[arg(index = 1, value_parser = clap::value_parser!(u16).range(1..))]
+        port: u16,
+    },
+
+    /// Turns maintenance mode on or off. While it is on, the proxy answers every request (except
+    /// the stats endpoint) with 503 Service Unavailable. Send SIGHUP to apply it to a running
+    /// server.
+    Maintenance {
+        #[arg(index = 1)]
+        state: Switch,
+        /// The body of the 503 responses; kept until it is replaced.
+        #[arg(long)]
+        message: Option<String>,
+    },
+
+    /// Shows per-route request counts from the running proxy server.
+    Stats,
+
+    /// Sets the active profile persisted in the configuration file.
+    UseProfile {
+        /// The name of an existing profile (or `default`).
+        #[arg(index = 1)]
+        name: String,
+    },
+
+    /// Checks the config for common problems (bad targets or keys, unreachable backends, an
+    /// unwritable config file) and suggests fixes.
+    Doctor,
+
+    /// Writes a starter config file with example routes to the config path, to start editing
+    /// from.
+    Init {
+        /// The routing mode of the starter config and its example routes.
+        #[arg(short, long, default_value = "path")]
+        mode: ProxyMode,
+
+        /// Replaces the config file if one already exists.
+        #[arg(long)]
+        force: bool,
+    },
+
+    /// Prints the version of the proxy.
+    Version {
+        /// Also prints the commit, build date, enabled features and config file, for bug reports.
+        #[arg(short, long)]
+        verbose: bool,
+    },
+}
+
+/// The `on`/`off` argument of toggle commands.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Switch {
+    On,
+    Off,
 }
@@ -1,16 +1,53 @@
 use clap::ValueEnum;
+use indexmap::IndexMap;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::fmt::{Display, Formatter, Result};
+use std::hash::{BuildHasher, RandomState};
+use std::net::{IpAddr, Ipv4Addr};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+/// The name under which the top-level `mode`/`routes` of a config file are addressed.
+pub const DEFAULT_PROFILE: &str = "default";
+
+/// The address `start` listens on when neither `--bind`, `BIND` nor the config file set one.
+pub const DEFAULT_BIND: IpAddr = IpAddr::V4(Ipv4Addr::LOCALHOST);
+
+/// The longest request target accepted when the config file doesn't set `max_uri_length`.
+pub const DEFAULT_MAX_URI_LENGTH: usize = 8 * 1024;
+
+/// How many proxies a request may pass through when the config file doesn't set `max_hops`.
+pub const DEFAULT_MAX_HOPS: u32 = 8;
+
+/// The page served in maintenance mode when the config file doesn't set
+/// `maintenance_message`.
+pub const DEFAULT_MAINTENANCE_MESSAGE: &str = "Local Http Proxy: Down for Maintenance";
+
+/// The path the route table is served on when `routes_endpoint` is on and the config file
+/// doesn't set `routes_endpoint_path`.
+pub const DEFAULT_ROUTES_ENDPOINT_PATH: &str = "/__routes";
+
+/// The domain suffixes a host-form source (`api.localhost`) may end in when the config file
+/// doesn't set `local_suffixes`.
+pub const DEFAULT_LOCAL_SUFFIXES: [&str; 3] = ["localhost", "test", "local"];
+
+/// The Domain mode route key matching any host label that has no route of its own.
+pub const WILDCARD_KEY: &str = "*";
 
 /// Defines the routing strategy for the proxy.
-#[derive(Serialize, Deserialize, Debug, Clone, Copy, ValueEnum)]
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
 #[serde(rename_all = "lowercase")]
 pub enum ProxyMode {
     /// Routes based on the request's hostname (e.g., `app.local`).
     Domain,
     /// Routes based on the request's path prefix (e.g., `/app`).
     Path,
+    /// Routes based on the longest matching multi-segment path prefix (e.g., `/app/v2`).
+    Prefix,
+    /// Routes based on regex patterns matched against the path, tried in config order.
+    Regex,
 }
 
 impl Display for ProxyMode {
@@ -18,25 +55,872 @@ impl Display for ProxyMode {
         match self {
             ProxyMode::Domain => write!(f, "domain"),
             ProxyMode::Path => write!(f, "path"),
+            ProxyMode::Prefix => write!(f, "prefix"),
+            ProxyMode::Regex => write!(f, "regex"),
+        }
+    }
+}
+
+impl ProxyMode {
+    /// Whether routes matched this way can share a profile with routes matched the other
+    /// way, through the route `mode`. Only Domain and Path can.
+    pub fn mixes(self) -> bool {
+        matches!(self, ProxyMode::Domain | ProxyMode::Path)
+    }
+}
+
+/// Routes keyed by source, in the order they appear in the config file.
+pub type Routes = IndexMap<String, Route>;
+
+/// A routing rule for a single source key.
+/// Stored in the config file as a plain target string unless it carries extra settings.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Route {
+    pub target: String,
+    /// What the route is for, shown by `list`. Has no effect on routing.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    /// Groups the route belongs to (e.g. `frontend`), for `list --tag` and `remove --tag`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    /// Answers the route's requests with 503 Service Unavailable while other routes keep
+    /// working.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub maintenance: bool,
+    /// Set to `false` to keep the route in the config file while it matches no requests;
+    /// they get `404 Not Found`.
+    #[serde(default = "enabled", skip_serializing_if = "is_enabled")]
+    pub enabled: bool,
+    /// Matches the route by host (`domain`) or by path (`path`) instead of the profile's
+    /// mode. Only Domain and Path mode routes can be mixed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mode: Option<ProxyMode>,
+    /// Whether requests are forwarded to the target or redirected to it.
+    #[serde(default, skip_serializing_if = "RouteKind::is_proxy")]
+    pub kind: RouteKind,
+    /// For redirect routes, the status of the redirect response. Defaults to `302`.
+    #[serde(default, skip_serializing_if = "RedirectStatus::is_found")]
+    pub redirect_status: RedirectStatus,
+    /// In Regex mode, the forwarded path built from the pattern's capture groups
+    /// (e.g. `/users/$1` or `/users/${id}`). The request path is forwarded as-is when unset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rewrite: Option<String>,
+    /// Alternative targets chosen by request headers; the first matching rule wins and
+    /// `target` is used when none match.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub header_routes: Vec<HeaderRoute>,
+    /// HTTP methods accepted by this route; other methods get `405 Method Not Allowed`.
+    /// Every method is accepted when empty.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub allowed_methods: Vec<String>,
+    /// More instances of the same upstream; requests are spread round-robin over `target`
+    /// followed by these.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub targets: Vec<String>,
+    /// The share of requests each instance gets, `target` first: with `[3, 1]`, three of every
+    /// four requests go to `target`. Every instance gets the same share when empty.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub weights: Vec<u32>,
+    /// Pins each client to one instance instead of rotating between them.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sticky: Option<StickySession>,
+    /// For the Domain mode wildcard route, the request header that carries the matched host
+    /// label to the upstream. Defaults to `X-Lhp-Label`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub label_header: Option<String>,
+    /// The `Host` header sent to the upstream (e.g. a vhost name), in place of the target's
+    /// authority.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub upstream_host: Option<String>,
+    /// Removes or replaces the client's `Origin` header before the request is forwarded.
+    /// Requests without one are forwarded without one.
+    #[serde(default, skip_serializing_if = "OriginHeader::is_keep")]
+    pub origin: OriginHeader,
+    /// Adds or removes the trailing slash of the forwarded path (the query is left alone).
+    #[serde(default, skip_serializing_if = "TrailingSlash::is_keep")]
+    pub trailing_slash: TrailingSlash,
+    /// Talks HTTP/2 to the upstream without negotiating it first (h2c with prior knowledge),
+    /// for backends that only speak HTTP/2 over plain TCP.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub http2: bool,
+    /// Accepts any certificate from an https upstream, including self-signed ones. Other
+    /// routes keep verifying theirs.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub insecure_skip_verify: bool,
+    /// The name an https upstream is asked for (SNI) and its certificate is checked against,
+    /// in place of the target's host. For targets given by IP, or behind another name.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tls_server_name: Option<String>,
+    /// Opens a new upstream connection for every request and closes it afterwards, for
+    /// backends that mishandle reused keep-alive connections.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub disable_keepalive: bool,
+    /// The client certificate for an https upstream that requires one, in place of the
+    /// config file's `client_cert`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_cert: Option<ClientCert>,
+    /// The most requests forwarded to each of the route's targets at once. Requests over the
+    /// limit get `503 Service Unavailable`, after waiting up to `queue_timeout_ms` for a slot.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_inflight: Option<usize>,
+    /// How long a request over `max_inflight` waits for a slot; it is turned away at once
+    /// when unset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub queue_timeout_ms: Option<u64>,
+    /// An artificial delay before each request is forwarded, to try a frontend against a slow
+    /// backend. The status and body are left alone.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub latency_ms: Option<Latency>,
+    /// Logs the first `log_body_limit` bytes of this route's request and response bodies to
+    /// stderr once each body has streamed through, for debugging.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub log_bodies: bool,
+    /// How many bytes of each body `log_bodies` shows. Defaults to 4 KiB.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub log_body_limit: Option<usize>,
+    /// Upstream statuses answered with another one, as in `{ "500": 503 }`, to try a client's
+    /// error handling. Headers and body are passed on unchanged.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub status_map: BTreeMap<String, u16>,
+    /// Points the `Location` of the upstream's redirects back through the proxy when they
+    /// lead to the upstream itself, e.g. `/login` becomes `/api/login` in Path mode.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub rewrite_location: bool,
+    /// Follows up to this many redirects that stay on the upstream instead of passing them
+    /// to the client. Only `GET` and `HEAD` requests are followed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub follow_redirects: Option<u32>,
+    #[serde(skip)]
+    pub rotation: Rotation,
+}
+
+fn enabled() -> bool {
+    true
+}
+
+fn is_enabled(enabled: &bool) -> bool {
+    *enabled
+}
+
+/// How a route answers the requests it matches.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum RouteKind {
+    /// Forwards the request to the target and relays its response.
+    #[default]
+    Proxy,
+    /// Sends the client to the target plus the rest of the path with a redirect.
+    Redirect,
+}
+
+impl RouteKind {
+    fn is_proxy(&self) -> bool {
+        *self == RouteKind::Proxy
+    }
+}
+
+/// The status codes a redirect route can answer with, written as the number in the config
+/// file.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(try_from = "u16", into = "u16")]
+pub enum RedirectStatus {
+    MovedPermanently,
+    #[default]
+    Found,
+    SeeOther,
+    TemporaryRedirect,
+    PermanentRedirect,
+}
+
+impl RedirectStatus {
+    pub fn code(self) -> u16 {
+        match self {
+            RedirectStatus::MovedPermanently => 301,
+            RedirectStatus::Found => 302,
+            RedirectStatus::SeeOther => 303,
+            RedirectStatus::TemporaryRedirect => 307,
+            RedirectStatus::PermanentRedirect => 308,
+        }
+    }
+
+    fn is_found(&self) -> bool {
+        *self == RedirectStatus::Found
+    }
+}
+
+impl TryFrom<u16> for RedirectStatus {
+    type Error = String;
+
+    fn try_from(code: u16) -> std::result::Result<Self, Self::Error> {
+        match code {
+            301 => Ok(RedirectStatus::MovedPermanently),
+            302 => Ok(RedirectStatus::Found),
+            303 => Ok(RedirectStatus::SeeOther),
+            307 => Ok(RedirectStatus::TemporaryRedirect),
+            308 => Ok(RedirectStatus::PermanentRedirect),
+            _ => Err(format!(
+                "redirect_status must be 301, 302, 303, 307 or 308, not {}",
+                code
+            )),
+        }
+    }
+}
+
+impl From<RedirectStatus> for u16 {
+    fn from(status: RedirectStatus) -> Self {
+        status.code()
+    }
+}
+
+/// What to do with the trailing slash of a forwarded path.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum TrailingSlash {
+    /// Forwards the path as it is.
+    #[default]
+    Keep,
+    /// Makes sure the path ends with `/`.
+    Add,
+    /// Removes trailing `/`s, except from the root path.
+    Strip,
+}
+
+impl TrailingSlash {
+    fn is_keep(&self) -> bool {
+        *self == TrailingSlash::Keep
+    }
+}
+
+/// What to do with the `Origin` header of a forwarded request.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum OriginHeader {
+    /// Forwards the header as the client sent it.
+    #[default]
+    Keep,
+    /// Removes the header, for backends that turn away cross-origin requests.
+    Strip,
+    /// Replaces the client's header with this value, as in `{ "set": "http://localhost:3000" }`.
+    Set(String),
+}
+
+impl OriginHeader {
+    fn is_keep(&self) -> bool {
+        *self == OriginHeader::Keep
+    }
+}
+
+/// How Domain mode answers a request without any `Host` header (or HTTP/2 `:authority`).
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum MissingHost {
+    /// `404 Not Found`, as for a host without a route.
+    #[default]
+    NotFound,
+    /// `400 Bad Request`, since HTTP/1.1 requires the header.
+    BadRequest,
+    /// Forwards the request with the route of this key, as in `{ "route": "api" }`.
+    Route(String),
+}
+
+impl MissingHost {
+    fn is_not_found(&self) -> bool {
+        *self == MissingHost::NotFound
+    }
+
+    /// The key of the route requests without a host go to, if they go to one.
+    pub fn route(&self) -> Option<&str> {
+        match self {
+            MissingHost::Route(key) => Some(key),
+            _ => None,
+        }
+    }
+}
+
+/// A client certificate presented to https upstreams that ask for one (mutual TLS).
+/// Paths may start with `~`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ClientCert {
+    /// PEM file with the certificate, optionally followed by its intermediates.
+    pub cert: String,
+    /// PEM file with the certificate's private key.
+    pub key: String,
+}
+
+/// The HTTP version to forward requests with, whatever version the client used.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HttpVersion {
+    #[serde(rename = "1.0")]
+    Http10,
+    #[serde(rename = "1.1")]
+    Http11,
+}
+
+/// Chooses a route's instance from a cookie, so a client keeps hitting the same one.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Default)]
+pub struct StickySession {
+    /// An existing cookie to hash (e.g. the backend's session cookie). When unset, the
+    /// proxy hands out its own `lhp_sticky` cookie.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cookie: Option<String>,
+}
+
+/// How long a route's requests are held before they are forwarded, in milliseconds.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(untagged)]
+pub enum Latency {
+    /// Always the same delay, as in `"latency_ms": 300`.
+    Fixed(u64),
+    /// A delay picked anew for each request, as in `"latency_ms": { "min": 100, "max": 800 }`.
+    Range { min: u64, max: u64 },
+}
+
+impl Latency {
+    /// The delay for the next request.
+    pub fn delay(&self) -> Duration {
+        let ms = match *self {
+            Latency::Fixed(ms) => ms,
+            Latency::Range { min, max } if max > min => {
+                min + RandomState::new().hash_one(Instant::now()) % (max - min + 1)
+            }
+            Latency::Range { min, .. } => min,
+        };
+        Duration::from_millis(ms)
+    }
+}
+
+/// The round-robin position over a route's instances, and the instances taken out of
+/// rotation for a while.
+/// Runtime state only: it is never written to the config file and routes compare equal
+/// regardless of it.
+#[derive(Debug, Default)]
+pub struct Rotation {
+    position: AtomicUsize,
+    // instance index and the time it may be used again
+    paused: Mutex<Vec<(usize, Instant)>>,
+}
+
+impl Rotation {
+    /// Returns the current position and advances to the next one.
+    pub fn next(&self) -> usize {
+        self.position.fetch_add(1, Ordering::Relaxed)
+    }
+
+    /// Skips the instance at `index` until `until`.
+    pub fn pause(&self, index: usize, until: Instant) {
+        let mut paused = self.paused.lock().unwrap_or_else(PoisonError::into_inner);
+        paused.retain(|(i, _)| *i != index);
+        paused.push((index, until));
+    }
+
+    /// Advances the position like `next` and returns the instance it lands on, each instance
+    /// taking as many turns in a row as its weight. Paused instances are skipped; returns
+    /// `None` when all of them are paused.
+    pub fn next_available(&self, weights: &[u32]) -> Option<usize> {
+        let total: usize = weights.iter().map(|&weight| weight as usize).sum();
+        let start = self.next();
+        let mut paused = self.paused.lock().unwrap_or_else(PoisonError::into_inner);
+        if paused.is_empty() {
+            return Some(weighted_index(weights, start % total));
+        }
+        let now = Instant::now();
+        paused.retain(|(_, until)| *until > now);
+        (0..total)
+            .map(|offset| weighted_index(weights, (start + offset) % total))
+            .find(|index| !paused.iter().any(|(i, _)| i == index))
+    }
+
+    pub fn is_paused(&self, index: usize) -> bool {
+        let now = Instant::now();
+        self.paused
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .iter()
+            .any(|(i, until)| *i == index && *until > now)
+    }
+}
+
+/// The instance that owns turn `turn` (below the sum of `weights`), counting each
+/// instance's weight in turns.
+pub fn weighted_index(weights: &[u32], turn: usize) -> usize {
+    let mut end = 0;
+    for (index, &weight) in weights.iter().enumerate() {
+        end += weight as usize;
+        if turn < end {
+            return index;
+        }
+    }
+    weights.len() - 1
+}
+
+impl Clone for Rotation {
+    fn clone(&self) -> Self {
+        Rotation::default()
+    }
+}
+
+impl PartialEq for Rotation {
+    fn eq(&self, _other: &Self) -> bool {
+        true
+    }
+}
+
+impl Eq for Rotation {}
+
+/// Sends requests carrying all of the given header values to another target.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct HeaderRoute {
+    /// Header names (case-insensitive) and the exact values they must have.
+    pub headers: IndexMap<String, String>,
+    pub target: String,
+}
+
+impl Route {
+    pub fn new(target: impl Into<String>) -> Self {
+        Self {
+            target: target.into(),
+            description: None,
+            tags: Vec::new(),
+            maintenance: false,
+            enabled: true,
+            mode: None,
+            kind: RouteKind::Proxy,
+            redirect_status: RedirectStatus::Found,
+            rewrite: None,
+            header_routes: Vec::new(),
+            allowed_methods: Vec::new(),
+            targets: Vec::new(),
+            weights: Vec::new(),
+            sticky: None,
+            label_header: None,
+            upstream_host: None,
+            origin: OriginHeader::Keep,
+            trailing_slash: TrailingSlash::Keep,
+            http2: false,
+            insecure_skip_verify: false,
+            tls_server_name: None,
+            disable_keepalive: false,
+            client_cert: None,
+            max_inflight: None,
+            queue_timeout_ms: None,
+            latency_ms: None,
+            log_bodies: false,
+            log_body_limit: None,
+            status_map: BTreeMap::new(),
+            rewrite_location: false,
+            follow_redirects: None,
+            rotation: Rotation::default(),
+        }
+    }
+
+    /// Every instance the route can forward to: `target` followed by `targets`.
+    pub fn instances(&self) -> impl Iterator<Item = &str> {
+        std::iter::once(self.target.as_str()).chain(self.targets.iter().map(String::as_str))
+    }
+
+    /// The weight of each of `instances`: `weights`, or 1 for each when it is empty.
+    pub fn instance_weights(&self) -> Vec<u32> {
+        if self.weights.is_empty() {
+            vec![1; 1 + self.targets.len()]
+        } else {
+            self.weights.clone()
+        }
+    }
+
+    /// Whether the route only has a target and can be written as a plain string.
+    fn is_plain(&self) -> bool {
+        *self == Route::new(self.target.clone())
+    }
+}
+
+// A named set of routes together with the mode used to match them.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(default)]
+pub struct Profile {
+    pub mode: ProxyMode,
+    #[serde(with = "route_map")]
+    pub routes: Routes,
+}
+
+impl Default for Profile {
+    fn default() -> Self {
+        Self {
+            mode: ProxyMode::Path,
+            routes: Routes::new(),
         }
     }
 }
 
 // Represents the structure of the config.json file on disk.
+//
+// The top-level `mode` and `routes` form the `default` profile, so flat
+// single-profile files keep working unchanged.
 #[derive(Serialize, Deserialize, Debug)]
 #[serde(default)]
 pub struct ConfigFile {
     pub port: u16,
-    pub mode: ProxyMode,
-    pub routes: HashMap<String, String>,
+    // The address `start` listens on, e.g. `0.0.0.0` inside a container. Defaults to
+    // `DEFAULT_BIND`, so only this machine can connect.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bind: Option<IpAddr>,
+    // The port given to targets that name only a host, so `add api myhost` writes
+    // `http://myhost:8080` instead of `http://myhost`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub default_upstream_port: Option<u16>,
+    #[serde(flatten)]
+    pub default_profile: Profile,
+    #[serde(skip_serializing_if = "HashMap::is_empty")]
+    pub profiles: HashMap<String, Profile>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub active_profile: Option<String>,
+    // In Domain mode, lets keys like `api:8001` match `api.localhost:8001` before `api` does.
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub match_host_port: bool,
+    // In Domain mode, answers a valid host without a route with 421 Misdirected Request
+    // instead of 404.
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub misdirected_unknown_hosts: bool,
+    // In Domain mode, how a request without a Host header is answered; 404 by default.
+    #[serde(skip_serializing_if = "MissingHost::is_not_found")]
+    pub missing_host: MissingHost,
+    // In Path mode, answers `/` with a page linking every route instead of 404.
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub root_index: bool,
+    // Answers `GET /__routes` with the route table as JSON instead of routing it.
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub routes_endpoint: bool,
+    // The path of that endpoint. Defaults to `DEFAULT_ROUTES_ENDPOINT_PATH`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub routes_endpoint_path: Option<String>,
+    // Answers `GET /__lhp/stats` with the request counters, for the `stats` command, instead
+    // of routing it.
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub stats_endpoint: bool,
+    // Answers every proxied request with 503 Service Unavailable; set with `maintenance on`.
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub maintenance: bool,
+    // The body of those 503 responses. Defaults to `DEFAULT_MAINTENANCE_MESSAGE`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub maintenance_message: Option<String>,
+    // The domain suffixes `add` accepts in host-form sources, so `api.github.com` isn't
+    // taken for the route `api`. Defaults to `DEFAULT_LOCAL_SUFFIXES`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub local_suffixes: Option<Vec<String>>,
+    // Longest request target (path and query) accepted before answering 414 URI Too Long.
+    // Defaults to `DEFAULT_MAX_URI_LENGTH`; at least 16.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_uri_length: Option<usize>,
+    // How many proxies a request may already have passed through, counted in `X-LHP-Hops`,
+    // before it is answered with 508 Loop Detected. Defaults to `DEFAULT_MAX_HOPS`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_hops: Option<u32>,
+    // The status answered when no route matches a request, in place of 404.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub not_found_status: Option<u16>,
+    // The status answered when the upstream can't be reached or fails, in place of 502.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bad_gateway_status: Option<u16>,
+    // Sets (or replaces the upstream's) `Server` header on every response.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub server_header: Option<String>,
+    // The `user:password` clients must send as `Proxy-Authorization: Basic ...`; requests
+    // without it get 407 Proxy Authentication Required. Off when unset.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub proxy_auth: Option<String>,
+    // OTLP/HTTP collector to export request spans to; tracing is off when unset.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub otlp_endpoint: Option<String>,
+    // Accepts HTTP/2 cleartext (h2c with prior knowledge) connections besides HTTP/1.1.
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub h2c: bool,
+    // Answers every HTTP/1 request with `Connection: close` and closes the connection after
+    // it, turning keep-alive off.
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub connection_close: bool,
+    // Client certificate presented to every https upstream that asks for one, unless the
+    // route sets its own.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub client_cert: Option<ClientCert>,
+    // Forwards every request with this HTTP version instead of the client's (except to
+    // `http2` routes).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub upstream_http_version: Option<HttpVersion>,
+    // Makes `start` fail instead of serving a profile without routes.
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub require_routes: bool,
+    // Appends a line per request to this file; no access log is written when unset.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub access_log_path: Option<String>,
+    // Size in bytes past which the access log is rotated. Defaults to
+    // `DEFAULT_ACCESS_LOG_MAX_SIZE`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub access_log_max_size: Option<u64>,
+    // The longest pause, in milliseconds, between accept attempts while the process is out
+    // of file descriptors. Defaults to `DEFAULT_ACCEPT_BACKOFF_MAX`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub accept_backoff_max_ms: Option<u64>,
+    // Counts the body bytes each route reads from clients and sends them, shown by `stats`.
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub count_bytes: bool,
 }
 
 impl Default for ConfigFile {
     fn default() -> Self {
         Self {
             port: 8000,
-            mode: ProxyMode::Path,
-            routes: HashMap::new(),
+            bind: None,
+            default_upstream_port: None,
+            default_profile: Profile::default(),
+            profiles: HashMap::new(),
+            active_profile: None,
+            match_host_port: false,
+            misdirected_unknown_hosts: false,
+            missing_host: MissingHost::NotFound,
+            root_index: false,
+            routes_endpoint: false,
+            routes_endpoint_path: None,
+            stats_endpoint: false,
+            maintenance: false,
+            maintenance_message: None,
+            local_suffixes: None,
+            max_uri_length: None,
+            max_hops: None,
+            not_found_status: None,
+            bad_gateway_status: None,
+            server_header: None,
+            proxy_auth: None,
+            otlp_endpoint: None,
+            h2c: false,
+            connection_close: false,
+            client_cert: None,
+            upstream_http_version: None,
+            require_routes: false,
+            access_log_path: None,
+            access_log_max_size: None,
+            accept_backoff_max_ms: None,
+            count_bytes: false,
+        }
+    }
+}
+
+impl ConfigFile {
+    /// The domain suffixes host-form sources may end in.
+    pub fn local_suffixes(&self) -> Vec<&str> {
+        match &self.local_suffixes {
+            Some(suffixes) => suffixes.iter().map(String::as_str).collect(),
+            None => DEFAULT_LOCAL_SUFFIXES.to_vec(),
+        }
+    }
+
+    /// Resolves the profile name to use: an explicit selection wins over `active_profile`.
+    pub fn active_profile_name<'a>(&'a self, selected: Option<&'a str>) -> &'a str {
+        selected
+            .or(self.active_profile.as_deref())
+            .unwrap_or(DEFAULT_PROFILE)
+    }
+
+    pub fn profile(&self, name: &str) -> Option<&Profile> {
+        if name == DEFAULT_PROFILE {
+            Some(&self.default_profile)
+        } else {
+            self.profiles.get(name)
+        }
+    }
+
+    /// Returns the named profile, creating an empty one if it doesn't exist yet.
+    pub fn profile_mut(&mut self, name: &str) -> &mut Profile {
+        if name == DEFAULT_PROFILE {
+            &mut self.default_profile
+        } else {
+            self.profiles.entry(name.to_string()).or_default()
+        }
+    }
+}
+
+/// (De)serializes a routes map whose values are either a target string or a route object.
+mod route_map {
+    use super::{Route, Routes};
+    use indexmap::IndexMap;
+    use serde::de::{self, MapAccess, Visitor};
+    use serde::ser::SerializeMap;
+    use serde::{Deserialize, Deserializer, Serializer};
+    use std::fmt;
+
+    struct RouteEntry(Route);
+
+    impl<'de> Deserialize<'de> for RouteEntry {
+        fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+            struct RouteVisitor;
+
+            impl<'de> Visitor<'de> for RouteVisitor {
+                type Value = RouteEntry;
+
+                fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                    f.write_str("a target string or a route object")
+                }
+
+                fn visit_str<E: de::Error>(self, target: &str) -> Result<RouteEntry, E> {
+                    Ok(RouteEntry(Route::new(target)))
+                }
+
+                fn visit_map<A: MapAccess<'de>>(self, map: A) -> Result<RouteEntry, A::Error> {
+                    Route::deserialize(de::value::MapAccessDeserializer::new(map)).map(RouteEntry)
+                }
+            }
+
+            deserializer.deserialize_any(RouteVisitor)
         }
     }
+
+    pub fn serialize<S: Serializer>(routes: &Routes, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(routes.len()))?;
+        for (key, route) in routes {
+            if route.is_plain() {
+                map.serialize_entry(key, &route.target)?;
+            } else {
+                map.serialize_entry(key, route)?;
+            }
+        }
+        map.end()
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Routes, D::Error> {
+        let entries = IndexMap::<String, RouteEntry>::deserialize(deserializer)?;
+        Ok(entries.into_iter().map(|(k, v)| (k, v.0)).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn routes_accept_target_strings_and_route_objects() {
+        let json = r#"{ "routes": { "api": "http://localhost:8080", "web": { "target": "http://localhost:3000" } } }"#;
+        let profile: Profile = serde_json::from_str(json).unwrap();
+        assert_eq!(profile.routes["api"], Route::new("http://localhost:8080"));
+        assert_eq!(profile.routes["web"], Route::new("http://localhost:3000"));
+    }
+
+    #[test]
+    fn routes_with_header_rules_round_trip() {
+        let json = r#"{ "routes": { "api": {
+            "target": "http://web",
+            "header_routes": [ { "headers": { "X-Client": "mobile" }, "target": "http://mobile" } ]
+        } } }"#;
+        let profile: Profile = serde_json::from_str(json).unwrap();
+        let route = &profile.routes["api"];
+        assert_eq!(route.header_routes.len(), 1);
+        assert_eq!(route.header_routes[0].headers["X-Client"], "mobile");
+
+        let value = serde_json::to_value(&profile).unwrap();
+        assert_eq!(value["routes"]["api"]["target"], "http://web");
+        assert_eq!(
+            value["routes"]["api"]["header_routes"][0]["target"],
+            "http://mobile"
+        );
+    }
+
+    #[test]
+    fn paused_instances_are_skipped_until_their_time() {
+        let rotation = Rotation::default();
+        rotation.pause(0, Instant::now() + Duration::from_millis(50));
+
+        for _ in 0..4 {
+            assert_eq!(rotation.next_available(&[1, 1]), Some(1));
+        }
+        rotation.pause(1, Instant::now() + Duration::from_secs(60));
+        assert_eq!(rotation.next_available(&[1, 1]), None);
+
+        std::thread::sleep(Duration::from_millis(60));
+        assert!(!rotation.is_paused(0));
+        assert_eq!(rotation.next_available(&[1, 1]), Some(0));
+    }
+
+    #[test]
+    fn latency_is_fixed_or_within_its_range() {
+        let json = r#"{ "routes": {
+            "fixed": { "target": "http://a", "latency_ms": 300 },
+            "range": { "target": "http://b", "latency_ms": { "min": 100, "max": 120 } }
+        } }"#;
+        let profile: Profile = serde_json::from_str(json).unwrap();
+        let fixed = profile.routes["fixed"].latency_ms.unwrap();
+        assert_eq!(fixed, Latency::Fixed(300));
+        assert_eq!(fixed.delay(), Duration::from_millis(300));
+
+        let range = profile.routes["range"].latency_ms.unwrap();
+        for _ in 0..20 {
+            let delay = range.delay();
+            assert!(delay >= Duration::from_millis(100) && delay <= Duration::from_millis(120));
+        }
+        // a reversed range waits its minimum
+        let reversed = Latency::Range { min: 50, max: 10 };
+        assert_eq!(reversed.delay(), Duration::from_millis(50));
+    }
+
+    #[test]
+    fn weighted_instances_take_turns_in_proportion() {
+        let rotation = Rotation::default();
+        let picks: Vec<usize> = (0..8)
+            .map(|_| rotation.next_available(&[3, 1]).unwrap())
+            .collect();
+        assert_eq!(picks, [0, 0, 0, 1, 0, 0, 0, 1]);
+
+        // a paused instance's turns go to the next available one
+        rotation.pause(0, Instant::now() + Duration::from_secs(60));
+        assert!((0..4).all(|_| rotation.next_available(&[3, 1]) == Some(1)));
+    }
+
+    #[test]
+    fn plain_routes_serialize_as_target_strings() {
+        let mut profile = Profile::default();
+        profile
+            .routes
+            .insert("api".into(), Route::new("http://localhost:8080"));
+        let value = serde_json::to_value(&profile).unwrap();
+        assert_eq!(value["routes"]["api"], "http://localhost:8080");
+    }
+
+    #[test]
+    fn targets_and_sticky_round_trip() {
+        let json = r#"{ "routes": { "api": {
+            "target": "http://a", "targets": ["http://b"], "sticky": { "cookie": "session" }
+        } } }"#;
+        let profile: Profile = serde_json::from_str(json).unwrap();
+        let route = &profile.routes["api"];
+        assert_eq!(
+            route.instances().collect::<Vec<_>>(),
+            ["http://a", "http://b"]
+        );
+        assert_eq!(
+            route.sticky.as_ref().unwrap().cookie.as_deref(),
+            Some("session")
+        );
+
+        route.rotation.next();
+        let value = serde_json::to_value(&profile).unwrap();
+        assert_eq!(value["routes"]["api"]["targets"][0], "http://b");
+        assert!(value["routes"]["api"].get("rotation").is_none());
+        assert_eq!(profile.routes["api"], route.clone());
+    }
+
+    #[test]
+    fn redirect_routes_round_trip_and_check_the_status() {
+        let json = r#"{ "routes": { "docs": {
+            "target": "https://docs", "kind": "redirect", "redirect_status": 307
+        } } }"#;
+        let profile: Profile = serde_json::from_str(json).unwrap();
+        let route = &profile.routes["docs"];
+        assert_eq!(route.kind, RouteKind::Redirect);
+        assert_eq!(route.redirect_status, RedirectStatus::TemporaryRedirect);
+
+        let value = serde_json::to_value(&profile).unwrap();
+        assert_eq!(value["routes"]["docs"]["kind"], "redirect");
+        assert_eq!(value["routes"]["docs"]["redirect_status"], 307);
+
+        let json =
+            r#"{ "routes": { "docs": { "target": "https://docs", "redirect_status": 200 } } }"#;
+        let err = serde_json::from_str::<Profile>(json).unwrap_err();
+        assert!(err.to_string().contains("redirect_status must be"), "{err}");
+    }
 }
@@ -1,18 +1,24 @@
 use super::models::ConfigFile;
+use crate::status;
+use serde::de::{self, DeserializeSeed, Deserializer, MapAccess, SeqAccess, Visitor};
+use serde_json::Value;
+use std::collections::HashSet;
 use std::error::Error;
+use std::fmt;
 use std::fs;
-use std::path::Path;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+const APP_DIR: &str = "local-http-proxy";
+const LEGACY_APP_DIR: &str = ".local-http-proxy";
+const CONFIG_FILE_NAME: &str = "config.json";
 
 /// Reads and parses the JSON config file from a given path.
 pub fn read_config_file(path: &Path) -> Result<ConfigFile, Box<dyn Error>> {
-    let content = fs::read_to_string(path)?;
-    let config: ConfigFile = serde_json::from_str(&content).map_err(|e| {
-        format!(
-            "Configuration file at '{}' is invalid.\n  Details: {}",
-            path.display(),
-            e
-        )
-    })?;
+    let content =
+        fs::read_to_string(path).map_err(|e| io_error("read the configuration file", path, e))?;
+    let config: ConfigFile = serde_json::from_str(&content).map_err(|e| invalid_config(path, e))?;
     Ok(config)
 }
 
@@ -20,20 +26,546 @@ pub fn read_config_file(path: &Path) -> Result<ConfigFile, Box<dyn Error>> {
 pub fn write_config_file(path: &Path, config: &ConfigFile) -> Result<(), Box<dyn Error>> {
     // Create parent directory if it doesn't exist.
     if let Some(parent) = path.parent() {
-        fs::create_dir_all(parent)?;
+        fs::create_dir_all(parent)
+            .map_err(|e| io_error("create the configuration directory", parent, e))?;
     }
     let content = serde_json::to_string_pretty(config)?;
-    fs::write(path, content)?;
+    fs::write(path, content).map_err(|e| io_error("write the configuration file", path, e))?;
     Ok(())
 }
 
 /// Ensures a config file exists, creating a default one if needed, then reads it.
 pub fn load_or_create_config_file(path: &Path) -> Result<ConfigFile, Box<dyn Error>> {
+    check_config_path(path)?;
     if !path.exists() {
         let config = ConfigFile::default();
         write_config_file(path, &config)?;
-        println!("Created a new default config file at: {}", path.display());
+        status!("Created a new default config file at: {}", path.display());
         return Ok(config);
     }
     read_config_file(path)
 }
+
+/// Loads the given config files in order and merges them into one config.
+/// Later files take precedence: objects (like `routes` and `profiles`) are merged key by key,
+/// and any other value replaces the earlier one.
+/// Only the last file (the one commands write to) is created if it doesn't exist.
+pub fn load_config_files(paths: &[PathBuf]) -> Result<ConfigFile, Box<dyn Error>> {
+    let Some(primary) = paths.last() else {
+        return Ok(ConfigFile::default());
+    };
+    load_or_create_config_file(primary)?;
+    read_config_files(paths)
+}
+
+/// Like `load_config_files`, but a missing file is an error instead of being created.
+pub fn read_config_files(paths: &[PathBuf]) -> Result<ConfigFile, Box<dyn Error>> {
+    let mut merged = Value::Object(Default::default());
+    for path in paths {
+        merge_config_values(&mut merged, read_config_value(path)?);
+    }
+    let config = serde_json::from_value(merged)
+        .map_err(|e| format!("Merged configuration is invalid.\n  Details: {}", e))?;
+    Ok(config)
+}
+
+/// Reads a config file as raw JSON after checking it is a valid config on its own.
+/// Every command loads the config through here, so this is where duplicate keys are
+/// reported.
+fn read_config_value(path: &Path) -> Result<Value, Box<dyn Error>> {
+    check_config_path(path)?;
+    let content =
+        fs::read_to_string(path).map_err(|e| io_error("read the configuration file", path, e))?;
+    let value: Value = serde_json::from_str(&content).map_err(|e| invalid_config(path, e))?;
+    serde_json::from_value::<ConfigFile>(value.clone()).map_err(|e| invalid_config(path, e))?;
+    warn_duplicate_keys(path, &content);
+    Ok(value)
+}
+
+/// Warns about keys listed twice in the same object of a config file, since only the last
+/// one takes effect and the earlier ones are dropped without notice.
+fn warn_duplicate_keys(path: &Path, content: &str) {
+    for key in duplicate_keys(content) {
+        eprintln!(
+            "⚠️  '{}' has the key '{}' more than once; only the last one is used.",
+            path.display(),
+            key
+        );
+    }
+}
+
+/// The keys (as dotted paths like `routes.api`) that appear more than once in the same
+/// object of a JSON document.
+fn duplicate_keys(content: &str) -> Vec<String> {
+    let mut found = Vec::new();
+    let mut deserializer = serde_json::Deserializer::from_str(content);
+    let scan = DuplicateKeys {
+        path: String::new(),
+        found: &mut found,
+    };
+    // the document was parsed already, so a failure here can only leave the list short
+    let _ = scan.deserialize(&mut deserializer);
+    found
+}
+
+/// Walks a JSON value, recording keys repeated within one object.
+struct DuplicateKeys<'a> {
+    path: String,
+    found: &'a mut Vec<String>,
+}
+
+impl DuplicateKeys<'_> {
+    fn child(&mut self, segment: &str) -> DuplicateKeys<'_> {
+        let path = if self.path.is_empty() {
+            segment.to_string()
+        } else {
+            format!("{}.{}", self.path, segment)
+        };
+        DuplicateKeys {
+            path,
+            found: self.found,
+        }
+    }
+}
+
+impl<'de> DeserializeSeed<'de> for DuplicateKeys<'_> {
+    type Value = ();
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<(), D::Error> {
+        deserializer.deserialize_any(self)
+    }
+}
+
+impl<'de> Visitor<'de> for DuplicateKeys<'_> {
+    type Value = ();
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a JSON value")
+    }
+
+    fn visit_bool<E: de::Error>(self, _: bool) -> Result<(), E> {
+        Ok(())
+    }
+
+    fn visit_i64<E: de::Error>(self, _: i64) -> Result<(), E> {
+        Ok(())
+    }
+
+    fn visit_u64<E: de::Error>(self, _: u64) -> Result<(), E> {
+        Ok(())
+    }
+
+    fn visit_f64<E: de::Error>(self, _: f64) -> Result<(), E> {
+        Ok(())
+    }
+
+    fn visit_str<E: de::Error>(self, _: &str) -> Result<(), E> {
+        Ok(())
+    }
+
+    fn visit_unit<E: de::Error>(self) -> Result<(), E> {
+        Ok(())
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(mut self, mut seq: A) -> Result<(), A::Error> {
+        let mut index = 0;
+        while seq
+            .next_element_seed(self.child(&index.to_string()))?
+            .is_some()
+        {
+            index += 1;
+        }
+        Ok(())
+    }
+
+    fn visit_map<A: MapAccess<'de>>(mut self, mut map: A) -> Result<(), A::Error> {
+        let mut seen = HashSet::new();
+        while let Some(key) = map.next_key::<String>()? {
+            let child = self.child(&key);
+            if !seen.insert(key) {
+                child.found.push(child.path.clone());
+            }
+            map.next_value_seed(child)?;
+        }
+        Ok(())
+    }
+}
+
+fn invalid_config(path: &Path, e: serde_json::Error) -> String {
+    format!(
+        "Configuration file at '{}' is invalid.\n  Details: {}",
+        path.display(),
+        e
+    )
+}
+
+/// Describes a failed file operation, with a hint for the common permission and missing
+/// path cases.
+fn io_error(action: &str, path: &Path, e: io::Error) -> String {
+    match e.kind() {
+        io::ErrorKind::PermissionDenied => format!(
+            "Could not {} at '{}': permission denied.\n  \
+             Check that the path and its parent directory are owned by your user and writable \
+             (e.g. with `ls -l`), or pass another file with --config-file.",
+            action,
+            path.display()
+        ),
+        io::ErrorKind::NotFound => format!(
+            "Could not {} at '{}': the path does not exist.\n  \
+             Check that the parent directory exists, or pass another file with --config-file.",
+            action,
+            path.display()
+        ),
+        _ => format!(
+            "Could not {} at '{}'.\n  Cause: {}",
+            action,
+            path.display(),
+            e
+        ),
+    }
+}
+
+/// Catches config paths that can never be read or created as a file: a directory, a symlink
+/// to nothing and a symlink loop. Without this they fail later with a bare IO error, or a
+/// dangling link gets a new file created wherever it points.
+fn check_config_path(path: &Path) -> Result<(), String> {
+    let is_link = fs::symlink_metadata(path).is_ok_and(|m| m.file_type().is_symlink());
+    match fs::metadata(path) {
+        Ok(metadata) if metadata.is_dir() => Err(format!(
+            "The configuration path '{}' is a directory, not a file.\n  \
+             Pass the path of a file in it instead, e.g. --config-file {}.",
+            path.display(),
+            path.join(CONFIG_FILE_NAME).display()
+        )),
+        Ok(_) => Ok(()),
+        Err(e) if is_link && e.kind() == io::ErrorKind::NotFound => Err(format!(
+            "The configuration path '{}' is a symbolic link to '{}', which does not exist.\n  \
+             Create the file it points to, or fix or remove the link.",
+            path.display(),
+            fs::read_link(path).unwrap_or_default().display()
+        )),
+        Err(e) if is_link => Err(format!(
+            "The configuration path '{}' is a symbolic link that can't be followed, e.g. \
+             because it points back at itself.\n  Fix or remove the link.\n  Cause: {}",
+            path.display(),
+            e
+        )),
+        // missing files are created, and other failures are reported by the read
+        Err(_) => Ok(()),
+    }
+}
+
+fn merge_config_values(base: &mut Value, overlay: Value) {
+    match (base, overlay) {
+        (Value::Object(base), Value::Object(overlay)) => {
+            for (key, value) in overlay {
+                merge_config_values(base.entry(key).or_insert(Value::Null), value);
+            }
+        }
+        (base, overlay) => *base = overlay,
+    }
+}
+
+/// Notices when a file changes by comparing its modification time and size between polls.
+/// A file that is created or deleted counts as changed too.
+#[derive(Debug)]
+pub struct FileWatcher {
+    path: PathBuf,
+    // `None` until the first poll, so that one always reports a change
+    seen: Option<Option<(SystemTime, u64)>>,
+}
+
+impl FileWatcher {
+    pub fn new(path: &Path) -> Self {
+        FileWatcher {
+            path: path.to_path_buf(),
+            seen: None,
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Whether the file changed since the last call. The first call always returns true.
+    pub fn changed(&mut self) -> bool {
+        let stamp = fs::metadata(&self.path)
+            .ok()
+            .map(|m| (m.modified().unwrap_or(SystemTime::UNIX_EPOCH), m.len()));
+        let changed = self.seen != Some(stamp);
+        self.seen = Some(stamp);
+        changed
+    }
+}
+
+/// Computes the default config file path for this platform.
+/// `$XDG_CONFIG_HOME` (or `~/.config`) on Linux, `~/Library/Application Support` on macOS and
+/// `%APPDATA%` on Windows. An existing config at the legacy `~/.local-http-proxy` location
+/// keeps being used so upgrades don't silently start from an empty config.
+pub fn default_config_path() -> PathBuf {
+    resolve_default_config_path(dirs::home_dir(), dirs::config_dir(), |p| p.exists())
+}
+
+fn resolve_default_config_path(
+    home_dir: Option<PathBuf>,
+    config_dir: Option<PathBuf>,
+    exists: impl Fn(&Path) -> bool,
+) -> PathBuf {
+    if let Some(home) = &home_dir {
+        let legacy = home.join(LEGACY_APP_DIR).join(CONFIG_FILE_NAME);
+        if exists(&legacy) {
+            return legacy;
+        }
+    }
+
+    match (config_dir, home_dir) {
+        (Some(dir), _) => dir.join(APP_DIR).join(CONFIG_FILE_NAME),
+        (None, Some(home)) => home.join(LEGACY_APP_DIR).join(CONFIG_FILE_NAME),
+        (None, None) => PathBuf::from(CONFIG_FILE_NAME),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ProxyMode;
+    use tempfile::TempDir;
+
+    fn write(dir: &TempDir, name: &str, content: &str) -> PathBuf {
+        let path = dir.path().join(name);
+        fs::write(&path, content).unwrap();
+        path
+    }
+
+    #[test]
+    fn later_config_files_override_earlier_ones() {
+        let dir = TempDir::new().unwrap();
+        let base = write(
+            &dir,
+            "base.json",
+            r#"{ "mode": "path", "routes": { "api": "http://base:8080", "web": "http://base:3000" } }"#,
+        );
+        let personal = write(
+            &dir,
+            "personal.json",
+            r#"{ "mode": "domain", "routes": { "api": "http://localhost:8080", "docs": "http://localhost:4000" } }"#,
+        );
+
+        let config = load_config_files(&[base.clone(), personal.clone()]).unwrap();
+        assert!(matches!(config.default_profile.mode, ProxyMode::Domain));
+        let routes = &config.default_profile.routes;
+        assert_eq!(routes["api"].target, "http://localhost:8080");
+        assert_eq!(routes["web"].target, "http://base:3000");
+        assert_eq!(routes["docs"].target, "http://localhost:4000");
+
+        // reversing the order flips which file wins
+        let config = load_config_files(&[personal, base]).unwrap();
+        assert!(matches!(config.default_profile.mode, ProxyMode::Path));
+        assert_eq!(
+            config.default_profile.routes["api"].target,
+            "http://base:8080"
+        );
+    }
+
+    #[test]
+    fn merge_keeps_fields_unset_in_later_files() {
+        let dir = TempDir::new().unwrap();
+        let base = write(
+            &dir,
+            "base.json",
+            r#"{ "port": 9000, "mode": "domain", "routes": { "api": "http://base:8080" } }"#,
+        );
+        let personal = write(
+            &dir,
+            "personal.json",
+            r#"{ "routes": { "web": "http://localhost:3000" } }"#,
+        );
+
+        let config = load_config_files(&[base, personal]).unwrap();
+        assert_eq!(config.port, 9000);
+        assert!(matches!(config.default_profile.mode, ProxyMode::Domain));
+        assert_eq!(config.default_profile.routes.len(), 2);
+    }
+
+    #[test]
+    fn only_the_last_config_file_is_created() {
+        let dir = TempDir::new().unwrap();
+        let missing_base = dir.path().join("missing.json");
+        let primary = dir.path().join("primary.json");
+
+        assert!(load_config_files(&[missing_base, primary.clone()]).is_err());
+
+        load_config_files(std::slice::from_ref(&primary)).unwrap();
+        assert!(primary.exists());
+    }
+
+    #[test]
+    fn duplicate_keys_are_found_in_every_object() {
+        let content = r#"{
+            "routes": { "api": "http://localhost:8080", "web": "http://localhost:3000", "api": "http://localhost:9090" },
+            "profiles": { "dev": { "routes": { "a": "1", "b": "2", "a": "3" } } }
+        }"#;
+        assert_eq!(
+            duplicate_keys(content),
+            ["routes.api", "profiles.dev.routes.a"]
+        );
+        assert!(duplicate_keys(
+            r#"{ "routes": { "api": "1" }, "profiles": { "dev": { "routes": { "api": "2" } } } }"#
+        )
+        .is_empty());
+
+        // serde_json keeps the last value of a duplicate key
+        let dir = TempDir::new().unwrap();
+        let config = read_config_file(&write(&dir, "config.json", content)).unwrap();
+        assert_eq!(
+            config.default_profile.routes["api"].target,
+            "http://localhost:9090"
+        );
+    }
+
+    fn resolve(home: &str, config_dir: Option<&str>) -> PathBuf {
+        resolve_default_config_path(Some(home.into()), config_dir.map(PathBuf::from), |_| false)
+    }
+
+    #[test]
+    fn default_path_uses_platform_config_dir() {
+        // Linux: XDG_CONFIG_HOME (or ~/.config)
+        assert_eq!(
+            resolve("/home/me", Some("/home/me/.config")),
+            PathBuf::from("/home/me/.config/local-http-proxy/config.json")
+        );
+        // macOS: Application Support
+        assert_eq!(
+            resolve("/Users/me", Some("/Users/me/Library/Application Support")),
+            PathBuf::from("/Users/me/Library/Application Support/local-http-proxy/config.json")
+        );
+        // Windows: %APPDATA%
+        assert_eq!(
+            resolve(r"C:\Users\me", Some(r"C:\Users\me\AppData\Roaming")),
+            PathBuf::from(r"C:\Users\me\AppData\Roaming")
+                .join("local-http-proxy")
+                .join("config.json")
+        );
+    }
+
+    #[test]
+    fn default_path_prefers_existing_legacy_config() {
+        let legacy = PathBuf::from("/home/me/.local-http-proxy/config.json");
+        let got = resolve_default_config_path(
+            Some("/home/me".into()),
+            Some("/home/me/.config".into()),
+            |p| p == legacy,
+        );
+        assert_eq!(got, legacy);
+    }
+
+    #[test]
+    fn default_path_falls_back_without_config_dir() {
+        assert_eq!(
+            resolve("/home/me", None),
+            PathBuf::from("/home/me/.local-http-proxy/config.json")
+        );
+        assert_eq!(
+            resolve_default_config_path(None, None, |_| false),
+            PathBuf::from("config.json")
+        );
+    }
+
+    #[test]
+    fn io_errors_name_the_path_and_cause() {
+        let path = Path::new("/etc/lhp/config.json");
+        let denied = io_error(
+            "write the configuration file",
+            path,
+            io::ErrorKind::PermissionDenied.into(),
+        );
+        assert!(denied.contains("'/etc/lhp/config.json': permission denied"));
+        assert!(denied.contains("owned by your user"));
+
+        let missing = io_error(
+            "read the configuration file",
+            path,
+            io::ErrorKind::NotFound.into(),
+        );
+        assert!(missing.contains("the path does not exist"));
+        assert!(!missing.contains("permission"));
+    }
+
+    #[test]
+    fn a_directory_as_config_path_is_reported_as_such() {
+        let dir = TempDir::new().unwrap();
+        let err = load_or_create_config_file(dir.path())
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("is a directory, not a file"), "{err}");
+        assert!(err.contains("config.json"), "{err}");
+
+        // an included file is checked too
+        let primary = write(&dir, "mine.json", "{}");
+        let err = load_config_files(&[dir.path().to_path_buf(), primary])
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("is a directory, not a file"), "{err}");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn broken_and_looping_symlinks_are_reported_without_creating_files() {
+        use std::os::unix::fs::symlink;
+
+        let dir = TempDir::new().unwrap();
+        let target = dir.path().join("elsewhere.json");
+        let dangling = dir.path().join("dangling.json");
+        symlink(&target, &dangling).unwrap();
+        let err = load_or_create_config_file(&dangling)
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("which does not exist"), "{err}");
+        assert!(err.contains("elsewhere.json"), "{err}");
+        assert!(!target.exists());
+
+        let looping = dir.path().join("loop.json");
+        symlink(&looping, &looping).unwrap();
+        let err = load_or_create_config_file(&looping)
+            .unwrap_err()
+            .to_string();
+        assert!(
+            err.contains("symbolic link that can't be followed"),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn reading_a_missing_file_reports_not_found() {
+        let dir = TempDir::new().unwrap();
+        let err = read_config_file(&dir.path().join("missing.json")).unwrap_err();
+        assert!(err.to_string().contains("the path does not exist"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn writing_into_a_read_only_directory_reports_permission_denied() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = TempDir::new().unwrap();
+        let locked = dir.path().join("locked");
+        fs::create_dir(&locked).unwrap();
+        fs::set_permissions(&locked, fs::Permissions::from_mode(0o555)).unwrap();
+
+        // privileged users (e.g. root in CI containers) can write anyway
+        if fs::write(locked.join("probe"), "").is_ok() {
+            return;
+        }
+
+        let err = write_config_file(&locked.join("config.json"), &ConfigFile::default())
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("permission denied"), "{err}");
+        assert!(err.contains("locked/config.json"), "{err}");
+
+        let err = write_config_file(&locked.join("sub/config.json"), &ConfigFile::default())
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("create the configuration directory"), "{err}");
+    }
+}
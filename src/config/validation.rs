@@ -0,0 +1,91 @@
+use regex::Regex;
+use std::sync::LazyLock;
+
+/// The longest route key label, as for a DNS label.
+pub const MAX_LABEL_LENGTH: usize = 63;
+
+/// A route key label: lowercase letters, digits and inner hyphens. It is the first segment
+/// of a Path mode request and the first label of a Domain mode host, so `add` and the proxy
+/// both build their checks from it.
+pub const LABEL_PATTERN: &str = "[a-z0-9](?:[a-z0-9-]*[a-z0-9])?";
+
+static LABEL_RE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(&format!("^{}$", LABEL_PATTERN)).unwrap());
+
+/// Whether `key` can be the key of a Path or Domain mode route: one lowercase label of at
+/// most `MAX_LABEL_LENGTH` characters. Ports (`api:8001`) and globs (`api-*`) are checked
+/// by the normalizers that accept them.
+pub fn is_valid_label(key: &str) -> bool {
+    key.len() <= MAX_LABEL_LENGTH && LABEL_RE.is_match(key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::util::normalize_source_key;
+    use crate::config::{ProxyMode, Route, DEFAULT_LOCAL_SUFFIXES};
+    use crate::server::proxy::get_destination;
+    use crate::server::router::Router;
+    use hyper::header::HOST;
+    use hyper::Request;
+
+    #[test]
+    fn labels_are_short_lowercase_and_hyphenated_inside() {
+        for valid in ["a", "0", "api", "my-app", "a--b", &"a".repeat(63)] {
+            assert!(is_valid_label(valid), "{valid}");
+        }
+        for invalid in [
+            "",
+            "API",
+            "-api",
+            "api-",
+            "my_app",
+            "app.v2",
+            "é",
+            &"a".repeat(64),
+        ] {
+            assert!(!is_valid_label(invalid), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn cli_and_proxy_agree_on_edge_case_keys() {
+        let keys = [
+            "a",
+            "api",
+            "my-app",
+            "API",
+            "a--b",
+            "-api",
+            "api-",
+            "my_app",
+            "app%20x",
+            "é",
+            &"a".repeat(63),
+            &"a".repeat(64),
+        ];
+        for key in keys {
+            let added = normalize_source_key(key, &DEFAULT_LOCAL_SUFFIXES).is_ok();
+            let lowered = key.to_ascii_lowercase();
+            let routes = [(lowered.clone(), Route::new("http://upstream"))].into();
+
+            let router = Router::for_mode(&ProxyMode::Path, routes).unwrap();
+            let req = Request::builder()
+                .uri(format!("/{key}/x"))
+                .body(())
+                .unwrap();
+            let by_path = get_destination(&req, &ProxyMode::Path, &router, false, None).is_ok();
+            assert_eq!(added, by_path, "'{key}': add and Path mode disagree");
+
+            let routes = [(lowered, Route::new("http://upstream"))].into();
+            let router = Router::for_mode(&ProxyMode::Domain, routes).unwrap();
+            let req = Request::builder()
+                .uri("/")
+                .header(HOST, format!("{key}.localhost"))
+                .body(())
+                .unwrap();
+            let by_host = get_destination(&req, &ProxyMode::Domain, &router, false, None).is_ok();
+            assert_eq!(added, by_host, "'{key}': add and Domain mode disagree");
+        }
+    }
+}
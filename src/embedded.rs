@@ -0,0 +1,145 @@
+use crate::config::{AppConfig, Args, Command, ConfigFile, ProxyMode, Route};
+use crate::server::{self, bind_error};
+use std::error::Error;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio::net::TcpListener;
+use tokio::sync::oneshot;
+use tokio::task::JoinHandle;
+
+/// A proxy run inside another program, such as a test harness, instead of by the
+/// `local-http-proxy` binary. It never reads the config file or the environment, and never
+/// exits the process.
+///
+/// ```no_run
+/// # async fn run() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+/// use local_http_proxy::config::{ProxyMode, Route};
+/// use local_http_proxy::Proxy;
+///
+/// let proxy = Proxy::builder()
+///     .mode(ProxyMode::Path)
+///     .route("api", Route::new("http://localhost:8080"))
+///     .serve()
+///     .await?;
+/// println!("proxying on {}", proxy.addr());
+/// proxy.shutdown().await?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct Proxy;
+
+impl Proxy {
+    pub fn builder() -> ProxyBuilder {
+        ProxyBuilder {
+            file: ConfigFile::default(),
+            bind: SocketAddr::from((Ipv4Addr::LOCALHOST, 0)),
+        }
+    }
+}
+
+/// Collects the settings of an embedded proxy; `serve` starts it.
+#[derive(Debug)]
+pub struct ProxyBuilder {
+    file: ConfigFile,
+    bind: SocketAddr,
+}
+
+impl ProxyBuilder {
+    /// Starts from the settings of a whole config file (its default profile is served).
+    /// Routes and the mode set afterwards are applied on top.
+    pub fn config(mut self, file: ConfigFile) -> Self {
+        self.file = file;
+        self
+    }
+
+    /// The routing mode. Defaults to `path`.
+    pub fn mode(mut self, mode: ProxyMode) -> Self {
+        self.file.default_profile.mode = mode;
+        self
+    }
+
+    /// Adds a route, replacing any with the same key. Targets are used as given, so they
+    /// need their scheme (`http://localhost:8080`).
+    pub fn route(mut self, key: impl Into<String>, route: Route) -> Self {
+        self.file.default_profile.routes.insert(key.into(), route);
+        self
+    }
+
+    /// Adds several routes, as `route` does.
+    pub fn routes(mut self, routes: impl IntoIterator<Item = (String, Route)>) -> Self {
+        self.file.default_profile.routes.extend(routes);
+        self
+    }
+
+    /// The address to listen on. Defaults to `127.0.0.1:0`, a free port on the loopback
+    /// interface; `ProxyHandle::addr` tells which one.
+    pub fn bind(mut self, addr: SocketAddr) -> Self {
+        self.bind = addr;
+        self
+    }
+
+    /// Binds the address and starts proxying in the background on the current Tokio
+    /// runtime. Fails if the routes are invalid or the address can't be bound.
+    pub async fn serve(mut self) -> Result<ProxyHandle, Box<dyn Error + Send + Sync>> {
+        self.file.port = self.bind.port();
+        self.file.bind = Some(self.bind.ip());
+        let args = Args {
+            config_file: Vec::new(),
+            profile: None,
+            quiet: true,
+            command: Command::Start {
+                port: None,
+                mode: None,
+                bind: None,
+                otlp_endpoint: None,
+                require_routes: false,
+                pidfile: None,
+                daemon: false,
+                log_file: None,
+            },
+        };
+        let config = AppConfig::from_file_and_env(PathBuf::new(), self.file, &args, |_| None)?;
+
+        let listener = TcpListener::bind(self.bind)
+            .await
+            .map_err(|e| bind_error(self.bind, e))?;
+        let addr = listener.local_addr()?;
+        let (shutdown, stopped) = oneshot::channel::<()>();
+        let task = tokio::spawn(async move {
+            tokio::select! {
+                result = server::run(listener, Arc::new(config)) => result,
+                // a dropped handle stops the proxy too
+                _ = stopped => Ok(()),
+            }
+        });
+        Ok(ProxyHandle {
+            addr,
+            shutdown,
+            task,
+        })
+    }
+}
+
+/// A running embedded proxy. Dropping the handle stops it as well.
+#[derive(Debug)]
+pub struct ProxyHandle {
+    addr: SocketAddr,
+    shutdown: oneshot::Sender<()>,
+    task: JoinHandle<Result<(), Box<dyn Error + Send + Sync>>>,
+}
+
+impl ProxyHandle {
+    /// The address the proxy listens on, with the port picked for `:0`.
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Stops accepting connections and waits for the accept loop to end. Requests already
+    /// being answered are finished in the background.
+    pub async fn shutdown(self) -> Result<(), Box<dyn Error + Send + Sync>> {
+        // the loop may have ended on its own already, with the error returned below
+        let _ = self.shutdown.send(());
+        self.task.await?
+    }
+}
@@ -0,0 +1,7 @@
+pub mod commands;
+pub mod config;
+mod embedded;
+pub mod output;
+pub mod server;
+
+pub use embedded::{Proxy, ProxyBuilder, ProxyHandle};
@@ -1,25 +1,40 @@
-mod commands;
-mod config;
-mod server;
-
-use crate::commands::{handle_config_command, handle_start_command};
-use crate::config::Command;
 use clap::Parser;
-use config::{AppConfig, Args};
+use local_http_proxy::commands::{
+    handle_config_command, handle_doctor_command, handle_init_command, handle_start_command,
+    handle_stats_command, handle_version_command,
+};
+use local_http_proxy::config::{AppConfig, Args, Command};
+use local_http_proxy::output;
 use std::error::Error;
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error + Send + Sync>> {
     let args = Args::parse();
+    output::set_quiet(args.quiet);
+
+    // `init` writes the config file, so it must not be read (or created) first
+    if let Command::Init { mode, force } = &args.command {
+        handle_init_command(&AppConfig::write_path(&args), *mode, *force);
+    }
 
-    // ensure the app config is loaded and ready to be used in commands
-    AppConfig::load(&args);
+    // load the app config once, to be handed to the command
+    let config = AppConfig::load(&args);
 
     match &args.command {
-        Command::Start { .. } => handle_start_command().await,
-        Command::List => handle_config_command(&args.command),
-        Command::Add { .. } => handle_config_command(&args.command),
-        Command::Remove { .. } => handle_config_command(&args.command),
-        Command::SetMode { .. } => handle_config_command(&args.command),
+        Command::Start { .. } => handle_start_command(config).await,
+        Command::List { .. } => handle_config_command(&args.command, &config),
+        Command::Add { .. } => handle_config_command(&args.command, &config),
+        Command::Remove { .. } => handle_config_command(&args.command, &config),
+        Command::SetMode { .. } => handle_config_command(&args.command, &config),
+        Command::SetPort { .. } => handle_config_command(&args.command, &config),
+        Command::Maintenance { .. } => handle_config_command(&args.command, &config),
+        Command::UseProfile { .. } => handle_config_command(&args.command, &config),
+        Command::Stats => handle_stats_command(&config).await,
+        Command::Doctor => handle_doctor_command(&config).await,
+        Command::Init { .. } => unreachable!(),
+        Command::Version { verbose } => {
+            handle_version_command(&config, *verbose);
+            Ok(())
+        }
     }
 }
@@ -1,31 +1,527 @@
-mod proxy;
+pub mod access_log;
+pub mod body_log;
+pub(crate) mod client;
+pub mod proxy;
+pub mod router;
+pub mod static_files;
+pub mod stats;
+pub mod telemetry;
 
 use crate::config::AppConfig;
+use hyper::body::Incoming;
 use hyper::server::conn::http1;
 use hyper::service::service_fn;
-use hyper_util::rt::TokioIo;
+use hyper::{Request, Response};
+use hyper_util::rt::{TokioExecutor, TokioIo};
+use hyper_util::server::conn::auto;
+use proxy::ProxyBody;
+use stats::ConnectionCounters;
+use std::convert::Infallible;
 use std::error::Error;
-use std::net::{IpAddr, Ipv4Addr, SocketAddr};
-use tokio::net::TcpListener;
+use std::io;
+use std::net::SocketAddr;
+use std::ops::Deref;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::{TcpListener, TcpStream};
 
-pub async fn start_server() -> Result<(), Box<dyn Error + Send + Sync>> {
-    let app_config = AppConfig::instance();
-    let ip = IpAddr::V4(Ipv4Addr::LOCALHOST);
-    let addr = SocketAddr::new(ip, app_config.port);
-    let listener = TcpListener::bind(addr).await?;
+/// The longest pause between accept attempts while the process is out of file descriptors
+/// or memory, when the config file doesn't set `accept_backoff_max_ms`.
+pub const DEFAULT_ACCEPT_BACKOFF_MAX: Duration = Duration::from_secs(1);
 
+/// The first pause after a failed accept; it doubles with every failure in a row.
+const FIRST_ACCEPT_BACKOFF: Duration = Duration::from_millis(5);
+
+pub async fn start_server(app_config: Arc<AppConfig>) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let addr = SocketAddr::new(app_config.bind, app_config.port);
+    let listener = TcpListener::bind(addr)
+        .await
+        .map_err(|e| bind_error(addr, e))?;
+    watch_for_reload(app_config.clone())?;
+    run(listener, app_config).await
+}
+
+/// Proxies the connections accepted on `listener` with `config`, until the returned future
+/// fails or is dropped.
+pub(crate) async fn run<C>(
+    listener: TcpListener,
+    config: C,
+) -> Result<(), Box<dyn Error + Send + Sync>>
+where
+    C: Deref<Target = AppConfig> + Clone + Send + Sync + 'static,
+{
+    let keep_alive = !config.connection_close;
+    let h2c = config.h2c;
+    // the proxy only accepts plain TCP connections so far
+    let is_tls = false;
+    let connections = config.connections.clone();
+    let backoff = config.accept_backoff_max;
+    serve(
+        listener,
+        h2c,
+        keep_alive,
+        connections,
+        backoff,
+        move |req, peer| {
+            let config = config.clone();
+            async move { proxy::proxy_service(req, peer, is_tls, &config).await }
+        },
+    )
+    .await
+}
+
+/// Reloads the routes whenever the process gets SIGHUP.
+#[cfg(unix)]
+fn watch_for_reload(config: Arc<AppConfig>) -> io::Result<()> {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let hangups = signal(SignalKind::hangup())?;
+    tokio::spawn(reload_on_hangup(config, hangups));
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn watch_for_reload(_config: Arc<AppConfig>) -> io::Result<()> {
+    eprintln!("Reloading the config on SIGHUP is only available on Unix; restart the server to apply changes.");
+    Ok(())
+}
+
+#[cfg(unix)]
+async fn reload_on_hangup(config: Arc<AppConfig>, mut hangups: tokio::signal::unix::Signal) {
+    while hangups.recv().await.is_some() {
+        match config.reload_routes() {
+            Ok(count) => println!("🔄 Reloaded the config. Routes loaded: {}", count),
+            Err(e) => eprintln!(
+                "Could not reload the config; keeping the current routes.\n  Cause: {}",
+                e
+            ),
+        }
+    }
+}
+
+/// Ports below this one need root or `CAP_NET_BIND_SERVICE` on Linux.
+pub(crate) const FIRST_UNPRIVILEGED_PORT: u16 = 1024;
+
+/// Explains why the server could not listen on `addr`, with a hint for the common causes.
+pub(crate) fn bind_error(addr: SocketAddr, e: io::Error) -> String {
+    match e.kind() {
+        io::ErrorKind::PermissionDenied if addr.port() < FIRST_UNPRIVILEGED_PORT => format!(
+            "Could not listen on {}: permission denied.\n  \
+             Ports below {} are privileged. Grant the binary the capability with \
+             `sudo setcap cap_net_bind_service=+ep $(which local-http-proxy)`, \
+             or pick a higher port with --port (e.g. --port 8080).",
+            addr, FIRST_UNPRIVILEGED_PORT
+        ),
+        io::ErrorKind::AddrInUse => format!(
+            "Could not listen on {}: the address is already in use.\n  \
+             Another process (maybe another proxy) holds port {}. Stop it, or pick another \
+             port with --port.",
+            addr,
+            addr.port()
+        ),
+        _ => format!("Could not listen on {}.\n  Cause: {}", addr, e),
+    }
+}
+
+/// Where `serve` gets its connections from; a `TcpListener` outside of tests.
+trait Accept {
+    fn accept(&mut self) -> impl Future<Output = io::Result<(TcpStream, SocketAddr)>> + Send;
+}
+
+impl Accept for TcpListener {
+    fn accept(&mut self) -> impl Future<Output = io::Result<(TcpStream, SocketAddr)>> + Send {
+        TcpListener::accept(self)
+    }
+}
+
+/// How the accept loop carries on after `accept` fails.
+#[derive(Debug, PartialEq, Eq)]
+enum AcceptFailure {
+    /// Only the connection being accepted is lost (the client gave up first).
+    Connection,
+    /// The process is out of file descriptors or memory for now; worth a pause.
+    Exhausted,
+    /// The listener itself is broken.
+    Fatal,
+}
+
+impl AcceptFailure {
+    fn of(e: &io::Error) -> Self {
+        match e.kind() {
+            io::ErrorKind::ConnectionAborted
+            | io::ErrorKind::ConnectionReset
+            | io::ErrorKind::ConnectionRefused
+            | io::ErrorKind::Interrupted => AcceptFailure::Connection,
+            io::ErrorKind::OutOfMemory => AcceptFailure::Exhausted,
+            #[cfg(unix)]
+            _ if matches!(
+                e.raw_os_error(),
+                Some(libc::EMFILE | libc::ENFILE | libc::ENOBUFS | libc::ENOMEM)
+            ) =>
+            {
+                AcceptFailure::Exhausted
+            }
+            _ => AcceptFailure::Fatal,
+        }
+    }
+}
+
+/// Accepts connections forever, passing each request to `handler` together with the
+/// address of the client that sent it. With `h2c`, connections starting with the HTTP/2
+/// preface are served as HTTP/2 and the rest as HTTP/1.1. Without `keep_alive`, HTTP/1
+/// connections are closed after their first response. Every connection is counted in
+/// `connections`. While accepting fails for want of file descriptors or memory, the loop
+/// pauses for up to `max_backoff` between attempts; it only ends on errors of the listener
+/// itself.
+async fn serve<L, F, Fut>(
+    mut listener: L,
+    h2c: bool,
+    keep_alive: bool,
+    connections: Arc<ConnectionCounters>,
+    max_backoff: Duration,
+    handler: F,
+) -> Result<(), Box<dyn Error + Send + Sync>>
+where
+    L: Accept,
+    F: Fn(Request<Incoming>, SocketAddr) -> Fut + Clone + Send + 'static,
+    Fut: Future<Output = Result<Response<ProxyBody>, Infallible>> + Send + 'static,
+{
+    let mut backoff = FIRST_ACCEPT_BACKOFF.min(max_backoff);
     loop {
-        let (stream, _) = listener.accept().await?;
+        let (stream, peer) = match listener.accept().await {
+            Ok(accepted) => {
+                backoff = FIRST_ACCEPT_BACKOFF.min(max_backoff);
+                accepted
+            }
+            Err(e) => match AcceptFailure::of(&e) {
+                AcceptFailure::Connection => continue,
+                AcceptFailure::Exhausted => {
+                    eprintln!(
+                        "⚠️  Could not accept a connection; retrying in {}ms.\n  Cause: {}",
+                        backoff.as_millis(),
+                        e
+                    );
+                    tokio::time::sleep(backoff).await;
+                    backoff = (backoff * 2).min(max_backoff);
+                    continue;
+                }
+                AcceptFailure::Fatal => return Err(e.into()),
+            },
+        };
 
         let io = TokioIo::new(stream);
+        let handler = handler.clone();
+        let connections = connections.clone();
+        connections.opened();
 
         tokio::task::spawn(async move {
-            if let Err(err) = http1::Builder::new()
-                .serve_connection(io, service_fn(proxy::proxy_service))
-                .await
-            {
+            let service = service_fn(move |req| handler(req, peer));
+            let result = if h2c {
+                let mut builder = auto::Builder::new(TokioExecutor::new());
+                builder.http1().keep_alive(keep_alive);
+                builder.serve_connection(io, service).await
+            } else {
+                http1::Builder::new()
+                    .keep_alive(keep_alive)
+                    .serve_connection(io, service)
+                    .await
+                    .map_err(Into::into)
+            };
+            connections.closed(result.is_err());
+            if let Err(err) = result {
                 eprintln!("Error serving connection: {:?}", err);
             }
         });
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http_body_util::{BodyExt, Empty, Full};
+    use hyper::body::Bytes;
+    use hyper::client::conn::http1 as client;
+    use tokio::net::TcpStream;
+
+    async fn echo_peer(
+        _req: Request<Incoming>,
+        peer: SocketAddr,
+    ) -> Result<Response<ProxyBody>, Infallible> {
+        let body = Full::new(Bytes::from(peer.to_string()))
+            .map_err(|never| match never {})
+            .boxed();
+        Ok(Response::new(body))
+    }
+
+    async fn echo_version(
+        req: Request<Incoming>,
+        _peer: SocketAddr,
+    ) -> Result<Response<ProxyBody>, Infallible> {
+        let body = Full::new(Bytes::from(format!("{:?}", req.version())))
+            .map_err(|never| match never {})
+            .boxed();
+        Ok(Response::new(body))
+    }
+
+    #[test]
+    fn bind_errors_explain_the_cause() {
+        let privileged = SocketAddr::from(([127, 0, 0, 1], 80));
+        let denied = bind_error(privileged, io::ErrorKind::PermissionDenied.into());
+        assert!(denied.contains("permission denied"));
+        assert!(denied.contains("cap_net_bind_service"));
+        assert!(denied.contains("--port"));
+
+        let in_use = bind_error(privileged, io::ErrorKind::AddrInUse.into());
+        assert!(in_use.contains("already in use"));
+        assert!(!in_use.contains("cap_net_bind_service"));
+
+        // a high port failing with permission denied isn't about privileged ports
+        let high = SocketAddr::from(([127, 0, 0, 1], 8080));
+        let other = bind_error(high, io::ErrorKind::PermissionDenied.into());
+        assert!(!other.contains("cap_net_bind_service"));
+        assert!(other.contains("Cause"));
+    }
+
+    #[tokio::test]
+    async fn h2c_serves_http2_and_http1_clients() {
+        use hyper::client::conn::http2;
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(serve(
+            listener,
+            true,
+            true,
+            Arc::default(),
+            DEFAULT_ACCEPT_BACKOFF_MAX,
+            echo_version,
+        ));
+
+        let stream = TcpStream::connect(addr).await.unwrap();
+        let (mut sender, conn) = http2::handshake(TokioExecutor::new(), TokioIo::new(stream))
+            .await
+            .unwrap();
+        tokio::spawn(conn);
+        let req = Request::builder()
+            .uri(format!("http://{addr}/"))
+            .body(Empty::<Bytes>::new())
+            .unwrap();
+        let res = sender.send_request(req).await.unwrap();
+        let body = res.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(body, "HTTP/2.0");
+
+        let stream = TcpStream::connect(addr).await.unwrap();
+        let (mut sender, conn) = client::handshake(TokioIo::new(stream)).await.unwrap();
+        tokio::spawn(conn);
+        let req = Request::builder()
+            .uri("/")
+            .header(hyper::header::HOST, addr.to_string())
+            .body(Empty::<Bytes>::new())
+            .unwrap();
+        let res = sender.send_request(req).await.unwrap();
+        let body = res.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(body, "HTTP/1.1");
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn sighup_reloads_routes() {
+        use crate::config::{util::write_config_file, Args, ConfigFile, Route};
+        use clap::Parser;
+        use std::time::Duration;
+        use tokio::signal::unix::{signal, SignalKind};
+
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("config.json");
+        let mut file = ConfigFile::default();
+        write_config_file(&path, &file).unwrap();
+        let args = Args::try_parse_from(["lhp", "start"]).unwrap();
+        let config = AppConfig::from_file(path.clone(), ConfigFile::default(), &args).unwrap();
+        let config = Arc::new(config);
+        tokio::spawn(reload_on_hangup(
+            config.clone(),
+            signal(SignalKind::hangup()).unwrap(),
+        ));
+
+        file.default_profile
+            .routes
+            .insert("api".into(), Route::new("http://localhost:3000"));
+        write_config_file(&path, &file).unwrap();
+        let status = std::process::Command::new("kill")
+            .args(["-HUP", &std::process::id().to_string()])
+            .status()
+            .unwrap();
+        assert!(status.success());
+
+        for _ in 0..100 {
+            if !config.routes.load().is_empty() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        let routes = config.routes.load();
+        let (_, route) = routes
+            .resolve(&config.mode, "api")
+            .expect("route is reloaded");
+        assert_eq!(route.target, "http://localhost:3000");
+    }
+
+    #[tokio::test]
+    async fn servers_in_one_process_keep_their_own_config() {
+        use crate::config::{Args, ConfigFile};
+        use clap::Parser;
+
+        let args = Args::try_parse_from(["lhp", "start"]).unwrap();
+        let mut servers = Vec::new();
+        for message in ["first is down", "second is down"] {
+            let file = ConfigFile {
+                maintenance: true,
+                maintenance_message: Some(message.to_string()),
+                ..ConfigFile::default()
+            };
+            let config = AppConfig::from_file_and_env(Default::default(), file, &args, |_| None);
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            servers.push((listener.local_addr().unwrap(), message));
+            tokio::spawn(run(listener, Arc::new(config.unwrap())));
+        }
+
+        for (addr, message) in servers {
+            let stream = TcpStream::connect(addr).await.unwrap();
+            let (mut sender, conn) = client::handshake(TokioIo::new(stream)).await.unwrap();
+            tokio::spawn(conn);
+            let req = Request::builder()
+                .uri("/api")
+                .header(hyper::header::HOST, addr.to_string())
+                .body(Empty::<Bytes>::new())
+                .unwrap();
+            let res = sender.send_request(req).await.unwrap();
+            let body = res.into_body().collect().await.unwrap().to_bytes();
+            assert_eq!(body, message);
+        }
+    }
+
+    #[tokio::test]
+    async fn accepted_connections_are_counted() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let connections = Arc::new(ConnectionCounters::default());
+        tokio::spawn(serve(
+            listener,
+            false,
+            true,
+            connections.clone(),
+            DEFAULT_ACCEPT_BACKOFF_MAX,
+            echo_peer,
+        ));
+
+        let mut senders = Vec::new();
+        for _ in 0..2 {
+            let stream = TcpStream::connect(addr).await.unwrap();
+            let (mut sender, conn) = client::handshake(TokioIo::new(stream)).await.unwrap();
+            tokio::spawn(conn);
+            let req = Request::builder()
+                .uri("/")
+                .header(hyper::header::HOST, addr.to_string())
+                .body(Empty::<Bytes>::new())
+                .unwrap();
+            sender.send_request(req).await.unwrap();
+            senders.push(sender);
+        }
+
+        let stats = connections.snapshot();
+        assert_eq!(stats.accepted, 2);
+        assert_eq!(stats.open, 2);
+        assert_eq!(stats.errors, 0);
+    }
+
+    /// A listener whose first accepts fail as if the process were out of file descriptors.
+    #[cfg(unix)]
+    struct Exhausted {
+        listener: TcpListener,
+        failures: usize,
+    }
+
+    #[cfg(unix)]
+    impl Accept for Exhausted {
+        async fn accept(&mut self) -> io::Result<(TcpStream, SocketAddr)> {
+            if self.failures > 0 {
+                self.failures -= 1;
+                return Err(io::Error::from_raw_os_error(libc::EMFILE));
+            }
+            self.listener.accept().await
+        }
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn accept_loop_backs_off_and_survives_running_out_of_descriptors() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let listener = Exhausted {
+            listener,
+            failures: 3,
+        };
+        let max_backoff = Duration::from_millis(10);
+        let server = tokio::spawn(serve(
+            listener,
+            false,
+            true,
+            Arc::default(),
+            max_backoff,
+            echo_peer,
+        ));
+
+        let stream = TcpStream::connect(addr).await.unwrap();
+        let (mut sender, conn) = client::handshake(TokioIo::new(stream)).await.unwrap();
+        tokio::spawn(conn);
+        let req = Request::builder()
+            .uri("/")
+            .header(hyper::header::HOST, addr.to_string())
+            .body(Empty::<Bytes>::new())
+            .unwrap();
+        let res = sender.send_request(req).await.unwrap();
+        assert_eq!(res.status(), hyper::StatusCode::OK);
+        assert!(!server.is_finished());
+
+        assert_eq!(
+            AcceptFailure::of(&io::Error::from_raw_os_error(libc::ENFILE)),
+            AcceptFailure::Exhausted
+        );
+        assert_eq!(
+            AcceptFailure::of(&io::ErrorKind::ConnectionAborted.into()),
+            AcceptFailure::Connection
+        );
+        assert_eq!(
+            AcceptFailure::of(&io::ErrorKind::InvalidInput.into()),
+            AcceptFailure::Fatal
+        );
+    }
+
+    #[tokio::test]
+    async fn handler_receives_client_address() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(serve(
+            listener,
+            false,
+            true,
+            Arc::default(),
+            DEFAULT_ACCEPT_BACKOFF_MAX,
+            echo_peer,
+        ));
+
+        let stream = TcpStream::connect(addr).await.unwrap();
+        let local = stream.local_addr().unwrap();
+        let (mut sender, conn) = client::handshake(TokioIo::new(stream)).await.unwrap();
+        tokio::spawn(conn);
+
+        let req = Request::builder()
+            .uri("/")
+            .header(hyper::header::HOST, addr.to_string())
+            .body(Empty::<Bytes>::new())
+            .unwrap();
+        let res = sender.send_request(req).await.unwrap();
+        let body = res.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(body, local.to_string());
+    }
+}
@@ -0,0 +1,223 @@
+use super::proxy::ClientAddr;
+use hyper::{Method, Request, StatusCode, Version};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, Sender, TryRecvError};
+use std::thread;
+use std::time::{Instant, SystemTime};
+
+/// The size an access log grows to before it is rotated, when the config file doesn't set
+/// `access_log_max_size`.
+pub const DEFAULT_ACCESS_LOG_MAX_SIZE: u64 = 10 * 1024 * 1024;
+
+/// How many rotated files (`access.log.1`, `access.log.2`, ...) are kept besides the
+/// current one.
+const KEPT_SEGMENTS: usize = 2;
+
+/// Writes one line per request to a file that rotates by size.
+/// Lines are handed to a background thread, so a slow disk never holds up a request.
+#[derive(Debug)]
+pub struct AccessLog {
+    lines: Sender<String>,
+}
+
+impl AccessLog {
+    /// Opens (or creates) the log at `path` and starts its writer thread.
+    pub fn open(path: &Path, max_size: u64) -> Result<Self, String> {
+        let file = RotatingFile::open(path, max_size).map_err(|e| {
+            format!(
+                "Could not open the access log '{}'.\n  Cause: {}",
+                path.display(),
+                e
+            )
+        })?;
+        let (lines, received) = mpsc::channel();
+        thread::Builder::new()
+            .name("access-log".into())
+            .spawn(move || write_lines(file, received))
+            .map_err(|e| format!("Could not start the access log writer.\n  Cause: {}", e))?;
+        Ok(AccessLog { lines })
+    }
+
+    pub fn write(&self, line: String) {
+        // the writer only stops if it failed, and has said so already
+        let _ = self.lines.send(line);
+    }
+}
+
+fn write_lines(mut file: RotatingFile, lines: Receiver<String>) {
+    while let Ok(line) = lines.recv() {
+        let mut result = file.write_line(&line);
+        // write whatever else is queued before flushing, so bursts share a write call
+        loop {
+            match lines.try_recv() {
+                Ok(line) => result = result.and_then(|_| file.write_line(&line)),
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => {
+                    let _ = file.flush();
+                    return;
+                }
+            }
+        }
+        if let Err(e) = result.and_then(|_| file.flush()) {
+            eprintln!(
+                "Could not write to the access log '{}'; access logging stops.\n  Cause: {}",
+                file.path.display(),
+                e
+            );
+            return;
+        }
+    }
+}
+
+/// A log file that is renamed to `<path>.1` once it would grow past `max_size`, shifting
+/// older segments up and dropping the oldest.
+struct RotatingFile {
+    path: PathBuf,
+    max_size: u64,
+    file: BufWriter<File>,
+    size: u64,
+}
+
+impl RotatingFile {
+    fn open(path: &Path, max_size: u64) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let size = file.metadata()?.len();
+        Ok(RotatingFile {
+            path: path.to_path_buf(),
+            max_size,
+            file: BufWriter::new(file),
+            size,
+        })
+    }
+
+    fn write_line(&mut self, line: &str) -> io::Result<()> {
+        let len = line.len() as u64 + 1;
+        if self.size > 0 && self.size + len > self.max_size {
+            self.rotate()?;
+        }
+        writeln!(self.file, "{}", line)?;
+        self.size += len;
+        Ok(())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+        for n in (1..KEPT_SEGMENTS).rev() {
+            let from = self.segment(n);
+            if from.exists() {
+                fs::rename(from, self.segment(n + 1))?;
+            }
+        }
+        fs::rename(&self.path, self.segment(1))?;
+        *self = RotatingFile::open(&self.path, self.max_size)?;
+        Ok(())
+    }
+
+    fn segment(&self, n: usize) -> PathBuf {
+        let mut name = self.path.clone().into_os_string();
+        name.push(format!(".{}", n));
+        PathBuf::from(name)
+    }
+}
+
+/// What the access log records about a request, captured when it arrives.
+pub struct AccessEntry {
+    client: Option<ClientAddr>,
+    method: Method,
+    target: String,
+    version: Version,
+    started: Instant,
+}
+
+impl AccessEntry {
+    pub fn new<B>(req: &Request<B>) -> Self {
+        AccessEntry {
+            client: req.extensions().get::<ClientAddr>().copied(),
+            method: req.method().clone(),
+            target: req
+                .uri()
+                .path_and_query()
+                .map_or("/", |target| target.as_str())
+                .to_string(),
+            version: req.version(),
+            started: Instant::now(),
+        }
+    }
+
+    /// Formats the log line, e.g.
+    /// `127.0.0.1 [Tue, 14 Oct 2025 09:30:00 GMT] "GET /api/users HTTP/1.1" 200 api 3ms`.
+    /// `route` is the matched route key, logged as `-` when nothing matched. The duration
+    /// runs until the response headers are ready; streaming the body isn't included.
+    pub fn finish(self, status: StatusCode, route: Option<&str>) -> String {
+        let client = self
+            .client
+            .map_or_else(|| "-".to_string(), |ClientAddr(addr)| addr.ip().to_string());
+        format!(
+            "{} [{}] \"{} {} {:?}\" {} {} {}ms",
+            client,
+            httpdate::fmt_http_date(SystemTime::now()),
+            self.method,
+            self.target,
+            self.version,
+            status.as_u16(),
+            route.unwrap_or("-"),
+            self.started.elapsed().as_millis()
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn writing_past_max_size_rotates_the_file() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("access.log");
+        let mut file = RotatingFile::open(&path, 20).unwrap();
+
+        for line in ["first line", "second line", "third line", "fourth line"] {
+            file.write_line(line).unwrap();
+        }
+        file.flush().unwrap();
+
+        assert_eq!(fs::read_to_string(&path).unwrap(), "fourth line\n");
+        assert_eq!(
+            fs::read_to_string(dir.path().join("access.log.1")).unwrap(),
+            "third line\n"
+        );
+        assert_eq!(
+            fs::read_to_string(dir.path().join("access.log.2")).unwrap(),
+            "second line\n"
+        );
+        // only `KEPT_SEGMENTS` old files are kept
+        assert!(!dir.path().join("access.log.3").exists());
+    }
+
+    #[test]
+    fn entry_formats_request_and_response() {
+        let mut req = Request::builder()
+            .method(Method::POST)
+            .uri("/api/users?page=2")
+            .body(())
+            .unwrap();
+        req.extensions_mut()
+            .insert(ClientAddr("127.0.0.1:51000".parse().unwrap()));
+
+        let line = AccessEntry::new(&req).finish(StatusCode::CREATED, Some("api"));
+        assert!(line.starts_with("127.0.0.1 ["));
+        assert!(line.contains("] \"POST /api/users?page=2 HTTP/1.1\" 201 api "));
+        assert!(line.ends_with("ms"));
+
+        let line = AccessEntry::new(&Request::new(())).finish(StatusCode::NOT_FOUND, None);
+        assert!(line.starts_with("- ["));
+        assert!(line.contains("\"GET / HTTP/1.1\" 404 - "));
+    }
+}
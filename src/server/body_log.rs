@@ -0,0 +1,427 @@
+use super::proxy::ProxyBody;
+use hyper::body::{Body, Bytes, Frame, SizeHint};
+use serde_json::Value;
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+
+/// How much of each body a `log_bodies` route logs when it doesn't set `log_body_limit`.
+pub const DEFAULT_LOG_BODY_LIMIT: usize = 4 * 1024;
+
+/// JSON fields whose values are replaced with `[redacted]` in logged bodies, matched
+/// case-insensitively at any depth.
+const REDACTED_FIELDS: [&str; 8] = [
+    "password",
+    "passwd",
+    "secret",
+    "token",
+    "access_token",
+    "refresh_token",
+    "api_key",
+    "authorization",
+];
+
+const REDACTED: &str = "[redacted]";
+
+/// Which way a logged body was going.
+#[derive(Debug, Clone, Copy)]
+pub enum Direction {
+    Request,
+    Response,
+}
+
+/// Formats a body of the route `key` for the log, e.g.
+/// `🔍 api request body (18 bytes): {"name":"lhp"}`, from its first bytes `head` and its
+/// full `size`. Only the first `limit` bytes are shown, with the values of JSON's
+/// `REDACTED_FIELDS` masked. Binary bodies, by their `content_type` or for not being UTF-8, are only
+/// described.
+pub fn format(
+    key: &str,
+    direction: Direction,
+    content_type: Option<&str>,
+    head: &[u8],
+    size: usize,
+    limit: usize,
+) -> String {
+    let direction = match direction {
+        Direction::Request => "request",
+        Direction::Response => "response",
+    };
+    if size == 0 {
+        return format!("🔍 {} {} body: (empty)", key, direction);
+    }
+    let head = &head[..head.len().min(limit)];
+    let cut = head.len() < size;
+    if !is_text(content_type, head, cut) {
+        return format!(
+            "🔍 {} {} body ({} bytes of {}, not shown)",
+            key,
+            direction,
+            size,
+            content_type.unwrap_or("binary data")
+        );
+    }
+
+    // a cut-off JSON body doesn't parse, so its fields are masked as they are found instead
+    let redacted = if cut { redact_head(head) } else { redact(head) };
+    let shown = redacted.as_deref().unwrap_or(head);
+    let text = String::from_utf8_lossy(shown);
+    let info = if size > limit {
+        format!("{} bytes, first {} shown", size, limit)
+    } else {
+        format!("{} bytes", size)
+    };
+    format!("🔍 {} {} body ({}): {}", key, direction, info, text)
+}
+
+/// Whether a body can be shown as text: its content type says so, or it has none and the
+/// body is UTF-8. When the body is `cut` off, a character split at the end still counts.
+fn is_text(content_type: Option<&str>, body: &[u8], cut: bool) -> bool {
+    let Some(content_type) = content_type else {
+        return match std::str::from_utf8(body) {
+            Ok(..) => true,
+            Err(e) => cut && e.error_len().is_none(),
+        };
+    };
+    let essence = content_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase();
+    essence.starts_with("text/")
+        || essence.ends_with("+json")
+        || essence.ends_with("+xml")
+        || matches!(
+            essence.as_str(),
+            "application/json"
+                | "application/xml"
+                | "application/javascript"
+                | "application/x-www-form-urlencoded"
+                | "application/graphql"
+        )
+}
+
+/// Writes a formatted body to stderr, next to the proxy's other diagnostics.
+pub fn write(line: &str) {
+    eprintln!("{}", line);
+    #[cfg(test)]
+    tests::LOGGED
+        .lock()
+        .unwrap_or_else(std::sync::PoisonError::into_inner)
+        .push(line.to_string());
+}
+
+/// A body that keeps its first `limit` bytes as they stream through, and logs them with
+/// `format` once it has ended. The body itself is passed on frame by frame, never buffered.
+pub struct LoggedBody {
+    inner: ProxyBody,
+    key: String,
+    direction: Direction,
+    content_type: Option<String>,
+    head: Vec<u8>,
+    size: usize,
+    limit: usize,
+    logged: bool,
+}
+
+impl LoggedBody {
+    pub fn new(
+        inner: ProxyBody,
+        key: &str,
+        direction: Direction,
+        content_type: Option<&str>,
+        limit: usize,
+    ) -> Self {
+        let mut body = LoggedBody {
+            inner,
+            key: key.to_string(),
+            direction,
+            content_type: content_type.map(str::to_string),
+            head: Vec::new(),
+            size: 0,
+            limit,
+            logged: false,
+        };
+        // an empty body may never be polled
+        if body.inner.is_end_stream() {
+            body.log();
+        }
+        body
+    }
+
+    fn log(&mut self) {
+        if !self.logged {
+            self.logged = true;
+            write(&format(
+                &self.key,
+                self.direction,
+                self.content_type.as_deref(),
+                &self.head,
+                self.size,
+                self.limit,
+            ));
+        }
+    }
+}
+
+impl Body for LoggedBody {
+    type Data = Bytes;
+    type Error = hyper::Error;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Bytes>, hyper::Error>>> {
+        let frame = ready!(Pin::new(&mut self.inner).poll_frame(cx));
+        match &frame {
+            Some(Ok(frame)) => {
+                if let Some(data) = frame.data_ref() {
+                    let this = &mut *self;
+                    this.size += data.len();
+                    let room = this.limit.saturating_sub(this.head.len());
+                    this.head.extend_from_slice(&data[..data.len().min(room)]);
+                }
+                if self.inner.is_end_stream() {
+                    self.log();
+                }
+            }
+            Some(Err(..)) => {}
+            None => self.log(),
+        }
+        Poll::Ready(frame)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+/// The body with its sensitive fields masked, if it is JSON that has any.
+fn redact(body: &[u8]) -> Option<Vec<u8>> {
+    let mut value: Value = serde_json::from_slice(body).ok()?;
+    if !mask(&mut value) {
+        return None;
+    }
+    serde_json::to_vec(&value).ok()
+}
+
+/// The first bytes of a body with the values of sensitive fields masked, if it looks like
+/// JSON and has any. Unlike `redact` it doesn't need the whole document: each string
+/// followed by `:` is read as a field name, and a sensitive field's value is skipped up to
+/// where it ends, or to the end of `head`.
+fn redact_head(head: &[u8]) -> Option<Vec<u8>> {
+    if !matches!(head.trim_ascii_start().first(), Some(b'{' | b'[')) {
+        return None;
+    }
+    let mut out = Vec::with_capacity(head.len());
+    let mut masked = false;
+    let mut i = 0;
+    while i < head.len() {
+        if head[i] != b'"' {
+            out.push(head[i]);
+            i += 1;
+            continue;
+        }
+        let end = string_end(head, i);
+        let field = &head[i..end];
+        out.extend_from_slice(field);
+        i = end;
+        let colon = i + head[i..]
+            .iter()
+            .take_while(|b| b.is_ascii_whitespace())
+            .count();
+        let sensitive = field.len() >= 2
+            && REDACTED_FIELDS
+                .iter()
+                .any(|name| field[1..field.len() - 1].eq_ignore_ascii_case(name.as_bytes()));
+        if !sensitive || head.get(colon) != Some(&b':') {
+            continue;
+        }
+        let value = colon
+            + 1
+            + head[colon + 1..]
+                .iter()
+                .take_while(|b| b.is_ascii_whitespace())
+                .count();
+        out.extend_from_slice(&head[i..value]);
+        out.extend_from_slice(format!("\"{}\"", REDACTED).as_bytes());
+        i = value_end(head, value);
+        masked = true;
+    }
+    masked.then_some(out)
+}
+
+/// Where the JSON string starting at `start` ends: past its closing quote, or at the end of
+/// `body` if it is cut off.
+fn string_end(body: &[u8], start: usize) -> usize {
+    let mut i = start + 1;
+    while i < body.len() {
+        match body[i] {
+            b'\\' => i += 2,
+            b'"' => return i + 1,
+            _ => i += 1,
+        }
+    }
+    body.len()
+}
+
+/// Where the JSON value starting at `start` ends, or the end of `body` if it is cut off.
+fn value_end(body: &[u8], start: usize) -> usize {
+    let mut depth = 0usize;
+    let mut i = start;
+    while i < body.len() {
+        match body[i] {
+            b'"' => {
+                i = string_end(body, i);
+                if depth == 0 {
+                    return i;
+                }
+                continue;
+            }
+            b'{' | b'[' => depth += 1,
+            b'}' | b']' if depth <= 1 => return if depth == 1 { i + 1 } else { i },
+            b'}' | b']' => depth -= 1,
+            b',' if depth == 0 => return i,
+            b if depth == 0 && b.is_ascii_whitespace() => return i,
+            _ => {}
+        }
+        i += 1;
+    }
+    body.len()
+}
+
+fn mask(value: &mut Value) -> bool {
+    match value {
+        Value::Object(map) => {
+            let mut masked = false;
+            for (field, value) in map.iter_mut() {
+                if REDACTED_FIELDS
+                    .iter()
+                    .any(|name| field.eq_ignore_ascii_case(name))
+                {
+                    *value = Value::String(REDACTED.to_string());
+                    masked = true;
+                } else {
+                    masked |= mask(value);
+                }
+            }
+            masked
+        }
+        Value::Array(items) => items
+            .iter_mut()
+            .fold(false, |masked, item| mask(item) | masked),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    /// Every line passed to `write` during the test run.
+    pub(crate) static LOGGED: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+    #[test]
+    fn bodies_are_truncated_at_the_limit() {
+        assert_eq!(
+            format("api", Direction::Request, None, b"hello", 5, 16),
+            "🔍 api request body (5 bytes): hello"
+        );
+        assert_eq!(
+            format("api", Direction::Response, None, b"hello", 11, 5),
+            "🔍 api response body (11 bytes, first 5 shown): hello"
+        );
+        assert_eq!(
+            format("api", Direction::Response, None, b"", 0, 5),
+            "🔍 api response body: (empty)"
+        );
+    }
+
+    #[test]
+    fn sensitive_json_fields_are_redacted() {
+        let body = br#"{"user":"ada","Password":"hunter2","nested":[{"token":"abc"}]}"#;
+        let line = format(
+            "auth",
+            Direction::Request,
+            Some("application/json"),
+            body,
+            body.len(),
+            1024,
+        );
+        assert!(line.ends_with(
+            r#"{"user":"ada","Password":"[redacted]","nested":[{"token":"[redacted]"}]}"#
+        ));
+        assert!(!line.contains("hunter2"));
+
+        // bodies without such fields are shown exactly as sent
+        let body = br#"{ "user": "ada" }"#;
+        assert!(format(
+            "auth",
+            Direction::Request,
+            Some("application/json"),
+            body,
+            body.len(),
+            1024
+        )
+        .ends_with(r#"{ "user": "ada" }"#));
+    }
+
+    #[test]
+    fn binary_bodies_are_described_instead_of_shown() {
+        let png = b"\x89PNG\r\n\x1a\n\0\0";
+        assert_eq!(
+            format("img", Direction::Response, Some("image/png"), png, 10, 1024),
+            "🔍 img response body (10 bytes of image/png, not shown)"
+        );
+        assert_eq!(
+            format("img", Direction::Request, None, &[0xff, 0xfe, 0], 3, 1024),
+            "🔍 img request body (3 bytes of binary data, not shown)"
+        );
+        // text types are shown even before the content type's parameters
+        assert_eq!(
+            format(
+                "api",
+                Direction::Request,
+                Some("Text/Plain; charset=utf-8"),
+                b"hi",
+                2,
+                1024
+            ),
+            "🔍 api request body (2 bytes): hi"
+        );
+    }
+
+    #[test]
+    fn cut_off_json_is_still_redacted() {
+        let body = br#"{"user":"ada","token": "abc123", "profile": {"api_key": ["k1", "k2"]}, "note": "hi"}"#;
+        let line = format(
+            "auth",
+            Direction::Request,
+            Some("application/json"),
+            &body[..60],
+            body.len(),
+            60,
+        );
+        assert_eq!(
+            line,
+            r#"🔍 auth request body (84 bytes, first 60 shown): {"user":"ada","token": "[redacted]", "profile": {"api_key": "[redacted]""#
+        );
+
+        // a sensitive value cut off part way is masked too
+        let line = format(
+            "auth",
+            Direction::Request,
+            None,
+            &body[..27],
+            body.len(),
+            27,
+        );
+        assert!(line.ends_with(r#"{"user":"ada","token": "[redacted]""#));
+        assert!(!line.contains("abc"));
+    }
+}
@@ -0,0 +1,210 @@
+use super::proxy::ProxyBody;
+use crate::config::{ClientCert, Route};
+use hyper_rustls::{FixedServerNameResolver, HttpsConnector, HttpsConnectorBuilder};
+use hyper_util::{
+    client::legacy::{connect::HttpConnector, Client},
+    rt::TokioExecutor,
+};
+use rustls::{
+    client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier},
+    crypto::{self, CryptoProvider},
+    pki_types::{pem::PemObject, CertificateDer, PrivateKeyDer, ServerName, UnixTime},
+    ClientConfig, DigitallySignedStruct, Error, RootCertStore, SignatureScheme,
+};
+use std::sync::{Arc, LazyLock};
+
+pub(crate) type UpstreamClient = Client<HttpsConnector<HttpConnector>, ProxyBody>;
+
+/// One client per combination of the route options that change how upstream connections
+/// are made, so routes with the same options share pooled connections.
+static CLIENTS: LazyLock<[UpstreamClient; 4]> = LazyLock::new(|| {
+    let shared = |http2, verify| {
+        build(http2, verify, None, None, true).expect("no client certificate to load")
+    };
+    [
+        shared(false, true),
+        shared(true, true),
+        shared(false, false),
+        shared(true, false),
+    ]
+});
+
+/// The shared client to forward a route's requests with, for routes without a client
+/// certificate or TLS server name that keep connections alive.
+pub(crate) fn for_route(route: &Route) -> &'static UpstreamClient {
+    let index = usize::from(route.http2) + 2 * usize::from(route.insecure_skip_verify);
+    &CLIENTS[index]
+}
+
+/// Builds a client of its own for a route that presents `cert` to its upstream, sends its
+/// `tls_server_name` or doesn't pool connections (`disable_keepalive`). Fails if the
+/// certificate or key can't be read or don't belong together, or if the server name is
+/// invalid.
+pub(crate) fn own_client(
+    route: &Route,
+    cert: Option<&ClientCert>,
+) -> Result<UpstreamClient, String> {
+    let identity = cert.map(Identity::load).transpose()?;
+    let server_name = route
+        .tls_server_name
+        .as_deref()
+        .map(|name| {
+            ServerName::try_from(name.to_string())
+                .map_err(|_| format!("Invalid tls_server_name \"{}\".", name))
+        })
+        .transpose()?;
+    build(
+        route.http2,
+        !route.insecure_skip_verify,
+        identity,
+        server_name,
+        !route.disable_keepalive,
+    )
+}
+
+/// A certificate chain and the private key that goes with it, read from PEM files.
+struct Identity {
+    chain: Vec<CertificateDer<'static>>,
+    key: PrivateKeyDer<'static>,
+}
+
+impl Identity {
+    fn load(cert: &ClientCert) -> Result<Self, String> {
+        let cert_path = shellexpand::tilde(&cert.cert);
+        let key_path = shellexpand::tilde(&cert.key);
+        let chain = CertificateDer::pem_file_iter(cert_path.as_ref())
+            .and_then(Iterator::collect::<Result<Vec<_>, _>>)
+            .map_err(|e| {
+                format!(
+                    "Could not read the client certificate '{}'.\n  Cause: {}",
+                    cert_path, e
+                )
+            })?;
+        if chain.is_empty() {
+            return Err(format!(
+                "The client certificate file '{}' holds no PEM certificate.",
+                cert_path
+            ));
+        }
+        let key = PrivateKeyDer::from_pem_file(key_path.as_ref()).map_err(|e| {
+            format!(
+                "Could not read the client key '{}'.\n  Cause: {}",
+                key_path, e
+            )
+        })?;
+        Ok(Identity { chain, key })
+    }
+}
+
+/// `server_name` replaces the target's host as the name sent in SNI and checked against the
+/// upstream's certificate. Without `keepalive`, no idle connection is kept for reuse.
+fn build(
+    http2: bool,
+    verify: bool,
+    identity: Option<Identity>,
+    server_name: Option<ServerName<'static>>,
+    keepalive: bool,
+) -> Result<UpstreamClient, String> {
+    let mut http = HttpConnector::new();
+    http.set_nodelay(true);
+    http.enforce_http(false);
+
+    let tls = HttpsConnectorBuilder::new()
+        .with_tls_config(tls_config(verify, identity)?)
+        .https_or_http();
+    let tls = match server_name {
+        Some(name) => tls.with_server_name_resolver(FixedServerNameResolver::new(name)),
+        None => tls,
+    };
+    // `http2` routes speak HTTP/2 from the first byte, so they offer only h2 over TLS too
+    let connector = if http2 {
+        tls.enable_http2().wrap_connector(http)
+    } else {
+        tls.enable_http1().wrap_connector(http)
+    };
+
+    let mut client = Client::builder(TokioExecutor::new());
+    client.http2_only(http2);
+    if !keepalive {
+        client.pool_max_idle_per_host(0);
+    }
+    Ok(client.build(connector))
+}
+
+fn tls_config(verify: bool, identity: Option<Identity>) -> Result<ClientConfig, String> {
+    let provider = Arc::new(crypto::ring::default_provider());
+    let builder = ClientConfig::builder_with_provider(provider.clone())
+        .with_safe_default_protocol_versions()
+        .expect("the ring provider supports the default protocol versions");
+    let builder = if verify {
+        builder.with_root_certificates(RootCertStore {
+            roots: webpki_roots::TLS_SERVER_ROOTS.to_vec(),
+        })
+    } else {
+        builder
+            .dangerous()
+            .with_custom_certificate_verifier(Arc::new(AcceptAnyCertificate(provider)))
+    };
+    match identity {
+        Some(identity) => builder
+            .with_client_auth_cert(identity.chain, identity.key)
+            .map_err(|e| {
+                format!(
+                    "The client certificate and key can't be used together.\n  Cause: {}",
+                    e
+                )
+            }),
+        None => Ok(builder.with_no_client_auth()),
+    }
+}
+
+/// Trusts whatever certificate the upstream presents, for routes with
+/// `insecure_skip_verify`. Handshake signatures are still checked, so the upstream must hold
+/// the key of the certificate it sends.
+#[derive(Debug)]
+struct AcceptAnyCertificate(Arc<CryptoProvider>);
+
+impl ServerCertVerifier for AcceptAnyCertificate {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, Error> {
+        crypto::verify_tls12_signature(
+            message,
+            cert,
+            dss,
+            &self.0.signature_verification_algorithms,
+        )
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, Error> {
+        crypto::verify_tls13_signature(
+            message,
+            cert,
+            dss,
+            &self.0.signature_verification_algorithms,
+        )
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.0.signature_verification_algorithms.supported_schemes()
+    }
+}
@@ -1,69 +1,637 @@
 use http_body_util::{combinators::BoxBody, BodyExt};
 use hyper::{
-    body::{Bytes, Incoming}, header::HOST, http, Request,
-    Response,
-    StatusCode,
-    Uri,
-};
-use hyper_util::{
-    client::legacy::{connect::HttpConnector, Client},
-    rt::TokioExecutor,
+    body::{Body, Bytes, Frame, Incoming, SizeHint},
+    header::{
+        HeaderName, HeaderValue, ALLOW, CONNECTION, CONTENT_TYPE, COOKIE, FORWARDED, HOST,
+        LOCATION, ORIGIN, PROXY_AUTHENTICATE, PROXY_AUTHORIZATION, RETRY_AFTER, SERVER, SET_COOKIE,
+        TE,
+    },
+    http::{self, uri::Authority},
+    HeaderMap, Method, Request, Response, StatusCode, Uri, Version,
 };
 use regex::Regex;
-use std::{collections::HashMap, convert::Infallible, sync::LazyLock};
+use std::{
+    convert::Infallible,
+    error::Error,
+    hash::{BuildHasher, DefaultHasher, Hash, Hasher, RandomState},
+    io,
+    net::{IpAddr, SocketAddr},
+    pin::Pin,
+    sync::{Arc, LazyLock},
+    task::{ready, Context, Poll},
+    time::{Duration, Instant, SystemTime},
+};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
 
-use crate::config::{AppConfig, ProxyMode};
+use super::access_log::AccessEntry;
+use super::body_log::{Direction, LoggedBody, DEFAULT_LOG_BODY_LIMIT};
+use super::router::Router;
+use super::static_files;
+use super::stats::{ConnectionCounters, CountingBody, StatsReport, STATS_PATH};
+use super::telemetry::RequestSpan;
+use crate::config::validation::{is_valid_label, LABEL_PATTERN};
+use crate::config::{
+    weighted_index, AppConfig, MissingHost, OriginHeader, ProxyMode, RedirectStatus, Route,
+    RouteKind, TrailingSlash, DEFAULT_MAINTENANCE_MESSAGE, WILDCARD_KEY,
+};
 
 #[derive(Debug, PartialEq, Eq)]
-struct HostAndPath {
+pub struct HostAndPath {
     host: String,
     path: String,
 }
 
-static LABEL_RE: LazyLock<Regex> =
-    LazyLock::new(|| Regex::new(r"^[a-z0-9](?:[a-z0-9-]*[a-z0-9])?$").unwrap());
+/// A resolved route together with where the request should be forwarded.
+#[derive(Debug)]
+pub struct Destination<'a> {
+    key: &'a str,
+    route: &'a Route,
+    upstream: HostAndPath,
+    // which of a multi-target route's instances `upstream` is
+    instance: Option<usize>,
+    // value for a new `lhp_sticky` cookie when the client has none yet
+    sticky_cookie: Option<String>,
+    // the host label matched by the Domain mode wildcard route
+    wildcard_label: Option<String>,
+}
+
+/// Why `get_destination` found no route for a request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RouteError {
+    /// The request carries nothing to route by, e.g. no Host in Domain mode or `/` in Path
+    /// mode.
+    NoKey,
+    /// The Host isn't a well-formed `host[:port]` (empty, control characters, a bad port).
+    MalformedHost,
+    /// The request names a key no route can have, e.g. `-api.localhost` or `/.well-known`.
+    InvalidKey,
+    /// The key is valid, but no route has it.
+    Unmapped,
+    /// The matched route is turned off with `enabled: false`.
+    Disabled,
+}
+
+/// The address of the client that opened the connection, stored in the request extensions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClientAddr(pub SocketAddr);
+
+/// Whether the client's connection was TLS, stored in the request extensions. Decides the
+/// scheme the upstream is told the request came in with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClientTls(pub bool);
+
+pub(crate) type ProxyBody = BoxBody<Bytes, hyper::Error>;
+
 static HOST_RE: LazyLock<Regex> = LazyLock::new(|| {
-    Regex::new(r"^(?P<key>[a-z0-9](?:[a-z0-9-]*[a-z0-9])?)\.[^:]+(?::\d+)?$").unwrap()
+    Regex::new(&format!(
+        r"^(?P<key>{LABEL_PATTERN})\.[^:]+(?::(?P<port>\d+))?$"
+    ))
+    .unwrap()
 });
+// the key is matched in any case here and lowercased before it is checked
 static PATH_RE: LazyLock<Regex> = LazyLock::new(|| {
-    Regex::new(
-        r"^/(?P<key>[A-Za-z0-9](?:[A-Za-z0-9-]*[A-Za-z0-9])?)(?P<rest>(?:/[^?]*)?(?:\?.*)?)?$",
-    )
+    Regex::new(&format!(
+        r"^/(?P<key>(?i:{LABEL_PATTERN}))(?P<rest>(?:/[^?]*)?(?:\?.*)?)?$"
+    ))
     .unwrap()
 });
 
-static CLIENT: LazyLock<Client<HttpConnector, Incoming>> = LazyLock::new(|| {
-    let mut http = HttpConnector::new();
-    http.set_nodelay(true);
-    Client::builder(TokioExecutor::new()).build(http)
-});
+/// Headers that only apply to the client's connection to the proxy, so they aren't forwarded.
+const HOP_BY_HOP_HEADERS: [&str; 7] = [
+    "connection",
+    "keep-alive",
+    "proxy-connection",
+    "te",
+    "trailer",
+    "transfer-encoding",
+    "upgrade",
+];
+
+/// Counts the proxies a request has passed through; each one adds 1 before forwarding.
+const X_LHP_HOPS: HeaderName = HeaderName::from_static("x-lhp-hops");
+const X_FORWARDED_FOR: HeaderName = HeaderName::from_static("x-forwarded-for");
+const X_FORWARDED_HOST: HeaderName = HeaderName::from_static("x-forwarded-host");
+const X_FORWARDED_PROTO: HeaderName = HeaderName::from_static("x-forwarded-proto");
+
+/// The longest `Host` matched against routes: a full DNS name (253) and a `:port`. Longer
+/// hosts can't be a route, so they are turned away before any regex runs on them.
+const MAX_HOST_LENGTH: usize = 253 + 6;
+
+/// The longest path (and query) matched against Path mode routes. Far above the default
+/// `max_uri_length`, it only bounds the regex when that limit is raised.
+const MAX_PATH_LENGTH: usize = 32 * 1024;
+
+/// The header carrying the host label to a Domain mode wildcard route's upstream.
+const DEFAULT_LABEL_HEADER: &str = "x-lhp-label";
+
+/// The cookie used to pin clients when a sticky route doesn't name its own.
+const STICKY_COOKIE: &str = "lhp_sticky";
+
+/// The methods the proxy forwards, sent in the `Allow` header of a `CONNECT` or `TRACE`
+/// request. Tunnels and traces aren't supported, so those two are turned away before routing.
+const SUPPORTED_METHODS: [&str; 7] = ["GET", "HEAD", "POST", "PUT", "DELETE", "PATCH", "OPTIONS"];
+
+/// The longest an instance is left out of rotation for a `Retry-After`, so a far-off date
+/// can't take it out for good.
+const MAX_RETRY_PAUSE: Duration = Duration::from_secs(60 * 60);
 
 pub async fn proxy_service(
-    req: Request<Incoming>,
-) -> Result<Response<BoxBody<Bytes, hyper::Error>>, Infallible> {
-    let config = AppConfig::instance();
-    let Some(destination) = get_destination(&req, &config.mode, &config.routes) else {
-        return Ok(not_found());
+    mut req: Request<Incoming>,
+    client_addr: SocketAddr,
+    is_tls: bool,
+    config: &AppConfig,
+) -> Result<Response<ProxyBody>, Infallible> {
+    req.extensions_mut().insert(ClientAddr(client_addr));
+    req.extensions_mut().insert(ClientTls(is_tls));
+    handle_request(req.map(BodyExt::boxed), config).await
+}
+
+async fn handle_request(
+    req: Request<ProxyBody>,
+    config: &AppConfig,
+) -> Result<Response<ProxyBody>, Infallible> {
+    let entry = config.access_log.as_ref().map(|_| AccessEntry::new(&req));
+    let routes = config.routes.load();
+    let (res, key) = route_request(req, config, &routes).await;
+    if let (Some(log), Some(entry)) = (&config.access_log, entry) {
+        log.write(entry.finish(res.status(), key));
+    }
+    Ok(res)
+}
+
+/// Answers a request, returning the key of the route it matched, if any.
+async fn route_request<'r>(
+    mut req: Request<ProxyBody>,
+    config: &AppConfig,
+    routes: &'r Router,
+) -> (Response<ProxyBody>, Option<&'r str>) {
+    if req.method() == Method::CONNECT || req.method() == Method::TRACE {
+        let allowed: Vec<String> = SUPPORTED_METHODS.map(String::from).into();
+        return (method_not_allowed(&allowed), None);
+    }
+    if request_target_len(req.uri()) > config.max_uri_length {
+        return (uri_too_long(), None);
+    }
+    // this proxy would be one hop too many
+    if hops(req.headers()) >= config.max_hops {
+        return (loop_detected(), None);
+    }
+
+    // the stats and the route table name every route, so they need the credentials too
+    if let Some(credentials) = &config.proxy_auth
+        && !proxy_authorized(req.headers(), credentials)
+    {
+        return (proxy_auth_required(), None);
+    }
+    if config.stats_endpoint && req.uri().path() == STATS_PATH {
+        return (
+            stats_response(routes, &config.connections, config.count_bytes),
+            None,
+        );
+    }
+    if config.routes_endpoint.as_deref() == Some(req.uri().path()) {
+        if req.method() != Method::GET && req.method() != Method::HEAD {
+            return (method_not_allowed(&["GET".into(), "HEAD".into()]), None);
+        }
+        return (routes_response(config.mode, routes), None);
+    }
+    if let Some(page) = config.maintenance_page() {
+        return (maintenance(page), None);
+    }
+    if config.root_index
+        && matches!(config.mode, ProxyMode::Path)
+        && req.uri().path() == "/"
+        && (req.method() == Method::GET || req.method() == Method::HEAD)
+    {
+        return (route_index(routes), None);
+    }
+
+    let http1 = req.version() < Version::HTTP_2;
+    if let Some(version) = config.upstream_http_version {
+        *req.version_mut() = version;
+    }
+    let span = RequestSpan::start(req.method(), req.uri().path(), req.headers());
+    let destination = get_destination(
+        &req,
+        &config.mode,
+        routes,
+        config.match_host_port,
+        config.missing_host.route(),
+    );
+    let (mut res, key) = match destination {
+        Ok(destination) => {
+            let key = destination.key;
+            let counters = routes.counters(key).filter(|_| config.count_bytes);
+            if let Some(counters) = counters {
+                req = req.map(|body| {
+                    CountingBody::new(body, counters.clone(), Direction::Request).boxed()
+                });
+            }
+            let mut res = forward(req, destination, config, routes, &span).await;
+            routes.record(key, res.status());
+            if let Some(counters) = counters {
+                res = res.map(|body| {
+                    CountingBody::new(body, counters.clone(), Direction::Response).boxed()
+                });
+            }
+            (res, Some(key))
+        }
+        Err(error) => (no_route(error, config), None),
     };
+    if let Some(server) = &config.server_header {
+        res.headers_mut().insert(SERVER, server.clone());
+    }
+    // HTTP/2 has no `Connection` header; its connections stay open
+    if config.connection_close && http1 {
+        res.headers_mut()
+            .insert(CONNECTION, HeaderValue::from_static("close"));
+    }
+    span.end(res.status());
+    (res, key)
+}
+
+async fn forward(
+    req: Request<ProxyBody>,
+    destination: Destination<'_>,
+    config: &AppConfig,
+    routes: &Router,
+    span: &RequestSpan,
+) -> Response<ProxyBody> {
+    let gateway_error = || with_status(bad_gateway(), config.bad_gateway_status);
+    let upstream = &destination.upstream;
+    span.record_route(destination.key, &upstream.host);
+
+    if destination.route.maintenance {
+        return maintenance(DEFAULT_MAINTENANCE_MESSAGE.to_string());
+    }
+    if !is_method_allowed(destination.route, req.method()) {
+        return method_not_allowed(&destination.route.allowed_methods);
+    }
+    if let Some(latency) = destination.route.latency_ms {
+        tokio::time::sleep(latency.delay()).await;
+    }
+
+    if destination.route.kind == RouteKind::Redirect {
+        return redirect(destination.route.redirect_status, upstream);
+    }
+    if let Some(root) = static_files::root(&upstream.host) {
+        return static_files::serve(&root, &upstream.path, req.method()).await;
+    }
 
-    let uri = match build_upstream_uri(&destination.host, &destination.path) {
+    let uri = match build_upstream_uri(&upstream.host, &upstream.path) {
         Some(u) => u,
-        None => return Ok(bad_gateway()),
+        None => return gateway_error(),
     };
 
-    let (parts, body) = req.into_parts();
-    let upstream_req = match build_upstream_request(parts, uri, body) {
+    let body_log_limit = destination.route.log_bodies.then(|| {
+        destination
+            .route
+            .log_body_limit
+            .unwrap_or(DEFAULT_LOG_BODY_LIMIT)
+    });
+    let (parts, mut body) = req.into_parts();
+    if let Some(limit) = body_log_limit {
+        body = LoggedBody::new(
+            body,
+            destination.key,
+            Direction::Request,
+            content_type(&parts.headers),
+            limit,
+        )
+        .boxed();
+    }
+    let mut upstream_req = match build_upstream_request(parts, uri, destination.route, body) {
         Some(r) => r,
-        None => return Ok(internal_error()),
+        None => return internal_error(),
+    };
+    span.inject(upstream_req.headers_mut());
+    let hops = hops(upstream_req.headers()).saturating_add(1);
+    upstream_req
+        .headers_mut()
+        .insert(X_LHP_HOPS, HeaderValue::from(hops));
+    if let Some(label) = &destination.wildcard_label {
+        let name = destination
+            .route
+            .label_header
+            .as_deref()
+            .unwrap_or(DEFAULT_LABEL_HEADER);
+        match (HeaderName::try_from(name), HeaderValue::from_str(label)) {
+            (Ok(name), Ok(value)) => {
+                upstream_req.headers_mut().insert(name, value);
+            }
+            _ => return internal_error(),
+        }
+    }
+
+    // an HTTP/2 client's request goes out over an HTTP/1.1 connection unless the route
+    // says otherwise
+    if !destination.route.http2 && upstream_req.version() == Version::HTTP_2 {
+        *upstream_req.version_mut() = Version::HTTP_11;
+    }
+    let permit = match routes.limit(&upstream.host) {
+        Some(slots) => match acquire_slot(slots, destination.route.queue_timeout_ms).await {
+            Some(permit) => Some(permit),
+            None => return service_unavailable(),
+        },
+        None => None,
+    };
+    let client = routes.client(destination.key, destination.route);
+    let follows = destination.route.follow_redirects.unwrap_or(0);
+    let mut followed = 0;
+    let res = loop {
+        // only requests without a body can be sent again
+        let replay = (followed < follows
+            && (upstream_req.method() == Method::GET || upstream_req.method() == Method::HEAD))
+            .then(|| replay_of(&upstream_req));
+        let current = upstream_req.uri().clone();
+        let res = match client.request(upstream_req).await {
+            Ok(r) => r,
+            Err(e) => {
+                let failure = UpstreamFailure::of(&e);
+                eprintln!(
+                    "{}",
+                    upstream_error(destination.key, &upstream.host, failure, &e)
+                );
+                return match failure.response() {
+                    Some(res) => res,
+                    None => gateway_error(),
+                };
+            }
+        };
+        match replay.zip(followed_location(&res, &current)) {
+            Some((mut next, location)) => {
+                *next.uri_mut() = location;
+                upstream_req = next;
+                followed += 1;
+            }
+            None => break res,
+        }
+    };
+
+    let (mut parts, body) = res.into_parts();
+    if parts.status == StatusCode::SERVICE_UNAVAILABLE
+        && let Some(index) = destination.instance
+        && let Some(delay) = retry_after(&parts.headers)
+    {
+        destination
+            .route
+            .rotation
+            .pause(index, Instant::now() + delay.min(MAX_RETRY_PAUSE));
+    }
+    if let Some(&status) = destination.route.status_map.get(parts.status.as_str())
+        && let Ok(status) = StatusCode::from_u16(status)
+    {
+        parts.status = status;
+    }
+    if destination.route.rewrite_location
+        && let Some(location) = parts.headers.get(LOCATION).and_then(|v| v.to_str().ok())
+        && let Some(location) = rewrite_location(
+            location,
+            destination.route.mode.unwrap_or(config.mode),
+            destination.key,
+            &upstream.host,
+        )
+        && let Ok(location) = HeaderValue::from_str(&location)
+    {
+        parts.headers.insert(LOCATION, location);
+    }
+    let body = UpstreamBody {
+        inner: body,
+        target: upstream.host.clone(),
+        received: 0,
+        _permit: permit,
     };
+    if let Some(value) = destination.sticky_cookie {
+        let cookie = format!("{STICKY_COOKIE}={value}; Path=/; HttpOnly");
+        if let Ok(cookie) = HeaderValue::from_str(&cookie) {
+            parts.headers.append(SET_COOKIE, cookie);
+        }
+    }
+    let Some(limit) = body_log_limit else {
+        return Response::from_parts(parts, body.boxed());
+    };
+    let body = LoggedBody::new(
+        body.boxed(),
+        destination.key,
+        Direction::Response,
+        content_type(&parts.headers),
+        limit,
+    );
+    Response::from_parts(parts, body.boxed())
+}
+
+/// A copy of a request without a body, to send again to where a redirect points.
+fn replay_of(req: &Request<ProxyBody>) -> Request<ProxyBody> {
+    let mut replay = Request::new(boxed_full(""));
+    *replay.method_mut() = req.method().clone();
+    *replay.version_mut() = req.version();
+    *replay.headers_mut() = req.headers().clone();
+    replay
+}
+
+/// Where a redirect from the upstream at `current` leads, if it stays on that upstream.
+fn followed_location<B>(res: &Response<B>, current: &Uri) -> Option<Uri> {
+    if !matches!(res.status().as_u16(), 301 | 302 | 303 | 307 | 308) {
+        return None;
+    }
+    let location = res.headers().get(LOCATION)?.to_str().ok()?;
+    let path = upstream_path(location, current)?;
+    let origin = format!("{}://{}", current.scheme_str()?, current.authority()?);
+    build_upstream_uri(&origin, &path)
+}
+
+/// The path and query of a `Location` that leads to the upstream `origin`: an absolute
+/// path, or a URL with the same scheme and host.
+fn upstream_path(location: &str, origin: &Uri) -> Option<String> {
+    if location.starts_with('/') && !location.starts_with("//") {
+        return Some(location.to_string());
+    }
+    let uri: Uri = location.parse().ok()?;
+    let same_host = uri
+        .authority()?
+        .as_str()
+        .eq_ignore_ascii_case(origin.authority()?.as_str());
+    if uri.scheme() != origin.scheme() || !same_host {
+        return None;
+    }
+    Some(
+        uri.path_and_query()
+            .map_or("/", |pq| pq.as_str())
+            .to_string(),
+    )
+}
 
-    let res = match CLIENT.request(upstream_req).await {
-        Ok(r) => r,
-        Err(_) => return Ok(bad_gateway()),
+/// `location` from a redirect of the route `key`, pointed back through the proxy when it
+/// leads to the route's `target`: `/login` becomes `/api/login` in Path mode, and
+/// `http://localhost:3000/login` becomes `/login` in Domain mode. Other locations, and
+/// those of Regex mode routes, whose paths can't be turned back, are left alone.
+fn rewrite_location(location: &str, mode: ProxyMode, key: &str, target: &str) -> Option<String> {
+    let target: Uri = target.parse().ok()?;
+    let path = upstream_path(location, &target)?;
+    // a target with a path, as `http://localhost:3000/app`, redirects below it
+    let base = target.path().trim_end_matches('/');
+    let rest = match path.strip_prefix(base) {
+        Some(rest) if !base.is_empty() && (rest.is_empty() || rest.starts_with(['/', '?'])) => rest,
+        _ => &path,
     };
+    let rest = if rest.starts_with('/') {
+        rest.to_string()
+    } else {
+        format!("/{rest}")
+    };
+    match mode {
+        ProxyMode::Domain => Some(rest),
+        ProxyMode::Path | ProxyMode::Prefix => Some(format!("/{key}{rest}")),
+        ProxyMode::Regex => None,
+    }
+}
+
+fn content_type(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+}
+
+/// Why a request couldn't be forwarded to its upstream, which picks the status the client
+/// gets: `504` for timeouts, `503` when this machine runs out of connections and `502`
+/// otherwise.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum UpstreamFailure {
+    Refused,
+    Dns,
+    TimedOut,
+    Reset,
+    Overloaded,
+    Connect,
+    Other,
+}
+
+impl UpstreamFailure {
+    fn of(e: &hyper_util::client::legacy::Error) -> Self {
+        let causes = causes(e);
+        let io_kind = causes
+            .iter()
+            .find_map(|cause| cause.downcast_ref::<io::Error>())
+            .map(io::Error::kind);
+        // the connector only says "dns error" for a failed lookup; its cause is opaque
+        let dns = causes.iter().any(|cause| cause.to_string() == "dns error");
+        Self::classify(io_kind, dns, e.is_connect())
+    }
+
+    fn classify(io_kind: Option<io::ErrorKind>, dns: bool, connect: bool) -> Self {
+        match io_kind {
+            _ if dns => UpstreamFailure::Dns,
+            Some(io::ErrorKind::ConnectionRefused) => UpstreamFailure::Refused,
+            Some(io::ErrorKind::TimedOut) => UpstreamFailure::TimedOut,
+            Some(io::ErrorKind::ConnectionReset | io::ErrorKind::ConnectionAborted) => {
+                UpstreamFailure::Reset
+            }
+            // no local port or memory left for another connection
+            Some(io::ErrorKind::AddrNotAvailable | io::ErrorKind::OutOfMemory) => {
+                UpstreamFailure::Overloaded
+            }
+            _ if connect => UpstreamFailure::Connect,
+            _ => UpstreamFailure::Other,
+        }
+    }
+
+    fn reason(self) -> &'static str {
+        match self {
+            UpstreamFailure::Refused => "connection refused",
+            UpstreamFailure::Dns => "DNS lookup failed",
+            UpstreamFailure::TimedOut => "timed out",
+            UpstreamFailure::Reset => "connection reset",
+            UpstreamFailure::Overloaded => "out of connections",
+            UpstreamFailure::Connect => "connection failed",
+            UpstreamFailure::Other => "request failed",
+        }
+    }
+
+    /// The response for a failure other than a bad gateway, whose status the config file may
+    /// change.
+    fn response(self) -> Option<Response<ProxyBody>> {
+        match self {
+            UpstreamFailure::TimedOut => Some(gateway_timeout()),
+            UpstreamFailure::Overloaded => Some(service_unavailable()),
+            _ => None,
+        }
+    }
+}
+
+/// The errors that led to `e`, outermost first.
+fn causes(e: &hyper_util::client::legacy::Error) -> Vec<&(dyn Error + 'static)> {
+    let mut causes = Vec::new();
+    let mut source = e.source();
+    while let Some(cause) = source {
+        causes.push(cause);
+        source = cause.source();
+    }
+    causes
+}
+
+/// Describes why a request of the route `key` couldn't be forwarded to `target`, e.g.
+/// `⚠️  Route 'api' could not reach http://localhost:3000: connection refused`, followed by
+/// the full error.
+fn upstream_error(
+    key: &str,
+    target: &str,
+    failure: UpstreamFailure,
+    e: &hyper_util::client::legacy::Error,
+) -> String {
+    let chain: Vec<String> = std::iter::once(e.to_string())
+        .chain(causes(e).iter().map(ToString::to_string))
+        .collect();
+    format!(
+        "⚠️  Route '{}' could not reach {}: {}\n  Cause: {}",
+        key,
+        target,
+        failure.reason(),
+        chain.join(": ")
+    )
+}
+
+/// An upstream response body on its way to the client.
+/// The status line and headers are already sent by the time it's read, so an upstream
+/// failing mid-stream can only be logged and end the client's response early.
+struct UpstreamBody {
+    inner: Incoming,
+    target: String,
+    received: u64,
+    // the target's in-flight slot, given back once the whole body is sent
+    _permit: Option<OwnedSemaphorePermit>,
+}
+
+impl Body for UpstreamBody {
+    type Data = Bytes;
+    type Error = hyper::Error;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Bytes>, hyper::Error>>> {
+        let frame = ready!(Pin::new(&mut self.inner).poll_frame(cx));
+        match &frame {
+            Some(Ok(frame)) => {
+                if let Some(data) = frame.data_ref() {
+                    self.received += data.len() as u64;
+                }
+            }
+            Some(Err(e)) => eprintln!(
+                "Upstream {} failed after sending {} bytes of the response body; the client got a truncated response.\n  Cause: {}",
+                self.target, self.received, e
+            ),
+            None => {}
+        }
+        Poll::Ready(frame)
+    }
 
-    let (parts, body) = res.into_parts();
-    Ok(Response::from_parts(parts, body.boxed()))
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
 }
 
 /// Determines the destination URL based on the request and proxy mode.
@@ -74,72 +642,460 @@ pub async fn proxy_service(
 /// - No `.` or `/`
 ///
 /// In Path mode: first path segment is the key.
-/// In Domain mode: host must be `routing-key.localdomain`.
-fn get_destination<B>(
+/// In Prefix mode: the longest key (e.g. `api/v2`) matching whole leading path segments wins.
+/// In Regex mode: the first key pattern matching the path wins; the route's `rewrite`
+/// template can rebuild the forwarded path from the captures.
+/// In Domain mode: host must be `routing-key.localdomain`. With `match_host_port`, a key
+/// including the Host port (`routing-key:8001`) is preferred over the bare key. A request
+/// without a host goes to the route of `missing_host_route`, when it is set.
+/// Routes setting their own `mode` mix the two: a route is only matched by a key extracted
+/// its way, the profile's mode first and the other one when that finds nothing.
+///
+/// Keys extracted from the request are lowercased in both modes, so `router` is expected
+/// to hold lowercase keys (`AppConfig` normalizes them at load). When nothing matches, the
+/// `RouteError` says why, so the caller can pick the status to answer with.
+pub fn get_destination<'r, B>(
     req: &Request<B>,
     mode: &ProxyMode,
-    mapping: &HashMap<String, String>,
-) -> Option<HostAndPath> {
-    let (route_key, path) = match mode {
-        ProxyMode::Domain => {
-            let key = extract_key_from_host(req)?;
-            let path = req
-                .uri()
-                .path_and_query()
-                .map(|pq| pq.as_str().to_string())
-                .unwrap_or_else(|| "/".to_string());
-            (key, path)
-        }
-        ProxyMode::Path => {
-            let caps = PATH_RE.captures(req.uri().path_and_query()?.as_str())?;
-            let key = caps.name("key")?.as_str().to_ascii_lowercase();
-            if !LABEL_RE.is_match(&key) {
-                return None;
+    router: &'r Router,
+    match_host_port: bool,
+    missing_host_route: Option<&str>,
+) -> Result<Destination<'r>, RouteError> {
+    let mut wildcard_label = None;
+    let (key, route, path) = match mode {
+        ProxyMode::Domain | ProxyMode::Path => {
+            let by = |extracted_by: ProxyMode| {
+                let found = match extracted_by {
+                    ProxyMode::Domain => {
+                        match_by_host(req, router, match_host_port, missing_host_route)
+                    }
+                    _ => match_by_path(req, router)
+                        .map(|(key, route, path)| (key, route, path, None)),
+                }?;
+                // a route matched by its own mode only
+                if found.1.mode.unwrap_or(*mode) == extracted_by {
+                    Ok(found)
+                } else {
+                    Err(RouteError::Unmapped)
+                }
+            };
+            let other = match mode {
+                ProxyMode::Domain => ProxyMode::Path,
+                _ => ProxyMode::Domain,
+            };
+            let (key, route, path, label) = match by(*mode) {
+                Err(e) if router.mixes_modes() => by(other).map_err(|_| e)?,
+                found => found?,
+            };
+            wildcard_label = label;
+            (key, route, path)
+        }
+        ProxyMode::Prefix => {
+            let pq = req.uri().path_and_query().ok_or(RouteError::NoKey)?;
+            let full_path = pq.path().strip_prefix('/').ok_or(RouteError::NoKey)?;
+            let (key, route) = router
+                .resolve_prefix(full_path)
+                .ok_or(RouteError::Unmapped)?;
+            let mut path = match &full_path[key.len()..] {
+                "" => "/".to_string(),
+                rest => rest.to_string(),
+            };
+            if let Some(query) = pq.query() {
+                path.push('?');
+                path.push_str(query);
             }
-            let mut path = caps.name("rest").map_or("/", |m| m.as_str()).to_string();
-            if path.is_empty() {
-                path = "/".to_string();
+            (key, route, path)
+        }
+        ProxyMode::Regex => {
+            let pq = req.uri().path_and_query().ok_or(RouteError::NoKey)?;
+            let (key, route, caps) = router
+                .resolve_pattern(pq.path())
+                .ok_or(RouteError::Unmapped)?;
+            let mut path = match &route.rewrite {
+                Some(template) => {
+                    let mut rewritten = String::new();
+                    caps.expand(template, &mut rewritten);
+                    if !rewritten.starts_with('/') {
+                        rewritten.insert(0, '/');
+                    }
+                    rewritten
+                }
+                None => pq.path().to_string(),
+            };
+            if let Some(query) = pq.query() {
+                path.push('?');
+                path.push_str(query);
             }
-            (key, path)
+            (key, route, path)
         }
     };
+    if !route.enabled {
+        return Err(RouteError::Disabled);
+    }
 
-    Some(HostAndPath {
-        host: mapping.get(&route_key)?.to_string(),
-        path,
+    let path = apply_trailing_slash(path, route.trailing_slash);
+    let selection = select_target(route, req.headers());
+    Ok(Destination {
+        key,
+        route,
+        upstream: HostAndPath {
+            host: selection.host.to_string(),
+            path,
+        },
+        instance: selection.instance,
+        sticky_cookie: selection.sticky_cookie,
+        wildcard_label,
     })
 }
 
-fn extract_key_from_host<B>(req: &Request<B>) -> Option<String> {
-    let host = req
-        .headers()
-        .get(HOST)?
-        .to_str()
-        .ok()?
-        .trim()
+/// Matches a request by its Host, as Domain mode does, falling back to the wildcard route
+/// (whose match returns the host label) and to `missing_host_route` for requests without a
+/// host.
+fn match_by_host<'r, B>(
+    req: &Request<B>,
+    router: &'r Router,
+    match_host_port: bool,
+    missing_host_route: Option<&str>,
+) -> Result<(&'r str, &'r Route, String, Option<String>), RouteError> {
+    let mode = &ProxyMode::Domain;
+    let mut wildcard_label = None;
+    let (key, route) = match (extract_key_from_host(req), missing_host_route) {
+        (Ok((key, port)), _) => {
+            let exact = port
+                .filter(|_| match_host_port)
+                .and_then(|port| router.resolve(mode, &format!("{key}:{port}")))
+                .or_else(|| router.resolve(mode, &key));
+            match exact {
+                Some(found) => found,
+                None => {
+                    wildcard_label = Some(key);
+                    router
+                        .resolve(mode, WILDCARD_KEY)
+                        .ok_or(RouteError::Unmapped)?
+                }
+            }
+        }
+        (Err(RouteError::NoKey), Some(fallback)) => router
+            .resolve(mode, &fallback.to_ascii_lowercase())
+            .ok_or(RouteError::Unmapped)?,
+        (Err(e), _) => return Err(e),
+    };
+    let path = req
+        .uri()
+        .path_and_query()
+        .map(|pq| pq.as_str().to_string())
+        .unwrap_or_else(|| "/".to_string());
+    Ok((key, route, path, wildcard_label))
+}
+
+/// Matches a request by the first segment of its path, as Path mode does, returning the
+/// rest of the path.
+fn match_by_path<'r, B>(
+    req: &Request<B>,
+    router: &'r Router,
+) -> Result<(&'r str, &'r Route, String), RouteError> {
+    let pq = req
+        .uri()
+        .path_and_query()
+        .ok_or(RouteError::NoKey)?
+        .as_str();
+    if pq == "/" || pq.starts_with("/?") {
+        return Err(RouteError::NoKey);
+    }
+    if pq.len() > MAX_PATH_LENGTH || !could_start_with_key(pq) {
+        return Err(RouteError::InvalidKey);
+    }
+    let caps = PATH_RE.captures(pq).ok_or(RouteError::InvalidKey)?;
+    let key = caps
+        .name("key")
+        .ok_or(RouteError::InvalidKey)?
+        .as_str()
         .to_ascii_lowercase();
+    if !is_valid_label(&key) {
+        return Err(RouteError::InvalidKey);
+    }
+    let mut path = caps.name("rest").map_or("/", |m| m.as_str()).to_string();
+    if path.is_empty() {
+        path = "/".to_string();
+    }
+    let (key, route) = router
+        .resolve(&ProxyMode::Path, &key)
+        .ok_or(RouteError::Unmapped)?;
+    Ok((key, route, path))
+}
 
-    if !host.chars().next()?.is_ascii_alphanumeric() {
-        return None;
+/// The target picked for a request.
+struct Selection<'a> {
+    host: &'a str,
+    // which of a multi-target route's instances `host` is
+    instance: Option<usize>,
+    // value for a new `lhp_sticky` cookie when the client has none yet
+    sticky_cookie: Option<String>,
+}
+
+impl<'a> Selection<'a> {
+    fn target(host: &'a str) -> Self {
+        Selection {
+            host,
+            instance: None,
+            sticky_cookie: None,
+        }
+    }
+}
+
+/// Picks the target of the first header rule whose headers all match, or the route's default.
+fn select_target<'a>(route: &'a Route, headers: &HeaderMap) -> Selection<'a> {
+    let rule = route.header_routes.iter().find(|rule| {
+        rule.headers
+            .iter()
+            .all(|(name, expected)| headers.get(name.as_str()).is_some_and(|v| v == expected))
+    });
+    match rule {
+        Some(rule) => Selection::target(&rule.target),
+        None => select_instance(route, headers),
+    }
+}
+
+/// Picks one of the route's instances, round-robin unless the route is sticky. Instances
+/// paused after a `503` with `Retry-After` are skipped while another one is available.
+/// Also returns the value of a new sticky cookie when one has to be handed out.
+fn select_instance<'a>(route: &'a Route, headers: &HeaderMap) -> Selection<'a> {
+    let instances: Vec<&str> = route.instances().collect();
+    if instances.len() == 1 {
+        return Selection::target(instances[0]);
+    }
+    let weights = route.instance_weights();
+
+    let pick = |index: usize, sticky_cookie| Selection {
+        host: instances[index],
+        instance: Some(index),
+        sticky_cookie,
+    };
+    // when every instance is paused, rotate as usual and let the upstream answer
+    let round_robin = || {
+        route
+            .rotation
+            .next_available(&weights)
+            .unwrap_or_else(|| route.rotation.next() % instances.len())
+    };
+    let Some(sticky) = &route.sticky else {
+        return pick(round_robin(), None);
+    };
+    let name = sticky.cookie.as_deref().unwrap_or(STICKY_COOKIE);
+    match find_cookie(headers, name) {
+        Some(value) => {
+            let pinned = sticky_index(value, &weights);
+            if route.rotation.is_paused(pinned) {
+                pick(round_robin(), None)
+            } else {
+                pick(pinned, None)
+            }
+        }
+        // the backend hasn't issued its session cookie yet
+        None if sticky.cookie.is_some() => pick(round_robin(), None),
+        None => {
+            let value = format!(
+                "{:016x}",
+                RandomState::new().hash_one(route.rotation.next())
+            );
+            pick(sticky_index(&value, &weights), Some(value))
+        }
+    }
+}
+
+/// Maps a cookie value to an instance, in proportion to the instances' weights; the same
+/// value always picks the same instance while the route's instances stay the same.
+fn sticky_index(value: &str, weights: &[u32]) -> usize {
+    let mut hasher = DefaultHasher::new();
+    value.hash(&mut hasher);
+    let total: u64 = weights.iter().map(|&weight| u64::from(weight)).sum();
+    weighted_index(weights, (hasher.finish() % total) as usize)
+}
+
+fn find_cookie<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers
+        .get_all(COOKIE)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(';'))
+        .filter_map(|pair| pair.trim().split_once('='))
+        .find(|(key, _)| *key == name)
+        .map(|(_, value)| value)
+}
+
+/// A cheap check that a Path mode target starts like `/<key>`, so requests like `/`,
+/// `//x` or `/.well-known` are rejected without running `PATH_RE`.
+fn could_start_with_key(path_and_query: &str) -> bool {
+    let bytes = path_and_query.as_bytes();
+    bytes.first() == Some(&b'/') && bytes.get(1).is_some_and(u8::is_ascii_alphanumeric)
+}
+
+/// Applies a route's trailing-slash policy to a forwarded path, leaving any query as it is.
+fn apply_trailing_slash(path: String, policy: TrailingSlash) -> String {
+    let (path_only, query) = match path.split_once('?') {
+        Some((p, q)) => (p, Some(q)),
+        None => (path.as_str(), None),
+    };
+    let normalized = match policy {
+        TrailingSlash::Keep => return path,
+        TrailingSlash::Add if path_only.ends_with('/') => return path,
+        TrailingSlash::Add => format!("{path_only}/"),
+        TrailingSlash::Strip => match path_only.trim_end_matches('/') {
+            "" => "/".to_string(),
+            trimmed => trimmed.to_string(),
+        },
+    };
+    match query {
+        Some(q) => format!("{normalized}?{q}"),
+        None => normalized,
+    }
+}
+
+/// Extracts the routing key and, if present, the port from the Host header.
+fn extract_key_from_host<B>(req: &Request<B>) -> Result<(String, Option<String>), RouteError> {
+    // HTTP/2 clients send the host as the `:authority` pseudo-header instead
+    let host = match req.headers().get(HOST) {
+        Some(host) => host.to_str().map_err(|_| RouteError::MalformedHost)?,
+        None => req.uri().authority().ok_or(RouteError::NoKey)?.as_str(),
+    }
+    .trim();
+    if host_is_malformed(host) {
+        return Err(RouteError::MalformedHost);
+    }
+    if host.len() > MAX_HOST_LENGTH {
+        return Err(RouteError::InvalidKey);
+    }
+    let host = host.to_ascii_lowercase();
+
+    if !host.starts_with(|c: char| c.is_ascii_alphanumeric()) {
+        return Err(RouteError::InvalidKey);
+    }
+
+    let caps = HOST_RE.captures(&host).ok_or(RouteError::InvalidKey)?;
+    let key = caps
+        .name("key")
+        .ok_or(RouteError::InvalidKey)?
+        .as_str()
+        .to_string();
+    if !is_valid_label(&key) {
+        return Err(RouteError::InvalidKey);
+    }
+    let port = caps.name("port").map(|m| m.as_str().to_string());
+    Ok((key, port))
+}
+
+/// Whether a (trimmed) host can't be parsed at all: empty, or not a `host[:port]` authority.
+fn host_is_malformed(host: &str) -> bool {
+    if host.is_empty() || host.contains('@') {
+        return true;
+    }
+    match host.parse::<Authority>() {
+        // whatever follows the host is a `:port`, which has to be a number
+        Ok(authority) => {
+            authority.as_str().len() > authority.host().len() && authority.port_u16().is_none()
+        }
+        Err(_) => true,
+    }
+}
+
+/// How many proxies the request has passed through, from `X-LHP-Hops`. A missing or
+/// unreadable count is 0.
+fn hops(headers: &HeaderMap) -> u32 {
+    headers
+        .get(X_LHP_HOPS)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.trim().parse().ok())
+        .unwrap_or(0)
+}
+
+/// Takes one of a target's in-flight slots, waiting up to `timeout_ms` for one to free up.
+/// Returns `None` if none is available in time.
+async fn acquire_slot(
+    slots: &Arc<Semaphore>,
+    timeout_ms: Option<u64>,
+) -> Option<OwnedSemaphorePermit> {
+    match timeout_ms {
+        None => slots.clone().try_acquire_owned().ok(),
+        Some(ms) => tokio::time::timeout(Duration::from_millis(ms), slots.clone().acquire_owned())
+            .await
+            .ok()?
+            .ok(),
+    }
+}
+
+/// Reads a `Retry-After` header given either as seconds or as an HTTP date.
+fn retry_after(headers: &HeaderMap) -> Option<Duration> {
+    let value = headers.get(RETRY_AFTER)?.to_str().ok()?.trim();
+    match value.parse::<u64>() {
+        Ok(seconds) => Some(Duration::from_secs(seconds)),
+        Err(_) => httpdate::parse_http_date(value)
+            .ok()?
+            .duration_since(SystemTime::now())
+            .ok(),
+    }
+}
+
+/// An empty `allowed_methods` list allows every method.
+fn is_method_allowed(route: &Route, method: &Method) -> bool {
+    route.allowed_methods.is_empty()
+        || route
+            .allowed_methods
+            .iter()
+            .any(|m| m.eq_ignore_ascii_case(method.as_str()))
+}
+
+/// The length of the request target as the client sent it, without allocating.
+fn request_target_len(uri: &Uri) -> usize {
+    let scheme = uri.scheme_str().map_or(0, |s| s.len() + "://".len());
+    let authority = uri.authority().map_or(0, |a| a.as_str().len());
+    let path = uri.path_and_query().map_or(0, |pq| pq.as_str().len());
+    scheme + authority + path
+}
+
+/// The response for a request no route matched. A Host that isn't even a well-formed
+/// authority is the client's mistake, so it gets 400 rather than 404.
+fn no_route(error: RouteError, config: &AppConfig) -> Response<BoxBody<Bytes, hyper::Error>> {
+    let domain = matches!(config.mode, ProxyMode::Domain);
+    match error {
+        RouteError::MalformedHost => bad_request(),
+        // only a missing Host leaves Domain mode without a key
+        RouteError::NoKey if domain && config.missing_host == MissingHost::BadRequest => {
+            bad_request()
+        }
+        RouteError::Unmapped if config.misdirected_unknown_hosts && domain => misdirected_request(),
+        RouteError::NoKey
+        | RouteError::InvalidKey
+        | RouteError::Unmapped
+        | RouteError::Disabled => with_status(not_found(), config.not_found_status),
     }
+}
 
-    let caps = HOST_RE.captures(&host)?;
-    Some(caps.name("key")?.as_str().to_string())
+/// `res` with its status replaced by one the config file sets.
+fn with_status(mut res: Response<ProxyBody>, status: StatusCode) -> Response<ProxyBody> {
+    *res.status_mut() = status;
+    res
 }
 
-fn boxed_full<T: Into<Bytes>>(data: T) -> BoxBody<Bytes, hyper::Error> {
+pub(super) fn boxed_full<T: Into<Bytes>>(data: T) -> BoxBody<Bytes, hyper::Error> {
     http_body_util::Full::new(data.into())
         .map_err(|never| match never {})
         .boxed()
 }
 
-fn not_found() -> Response<BoxBody<Bytes, hyper::Error>> {
+pub(super) fn not_found() -> Response<BoxBody<Bytes, hyper::Error>> {
     Response::builder()
         .status(StatusCode::NOT_FOUND)
         .body(boxed_full("Local Http Proxy: Route Not Found"))
         .unwrap()
 }
 
+fn bad_request() -> Response<BoxBody<Bytes, hyper::Error>> {
+    Response::builder()
+        .status(StatusCode::BAD_REQUEST)
+        .body(boxed_full("Local Http Proxy: Bad Request"))
+        .unwrap()
+}
+
 fn bad_gateway() -> Response<BoxBody<Bytes, hyper::Error>> {
     Response::builder()
         .status(StatusCode::BAD_GATEWAY)
@@ -147,75 +1103,424 @@ fn bad_gateway() -> Response<BoxBody<Bytes, hyper::Error>> {
         .unwrap()
 }
 
-fn internal_error() -> Response<BoxBody<Bytes, hyper::Error>> {
+fn service_unavailable() -> Response<BoxBody<Bytes, hyper::Error>> {
     Response::builder()
-        .status(StatusCode::INTERNAL_SERVER_ERROR)
-        .body(boxed_full("Local Http Proxy: Internal Error"))
+        .status(StatusCode::SERVICE_UNAVAILABLE)
+        .body(boxed_full("Local Http Proxy: Service Unavailable"))
         .unwrap()
 }
 
-fn build_upstream_uri(host: &str, path: &str) -> Option<Uri> {
-    let uri = format!("{}{}", host, path);
-    uri.parse().ok()
+fn gateway_timeout() -> Response<BoxBody<Bytes, hyper::Error>> {
+    Response::builder()
+        .status(StatusCode::GATEWAY_TIMEOUT)
+        .body(boxed_full("Local Http Proxy: Gateway Timeout"))
+        .unwrap()
 }
 
-fn build_upstream_request(
-    parts: http::request::Parts,
-    uri: Uri,
-    body: Incoming,
-) -> Option<Request<Incoming>> {
-    let mut builder = Request::builder()
-        .method(parts.method)
-        .version(parts.version)
-        .uri(uri);
-
-    if let Some(headers) = builder.headers_mut() {
-        for (k, v) in parts.headers.iter() {
-            if k != HOST {
-                headers.insert(k, v.clone());
-            }
-        }
-    } else {
-        return None;
+/// Sends the client to the target followed by the rest of the request path and its query.
+fn redirect(
+    status: RedirectStatus,
+    upstream: &HostAndPath,
+) -> Response<BoxBody<Bytes, hyper::Error>> {
+    let location = format!("{}{}", upstream.host, upstream.path);
+    match HeaderValue::from_str(&location) {
+        Ok(location) => Response::builder()
+            .status(status.code())
+            .header(LOCATION, location)
+            .body(boxed_full(""))
+            .unwrap(),
+        Err(_) => internal_error(),
     }
+}
 
-    builder.body(body).ok()
+fn uri_too_long() -> Response<BoxBody<Bytes, hyper::Error>> {
+    Response::builder()
+        .status(StatusCode::URI_TOO_LONG)
+        .body(boxed_full("Local Http Proxy: URI Too Long"))
+        .unwrap()
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use hyper::Request;
+/// Answers a request while maintenance mode is on, for every route or just its own.
+fn maintenance(page: String) -> Response<BoxBody<Bytes, hyper::Error>> {
+    Response::builder()
+        .status(StatusCode::SERVICE_UNAVAILABLE)
+        .header(CONTENT_TYPE, "text/plain; charset=utf-8")
+        .body(boxed_full(page))
+        .unwrap()
+}
 
-    fn mapping(pairs: &[(&str, &str)]) -> HashMap<String, String> {
-        pairs
-            .iter()
-            .map(|(k, v)| (k.to_string(), v.to_string()))
-            .collect()
-    }
+/// Whether `Proxy-Authorization` carries the Basic `credentials` of `proxy_auth`.
+fn proxy_authorized(headers: &HeaderMap, credentials: &str) -> bool {
+    headers
+        .get(PROXY_AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.trim().split_once(' '))
+        .is_some_and(|(scheme, sent)| {
+            scheme.eq_ignore_ascii_case("basic") && sent.trim() == credentials
+        })
+}
 
-    // --- Host (Domain) mode ---
+fn proxy_auth_required() -> Response<BoxBody<Bytes, hyper::Error>> {
+    Response::builder()
+        .status(StatusCode::PROXY_AUTHENTICATION_REQUIRED)
+        .header(PROXY_AUTHENTICATE, "Basic realm=\"local-http-proxy\"")
+        .body(boxed_full(
+            "Local Http Proxy: Proxy Authentication Required",
+        ))
+        .unwrap()
+}
 
-    #[test]
-    fn host_mode_key_dot_localdomain_ok() {
-        let req = Request::builder()
-            .uri("/v1/users?limit=10")
-            .header(HOST, "api.localhost:8080")
-            .body(())
-            .unwrap();
+fn loop_detected() -> Response<BoxBody<Bytes, hyper::Error>> {
+    Response::builder()
+        .status(StatusCode::LOOP_DETECTED)
+        .body(boxed_full("Local Http Proxy: Loop Detected"))
+        .unwrap()
+}
 
-        let map = mapping(&[("api", "http://upstream-api")]);
-        let got = get_destination(&req, &ProxyMode::Domain, &map).unwrap();
+fn misdirected_request() -> Response<BoxBody<Bytes, hyper::Error>> {
+    Response::builder()
+        .status(StatusCode::MISDIRECTED_REQUEST)
+        .body(boxed_full("Local Http Proxy: Misdirected Request"))
+        .unwrap()
+}
 
-        assert_eq!(
-            got,
-            HostAndPath {
+pub(super) fn method_not_allowed(allowed: &[String]) -> Response<BoxBody<Bytes, hyper::Error>> {
+    let allow = allowed
+        .iter()
+        .map(|m| m.to_ascii_uppercase())
+        .collect::<Vec<_>>()
+        .join(", ");
+    Response::builder()
+        .status(StatusCode::METHOD_NOT_ALLOWED)
+        .header(ALLOW, allow)
+        .body(boxed_full("Local Http Proxy: Method Not Allowed"))
+        .unwrap()
+}
+
+fn stats_response(
+    router: &Router,
+    connections: &ConnectionCounters,
+    bytes: bool,
+) -> Response<BoxBody<Bytes, hyper::Error>> {
+    let report = StatsReport {
+        routes: router.stats(bytes),
+        connections: connections.snapshot(),
+    };
+    match serde_json::to_vec(&report) {
+        Ok(json) => Response::builder()
+            .header(CONTENT_TYPE, "application/json")
+            .body(boxed_full(json))
+            .unwrap(),
+        Err(_) => internal_error(),
+    }
+}
+
+/// The route table as JSON, answered on the `routes_endpoint` path. Routes are listed in
+/// config order, with the same fields as in the config file.
+fn routes_response(mode: ProxyMode, router: &Router) -> Response<BoxBody<Bytes, hyper::Error>> {
+    let routes: serde_json::Map<String, serde_json::Value> = router
+        .iter()
+        .filter_map(|(key, route)| Some((key.to_string(), serde_json::to_value(route).ok()?)))
+        .collect();
+    let table = serde_json::json!({ "mode": mode, "routes": routes });
+    match serde_json::to_vec(&table) {
+        Ok(json) => Response::builder()
+            .header(CONTENT_TYPE, "application/json")
+            .body(boxed_full(json))
+            .unwrap(),
+        Err(_) => internal_error(),
+    }
+}
+
+/// A page linking every route, answered for `/` in Path mode with `root_index`.
+fn route_index(router: &Router) -> Response<BoxBody<Bytes, hyper::Error>> {
+    // glob keys like `api-*` name no single page to link, and disabled routes answer 404
+    let mut keys: Vec<&str> = router
+        .iter()
+        .filter(|(key, route)| !key.contains('*') && route.enabled)
+        .map(|(key, _)| key)
+        .collect();
+    keys.sort_unstable();
+    let mut page = String::from(
+        "<!DOCTYPE html>\n<html>\n<head><title>Local Http Proxy</title></head>\n<body>\n<h1>Routes</h1>\n<ul>\n",
+    );
+    for key in keys {
+        let key = escape_html(key);
+        page.push_str(&format!("<li><a href=\"/{key}/\">/{key}</a></li>\n"));
+    }
+    page.push_str("</ul>\n</body>\n</html>\n");
+    Response::builder()
+        .header(CONTENT_TYPE, "text/html; charset=utf-8")
+        .body(boxed_full(page))
+        .unwrap()
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn internal_error() -> Response<BoxBody<Bytes, hyper::Error>> {
+    Response::builder()
+        .status(StatusCode::INTERNAL_SERVER_ERROR)
+        .body(boxed_full("Local Http Proxy: Internal Error"))
+        .unwrap()
+}
+
+pub fn build_upstream_uri(host: &str, path: &str) -> Option<Uri> {
+    let uri = format!("{}{}", host, path);
+    uri.parse().ok()
+}
+
+/// Builds the forwarded request, moving the client's headers over instead of cloning them.
+/// `Host` is dropped so the client sets it from the upstream URI, unless `upstream_host`
+/// replaces it, along with the client's credentials for the proxy and the hop-by-hop headers
+/// that only apply to the client's connection.
+/// The original host, scheme and client address are passed on in forwarding headers.
+fn build_upstream_request<B>(
+    parts: http::request::Parts,
+    uri: Uri,
+    route: &Route,
+    body: B,
+) -> Option<Request<B>> {
+    let mut headers = parts.headers;
+    let host = headers.remove(HOST);
+    headers.remove(PROXY_AUTHORIZATION);
+    remove_hop_by_hop_headers(&mut headers, route.http2);
+    let client = parts.extensions.get::<ClientAddr>().copied();
+    let tls = parts
+        .extensions
+        .get::<ClientTls>()
+        .is_some_and(|ClientTls(tls)| *tls);
+    set_forwarding_headers(&mut headers, host.as_ref(), client, tls);
+    if let Some(upstream_host) = &route.upstream_host {
+        headers.insert(HOST, HeaderValue::from_str(upstream_host).ok()?);
+    }
+    match &route.origin {
+        OriginHeader::Keep => {}
+        OriginHeader::Strip => {
+            headers.remove(ORIGIN);
+        }
+        OriginHeader::Set(origin) if headers.contains_key(ORIGIN) => {
+            headers.insert(ORIGIN, HeaderValue::from_str(origin).ok()?);
+        }
+        OriginHeader::Set(_) => {}
+    }
+    // HTTP/2 has no `Connection` header; its client doesn't pool the connection either way
+    if route.disable_keepalive && !route.http2 {
+        headers.insert(CONNECTION, HeaderValue::from_static("close"));
+    }
+
+    let mut req = Request::builder()
+        .method(parts.method)
+        .version(parts.version)
+        .uri(uri)
+        .body(body)
+        .ok()?;
+    *req.headers_mut() = headers;
+    Some(req)
+}
+
+/// Drops `HOP_BY_HOP_HEADERS` and the headers the client's `Connection` lists as hop-by-hop
+/// (RFC 9110 §7.6.1). An `http2` upstream still gets `te: trailers`, the one `TE` HTTP/2
+/// allows, since gRPC servers refuse requests without it.
+fn remove_hop_by_hop_headers(headers: &mut HeaderMap, http2: bool) {
+    let te_trailers = http2
+        && headers
+            .get_all(TE)
+            .iter()
+            .filter_map(|v| v.to_str().ok())
+            .flat_map(|v| v.split(','))
+            .any(|coding| coding.trim().eq_ignore_ascii_case("trailers"));
+    let listed: Vec<HeaderName> = headers
+        .get_all(CONNECTION)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .filter_map(|name| HeaderName::from_bytes(name.trim().as_bytes()).ok())
+        .collect();
+    for name in listed {
+        headers.remove(name);
+    }
+    for name in HOP_BY_HOP_HEADERS {
+        headers.remove(name);
+    }
+    if te_trailers {
+        headers.insert(TE, HeaderValue::from_static("trailers"));
+    }
+}
+
+/// Tells the upstream how the client reached the proxy, with both the `X-Forwarded-*`
+/// headers and `Forwarded` (RFC 7239), since frameworks differ in which they read.
+/// `X-Forwarded-Proto` and `X-Forwarded-Host` describe this hop and replace what the client
+/// sent; the client address is appended to any `X-Forwarded-For` and `Forwarded` chain.
+fn set_forwarding_headers(
+    headers: &mut HeaderMap,
+    host: Option<&HeaderValue>,
+    client: Option<ClientAddr>,
+    tls: bool,
+) {
+    let proto = if tls { "https" } else { "http" };
+    headers.insert(X_FORWARDED_PROTO, HeaderValue::from_static(proto));
+    let mut forwarded = Vec::new();
+    if let Some(ClientAddr(addr)) = client {
+        let ip = addr.ip();
+        if let Ok(value) = HeaderValue::from_str(&ip.to_string()) {
+            headers.append(X_FORWARDED_FOR, value);
+        }
+        forwarded.push(match ip {
+            IpAddr::V4(ip) => format!("for={}", ip),
+            IpAddr::V6(ip) => format!("for=\"[{}]\"", ip),
+        });
+    }
+    match host {
+        Some(host) => {
+            headers.insert(X_FORWARDED_HOST, host.clone());
+            if let Ok(host) = host.to_str() {
+                forwarded.push(format!("host=\"{}\"", host.replace(['"', '\\'], "")));
+            }
+        }
+        None => {
+            headers.remove(X_FORWARDED_HOST);
+        }
+    }
+    forwarded.push(format!("proto={}", proto));
+    if let Ok(value) = HeaderValue::from_str(&forwarded.join(";")) {
+        headers.append(FORWARDED, value);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{HeaderRoute, StickySession};
+    use crate::server::body_log;
+    use hyper::Request;
+
+    fn upstream_for<B>(
+        req: &Request<B>,
+        mode: &ProxyMode,
+        router: &Router,
+        match_host_port: bool,
+    ) -> Result<HostAndPath, RouteError> {
+        get_destination(req, mode, router, match_host_port, None).map(|d| d.upstream)
+    }
+
+    fn mapping(pairs: &[(&str, &str)]) -> Router {
+        Router::new(
+            pairs
+                .iter()
+                .map(|(k, v)| (k.to_string(), Route::new(*v)))
+                .collect(),
+        )
+    }
+
+    // --- Host (Domain) mode ---
+
+    #[test]
+    fn host_mode_key_dot_localdomain_ok() {
+        let req = Request::builder()
+            .uri("/v1/users?limit=10")
+            .header(HOST, "api.localhost:8080")
+            .body(())
+            .unwrap();
+
+        let map = mapping(&[("api", "http://upstream-api")]);
+        let got = upstream_for(&req, &ProxyMode::Domain, &map, false).unwrap();
+
+        assert_eq!(
+            got,
+            HostAndPath {
                 host: "http://upstream-api".into(),
                 path: "/v1/users?limit=10".into()
             }
         );
     }
 
+    fn host_request(host: &str) -> Request<()> {
+        Request::builder()
+            .uri("/")
+            .header(HOST, host)
+            .body(())
+            .unwrap()
+    }
+
+    #[test]
+    fn host_mode_wildcard_matches_unmapped_labels() {
+        let map = mapping(&[("api", "http://api"), ("*", "http://previews")]);
+        let req = host_request("feature-x.preview.localhost:8000");
+        let got = get_destination(&req, &ProxyMode::Domain, &map, false, None).unwrap();
+        assert_eq!(got.key, "*");
+        assert_eq!(got.upstream.host, "http://previews");
+        assert_eq!(got.wildcard_label.as_deref(), Some("feature-x"));
+    }
+
+    #[test]
+    fn host_mode_exact_key_beats_wildcard() {
+        let map = mapping(&[("*", "http://previews"), ("api", "http://api")]);
+        let req = host_request("api.localhost");
+        let got = get_destination(&req, &ProxyMode::Domain, &map, false, None).unwrap();
+        assert_eq!(got.key, "api");
+        assert_eq!(got.upstream.host, "http://api");
+        assert!(got.wildcard_label.is_none());
+
+        // invalid labels never fall through to the wildcard
+        let req = host_request("-bad.localhost");
+        assert_eq!(
+            upstream_for(&req, &ProxyMode::Domain, &map, false),
+            Err(RouteError::InvalidKey)
+        );
+    }
+
+    #[test]
+    fn wildcard_is_domain_mode_only() {
+        let routes = [("*".to_string(), Route::new("http://previews"))];
+        let err = Router::for_mode(&ProxyMode::Path, routes.clone().into()).unwrap_err();
+        assert!(
+            err.contains("'*' alone is the wildcard of domain mode"),
+            "{err}"
+        );
+
+        // a route of its own domain mode keeps its wildcard out of path matching
+        let mut wildcard = Route::new("http://previews");
+        wildcard.mode = Some(ProxyMode::Domain);
+        let routes = [
+            ("*".to_string(), wildcard),
+            ("api".to_string(), Route::new("http://api")),
+        ];
+        let map = Router::for_mode(&ProxyMode::Path, routes.into()).unwrap();
+        let req = Request::builder().uri("/anything").body(()).unwrap();
+        assert_eq!(
+            upstream_for(&req, &ProxyMode::Path, &map, false),
+            Err(RouteError::Unmapped)
+        );
+    }
+
+    #[test]
+    fn path_mode_glob_routes_match_in_a_domain_profile() {
+        let mut glob = Route::new("http://any-api");
+        glob.mode = Some(ProxyMode::Path);
+        let routes = [
+            ("api-*".to_string(), glob),
+            ("web".to_string(), Route::new("http://web")),
+        ];
+        let map = Router::for_mode(&ProxyMode::Domain, routes.into()).unwrap();
+        let req = Request::builder()
+            .uri("/api-v1/x")
+            .header(HOST, "localhost")
+            .body(())
+            .unwrap();
+        assert_eq!(
+            upstream_for(&req, &ProxyMode::Domain, &map, false),
+            Ok(HostAndPath {
+                host: "http://any-api".into(),
+                path: "/x".into()
+            })
+        );
+    }
+
     #[test]
     fn host_mode_hyphen_edges_invalid() {
         for h in ["-api.local", "api-.local"] {
@@ -225,7 +1530,10 @@ mod tests {
                 .body(())
                 .unwrap();
             let map = mapping(&[("api", "http://x")]);
-            assert!(get_destination(&req, &ProxyMode::Domain, &map).is_none());
+            assert_eq!(
+                upstream_for(&req, &ProxyMode::Domain, &map, false),
+                Err(RouteError::InvalidKey)
+            );
         }
     }
 
@@ -238,7 +1546,10 @@ mod tests {
             .unwrap();
 
         let map = mapping(&[("api", "http://upstream")]);
-        assert!(get_destination(&req, &ProxyMode::Domain, &map).is_none());
+        assert_eq!(
+            upstream_for(&req, &ProxyMode::Domain, &map, false),
+            Err(RouteError::InvalidKey)
+        );
     }
 
     #[test]
@@ -250,52 +1561,2532 @@ mod tests {
             .unwrap();
 
         let map = mapping(&[("::1", "http://local-ipv6")]);
-        assert!(get_destination(&req, &ProxyMode::Domain, &map).is_none());
+        assert_eq!(
+            upstream_for(&req, &ProxyMode::Domain, &map, false),
+            Err(RouteError::InvalidKey)
+        );
     }
 
-    // --- Path mode ---
+    #[test]
+    fn host_mode_port_aware_keys_prefer_exact_port() {
+        let map = mapping(&[
+            ("api", "http://default-api"),
+            ("api:8001", "http://api-one"),
+            ("api:8002", "http://api-two"),
+        ]);
+        let dest = |host: &str| {
+            let req = Request::builder()
+                .uri("/")
+                .header(HOST, host)
+                .body(())
+                .unwrap();
+            upstream_for(&req, &ProxyMode::Domain, &map, true)
+                .map(|d| d.host)
+                .ok()
+        };
+
+        assert_eq!(
+            dest("api.localhost:8001").as_deref(),
+            Some("http://api-one")
+        );
+        assert_eq!(
+            dest("api.localhost:8002").as_deref(),
+            Some("http://api-two")
+        );
+        // unmapped ports and portless hosts fall back to the bare key
+        assert_eq!(
+            dest("api.localhost:9999").as_deref(),
+            Some("http://default-api")
+        );
+        assert_eq!(dest("api.localhost").as_deref(), Some("http://default-api"));
+    }
 
     #[test]
-    fn path_mode_basic_with_query() {
-        let req = Request::builder().uri("/svc/status?x=1").body(()).unwrap();
+    fn host_mode_port_agnostic_ignores_port_keys() {
+        let map = mapping(&[
+            ("api", "http://default-api"),
+            ("api:8001", "http://api-one"),
+        ]);
+        let req = Request::builder()
+            .uri("/")
+            .header(HOST, "api.localhost:8001")
+            .body(())
+            .unwrap();
+
+        let got = upstream_for(&req, &ProxyMode::Domain, &map, false).unwrap();
+        assert_eq!(got.host, "http://default-api");
+    }
+
+    // --- Prefix mode ---
+
+    #[test]
+    fn prefix_mode_longest_key_wins() {
+        let map = mapping(&[("api", "http://api-v1"), ("api/v2", "http://api-v2")]);
+        let dest = |uri: &str| {
+            let req = Request::builder().uri(uri).body(()).unwrap();
+            upstream_for(&req, &ProxyMode::Prefix, &map, false)
+        };
 
-        let map = mapping(&[("svc", "http://upstream-svc")]);
-        let got = get_destination(&req, &ProxyMode::Path, &map).unwrap();
         assert_eq!(
-            got,
+            dest("/api/v2/users?limit=5").unwrap(),
             HostAndPath {
-                host: "http://upstream-svc".into(),
-                path: "/status?x=1".into()
+                host: "http://api-v2".into(),
+                path: "/users?limit=5".into()
+            }
+        );
+        assert_eq!(
+            dest("/API/V2").unwrap(),
+            HostAndPath {
+                host: "http://api-v2".into(),
+                path: "/".into()
+            }
+        );
+        assert_eq!(
+            dest("/api/v1/users").unwrap(),
+            HostAndPath {
+                host: "http://api-v1".into(),
+                path: "/v1/users".into()
             }
         );
     }
 
     #[test]
-    fn path_mode_only_prefix_becomes_root() {
-        let req = Request::builder().uri("/svc").body(()).unwrap();
+    fn prefix_mode_matches_whole_segments_only() {
+        let map = mapping(&[("api", "http://api-v1"), ("api/v2", "http://api-v2")]);
+        let req = Request::builder().uri("/api/v2beta/x").body(()).unwrap();
+        let got = upstream_for(&req, &ProxyMode::Prefix, &map, false).unwrap();
+        assert_eq!(got.host, "http://api-v1");
+        assert_eq!(got.path, "/v2beta/x");
 
-        let map = mapping(&[("svc", "http://upstream-svc")]);
-        let got = get_destination(&req, &ProxyMode::Path, &map).unwrap();
+        let req = Request::builder().uri("/apis/v2").body(()).unwrap();
+        assert_eq!(
+            upstream_for(&req, &ProxyMode::Prefix, &map, false),
+            Err(RouteError::Unmapped)
+        );
+    }
+
+    // --- Regex mode ---
+
+    fn pattern_router(pairs: &[(&str, Route)]) -> Router {
+        let routes = pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.clone()))
+            .collect();
+        Router::for_mode(&ProxyMode::Regex, routes).unwrap()
+    }
+
+    #[test]
+    fn regex_mode_forwards_full_path_on_match() {
+        let router = pattern_router(&[(r"^/user-\d+/", Route::new("http://users"))]);
+        let req = Request::builder()
+            .uri("/user-42/orders?page=2")
+            .body(())
+            .unwrap();
+
+        let got = upstream_for(&req, &ProxyMode::Regex, &router, false).unwrap();
         assert_eq!(
             got,
             HostAndPath {
-                host: "http://upstream-svc".into(),
-                path: "/".into()
+                host: "http://users".into(),
+                path: "/user-42/orders?page=2".into()
             }
         );
     }
 
     #[test]
-    fn path_mode_root_is_none() {
-        let req = Request::builder().uri("/").body(()).unwrap();
-        let map = mapping(&[("svc", "http://upstream-svc")]);
-        assert!(get_destination(&req, &ProxyMode::Path, &map).is_none());
+    fn regex_mode_no_match_is_unmapped() {
+        let router = pattern_router(&[(r"^/user-\d+/", Route::new("http://users"))]);
+        let req = Request::builder().uri("/user-abc/orders").body(()).unwrap();
+        assert_eq!(
+            upstream_for(&req, &ProxyMode::Regex, &router, false),
+            Err(RouteError::Unmapped)
+        );
     }
 
     #[test]
-    fn path_mode_invalid_key_rejected() {
-        let req = Request::builder().uri("/-bad/users").body(()).unwrap();
-        let map = mapping(&[("-bad", "http://x")]);
-        assert!(get_destination(&req, &ProxyMode::Path, &map).is_none());
+    fn regex_mode_rewrites_path_from_captures() {
+        let mut route = Route::new("http://users");
+        route.rewrite = Some("/users/${id}$rest".into());
+        let router = pattern_router(&[(r"^/user-(?P<id>\d+)(?P<rest>/.*)?$", route)]);
+        let req = Request::builder()
+            .uri("/user-42/orders?page=2")
+            .body(())
+            .unwrap();
+
+        let got = upstream_for(&req, &ProxyMode::Regex, &router, false).unwrap();
+        assert_eq!(got.path, "/users/42/orders?page=2");
+    }
+
+    // --- Header rules ---
+
+    fn header_router() -> Router {
+        let mut route = Route::new("http://web");
+        route.header_routes = vec![
+            HeaderRoute {
+                headers: [("X-Client".to_string(), "mobile".to_string())].into(),
+                target: "http://mobile".into(),
+            },
+            HeaderRoute {
+                headers: [
+                    ("x-client".to_string(), "web".to_string()),
+                    ("x-beta".to_string(), "1".to_string()),
+                ]
+                .into(),
+                target: "http://web-beta".into(),
+            },
+        ];
+        Router::new([("api".to_string(), route)].into())
+    }
+
+    fn header_dest(headers: &[(&str, &str)]) -> String {
+        let mut builder = Request::builder().uri("/api/items");
+        for (name, value) in headers {
+            builder = builder.header(*name, *value);
+        }
+        let req = builder.body(()).unwrap();
+        upstream_for(&req, &ProxyMode::Path, &header_router(), false)
+            .unwrap()
+            .host
+    }
+
+    #[test]
+    fn header_rules_select_matching_target() {
+        assert_eq!(header_dest(&[("x-client", "mobile")]), "http://mobile");
+        assert_eq!(
+            header_dest(&[("x-client", "web"), ("x-beta", "1")]),
+            "http://web-beta"
+        );
+    }
+
+    #[test]
+    fn header_rules_fall_back_to_default_target() {
+        assert_eq!(header_dest(&[]), "http://web");
+        assert_eq!(header_dest(&[("x-client", "desktop")]), "http://web");
+        // rules are AND-combined, so a partial match falls back
+        assert_eq!(header_dest(&[("x-client", "web")]), "http://web");
+    }
+
+    // --- Multiple targets ---
+
+    fn pool_router(sticky: Option<StickySession>) -> Router {
+        let mut route = Route::new("http://a");
+        route.targets = vec!["http://b".into(), "http://c".into()];
+        route.sticky = sticky;
+        Router::new([("api".to_string(), route)].into())
+    }
+
+    fn pool_dest<'r>(router: &'r Router, cookie: Option<&str>) -> Destination<'r> {
+        let mut builder = Request::builder().uri("/api/items");
+        if let Some(cookie) = cookie {
+            builder = builder.header(COOKIE, cookie);
+        }
+        let req = builder.body(()).unwrap();
+        get_destination(&req, &ProxyMode::Path, router, false, None).unwrap()
+    }
+
+    #[test]
+    fn targets_rotate_round_robin() {
+        let router = pool_router(None);
+        let hosts: Vec<String> = (0..4)
+            .map(|_| pool_dest(&router, None).upstream.host)
+            .collect();
+        assert_eq!(hosts, ["http://a", "http://b", "http://c", "http://a"]);
+    }
+
+    #[test]
+    fn weighted_targets_get_their_share_of_requests() {
+        let mut route = Route::new("http://a");
+        route.targets = vec!["http://b".into(), "http://c".into()];
+        route.weights = vec![5, 3, 2];
+        let router = Router::new([("api".to_string(), route)].into());
+
+        let mut counts = std::collections::HashMap::new();
+        for _ in 0..1000 {
+            *counts
+                .entry(pool_dest(&router, None).upstream.host)
+                .or_insert(0) += 1;
+        }
+        assert_eq!(counts["http://a"], 500);
+        assert_eq!(counts["http://b"], 300);
+        assert_eq!(counts["http://c"], 200);
+
+        // weights that don't match the targets are refused
+        let mut route = Route::new("http://a");
+        route.weights = vec![1, 1];
+        let err =
+            Router::for_mode(&ProxyMode::Path, [("api".to_string(), route)].into()).unwrap_err();
+        assert!(err.contains("one weight of at least 1 for each of its 1 target(s)"));
+    }
+
+    #[test]
+    fn same_session_cookie_maps_to_same_target() {
+        let router = pool_router(Some(StickySession {
+            cookie: Some("session".into()),
+        }));
+        for session in ["abc", "def", "0123456789"] {
+            let cookie = format!("theme=dark; session={session}");
+            let first = pool_dest(&router, Some(&cookie));
+            assert!(first.sticky_cookie.is_none());
+            for _ in 0..5 {
+                let next = pool_dest(&router, Some(&cookie));
+                assert_eq!(next.upstream.host, first.upstream.host);
+            }
+        }
+    }
+
+    #[test]
+    fn sticky_cookie_is_handed_out_and_honored() {
+        let router = pool_router(Some(StickySession::default()));
+        let first = pool_dest(&router, None);
+        let value = first.sticky_cookie.expect("a new sticky cookie");
+
+        let cookie = format!("{STICKY_COOKIE}={value}");
+        for _ in 0..5 {
+            let next = pool_dest(&router, Some(&cookie));
+            assert_eq!(next.upstream.host, first.upstream.host);
+            assert!(next.sticky_cookie.is_none());
+        }
+    }
+
+    #[test]
+    fn header_rules_take_precedence_over_targets() {
+        let mut route = Route::new("http://a");
+        route.targets = vec!["http://b".into()];
+        route.header_routes = vec![HeaderRoute {
+            headers: [("x-client".to_string(), "mobile".to_string())].into(),
+            target: "http://mobile".into(),
+        }];
+        let router = Router::new([("api".to_string(), route)].into());
+        let req = Request::builder()
+            .uri("/api")
+            .header("x-client", "mobile")
+            .body(())
+            .unwrap();
+        for _ in 0..3 {
+            assert_eq!(
+                upstream_for(&req, &ProxyMode::Path, &router, false)
+                    .unwrap()
+                    .host,
+                "http://mobile"
+            );
+        }
+    }
+
+    // --- Trailing slash ---
+
+    fn slash_dest(policy: TrailingSlash, uri: &str) -> String {
+        let mut route = Route::new("http://svc");
+        route.trailing_slash = policy;
+        let router = Router::new([("svc".to_string(), route)].into());
+        let req = Request::builder().uri(uri).body(()).unwrap();
+        upstream_for(&req, &ProxyMode::Path, &router, false)
+            .unwrap()
+            .path
+    }
+
+    #[test]
+    fn trailing_slash_keep_forwards_path_as_is() {
+        assert_eq!(slash_dest(TrailingSlash::Keep, "/svc/foo"), "/foo");
+        assert_eq!(slash_dest(TrailingSlash::Keep, "/svc/foo/"), "/foo/");
+        assert_eq!(
+            slash_dest(TrailingSlash::Keep, "/svc/foo/?a=/b"),
+            "/foo/?a=/b"
+        );
+    }
+
+    #[test]
+    fn trailing_slash_add() {
+        assert_eq!(slash_dest(TrailingSlash::Add, "/svc/foo"), "/foo/");
+        assert_eq!(slash_dest(TrailingSlash::Add, "/svc/foo/"), "/foo/");
+        assert_eq!(slash_dest(TrailingSlash::Add, "/svc/foo?a=b"), "/foo/?a=b");
+        assert_eq!(
+            slash_dest(TrailingSlash::Add, "/svc/foo?next=/x"),
+            "/foo/?next=/x"
+        );
+        assert_eq!(slash_dest(TrailingSlash::Add, "/svc"), "/");
+    }
+
+    #[test]
+    fn trailing_slash_strip() {
+        assert_eq!(slash_dest(TrailingSlash::Strip, "/svc/foo/"), "/foo");
+        assert_eq!(slash_dest(TrailingSlash::Strip, "/svc/foo"), "/foo");
+        assert_eq!(
+            slash_dest(TrailingSlash::Strip, "/svc/foo/?a=b/"),
+            "/foo?a=b/"
+        );
+        assert_eq!(slash_dest(TrailingSlash::Strip, "/svc/"), "/");
+        assert_eq!(slash_dest(TrailingSlash::Strip, "/svc/?a=b"), "/?a=b");
+    }
+
+    // --- Upstream request ---
+
+    fn forwarded_request(client: &str, tls: Option<bool>) -> Request<()> {
+        let mut req = Request::builder()
+            .uri("/svc/items")
+            .header(HOST, "app.localhost:8000")
+            .header(X_FORWARDED_FOR, "10.0.0.1")
+            .header(X_FORWARDED_PROTO, "https")
+            .body(())
+            .unwrap();
+        req.extensions_mut()
+            .insert(ClientAddr(client.parse().unwrap()));
+        if let Some(tls) = tls {
+            req.extensions_mut().insert(ClientTls(tls));
+        }
+        let (parts, body) = req.into_parts();
+        let uri = "http://upstream:3000/items".parse().unwrap();
+        build_upstream_request(parts, uri, &Route::new("http://upstream:3000"), body).unwrap()
+    }
+
+    #[test]
+    fn forwarding_headers_follow_the_inbound_connection() {
+        let upstream = forwarded_request("127.0.0.1:51000", Some(false));
+        let headers = upstream.headers();
+        // the client's claim of https is replaced by what this connection was
+        assert_eq!(headers[X_FORWARDED_PROTO], "http");
+        assert_eq!(headers[X_FORWARDED_HOST], "app.localhost:8000");
+        let chain: Vec<_> = headers.get_all(X_FORWARDED_FOR).iter().collect();
+        assert_eq!(chain, ["10.0.0.1", "127.0.0.1"]);
+        assert_eq!(
+            headers[FORWARDED],
+            r#"for=127.0.0.1;host="app.localhost:8000";proto=http"#
+        );
+
+        let upstream = forwarded_request("[::1]:51000", Some(true));
+        let headers = upstream.headers();
+        assert_eq!(headers[X_FORWARDED_PROTO], "https");
+        assert_eq!(
+            headers[FORWARDED],
+            r#"for="[::1]";host="app.localhost:8000";proto=https"#
+        );
+
+        // requests that didn't come through `proxy_service` count as plain http
+        let upstream = forwarded_request("127.0.0.1:51000", None);
+        assert_eq!(upstream.headers()[X_FORWARDED_PROTO], "http");
+    }
+
+    #[test]
+    fn upstream_host_replaces_the_host_header() {
+        let req = Request::builder()
+            .uri("/svc/items")
+            .header(HOST, "app.localhost:8000")
+            .body(())
+            .unwrap();
+        let (parts, body) = req.into_parts();
+        let uri = "http://upstream:3000/items".parse().unwrap();
+        let mut route = Route::new("http://upstream:3000");
+        route.upstream_host = Some("tenant-a.example".into());
+        let upstream = build_upstream_request(parts, uri, &route, body).unwrap();
+        assert_eq!(upstream.headers()[HOST], "tenant-a.example");
+        // the client's host is still passed on
+        assert_eq!(upstream.headers()[X_FORWARDED_HOST], "app.localhost:8000");
+
+        // a host that can't be sent is refused when the routes load
+        route.upstream_host = Some("tenant\na".into());
+        let err =
+            Router::for_mode(&ProxyMode::Path, [("svc".to_string(), route)].into()).unwrap_err();
+        assert!(err.contains("Route 'svc' sets upstream_host"), "{err}");
+    }
+
+    #[test]
+    fn origin_is_stripped_or_replaced_per_route() {
+        let forwarded_origin = |origin: &str, sent: Option<&str>| {
+            let route: Route = serde_json::from_str(&format!(
+                r#"{{ "target": "http://upstream:3000", "origin": {origin} }}"#
+            ))
+            .unwrap();
+            let mut req = Request::builder().uri("/api/items");
+            if let Some(sent) = sent {
+                req = req.header(ORIGIN, sent);
+            }
+            let (parts, body) = req.body(()).unwrap().into_parts();
+            let uri = "http://upstream:3000/items".parse().unwrap();
+            let upstream = build_upstream_request(parts, uri, &route, body).unwrap();
+            upstream
+                .headers()
+                .get(ORIGIN)
+                .map(|value| value.to_str().unwrap().to_string())
+        };
+        let client = Some("http://localhost:5173");
+
+        assert_eq!(
+            forwarded_origin(r#""keep""#, client).as_deref(),
+            Some("http://localhost:5173")
+        );
+        assert_eq!(forwarded_origin(r#""strip""#, client), None);
+        let set = r#"{ "set": "http://localhost:3000" }"#;
+        assert_eq!(
+            forwarded_origin(set, client).as_deref(),
+            Some("http://localhost:3000")
+        );
+        // a same-origin request without the header doesn't get one
+        assert_eq!(forwarded_origin(set, None), None);
+    }
+
+    #[tokio::test]
+    async fn disable_keepalive_closes_upstream_connections() {
+        fn echo_connection(req: Request<Incoming>) -> Response<ProxyBody> {
+            let connection = req
+                .headers()
+                .get(CONNECTION)
+                .map_or("keep-alive", |value| value.to_str().unwrap());
+            Response::new(boxed_full(connection.to_string()))
+        }
+        let flaky = spawn_upstream_with(echo_connection).await;
+        let api = spawn_upstream_with(echo_connection).await;
+        let config = app_config(&format!(
+            r#"{{ "mode": "path", "routes": {{
+                "flaky": {{ "target": "{flaky}", "disable_keepalive": true }},
+                "api": "{api}"
+            }} }}"#
+        ));
+        for (path, connection) in [("/flaky", "close"), ("/api", "keep-alive")] {
+            let res = handle_request(request(Method::GET, path), &config)
+                .await
+                .unwrap();
+            let body = res.into_body().collect().await.unwrap().to_bytes();
+            assert_eq!(body, connection, "{path}");
+        }
+    }
+
+    #[tokio::test]
+    async fn upstream_host_is_sent_to_the_upstream() {
+        let upstream = spawn_upstream_with(|req| {
+            let host = req.headers()[HOST].to_str().unwrap().to_string();
+            Response::new(boxed_full(host))
+        })
+        .await;
+        let config = app_config(&format!(
+            r#"{{ "mode": "path", "routes": {{ "api": {{ "target": "{upstream}", "upstream_host": "tenant-a.example" }} }} }}"#
+        ));
+        let mut req = request(Method::GET, "/api");
+        req.headers_mut()
+            .insert(HOST, HeaderValue::from_static("localhost:8000"));
+        let res = handle_request(req, &config).await.unwrap();
+        let body = res.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(body, "tenant-a.example");
+    }
+
+    #[test]
+    fn upstream_request_preserves_end_to_end_headers() {
+        let req = Request::builder()
+            .method("POST")
+            .uri("/svc/items")
+            .header(HOST, "localhost:8000")
+            .header("accept", "text/html")
+            .header("accept", "application/json")
+            .header("x-request-id", "abc")
+            .header(CONNECTION, "keep-alive, x-hop")
+            .header("keep-alive", "timeout=5")
+            .header("x-hop", "1")
+            .header(PROXY_AUTHORIZATION, "Basic ZGV2OnNlY3JldA==")
+            .body(())
+            .unwrap();
+        let (parts, body) = req.into_parts();
+        let uri: Uri = "http://upstream:3000/items".parse().unwrap();
+
+        let route = Route::new("http://upstream:3000");
+        let upstream = build_upstream_request(parts, uri.clone(), &route, body).unwrap();
+
+        assert_eq!(upstream.method(), "POST");
+        assert_eq!(upstream.uri(), &uri);
+        let headers = upstream.headers();
+        let accept: Vec<_> = headers.get_all("accept").iter().collect();
+        assert_eq!(accept, ["text/html", "application/json"]);
+        assert_eq!(headers["x-request-id"], "abc");
+        for dropped in [
+            "host",
+            "connection",
+            "keep-alive",
+            "x-hop",
+            "proxy-authorization",
+        ] {
+            assert!(
+                !headers.contains_key(dropped),
+                "{dropped} should be dropped"
+            );
+        }
+    }
+
+    #[test]
+    fn te_trailers_reaches_http2_upstreams_only() {
+        let forwarded_te = |http2: bool| {
+            let req = Request::builder()
+                .uri("/grpc/svc.Echo/Say")
+                .header(CONNECTION, "te")
+                .header(TE, "trailers")
+                .body(())
+                .unwrap();
+            let (parts, body) = req.into_parts();
+            let mut route = Route::new("http://upstream:3000");
+            route.http2 = http2;
+            let uri = "http://upstream:3000/svc.Echo/Say".parse().unwrap();
+            let upstream = build_upstream_request(parts, uri, &route, body).unwrap();
+            upstream.headers().get(TE).cloned()
+        };
+        assert_eq!(forwarded_te(true).unwrap(), "trailers");
+        assert_eq!(forwarded_te(false), None);
+    }
+
+    // --- Path mode ---
+
+    #[test]
+    fn path_mode_basic_with_query() {
+        let req = Request::builder().uri("/svc/status?x=1").body(()).unwrap();
+
+        let map = mapping(&[("svc", "http://upstream-svc")]);
+        let got = upstream_for(&req, &ProxyMode::Path, &map, false).unwrap();
+        assert_eq!(
+            got,
+            HostAndPath {
+                host: "http://upstream-svc".into(),
+                path: "/status?x=1".into()
+            }
+        );
+    }
+
+    #[test]
+    fn path_mode_only_prefix_becomes_root() {
+        let req = Request::builder().uri("/svc").body(()).unwrap();
+
+        let map = mapping(&[("svc", "http://upstream-svc")]);
+        let got = upstream_for(&req, &ProxyMode::Path, &map, false).unwrap();
+        assert_eq!(
+            got,
+            HostAndPath {
+                host: "http://upstream-svc".into(),
+                path: "/".into()
+            }
+        );
+    }
+
+    #[test]
+    fn over_long_hosts_and_paths_are_not_matched() {
+        let map = mapping(&[("svc", "http://upstream-svc")]);
+        let host = format!("svc.{}.localhost", "a".repeat(MAX_HOST_LENGTH));
+        let req = host_request(&host);
+        assert_eq!(
+            upstream_for(&req, &ProxyMode::Domain, &map, false),
+            Err(RouteError::InvalidKey)
+        );
+        let req = host_request("svc.localhost");
+        assert!(upstream_for(&req, &ProxyMode::Domain, &map, false).is_ok());
+
+        let uri = format!("/svc/{}", "a".repeat(MAX_PATH_LENGTH));
+        let req = Request::builder().uri(uri).body(()).unwrap();
+        assert_eq!(
+            upstream_for(&req, &ProxyMode::Path, &map, false),
+            Err(RouteError::InvalidKey)
+        );
+    }
+
+    #[tokio::test]
+    async fn malformed_hosts_get_400_and_unmapped_ones_404() {
+        let config =
+            app_config(r#"{ "mode": "domain", "routes": { "api": "http://localhost:1" } }"#);
+        let status = |host: HeaderValue| {
+            let mut req = request(Method::GET, "/");
+            req.headers_mut().insert(HOST, host);
+            let config = &config;
+            async move { handle_request(req, config).await.unwrap().status() }
+        };
+
+        for host in [
+            "",
+            "  ",
+            "api\t.localhost",
+            "api.localhost:http",
+            "user@api.localhost",
+        ] {
+            let value = HeaderValue::from_str(host).unwrap();
+            assert_eq!(status(value).await, StatusCode::BAD_REQUEST, "{host:?}");
+        }
+        let host = HeaderValue::from_bytes(b"api.localhost\xff").unwrap();
+        assert_eq!(status(host).await, StatusCode::BAD_REQUEST);
+
+        // well-formed hosts without a route are still 404, including invalid keys
+        for host in ["web.localhost", "-api.localhost", "localhost:8000"] {
+            let value = HeaderValue::from_static(host);
+            assert_eq!(status(value).await, StatusCode::NOT_FOUND, "{host:?}");
+        }
+        let res = handle_request(request(Method::GET, "/"), &config)
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+
+        // Path mode doesn't look at the host
+        let config = app_config(r#"{ "mode": "path", "routes": { "api": "http://localhost:1" } }"#);
+        let mut req = request(Method::GET, "/web");
+        req.headers_mut().insert(HOST, HeaderValue::from_static(""));
+        let res = handle_request(req, &config).await.unwrap();
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn over_long_hosts_get_404() {
+        let config =
+            app_config(r#"{ "mode": "domain", "routes": { "svc": "http://localhost:1" } }"#);
+        let host = format!("svc.{}.localhost", "a".repeat(MAX_HOST_LENGTH));
+        let mut req = request(Method::GET, "/");
+        req.headers_mut().insert(HOST, host.parse().unwrap());
+        let res = handle_request(req, &config).await.unwrap();
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[test]
+    fn path_mode_root_has_no_key() {
+        let req = Request::builder().uri("/").body(()).unwrap();
+        let map = mapping(&[("svc", "http://upstream-svc")]);
+        assert_eq!(
+            upstream_for(&req, &ProxyMode::Path, &map, false),
+            Err(RouteError::NoKey)
+        );
+    }
+
+    #[test]
+    fn path_precheck_agrees_with_path_regex() {
+        for pq in [
+            "/",
+            "//x",
+            "/.well-known/x",
+            "/-bad",
+            "/_x",
+            "/?q",
+            "/%20",
+            "",
+            "api",
+            "/a",
+            "/api",
+            "/A1/x?y",
+            "/9/",
+            "/api?x",
+            "/a-/x",
+            "/é",
+        ] {
+            if PATH_RE.is_match(pq) {
+                assert!(could_start_with_key(pq), "{pq:?} would be skipped");
+            }
+        }
+        assert!(!could_start_with_key("/"));
+        assert!(!could_start_with_key("/.well-known"));
+        assert!(could_start_with_key("/api"));
+    }
+
+    #[test]
+    fn route_errors_tell_why_nothing_matched() {
+        let map = mapping(&[("svc", "http://upstream-svc")]);
+        let domain = |req: &Request<()>| upstream_for(req, &ProxyMode::Domain, &map, false);
+        let path = |uri: &str| {
+            let req = Request::builder().uri(uri).body(()).unwrap();
+            upstream_for(&req, &ProxyMode::Path, &map, false)
+        };
+
+        let no_host = Request::builder().uri("/").body(()).unwrap();
+        assert_eq!(domain(&no_host), Err(RouteError::NoKey));
+        assert_eq!(domain(&host_request("")), Err(RouteError::MalformedHost));
+        assert_eq!(
+            domain(&host_request("svc.localhost:port")),
+            Err(RouteError::MalformedHost)
+        );
+        assert_eq!(
+            domain(&host_request("svc-.localhost")),
+            Err(RouteError::InvalidKey)
+        );
+        assert_eq!(
+            domain(&host_request("web.localhost")),
+            Err(RouteError::Unmapped)
+        );
+        assert!(domain(&host_request("svc.localhost")).is_ok());
+
+        assert_eq!(path("/?page=2"), Err(RouteError::NoKey));
+        assert_eq!(path("/.well-known/x"), Err(RouteError::InvalidKey));
+        assert_eq!(path("/web/x"), Err(RouteError::Unmapped));
+        assert!(path("/svc/x").is_ok());
+    }
+
+    #[test]
+    fn path_mode_invalid_key_rejected() {
+        let req = Request::builder().uri("/-bad/users").body(()).unwrap();
+        let map = mapping(&[("-bad", "http://x")]);
+        assert_eq!(
+            upstream_for(&req, &ProxyMode::Path, &map, false),
+            Err(RouteError::InvalidKey)
+        );
+    }
+
+    // --- Allowed methods ---
+
+    fn app_config(json: &str) -> AppConfig {
+        use crate::config::{Args, ConfigFile};
+        use clap::Parser;
+
+        let file: ConfigFile = serde_json::from_str(json).unwrap();
+        let args = Args::try_parse_from(["local-http-proxy", "start"]).unwrap();
+        AppConfig::from_file("config.json".into(), file, &args).unwrap()
+    }
+
+    /// Serves a single connection that answers every request with `200 OK`.
+    async fn spawn_upstream() -> String {
+        spawn_upstream_with(|_req| Response::new(boxed_full("ok"))).await
+    }
+
+    /// Serves a single connection, answering every request with `respond`.
+    async fn spawn_upstream_with(respond: fn(Request<Incoming>) -> Response<ProxyBody>) -> String {
+        use hyper::{server::conn::http1, service::service_fn};
+        use hyper_util::rt::TokioIo;
+        use tokio::net::TcpListener;
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let service = service_fn(move |req: Request<Incoming>| async move {
+                Ok::<_, Infallible>(respond(req))
+            });
+            let _ = http1::Builder::new()
+                .serve_connection(TokioIo::new(stream), service)
+                .await;
+        });
+        format!("http://{addr}")
+    }
+
+    /// Serves HTTP/2 with prior knowledge on a single connection, echoing the request version.
+    async fn spawn_h2c_upstream() -> String {
+        use hyper::{server::conn::http2, service::service_fn};
+        use hyper_util::rt::{TokioExecutor, TokioIo};
+        use tokio::net::TcpListener;
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let service = service_fn(|req: Request<Incoming>| async move {
+                let body = format!("{:?} {}", req.version(), req.uri().path());
+                Ok::<_, Infallible>(Response::new(boxed_full(body)))
+            });
+            let _ = http2::Builder::new(TokioExecutor::new())
+                .serve_connection(TokioIo::new(stream), service)
+                .await;
+        });
+        format!("http://{addr}")
+    }
+
+    /// Serves HTTPS on a single connection with a self-signed certificate for `localhost`.
+    async fn spawn_self_signed_upstream() -> String {
+        use hyper::{server::conn::http1, service::service_fn};
+        use hyper_util::rt::TokioIo;
+        use rustls::pki_types::{PrivateKeyDer, PrivatePkcs8KeyDer};
+        use std::sync::Arc;
+        use tokio::net::TcpListener;
+
+        let certified = rcgen::generate_simple_self_signed(vec!["localhost".into()]).unwrap();
+        let key = PrivatePkcs8KeyDer::from(certified.signing_key.serialize_der());
+        let tls = rustls::ServerConfig::builder_with_provider(Arc::new(
+            rustls::crypto::ring::default_provider(),
+        ))
+        .with_safe_default_protocol_versions()
+        .unwrap()
+        .with_no_client_auth()
+        .with_single_cert(
+            vec![certified.cert.der().clone()],
+            PrivateKeyDer::Pkcs8(key),
+        )
+        .unwrap();
+        let acceptor = tokio_rustls::TlsAcceptor::from(Arc::new(tls));
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let Ok(stream) = acceptor.accept(stream).await else {
+                return;
+            };
+            let service = service_fn(|_req: Request<Incoming>| async {
+                Ok::<_, Infallible>(Response::new(boxed_full("secure")))
+            });
+            let _ = http1::Builder::new()
+                .serve_connection(TokioIo::new(stream), service)
+                .await;
+        });
+        format!("https://localhost:{port}")
+    }
+
+    /// Serves HTTPS on 127.0.0.1 for a single connection, answering with the server name the
+    /// client asked for (SNI), or `none`.
+    async fn spawn_sni_upstream() -> String {
+        use hyper::{server::conn::http1, service::service_fn};
+        use hyper_util::rt::TokioIo;
+        use rustls::pki_types::{PrivateKeyDer, PrivatePkcs8KeyDer};
+        use std::sync::Arc;
+        use tokio::net::TcpListener;
+
+        let certified = rcgen::generate_simple_self_signed(vec!["api.test".into()]).unwrap();
+        let key = PrivatePkcs8KeyDer::from(certified.signing_key.serialize_der());
+        let tls = rustls::ServerConfig::builder_with_provider(Arc::new(
+            rustls::crypto::ring::default_provider(),
+        ))
+        .with_safe_default_protocol_versions()
+        .unwrap()
+        .with_no_client_auth()
+        .with_single_cert(
+            vec![certified.cert.der().clone()],
+            PrivateKeyDer::Pkcs8(key),
+        )
+        .unwrap();
+        let acceptor = tokio_rustls::TlsAcceptor::from(Arc::new(tls));
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let Ok(stream) = acceptor.accept(stream).await else {
+                return;
+            };
+            let sni = stream
+                .get_ref()
+                .1
+                .server_name()
+                .unwrap_or("none")
+                .to_string();
+            let service = service_fn(move |_req: Request<Incoming>| {
+                let sni = sni.clone();
+                async move { Ok::<_, Infallible>(Response::new(boxed_full(sni))) }
+            });
+            let _ = http1::Builder::new()
+                .serve_connection(TokioIo::new(stream), service)
+                .await;
+        });
+        format!("https://{addr}")
+    }
+
+    /// Serves HTTPS on a single connection, answering only clients whose certificate is
+    /// signed by `ca`.
+    async fn spawn_mtls_upstream(ca: &rustls::pki_types::CertificateDer<'static>) -> String {
+        use hyper::{server::conn::http1, service::service_fn};
+        use hyper_util::rt::TokioIo;
+        use rustls::{
+            pki_types::{PrivateKeyDer, PrivatePkcs8KeyDer},
+            server::WebPkiClientVerifier,
+            RootCertStore,
+        };
+        use std::sync::Arc;
+        use tokio::net::TcpListener;
+
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let mut roots = RootCertStore::empty();
+        roots.add(ca.clone()).unwrap();
+        let verifier = WebPkiClientVerifier::builder_with_provider(roots.into(), provider.clone())
+            .build()
+            .unwrap();
+        let certified = rcgen::generate_simple_self_signed(vec!["localhost".into()]).unwrap();
+        let key = PrivatePkcs8KeyDer::from(certified.signing_key.serialize_der());
+        let tls = rustls::ServerConfig::builder_with_provider(provider)
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_client_cert_verifier(verifier)
+            .with_single_cert(
+                vec![certified.cert.der().clone()],
+                PrivateKeyDer::Pkcs8(key),
+            )
+            .unwrap();
+        let acceptor = tokio_rustls::TlsAcceptor::from(Arc::new(tls));
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let Ok(stream) = acceptor.accept(stream).await else {
+                return;
+            };
+            let service = service_fn(|_req: Request<Incoming>| async {
+                Ok::<_, Infallible>(Response::new(boxed_full("mutual")))
+            });
+            let _ = http1::Builder::new()
+                .serve_connection(TokioIo::new(stream), service)
+                .await;
+        });
+        format!("https://localhost:{port}")
+    }
+
+    fn request(method: Method, uri: &str) -> Request<ProxyBody> {
+        Request::builder()
+            .method(method)
+            .uri(uri)
+            .body(boxed_full(""))
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn http2_route_forwards_to_h2c_upstream() {
+        let upstream = spawn_h2c_upstream().await;
+        let config = app_config(&format!(
+            r#"{{ "mode": "path", "routes": {{ "grpc": {{ "target": "{upstream}", "http2": true }} }} }}"#
+        ));
+
+        let res = handle_request(request(Method::GET, "/grpc/svc.Echo/Say"), &config)
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let body = res.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(body, "HTTP/2.0 /svc.Echo/Say");
+    }
+
+    #[tokio::test]
+    async fn http2_request_reaches_http1_upstream() {
+        let upstream =
+            spawn_upstream_with(|req| Response::new(boxed_full(format!("{:?}", req.version()))))
+                .await;
+        let config = app_config(&format!(
+            r#"{{ "mode": "domain", "routes": {{ "api": "{upstream}" }} }}"#
+        ));
+        // as an h2c client sends it: absolute URI, no Host header
+        let req = Request::builder()
+            .version(Version::HTTP_2)
+            .uri("http://api.localhost:8000/users")
+            .body(boxed_full(""))
+            .unwrap();
+
+        let res = handle_request(req, &config).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let body = res.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(body, "HTTP/1.1");
+    }
+
+    #[tokio::test]
+    async fn self_signed_upstream_needs_insecure_skip_verify() {
+        let upstream = spawn_self_signed_upstream().await;
+        let config = app_config(&format!(
+            r#"{{ "mode": "path", "routes": {{ "api": "{upstream}" }} }}"#
+        ));
+        let res = handle_request(request(Method::GET, "/api"), &config)
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::BAD_GATEWAY);
+
+        let upstream = spawn_self_signed_upstream().await;
+        let config = app_config(&format!(
+            r#"{{ "mode": "path", "routes": {{
+                "api": {{ "target": "{upstream}", "insecure_skip_verify": true }}
+            }} }}"#
+        ));
+        let res = handle_request(request(Method::GET, "/api"), &config)
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let body = res.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(body, "secure");
+    }
+
+    #[tokio::test]
+    async fn tls_server_name_is_sent_to_ip_targets() {
+        use clap::Parser;
+
+        for (tls_server_name, sni) in [
+            (r#", "tls_server_name": "api.test""#, "api.test"),
+            ("", "none"),
+        ] {
+            let upstream = spawn_sni_upstream().await;
+            let config = app_config(&format!(
+                r#"{{ "mode": "path", "routes": {{
+                    "api": {{ "target": "{upstream}", "insecure_skip_verify": true {tls_server_name} }}
+                }} }}"#
+            ));
+            let res = handle_request(request(Method::GET, "/api"), &config)
+                .await
+                .unwrap();
+            assert_eq!(res.status(), StatusCode::OK);
+            let body = res.into_body().collect().await.unwrap().to_bytes();
+            assert_eq!(body, sni);
+        }
+
+        let file: crate::config::ConfigFile = serde_json::from_str(
+            r#"{ "routes": { "api": { "target": "https://10.0.0.1", "tls_server_name": "bad name" } } }"#,
+        )
+        .unwrap();
+        let args = crate::config::Args::try_parse_from(["local-http-proxy", "start"]).unwrap();
+        let err = AppConfig::from_file("config.json".into(), file, &args).unwrap_err();
+        assert_eq!(err, r#"Route 'api': Invalid tls_server_name "bad name"."#);
+    }
+
+    #[tokio::test]
+    async fn client_cert_is_presented_to_upstream() {
+        use rcgen::{BasicConstraints, CertificateParams, CertifiedIssuer, IsCa, KeyPair};
+
+        let mut ca_params = CertificateParams::new(Vec::new()).unwrap();
+        ca_params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        let ca = CertifiedIssuer::self_signed(ca_params, KeyPair::generate().unwrap()).unwrap();
+        let client_key = KeyPair::generate().unwrap();
+        let client_cert = CertificateParams::new(vec!["dev-client".into()])
+            .unwrap()
+            .signed_by(&client_key, &ca)
+            .unwrap();
+        let dir = tempfile::TempDir::new().unwrap();
+        let cert_path = dir.path().join("client.pem");
+        let key_path = dir.path().join("client.key");
+        std::fs::write(&cert_path, client_cert.pem()).unwrap();
+        std::fs::write(&key_path, client_key.serialize_pem()).unwrap();
+
+        let upstream = spawn_mtls_upstream(ca.der()).await;
+        let config = app_config(&format!(
+            r#"{{ "mode": "path", "routes": {{ "api": {{ "target": "{upstream}", "insecure_skip_verify": true }} }} }}"#
+        ));
+        let res = handle_request(request(Method::GET, "/api"), &config)
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::BAD_GATEWAY);
+
+        let upstream = spawn_mtls_upstream(ca.der()).await;
+        let config = app_config(&format!(
+            r#"{{ "mode": "path", "routes": {{ "api": {{
+                "target": "{upstream}", "insecure_skip_verify": true,
+                "client_cert": {{ "cert": {:?}, "key": {:?} }}
+            }} }} }}"#,
+            cert_path, key_path
+        ));
+        let res = handle_request(request(Method::GET, "/api"), &config)
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let body = res.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(body, "mutual");
+    }
+
+    #[tokio::test]
+    async fn upstream_http_version_replaces_client_version() {
+        let echo_version =
+            |req: Request<Incoming>| Response::new(boxed_full(format!("{:?}", req.version())));
+        let http10_request = || {
+            Request::builder()
+                .version(Version::HTTP_10)
+                .uri("/api")
+                .body(boxed_full(""))
+                .unwrap()
+        };
+
+        let upstream = spawn_upstream_with(echo_version).await;
+        let config = app_config(&format!(
+            r#"{{ "mode": "path", "upstream_http_version": "1.1", "routes": {{ "api": "{upstream}" }} }}"#
+        ));
+        let res = handle_request(http10_request(), &config).await.unwrap();
+        let body = res.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(body, "HTTP/1.1");
+
+        let upstream = spawn_upstream_with(echo_version).await;
+        let config = app_config(&format!(
+            r#"{{ "mode": "path", "routes": {{ "api": "{upstream}" }} }}"#
+        ));
+        let res = handle_request(http10_request(), &config).await.unwrap();
+        let body = res.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(body, "HTTP/1.0");
+    }
+
+    #[tokio::test]
+    async fn retry_after_503_takes_instance_out_of_rotation() {
+        let busy = spawn_upstream_with(|_req| {
+            let mut res = Response::new(boxed_full("busy"));
+            *res.status_mut() = StatusCode::SERVICE_UNAVAILABLE;
+            res.headers_mut()
+                .insert(RETRY_AFTER, HeaderValue::from_static("60"));
+            res
+        })
+        .await;
+        let healthy = spawn_upstream_with(|_req| Response::new(boxed_full("healthy"))).await;
+        let config = app_config(&format!(
+            r#"{{ "mode": "path", "routes": {{ "api": {{ "target": "{busy}", "targets": ["{healthy}"] }} }} }}"#
+        ));
+
+        let res = handle_request(request(Method::GET, "/api"), &config)
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::SERVICE_UNAVAILABLE);
+        for _ in 0..4 {
+            let res = handle_request(request(Method::GET, "/api"), &config)
+                .await
+                .unwrap();
+            let body = res.into_body().collect().await.unwrap().to_bytes();
+            assert_eq!(body, "healthy");
+        }
+    }
+
+    #[test]
+    fn retry_after_accepts_seconds_and_dates() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(retry_after(&headers), None);
+
+        headers.insert(RETRY_AFTER, HeaderValue::from_static("120"));
+        assert_eq!(retry_after(&headers), Some(Duration::from_secs(120)));
+
+        let later = httpdate::fmt_http_date(SystemTime::now() + Duration::from_secs(300));
+        headers.insert(RETRY_AFTER, HeaderValue::from_str(&later).unwrap());
+        let delay = retry_after(&headers).unwrap();
+        assert!(delay > Duration::from_secs(290) && delay <= Duration::from_secs(300));
+
+        headers.insert(
+            RETRY_AFTER,
+            HeaderValue::from_static("Wed, 21 Oct 2015 07:28:00 GMT"),
+        );
+        assert_eq!(retry_after(&headers), None);
+    }
+
+    #[tokio::test]
+    async fn allowed_method_is_forwarded() {
+        let upstream = spawn_upstream().await;
+        let config = app_config(&format!(
+            r#"{{ "mode": "path", "routes": {{ "api": {{
+                "target": "{upstream}", "allowed_methods": ["get", "POST"]
+            }} }} }}"#
+        ));
+
+        let res = handle_request(request(Method::GET, "/api/users"), &config)
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let body = res.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(&body[..], b"ok");
+    }
+
+    #[tokio::test]
+    async fn blocked_method_gets_405_with_allow_header() {
+        let config = app_config(
+            r#"{ "mode": "path", "routes": { "api": {
+                "target": "http://127.0.0.1:9", "allowed_methods": ["get", "POST"]
+            } } }"#,
+        );
+
+        let res = handle_request(request(Method::DELETE, "/api/users"), &config)
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::METHOD_NOT_ALLOWED);
+        assert_eq!(res.headers()[ALLOW], "GET, POST");
+    }
+
+    #[test]
+    fn empty_allowed_methods_accepts_everything() {
+        let route = Route::new("http://x");
+        assert!(is_method_allowed(&route, &Method::PATCH));
+        assert!(is_method_allowed(&route, &Method::OPTIONS));
+    }
+
+    // --- Unmatched requests ---
+
+    fn domain_request(host: &str) -> Request<ProxyBody> {
+        Request::builder()
+            .uri("/")
+            .header(HOST, host)
+            .body(boxed_full(""))
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn unknown_host_gets_421_when_enabled() {
+        let config = app_config(
+            r#"{ "mode": "domain", "misdirected_unknown_hosts": true,
+                 "routes": { "api": "http://127.0.0.1:9" } }"#,
+        );
+        let res = handle_request(domain_request("web.localhost:8000"), &config)
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::MISDIRECTED_REQUEST);
+
+        // a host without a valid label is still not found
+        let res = handle_request(domain_request("-bad.localhost"), &config)
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn requests_without_a_host_get_the_configured_answer() {
+        let upstream = spawn_upstream_with(|_| Response::new(boxed_full("fallback"))).await;
+        let no_host = || request(Method::GET, "/users");
+
+        let config =
+            app_config(r#"{ "mode": "domain", "routes": { "api": "http://127.0.0.1:9" } }"#);
+        let res = handle_request(no_host(), &config).await.unwrap();
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+
+        let config = app_config(
+            r#"{ "mode": "domain", "missing_host": "bad_request",
+                 "routes": { "api": "http://127.0.0.1:9" } }"#,
+        );
+        let res = handle_request(no_host(), &config).await.unwrap();
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+        // only a missing host is answered that way
+        let res = handle_request(domain_request("web.localhost"), &config)
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+
+        let config = app_config(&format!(
+            r#"{{ "mode": "domain", "missing_host": {{ "route": "api" }},
+                 "routes": {{ "api": "{upstream}" }} }}"#
+        ));
+        let res = handle_request(no_host(), &config).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let body = res.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(body, "fallback");
+    }
+
+    #[tokio::test]
+    async fn configured_statuses_replace_404_and_502() {
+        let config = app_config(
+            r#"{ "mode": "path", "not_found_status": 410, "bad_gateway_status": 500,
+                 "routes": { "down": "http://127.0.0.1:9" } }"#,
+        );
+        let res = handle_request(request(Method::GET, "/unknown"), &config)
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::GONE);
+        let res = handle_request(request(Method::GET, "/down"), &config)
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        let body = res.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(body, "Local Http Proxy: Bad Gateway");
+    }
+
+    #[tokio::test]
+    async fn unknown_host_gets_404_by_default() {
+        let config =
+            app_config(r#"{ "mode": "domain", "routes": { "api": "http://127.0.0.1:9" } }"#);
+        let res = handle_request(domain_request("web.localhost:8000"), &config)
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn wildcard_label_is_sent_upstream() {
+        let upstream = spawn_upstream_with(|req| {
+            let label = req.headers().get("x-preview-name").cloned();
+            Response::new(boxed_full(
+                label.map(|v| v.as_bytes().to_vec()).unwrap_or_default(),
+            ))
+        })
+        .await;
+        let config = app_config(&format!(
+            r#"{{ "mode": "domain", "routes": {{ "*": {{
+                "target": "{upstream}", "label_header": "X-Preview-Name"
+            }} }} }}"#
+        ));
+
+        let res = handle_request(domain_request("pr-42.preview.localhost"), &config)
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let body = res.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(&body[..], b"pr-42");
+
+        // a header that can't be sent is refused when the routes load
+        let mut route = Route::new("http://previews");
+        route.label_header = Some("Bad Header".into());
+        let err =
+            Router::for_mode(&ProxyMode::Domain, [("*".to_string(), route)].into()).unwrap_err();
+        assert!(
+            err.contains("Route '*' sets label_header to 'Bad Header'"),
+            "{err}"
+        );
+    }
+
+    // --- URI length ---
+
+    #[tokio::test]
+    async fn over_length_uri_gets_414() {
+        let config = app_config(
+            r#"{ "mode": "path", "max_uri_length": 32, "routes": { "api": "http://127.0.0.1:9" } }"#,
+        );
+        let long = format!("/api/{}", "a".repeat(27));
+        assert_eq!(long.len(), 32);
+        let res = handle_request(request(Method::GET, &format!("{long}?q")), &config)
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::URI_TOO_LONG);
+
+        // exactly at the limit is routed as usual
+        let res = handle_request(request(Method::GET, &long), &config)
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::BAD_GATEWAY);
+    }
+
+    #[test]
+    fn request_target_len_counts_absolute_form() {
+        let uri: Uri = "http://api.localhost:8000/v1?x=1".parse().unwrap();
+        assert_eq!(
+            request_target_len(&uri),
+            "http://api.localhost:8000/v1?x=1".len()
+        );
+        let uri: Uri = "/v1?x=1".parse().unwrap();
+        assert_eq!(request_target_len(&uri), 7);
+    }
+
+    #[test]
+    fn max_uri_length_has_a_default() {
+        let config = app_config(r#"{ "routes": {} }"#);
+        assert_eq!(config.max_uri_length, crate::config::DEFAULT_MAX_URI_LENGTH);
+    }
+
+    // --- Connection: close ---
+
+    #[tokio::test]
+    async fn connection_close_is_sent_when_configured() {
+        let upstream = spawn_upstream().await;
+        let config = app_config(&format!(
+            r#"{{ "mode": "path", "connection_close": true, "routes": {{ "api": "{upstream}" }} }}"#
+        ));
+        let res = handle_request(request(Method::GET, "/api"), &config)
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.headers()[CONNECTION], "close");
+
+        let res = handle_request(request(Method::GET, "/missing"), &config)
+            .await
+            .unwrap();
+        assert_eq!(res.headers()[CONNECTION], "close");
+    }
+
+    #[tokio::test]
+    async fn connect_and_trace_get_405_before_routing() {
+        let config = app_config(r#"{ "mode": "domain", "routes": { "api": "dir:/" } }"#);
+        let mut connect = request(Method::CONNECT, "api.localhost:443");
+        connect
+            .headers_mut()
+            .insert(HOST, HeaderValue::from_static("api.localhost:443"));
+        let mut trace = request(Method::TRACE, "/");
+        trace
+            .headers_mut()
+            .insert(HOST, HeaderValue::from_static("api.localhost"));
+
+        for req in [connect, trace] {
+            let res = handle_request(req, &config).await.unwrap();
+            assert_eq!(res.status(), StatusCode::METHOD_NOT_ALLOWED);
+            assert_eq!(
+                res.headers()[ALLOW],
+                "GET, HEAD, POST, PUT, DELETE, PATCH, OPTIONS"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn root_index_lists_the_routes_when_enabled() {
+        let routes = r#""web": "http://localhost:3000", "api": "http://localhost:8080""#;
+        let config = app_config(&format!(
+            r#"{{ "mode": "path", "root_index": true, "routes": {{ {routes} }} }}"#
+        ));
+        let res = handle_request(request(Method::GET, "/"), &config)
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.headers()[CONTENT_TYPE], "text/html; charset=utf-8");
+        let page = res.into_body().collect().await.unwrap().to_bytes();
+        let page = String::from_utf8(page.to_vec()).unwrap();
+        let api = page.find(r#"<a href="/api/">/api</a>"#).unwrap();
+        let web = page.find(r#"<a href="/web/">/web</a>"#).unwrap();
+        assert!(api < web, "{page}");
+
+        // off by default
+        let config = app_config(&format!(
+            r#"{{ "mode": "path", "routes": {{ {routes} }} }}"#
+        ));
+        let res = handle_request(request(Method::GET, "/"), &config)
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn routes_endpoint_answers_with_the_route_table_instead_of_proxying() {
+        let upstream = spawn_upstream_with(|_| Response::new(boxed_full("proxied"))).await;
+        let routes = format!(r#""table": "{upstream}", "web": "http://localhost:3000""#);
+        let config = app_config(&format!(
+            r#"{{ "mode": "path", "routes_endpoint": true, "routes_endpoint_path": "/table",
+                "routes": {{ {routes} }} }}"#
+        ));
+        let res = handle_request(request(Method::GET, "/table"), &config)
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.headers()[CONTENT_TYPE], "application/json");
+        let body = res.into_body().collect().await.unwrap().to_bytes();
+        let table: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(table["mode"], "path");
+        assert_eq!(table["routes"]["web"]["target"], "http://localhost:3000");
+        let keys: Vec<_> = table["routes"].as_object().unwrap().keys().collect();
+        assert_eq!(keys, ["table", "web"]);
+
+        let res = handle_request(request(Method::POST, "/table"), &config)
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::METHOD_NOT_ALLOWED);
+
+        // off by default, so the path is routed like any other
+        let config = app_config(&format!(
+            r#"{{ "mode": "path", "routes": {{ {routes} }} }}"#
+        ));
+        let res = handle_request(request(Method::GET, "/table"), &config)
+            .await
+            .unwrap();
+        let body = res.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(body, "proxied");
+    }
+
+    #[tokio::test]
+    async fn status_map_rewrites_only_the_listed_upstream_statuses() {
+        let upstream = spawn_upstream_with(|req| {
+            let status = match req.uri().path() {
+                "/fail" => StatusCode::INTERNAL_SERVER_ERROR,
+                "/missing" => StatusCode::NOT_FOUND,
+                _ => StatusCode::OK,
+            };
+            Response::builder()
+                .status(status)
+                .body(boxed_full("from upstream"))
+                .unwrap()
+        })
+        .await;
+        let config = app_config(&format!(
+            r#"{{ "mode": "path", "routes": {{
+                "api": {{ "target": "{upstream}", "status_map": {{ "500": 503 }} }}
+            }} }}"#
+        ));
+
+        let res = handle_request(request(Method::GET, "/api/fail"), &config)
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::SERVICE_UNAVAILABLE);
+        let body = res.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(body, "from upstream");
+
+        for (path, status) in [
+            ("/api/missing", StatusCode::NOT_FOUND),
+            ("/api", StatusCode::OK),
+        ] {
+            let res = handle_request(request(Method::GET, path), &config)
+                .await
+                .unwrap();
+            assert_eq!(res.status(), status, "{path}");
+        }
+
+        let mut route = Route::new("http://a");
+        route.status_map.insert("500".to_string(), 1000);
+        let err =
+            Router::for_mode(&ProxyMode::Path, [("api".to_string(), route)].into()).unwrap_err();
+        assert!(err.contains("Route 'api' maps a status of 1000"), "{err}");
+    }
+
+    #[test]
+    fn locations_leading_to_the_upstream_are_rewritten() {
+        let target = "http://localhost:3000";
+        let rewrite = |location, mode| rewrite_location(location, mode, "api", target);
+        assert_eq!(
+            rewrite("/login?next=/", ProxyMode::Path).unwrap(),
+            "/api/login?next=/"
+        );
+        assert_eq!(
+            rewrite("http://LOCALHOST:3000", ProxyMode::Path).unwrap(),
+            "/api/"
+        );
+        assert_eq!(
+            rewrite("http://localhost:3000/login", ProxyMode::Domain).unwrap(),
+            "/login"
+        );
+        assert_eq!(rewrite("/login", ProxyMode::Domain).unwrap(), "/login");
+        // elsewhere, or relative to the current page, which already goes through the proxy
+        for location in [
+            "https://localhost:3000/login",
+            "http://auth.example/login",
+            "//cdn/x",
+            "next",
+        ] {
+            assert_eq!(rewrite(location, ProxyMode::Path), None, "{location}");
+        }
+        assert_eq!(rewrite("/login", ProxyMode::Regex), None);
+
+        let below = |location| rewrite_location(location, ProxyMode::Path, "web", "http://h/app/");
+        assert_eq!(below("/app/login").unwrap(), "/web/login");
+        assert_eq!(below("/app?x=1").unwrap(), "/web/?x=1");
+        assert_eq!(below("/application").unwrap(), "/web/application");
+    }
+
+    #[tokio::test]
+    async fn rewrite_location_keeps_redirects_on_the_proxy() {
+        let upstream = spawn_upstream_with(|req| {
+            let location = match req.uri().path() {
+                "/absolute" => format!("http://{}/login", req.headers()[HOST].to_str().unwrap()),
+                _ => "/login".to_string(),
+            };
+            Response::builder()
+                .status(StatusCode::FOUND)
+                .header(LOCATION, location)
+                .body(boxed_full(""))
+                .unwrap()
+        })
+        .await;
+        let config = app_config(&format!(
+            r#"{{ "mode": "path", "routes": {{
+                "api": {{ "target": "{upstream}", "rewrite_location": true }},
+                "app": {{ "target": "{upstream}", "mode": "domain", "rewrite_location": true }}
+            }} }}"#
+        ));
+
+        for path in ["/api/relative", "/api/absolute"] {
+            let res = handle_request(request(Method::GET, path), &config)
+                .await
+                .unwrap();
+            assert_eq!(res.status(), StatusCode::FOUND);
+            assert_eq!(res.headers()[LOCATION], "/api/login", "{path}");
+        }
+
+        let mut req = domain_request("app.localhost");
+        *req.uri_mut() = "/absolute".parse().unwrap();
+        let res = handle_request(req, &config).await.unwrap();
+        assert_eq!(res.headers()[LOCATION], "/login");
+    }
+
+    #[tokio::test]
+    async fn follow_redirects_answers_with_where_the_upstream_redirects_to() {
+        let upstream = spawn_upstream_with(|req| {
+            let next = match req.uri().path() {
+                "/start" => "/middle",
+                "/middle" => "/end",
+                "/loop" => "/loop",
+                _ => return Response::new(boxed_full(format!("{} {}", req.method(), req.uri()))),
+            };
+            Response::builder()
+                .status(StatusCode::MOVED_PERMANENTLY)
+                .header(LOCATION, next)
+                .body(boxed_full(""))
+                .unwrap()
+        })
+        .await;
+        let config = app_config(&format!(
+            r#"{{ "mode": "path", "routes": {{
+                "api": {{ "target": "{upstream}", "follow_redirects": 2 }}
+            }} }}"#
+        ));
+
+        let res = handle_request(request(Method::GET, "/api/start?q=1"), &config)
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let body = res.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(body, "GET /end");
+
+        // past the limit, and for requests with a body, the client gets the redirect
+        let res = handle_request(request(Method::GET, "/api/loop"), &config)
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::MOVED_PERMANENTLY);
+        let res = handle_request(request(Method::POST, "/api/start"), &config)
+            .await
+            .unwrap();
+        assert_eq!(res.headers()[LOCATION], "/middle");
+    }
+
+    #[tokio::test]
+    async fn proxy_auth_requires_the_credentials_and_keeps_them_from_the_upstream() {
+        let upstream = spawn_upstream_with(|req| {
+            let forwarded = req.headers().contains_key(PROXY_AUTHORIZATION);
+            Response::new(boxed_full(format!("forwarded: {forwarded}")))
+        })
+        .await;
+        let config = app_config(&format!(
+            r#"{{ "mode": "path", "proxy_auth": "dev:secret", "stats_endpoint": true, "routes": {{ "api": "{upstream}" }} }}"#
+        ));
+
+        for credentials in [
+            None,
+            Some("Basic ZGV2Ondyb25n"),
+            Some("Bearer ZGV2OnNlY3JldA=="),
+        ] {
+            let mut req = request(Method::GET, "/api");
+            if let Some(credentials) = credentials {
+                req.headers_mut()
+                    .insert(PROXY_AUTHORIZATION, HeaderValue::from_static(credentials));
+            }
+            let res = handle_request(req, &config).await.unwrap();
+            assert_eq!(res.status(), StatusCode::PROXY_AUTHENTICATION_REQUIRED);
+            assert_eq!(
+                res.headers()[PROXY_AUTHENTICATE],
+                "Basic realm=\"local-http-proxy\""
+            );
+        }
+
+        // base64 of `dev:secret`
+        let mut req = request(Method::GET, "/api");
+        req.headers_mut().insert(
+            PROXY_AUTHORIZATION,
+            HeaderValue::from_static("basic ZGV2OnNlY3JldA=="),
+        );
+        let res = handle_request(req, &config).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let body = res.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(body, "forwarded: false");
+
+        // the stats name every route, so they are kept from unauthenticated clients too
+        let res = handle_request(request(Method::GET, STATS_PATH), &config)
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::PROXY_AUTHENTICATION_REQUIRED);
+    }
+
+    #[tokio::test]
+    async fn hop_count_is_incremented_and_capped() {
+        let upstream = spawn_upstream_with(|req| {
+            let hops = req.headers()[X_LHP_HOPS].to_str().unwrap().to_string();
+            Response::new(boxed_full(hops))
+        })
+        .await;
+        let config = app_config(&format!(
+            r#"{{ "mode": "path", "max_hops": 3, "routes": {{ "api": "{upstream}" }} }}"#
+        ));
+        let mut req = request(Method::GET, "/api");
+        req.headers_mut()
+            .insert(X_LHP_HOPS, HeaderValue::from_static("2"));
+        let res = handle_request(req, &config).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let body = res.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(body, "3");
+
+        // a request that already went through `max_hops` proxies is stopped here
+        let mut req = request(Method::GET, "/api");
+        req.headers_mut()
+            .insert(X_LHP_HOPS, HeaderValue::from_static("3"));
+        let res = handle_request(req, &config).await.unwrap();
+        assert_eq!(res.status(), StatusCode::LOOP_DETECTED);
+    }
+
+    #[tokio::test]
+    async fn first_hop_is_counted_as_one() {
+        let upstream = spawn_upstream_with(|req| {
+            let hops = req.headers()[X_LHP_HOPS].to_str().unwrap().to_string();
+            Response::new(boxed_full(hops))
+        })
+        .await;
+        let config = app_config(&format!(
+            r#"{{ "mode": "path", "routes": {{ "api": "{upstream}" }} }}"#
+        ));
+        let res = handle_request(request(Method::GET, "/api"), &config)
+            .await
+            .unwrap();
+        let body = res.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(body, "1");
+    }
+
+    #[tokio::test]
+    async fn connection_is_kept_alive_by_default() {
+        let upstream = spawn_upstream().await;
+        let config = app_config(&format!(
+            r#"{{ "mode": "path", "routes": {{ "api": "{upstream}" }} }}"#
+        ));
+        let res = handle_request(request(Method::GET, "/api"), &config)
+            .await
+            .unwrap();
+        assert!(res.headers().get(CONNECTION).is_none());
+    }
+
+    // --- Server header ---
+
+    #[tokio::test]
+    async fn server_header_is_set_when_configured() {
+        let upstream = spawn_upstream_with(|_req| {
+            let mut res = Response::new(boxed_full("ok"));
+            res.headers_mut()
+                .insert(SERVER, HeaderValue::from_static("upstream/1.0"));
+            res
+        })
+        .await;
+        let config = app_config(&format!(
+            r#"{{ "mode": "path", "server_header": "local-http-proxy/0.1",
+                 "routes": {{ "api": "{upstream}" }} }}"#
+        ));
+
+        let res = handle_request(request(Method::GET, "/api"), &config)
+            .await
+            .unwrap();
+        assert_eq!(res.headers()[SERVER], "local-http-proxy/0.1");
+        assert_eq!(res.headers().get_all(SERVER).iter().count(), 1);
+
+        // responses made by the proxy itself carry it too
+        let res = handle_request(request(Method::GET, "/missing"), &config)
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+        assert_eq!(res.headers()[SERVER], "local-http-proxy/0.1");
+    }
+
+    #[tokio::test]
+    async fn server_header_is_untouched_by_default() {
+        let upstream = spawn_upstream_with(|_req| {
+            let mut res = Response::new(boxed_full("ok"));
+            res.headers_mut()
+                .insert(SERVER, HeaderValue::from_static("upstream/1.0"));
+            res
+        })
+        .await;
+        let config = app_config(&format!(
+            r#"{{ "mode": "path", "routes": {{ "api": "{upstream}" }} }}"#
+        ));
+
+        let res = handle_request(request(Method::GET, "/api"), &config)
+            .await
+            .unwrap();
+        assert_eq!(res.headers()[SERVER], "upstream/1.0");
+
+        let res = handle_request(request(Method::GET, "/missing"), &config)
+            .await
+            .unwrap();
+        assert!(res.headers().get(SERVER).is_none());
+    }
+
+    // --- Stats ---
+
+    #[tokio::test]
+    async fn responses_are_counted_per_route() {
+        let upstream = spawn_upstream().await;
+        let config = app_config(&format!(
+            r#"{{ "mode": "path", "stats_endpoint": true, "routes": {{
+                "api": "{upstream}",
+                "down": "http://127.0.0.1:9",
+                "idle": "http://127.0.0.1:9"
+            }} }}"#
+        ));
+
+        for uri in ["/api/users", "/down/x", "/unknown/x"] {
+            handle_request(request(Method::GET, uri), &config)
+                .await
+                .unwrap();
+        }
+
+        let res = handle_request(request(Method::GET, STATS_PATH), &config)
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.headers()[CONTENT_TYPE], "application/json");
+        let body = res.into_body().collect().await.unwrap().to_bytes();
+        let report: StatsReport = serde_json::from_slice(&body).unwrap();
+        assert_eq!(report.routes.len(), 3);
+        assert_eq!(report.routes["api"].total, 1);
+        assert_eq!(report.routes["api"].success, 1);
+        assert_eq!(report.routes["down"].server_error, 1);
+        assert_eq!(report.routes["idle"].total, 0);
+        assert_eq!(report.routes["api"].request_bytes, None);
+
+        // the endpoint is off unless the config file turns it on, leaving the path to routing
+        let config = app_config(&format!(
+            r#"{{ "mode": "path", "routes": {{ "__lhp": "{upstream}" }} }}"#
+        ));
+        let res = handle_request(request(Method::GET, STATS_PATH), &config)
+            .await
+            .unwrap();
+        assert_ne!(
+            res.headers().get(CONTENT_TYPE).map(|v| v.as_bytes()),
+            Some(&b"application/json"[..])
+        );
+    }
+
+    #[tokio::test]
+    async fn count_bytes_counts_the_bodies_of_each_route() {
+        let upstream = spawn_upstream_with(|req| match req.uri().path() {
+            "/echo" => Response::new(req.into_body().boxed()),
+            _ => Response::new(boxed_full("x".repeat(1000))),
+        })
+        .await;
+        let config = app_config(&format!(
+            r#"{{ "mode": "path", "stats_endpoint": true, "count_bytes": true, "routes": {{
+                "api": "{upstream}",
+                "idle": "http://127.0.0.1:9"
+            }} }}"#
+        ));
+
+        let req = Request::builder()
+            .method(Method::POST)
+            .uri("/api/echo")
+            .body(boxed_full(vec![7u8; 300]))
+            .unwrap();
+        let res = handle_request(req, &config).await.unwrap();
+        let body = res.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(body.len(), 300);
+        let res = handle_request(request(Method::GET, "/api/big"), &config)
+            .await
+            .unwrap();
+        res.into_body().collect().await.unwrap();
+
+        let res = handle_request(request(Method::GET, STATS_PATH), &config)
+            .await
+            .unwrap();
+        let body = res.into_body().collect().await.unwrap().to_bytes();
+        let report: StatsReport = serde_json::from_slice(&body).unwrap();
+        assert_eq!(report.routes["api"].request_bytes, Some(300));
+        assert_eq!(report.routes["api"].response_bytes, Some(1300));
+        assert_eq!(report.routes["idle"].response_bytes, Some(0));
+    }
+
+    #[tokio::test]
+    async fn maintenance_mode_answers_every_route_with_503() {
+        let config = app_config(
+            r#"{ "mode": "path", "maintenance": true, "stats_endpoint": true, "routes": { "api": "dir:/" } }"#,
+        );
+        let res = handle_request(request(Method::GET, "/api/users"), &config)
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::SERVICE_UNAVAILABLE);
+        let body = res.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(body, DEFAULT_MAINTENANCE_MESSAGE);
+
+        // the stats endpoint keeps answering
+        let res = handle_request(request(Method::GET, STATS_PATH), &config)
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+
+        let config = app_config(
+            r#"{ "mode": "path", "maintenance": true, "maintenance_message": "Back at 5pm.",
+                 "routes": { "api": "dir:/" } }"#,
+        );
+        let res = handle_request(request(Method::GET, "/missing"), &config)
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::SERVICE_UNAVAILABLE);
+        let body = res.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(body, "Back at 5pm.");
+    }
+
+    #[tokio::test]
+    async fn latency_delays_the_response_without_changing_it() {
+        let site = tempfile::TempDir::new().unwrap();
+        std::fs::write(site.path().join("index.html"), "home").unwrap();
+        let config = app_config(&format!(
+            r#"{{ "mode": "path", "routes": {{
+                "slow": {{ "target": "dir:{root}", "latency_ms": 200 }},
+                "fast": "dir:{root}"
+            }} }}"#,
+            root = site.path().display()
+        ));
+
+        let started = Instant::now();
+        let res = handle_request(request(Method::GET, "/slow/"), &config)
+            .await
+            .unwrap();
+        assert!(started.elapsed() >= Duration::from_millis(200));
+        assert_eq!(res.status(), StatusCode::OK);
+        let body = res.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(body, "home");
+
+        let started = Instant::now();
+        handle_request(request(Method::GET, "/fast/"), &config)
+            .await
+            .unwrap();
+        assert!(started.elapsed() < Duration::from_millis(200));
+    }
+
+    #[tokio::test]
+    async fn only_routes_in_maintenance_answer_503() {
+        let site = tempfile::TempDir::new().unwrap();
+        std::fs::write(site.path().join("index.html"), "home").unwrap();
+        let config = app_config(&format!(
+            r#"{{ "mode": "path", "routes": {{
+                "api": {{ "target": "dir:{root}", "maintenance": true }},
+                "web": "dir:{root}"
+            }} }}"#,
+            root = site.path().display()
+        ));
+
+        let res = handle_request(request(Method::GET, "/api/"), &config)
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::SERVICE_UNAVAILABLE);
+        let body = res.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(body, DEFAULT_MAINTENANCE_MESSAGE);
+
+        let res = handle_request(request(Method::GET, "/web/"), &config)
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+    }
+
+    #[test]
+    fn routes_with_their_own_mode_mix_host_and_path_matching() {
+        let config = app_config(
+            r#"{ "mode": "path", "routes": {
+                "api": "http://api:8080",
+                "docs": { "target": "http://docs:4000", "mode": "domain" }
+            } }"#,
+        );
+        let routes = config.routes.load();
+        let destination = |host: &str, uri: &str| {
+            let req = Request::builder()
+                .uri(uri)
+                .header(HOST, host)
+                .body(())
+                .unwrap();
+            get_destination(&req, &config.mode, &routes, false, None)
+                .map(|d| (d.key.to_string(), d.upstream.host, d.upstream.path))
+        };
+
+        assert_eq!(
+            destination("localhost", "/api/users"),
+            Ok(("api".into(), "http://api:8080".into(), "/users".into()))
+        );
+        // the path is left alone for a route matched by host
+        assert_eq!(
+            destination("docs.localhost", "/guide"),
+            Ok(("docs".into(), "http://docs:4000".into(), "/guide".into()))
+        );
+        // matched by path first, as the profile's mode says
+        assert_eq!(
+            destination("docs.localhost", "/api/users"),
+            Ok(("api".into(), "http://api:8080".into(), "/users".into()))
+        );
+        // each route only matches its own way
+        assert_eq!(
+            destination("localhost", "/docs/guide").err(),
+            Some(RouteError::Unmapped)
+        );
+        assert_eq!(
+            destination("api.localhost", "/").err(),
+            Some(RouteError::NoKey)
+        );
+
+        let mut route = Route::new("http://a");
+        route.mode = Some(ProxyMode::Domain);
+        let err =
+            Router::for_mode(&ProxyMode::Regex, [("^/a".to_string(), route)].into()).unwrap_err();
+        assert!(err.contains("only domain and path routes can"), "{err}");
+    }
+
+    #[tokio::test]
+    async fn disabled_routes_in_the_file_do_not_match() {
+        let site = tempfile::TempDir::new().unwrap();
+        std::fs::write(site.path().join("index.html"), "home").unwrap();
+        let config = app_config(&format!(
+            r#"{{ "mode": "path", "routes": {{
+                "api": {{ "target": "dir:{root}", "enabled": false }},
+                "web": {{ "target": "dir:{root}", "enabled": true }}
+            }} }}"#,
+            root = site.path().display()
+        ));
+        let routes = config.routes.load();
+        let req = request(Method::GET, "/api/");
+        let err = get_destination(&req, &config.mode, &routes, false, None).err();
+        assert_eq!(err, Some(RouteError::Disabled));
+
+        let res = handle_request(request(Method::GET, "/api/"), &config)
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+        let res = handle_request(request(Method::GET, "/web/"), &config)
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+    }
+
+    // --- Tracing ---
+
+    #[tokio::test]
+    async fn request_span_records_route_upstream_and_status() {
+        use crate::server::telemetry::tests::{attribute, exporter, finished_span};
+
+        exporter();
+        let config = app_config(
+            r#"{ "mode": "path", "routes": { "traced": {
+                "target": "http://127.0.0.1:9", "allowed_methods": ["GET"]
+            } } }"#,
+        );
+        let res = handle_request(request(Method::PUT, "/traced/items"), &config)
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::METHOD_NOT_ALLOWED);
+
+        let span = finished_span("/traced/items").expect("span is recorded");
+        assert_eq!(span.name, "PUT");
+        assert_eq!(attribute(&span, "http.request.method").unwrap(), "PUT");
+        assert_eq!(attribute(&span, "lhp.route").unwrap(), "traced");
+        assert_eq!(
+            attribute(&span, "lhp.upstream").unwrap(),
+            "http://127.0.0.1:9"
+        );
+        assert_eq!(
+            attribute(&span, "http.response.status_code").unwrap(),
+            "405"
+        );
+    }
+
+    // --- Streaming ---
+
+    use std::sync::atomic::{AtomicUsize, Ordering::SeqCst};
+    use std::sync::Arc;
+
+    const STREAM_TOTAL: usize = 256 * 1024 * 1024;
+    const STREAM_CHUNK: usize = 64 * 1024;
+    // sockets and hyper buffer a few MB at most; a proxy collecting the body would
+    // let the producer run all the way ahead
+    const MAX_LAG: usize = 32 * 1024 * 1024;
+    static ZEROS: [u8; STREAM_CHUNK] = [0; STREAM_CHUNK];
+
+    /// Produces zeroes lazily while tracking how far production runs ahead of consumption.
+    struct Source {
+        remaining: usize,
+        progress: Arc<Progress>,
+    }
+
+    #[derive(Default)]
+    struct Progress {
+        produced: AtomicUsize,
+        consumed: AtomicUsize,
+        max_lag: AtomicUsize,
+    }
+
+    impl Progress {
+        fn consume(&self, n: usize) {
+            self.consumed.fetch_add(n, SeqCst);
+        }
+
+        fn max_lag(&self) -> usize {
+            self.max_lag.load(SeqCst)
+        }
+    }
+
+    impl Body for Source {
+        type Data = Bytes;
+        type Error = hyper::Error;
+
+        fn poll_frame(
+            mut self: Pin<&mut Self>,
+            _cx: &mut Context<'_>,
+        ) -> Poll<Option<Result<Frame<Bytes>, hyper::Error>>> {
+            if self.remaining == 0 {
+                return Poll::Ready(None);
+            }
+            let n = self.remaining.min(STREAM_CHUNK);
+            self.remaining -= n;
+            let progress = &self.progress;
+            let produced = progress.produced.fetch_add(n, SeqCst) + n;
+            let lag = produced.saturating_sub(progress.consumed.load(SeqCst));
+            progress.max_lag.fetch_max(lag, SeqCst);
+            let chunk = Bytes::from_static(&ZEROS[..n]);
+            Poll::Ready(Some(Ok(Frame::data(chunk))))
+        }
+    }
+
+    fn source(progress: &Arc<Progress>) -> ProxyBody {
+        Source {
+            remaining: STREAM_TOTAL,
+            progress: progress.clone(),
+        }
+        .boxed()
+    }
+
+    /// Reads a body frame by frame, reporting each chunk to `progress`.
+    async fn drain<B: Body<Data = Bytes> + Unpin>(mut body: B, progress: &Progress) -> usize
+    where
+        B::Error: std::fmt::Debug,
+    {
+        let mut total = 0;
+        while let Some(frame) = body.frame().await {
+            if let Ok(data) = frame.unwrap().into_data() {
+                total += data.len();
+                progress.consume(data.len());
+                tokio::task::yield_now().await;
+            }
+        }
+        total
+    }
+
+    /// Serves a single connection whose handler gets the shared progress counters.
+    async fn spawn_streaming_upstream(progress: Arc<Progress>, upload: bool) -> String {
+        use hyper::{server::conn::http1, service::service_fn};
+        use hyper_util::rt::TokioIo;
+        use tokio::net::TcpListener;
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let service = service_fn(move |req: Request<Incoming>| {
+                let progress = progress.clone();
+                async move {
+                    let res = if upload {
+                        let received = drain(req.into_body(), &progress).await;
+                        Response::new(boxed_full(received.to_string()))
+                    } else {
+                        Response::new(source(&progress))
+                    };
+                    Ok::<_, Infallible>(res)
+                }
+            });
+            let _ = http1::Builder::new()
+                .serve_connection(TokioIo::new(stream), service)
+                .await;
+        });
+        format!("http://{addr}")
+    }
+
+    #[tokio::test]
+    async fn large_request_body_is_streamed_to_upstream() {
+        let progress = Arc::new(Progress::default());
+        let upstream = spawn_streaming_upstream(progress.clone(), true).await;
+        let config = app_config(&format!(
+            r#"{{ "mode": "path", "routes": {{ "api": "{upstream}" }} }}"#
+        ));
+
+        let req = Request::builder()
+            .method(Method::POST)
+            .uri("/api/upload")
+            .body(source(&progress))
+            .unwrap();
+        let res = handle_request(req, &config).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let body = res.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(body, STREAM_TOTAL.to_string());
+        assert!(
+            progress.max_lag() < MAX_LAG,
+            "request body ran {} bytes ahead of the upstream",
+            progress.max_lag()
+        );
+    }
+
+    #[tokio::test]
+    async fn large_response_body_is_streamed_to_client() {
+        let progress = Arc::new(Progress::default());
+        let upstream = spawn_streaming_upstream(progress.clone(), false).await;
+        let config = app_config(&format!(
+            r#"{{ "mode": "path", "routes": {{ "api": "{upstream}" }} }}"#
+        ));
+
+        let res = handle_request(request(Method::GET, "/api/download"), &config)
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(drain(res.into_body(), &progress).await, STREAM_TOTAL);
+        assert!(
+            progress.max_lag() < MAX_LAG,
+            "response body ran {} bytes ahead of the client",
+            progress.max_lag()
+        );
+    }
+
+    // --- Upstream failures ---
+
+    /// Accepts one connection, reads the request head and answers with `raw` before closing.
+    async fn spawn_raw_upstream(raw: &'static [u8]) -> String {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+        use tokio::net::TcpListener;
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut buf = [0u8; 4096];
+            let _ = stream.read(&mut buf).await;
+            let _ = stream.write_all(raw).await;
+        });
+        format!("http://{addr}")
+    }
+
+    #[tokio::test]
+    async fn refused_upstream_connections_are_logged_with_the_reason() {
+        let closed = {
+            let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+            format!("http://{}", listener.local_addr().unwrap())
+        };
+        let route = Route::new(closed.clone());
+        let req = Request::builder()
+            .uri(format!("{closed}/users"))
+            .body(boxed_full(""))
+            .unwrap();
+        let err = crate::server::client::for_route(&route)
+            .request(req)
+            .await
+            .unwrap_err();
+
+        assert_eq!(UpstreamFailure::of(&err), UpstreamFailure::Refused);
+        let line = upstream_error("api", &closed, UpstreamFailure::of(&err), &err);
+        assert!(
+            line.starts_with(&format!(
+                "⚠️  Route 'api' could not reach {closed}: connection refused\n  Cause: "
+            )),
+            "{line}"
+        );
+    }
+
+    #[test]
+    fn upstream_failures_map_to_their_status() {
+        use io::ErrorKind;
+
+        for (io_kind, dns, connect, status) in [
+            (
+                Some(ErrorKind::ConnectionRefused),
+                false,
+                true,
+                StatusCode::BAD_GATEWAY,
+            ),
+            (None, true, true, StatusCode::BAD_GATEWAY),
+            (
+                Some(ErrorKind::ConnectionReset),
+                false,
+                false,
+                StatusCode::BAD_GATEWAY,
+            ),
+            (None, false, false, StatusCode::BAD_GATEWAY),
+            (
+                Some(ErrorKind::TimedOut),
+                false,
+                true,
+                StatusCode::GATEWAY_TIMEOUT,
+            ),
+            (
+                Some(ErrorKind::AddrNotAvailable),
+                false,
+                true,
+                StatusCode::SERVICE_UNAVAILABLE,
+            ),
+        ] {
+            let failure = UpstreamFailure::classify(io_kind, dns, connect);
+            let got = failure
+                .response()
+                .map_or(StatusCode::BAD_GATEWAY, |res| res.status());
+            assert_eq!(got, status, "{failure:?}");
+        }
+    }
+
+    #[tokio::test]
+    async fn upstream_closing_before_headers_is_bad_gateway() {
+        let upstream = spawn_raw_upstream(b"").await;
+        let config = app_config(&format!(
+            r#"{{ "mode": "path", "routes": {{ "api": "{upstream}" }} }}"#
+        ));
+        let res = handle_request(request(Method::GET, "/api"), &config)
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::BAD_GATEWAY);
+    }
+
+    #[tokio::test]
+    async fn upstream_closing_mid_body_ends_the_client_body_with_an_error() {
+        let upstream =
+            spawn_raw_upstream(b"HTTP/1.1 200 OK\r\ncontent-length: 100\r\n\r\nhello").await;
+        let config = app_config(&format!(
+            r#"{{ "mode": "path", "routes": {{ "api": "{upstream}" }} }}"#
+        ));
+        let res = handle_request(request(Method::GET, "/api"), &config)
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+
+        let mut body = res.into_body();
+        let first = body.frame().await.unwrap().unwrap();
+        assert_eq!(first.into_data().unwrap(), "hello");
+        assert!(body.frame().await.unwrap().is_err());
+    }
+
+    // --- Concurrency limit ---
+
+    /// Answers every connection with the start of a body and then holds it open, so each
+    /// request stays in flight until the client drops the response.
+    async fn spawn_stalling_upstream() -> String {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+        use tokio::net::TcpListener;
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            loop {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                tokio::spawn(async move {
+                    let mut buf = [0u8; 4096];
+                    let _ = stream.read(&mut buf).await;
+                    let _ = stream
+                        .write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 100\r\n\r\nhello")
+                        .await;
+                    let _ = stream.read(&mut buf).await;
+                });
+            }
+        });
+        format!("http://{addr}")
+    }
+
+    #[tokio::test]
+    async fn request_over_max_inflight_is_shed() {
+        let upstream = spawn_stalling_upstream().await;
+        let config = app_config(&format!(
+            r#"{{ "mode": "path", "routes": {{ "api": {{ "target": "{upstream}", "max_inflight": 2 }} }} }}"#
+        ));
+
+        let first = handle_request(request(Method::GET, "/api"), &config)
+            .await
+            .unwrap();
+        let second = handle_request(request(Method::GET, "/api"), &config)
+            .await
+            .unwrap();
+        assert_eq!(first.status(), StatusCode::OK);
+        assert_eq!(second.status(), StatusCode::OK);
+
+        let third = handle_request(request(Method::GET, "/api"), &config)
+            .await
+            .unwrap();
+        assert_eq!(third.status(), StatusCode::SERVICE_UNAVAILABLE);
+
+        // the slot comes back once a response is dropped
+        drop(first);
+        let fourth = handle_request(request(Method::GET, "/api"), &config)
+            .await
+            .unwrap();
+        assert_eq!(fourth.status(), StatusCode::OK);
+
+        let mut route = Route::new("http://a");
+        route.max_inflight = Some(0);
+        let err =
+            Router::for_mode(&ProxyMode::Path, [("api".to_string(), route)].into()).unwrap_err();
+        assert!(err.contains("Route 'api' sets max_inflight to 0"), "{err}");
+    }
+
+    #[tokio::test]
+    async fn request_over_max_inflight_waits_for_a_slot() {
+        let upstream = spawn_stalling_upstream().await;
+        let config = Arc::new(app_config(&format!(
+            r#"{{ "mode": "path", "routes": {{ "api": {{ "target": "{upstream}", "max_inflight": 1, "queue_timeout_ms": 5000 }} }} }}"#
+        )));
+
+        let first = handle_request(request(Method::GET, "/api"), &config)
+            .await
+            .unwrap();
+        assert_eq!(first.status(), StatusCode::OK);
+
+        let queued = tokio::spawn({
+            let config = config.clone();
+            async move {
+                handle_request(request(Method::GET, "/api"), &config)
+                    .await
+                    .unwrap()
+                    .status()
+            }
+        });
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(!queued.is_finished(), "the second request should be queued");
+
+        drop(first);
+        assert_eq!(queued.await.unwrap(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn queued_request_is_shed_after_queue_timeout() {
+        let upstream = spawn_stalling_upstream().await;
+        let config = app_config(&format!(
+            r#"{{ "mode": "path", "routes": {{ "api": {{ "target": "{upstream}", "max_inflight": 1, "queue_timeout_ms": 50 }} }} }}"#
+        ));
+
+        let _first = handle_request(request(Method::GET, "/api"), &config)
+            .await
+            .unwrap();
+        let second = handle_request(request(Method::GET, "/api"), &config)
+            .await
+            .unwrap();
+        assert_eq!(second.status(), StatusCode::SERVICE_UNAVAILABLE);
+    }
+
+    // --- Static directories ---
+
+    #[tokio::test]
+    async fn dir_route_serves_files_instead_of_proxying() {
+        let site = tempfile::TempDir::new().unwrap();
+        std::fs::write(site.path().join("index.html"), "home").unwrap();
+        let root = site.path().display();
+        let config = app_config(&format!(
+            r#"{{ "mode": "path", "routes": {{ "site": "dir:{root}" }} }}"#
+        ));
+
+        let res = handle_request(request(Method::GET, "/site/"), &config)
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.headers()[CONTENT_TYPE], "text/html; charset=utf-8");
+        let body = res.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(body, "home");
+
+        let res = handle_request(request(Method::GET, "/site/../secret"), &config)
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+    }
+
+    // --- Redirect routes ---
+
+    #[tokio::test]
+    async fn redirect_route_keeps_sub_path_and_query() {
+        let config = app_config(
+            r#"{ "mode": "path", "routes": {
+                "docs": { "target": "https://external-docs", "kind": "redirect" },
+                "old": { "target": "http://localhost:9000/new", "kind": "redirect", "redirect_status": 301 }
+            } }"#,
+        );
+
+        let res = handle_request(request(Method::GET, "/docs/guide/intro?lang=en"), &config)
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::FOUND);
+        assert_eq!(
+            res.headers()[LOCATION],
+            "https://external-docs/guide/intro?lang=en"
+        );
+
+        let res = handle_request(request(Method::GET, "/old"), &config)
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::MOVED_PERMANENTLY);
+        assert_eq!(res.headers()[LOCATION], "http://localhost:9000/new/");
+    }
+
+    // --- Body logging ---
+
+    /// The body lines logged so far for the route `key`.
+    fn body_log_lines(key: &str) -> Vec<String> {
+        let prefix = format!("🔍 {key} ");
+        body_log::tests::LOGGED
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|line| line.starts_with(&prefix))
+            .cloned()
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn bodies_are_logged_and_truncated_only_when_enabled() {
+        let upstream =
+            spawn_upstream_with(|_req| Response::new(boxed_full("a long response body"))).await;
+        let config = app_config(&format!(
+            r#"{{ "mode": "path", "routes": {{
+                "logged-api": {{ "target": "{upstream}", "log_bodies": true, "log_body_limit": 6 }}
+            }} }}"#
+        ));
+        let req = Request::builder()
+            .method(Method::POST)
+            .uri("/logged-api/items")
+            .body(boxed_full("name=lhp"))
+            .unwrap();
+
+        let res = handle_request(req, &config).await.unwrap();
+        let body = res.into_body().collect().await.unwrap().to_bytes();
+        // the client still gets the whole body
+        assert_eq!(body, "a long response body");
+        assert_eq!(
+            body_log_lines("logged-api"),
+            [
+                "🔍 logged-api request body (8 bytes, first 6 shown): name=l",
+                "🔍 logged-api response body (20 bytes, first 6 shown): a long",
+            ]
+        );
+
+        let upstream = spawn_upstream().await;
+        let config = app_config(&format!(
+            r#"{{ "mode": "path", "routes": {{ "quiet-api": "{upstream}" }} }}"#
+        ));
+        let res = handle_request(request(Method::GET, "/quiet-api"), &config)
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        assert!(body_log_lines("quiet-api").is_empty());
     }
 }
@@ -0,0 +1,496 @@
+use super::client::{self, UpstreamClient};
+use super::stats::{RouteCounters, RouteStats};
+use crate::config::{ClientCert, ProxyMode, Route, Routes};
+use hyper::header::{HeaderName, HeaderValue};
+use hyper::StatusCode;
+use indexmap::IndexMap;
+use regex::{Captures, Regex};
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::sync::{Arc, PoisonError, RwLock};
+use tokio::sync::Semaphore;
+
+/// The route table consulted for every request.
+///
+/// Lookups go through `resolve` so the representation can change (e.g. a trie for
+/// prefix matching) without touching the request handling code.
+#[derive(Debug, Default)]
+pub struct Router {
+    routes: Routes,
+    // compiled route keys for Regex mode, in config order
+    patterns: Vec<(Regex, usize)>,
+    // indexes of the glob keys (`api-*`) of Path mode, most specific first
+    globs: Vec<usize>,
+    // request counters, by route index
+    counters: Vec<Arc<RouteCounters>>,
+    // upstream clients of routes with a client certificate or TLS server name, by route index
+    clients: Vec<Option<UpstreamClient>>,
+    // in-flight request slots of targets whose route sets `max_inflight`, by target
+    limits: HashMap<String, Arc<Semaphore>>,
+    // whether any route sets a `mode` other than the profile's
+    mixes_modes: bool,
+}
+
+impl Router {
+    pub fn new(routes: Routes) -> Self {
+        let counters = routes.iter().map(|_| Arc::default()).collect();
+        let mut limits = HashMap::new();
+        for route in routes.values() {
+            if let Some(max) = route.max_inflight {
+                let rule_targets = route.header_routes.iter().map(|rule| rule.target.as_str());
+                for target in route.instances().chain(rule_targets) {
+                    // a target shared by several routes gets the first route's limit
+                    limits
+                        .entry(target.to_string())
+                        .or_insert_with(|| Arc::new(Semaphore::new(max)));
+                }
+            }
+        }
+        Self {
+            routes,
+            patterns: Vec::new(),
+            globs: Vec::new(),
+            counters,
+            clients: Vec::new(),
+            limits,
+            mixes_modes: false,
+        }
+    }
+
+    /// Builds the router for a mode, compiling the route keys as patterns in Regex mode and
+    /// collecting the glob keys of the routes matched in Path mode, whatever the profile's mode.
+    pub fn for_mode(mode: &ProxyMode, routes: Routes) -> Result<Self, String> {
+        for (key, route) in &routes {
+            let instances = route.instances().count();
+            if !route.weights.is_empty()
+                && (route.weights.len() != instances || route.weights.contains(&0))
+            {
+                return Err(format!(
+                    "Route '{}' needs one weight of at least 1 for each of its {} target(s).",
+                    key, instances
+                ));
+            }
+            if route.max_inflight == Some(0) {
+                return Err(format!(
+                    "Route '{}' sets max_inflight to 0, which would turn away every request; \
+                     use at least 1, or leave it out for no limit.",
+                    key
+                ));
+            }
+            let is_status =
+                |status: &str| status.parse().is_ok_and(|n: u16| (100..=599).contains(&n));
+            let mut statuses = route
+                .status_map
+                .iter()
+                .flat_map(|(from, to)| [from.clone(), to.to_string()]);
+            if let Some(status) = statuses.find(|status| !is_status(status)) {
+                return Err(format!(
+                    "Route '{}' maps a status of {}; statuses go from 100 to 599.",
+                    key, status
+                ));
+            }
+            if let Some(name) = &route.label_header
+                && HeaderName::try_from(name.as_str()).is_err()
+            {
+                return Err(format!(
+                    "Route '{}' sets label_header to '{}', which is not a valid header name.",
+                    key, name
+                ));
+            }
+            if let Some(host) = &route.upstream_host
+                && HeaderValue::from_str(host).is_err()
+            {
+                return Err(format!(
+                    "Route '{}' sets upstream_host to '{}', which can't be sent as a Host header.",
+                    key,
+                    host.escape_debug()
+                ));
+            }
+            if let Some(own) = route.mode
+                && own != *mode
+                && !(own.mixes() && mode.mixes())
+            {
+                return Err(format!(
+                    "Route '{}' sets mode {}, which can't be mixed with {} mode; only domain and path routes can.",
+                    key, own, mode
+                ));
+            }
+            // Domain mode's wildcard key would be a glob matching every path
+            if route.mode.unwrap_or(*mode) == ProxyMode::Path && key.chars().all(|c| c == '*') {
+                return Err(format!(
+                    "Route '{}' can't be matched in path mode: a glob key needs characters besides \
+                     '*' (e.g., 'api-*'), and '*' alone is the wildcard of domain mode.",
+                    key
+                ));
+            }
+        }
+        let mixes_modes = routes
+            .values()
+            .any(|route| route.mode.is_some_and(|own| own != *mode));
+        let mut router = Router::new(routes);
+        router.mixes_modes = mixes_modes;
+        if let ProxyMode::Regex = mode {
+            for (index, key) in router.routes.keys().enumerate() {
+                let pattern = Regex::new(key)
+                    .map_err(|e| format!("Invalid route pattern \"{}\".\n  {}", key, e))?;
+                router.patterns.push((pattern, index));
+            }
+        }
+        let mut globs: Vec<(usize, &String)> = router
+            .routes
+            .iter()
+            .enumerate()
+            .filter(|(_, (key, route))| {
+                key.contains('*') && route.mode.unwrap_or(*mode) == ProxyMode::Path
+            })
+            .map(|(index, (key, _))| (index, key))
+            .collect();
+        // the glob with the most literal characters wins, then the first in the config
+        globs.sort_by_key(|(_, key)| Reverse(key.chars().filter(|&c| c != '*').count()));
+        router.globs = globs.into_iter().map(|(index, _)| index).collect();
+        Ok(router)
+    }
+
+    /// Whether some routes are matched by host and others by path.
+    pub fn mixes_modes(&self) -> bool {
+        self.mixes_modes
+    }
+
+    /// Finds the route for a key extracted from a request in the given mode, falling back to
+    /// the glob keys of Path mode when no key is equal to it.
+    /// Returns the matched key alongside the route.
+    pub fn resolve(&self, mode: &ProxyMode, key: &str) -> Option<(&str, &Route)> {
+        let exact = self.routes.get_key_value(key);
+        exact
+            .or_else(|| {
+                if *mode != ProxyMode::Path {
+                    return None;
+                }
+                self.globs
+                    .iter()
+                    .filter_map(|&index| self.routes.get_index(index))
+                    .find(|(glob, _)| glob_matches(glob, key))
+            })
+            .map(|(key, route)| (key.as_str(), route))
+    }
+
+    /// Finds the route with the longest key matching whole leading segments of `path`
+    /// (given without its leading `/`). Returns the matched key alongside the route.
+    pub fn resolve_prefix(&self, path: &str) -> Option<(&str, &Route)> {
+        let lowered = path.to_ascii_lowercase();
+        let ends: Vec<usize> = lowered
+            .match_indices('/')
+            .map(|(i, _)| i)
+            .chain([lowered.len()])
+            .collect();
+
+        ends.into_iter().rev().find_map(|end| {
+            self.routes
+                .get_key_value(&lowered[..end])
+                .map(|(key, route)| (key.as_str(), route))
+        })
+    }
+
+    /// Finds the first route (in config order) whose pattern matches `path`,
+    /// along with its key and the pattern's captures. Only routers built for Regex mode
+    /// have patterns.
+    pub fn resolve_pattern<'h>(&self, path: &'h str) -> Option<(&str, &Route, Captures<'h>)> {
+        self.patterns.iter().find_map(|(pattern, index)| {
+            let caps = pattern.captures(path)?;
+            let (key, route) = self.routes.get_index(*index)?;
+            Some((key.as_str(), route, caps))
+        })
+    }
+
+    /// Sets up a client of its own for every route that presents a client certificate (its
+    /// own, or `default` for routes without one), sets `tls_server_name` or disables
+    /// keep-alive, so bad files and names are reported before serving.
+    pub fn load_clients(&mut self, default: Option<&ClientCert>) -> Result<(), String> {
+        self.clients = self
+            .routes
+            .iter()
+            .map(|(key, route)| {
+                let cert = route.client_cert.as_ref().or(default);
+                (cert.is_some() || route.tls_server_name.is_some() || route.disable_keepalive)
+                    .then(|| {
+                        client::own_client(route, cert)
+                            .map_err(|e| format!("Route '{}': {}", key, e))
+                    })
+                    .transpose()
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(())
+    }
+
+    /// The client to forward requests of the route with the given key with.
+    pub(crate) fn client(&self, key: &str, route: &Route) -> &UpstreamClient {
+        self.routes
+            .get_index_of(key)
+            .and_then(|index| self.clients.get(index)?.as_ref())
+            .unwrap_or_else(|| client::for_route(route))
+    }
+
+    /// The in-flight request slots of `target`, if its route limits them.
+    pub fn limit(&self, target: &str) -> Option<&Arc<Semaphore>> {
+        self.limits.get(target)
+    }
+
+    /// Counts a response sent for the route with the given key.
+    pub fn record(&self, key: &str, status: StatusCode) {
+        if let Some(counters) = self.counters(key) {
+            counters.record(status);
+        }
+    }
+
+    /// The counters of the route with the given key, for bodies that outlive the request.
+    pub fn counters(&self, key: &str) -> Option<&Arc<RouteCounters>> {
+        self.routes
+            .get_index_of(key)
+            .and_then(|index| self.counters.get(index))
+    }
+
+    /// The request counters of every route, in config order; `bytes` adds the body byte
+    /// counts.
+    pub fn stats(&self, bytes: bool) -> IndexMap<String, RouteStats> {
+        self.routes
+            .keys()
+            .zip(&self.counters)
+            .map(|(key, counters)| (key.clone(), counters.snapshot(bytes)))
+            .collect()
+    }
+
+    /// Every route with its key, in config order.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &Route)> {
+        self.routes.iter().map(|(key, route)| (key.as_str(), route))
+    }
+
+    pub fn len(&self) -> usize {
+        self.routes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.routes.is_empty()
+    }
+}
+
+/// The route table of a running server. Reloading the config swaps in a new table as a
+/// whole; requests already in flight keep the one they started with.
+#[derive(Debug, Default)]
+pub struct SharedRouter(RwLock<Arc<Router>>);
+
+impl SharedRouter {
+    pub fn new(router: Router) -> Self {
+        SharedRouter(RwLock::new(Arc::new(router)))
+    }
+
+    /// The current route table.
+    pub fn load(&self) -> Arc<Router> {
+        self.0
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+
+    /// Replaces the route table. Request counters start over with the new table.
+    pub fn replace(&self, router: Router) {
+        *self.0.write().unwrap_or_else(PoisonError::into_inner) = Arc::new(router);
+    }
+}
+
+/// Whether `text` matches `glob`, whose `*`s stand for any (possibly empty) run of
+/// characters.
+fn glob_matches(glob: &str, text: &str) -> bool {
+    let mut parts = glob.split('*');
+    let Some(mut rest) = parts.next().and_then(|first| text.strip_prefix(first)) else {
+        return false;
+    };
+    let parts: Vec<&str> = parts.collect();
+    let Some((last, middle)) = parts.split_last() else {
+        // no `*` at all
+        return rest.is_empty();
+    };
+    for part in middle {
+        match rest.find(part) {
+            Some(at) => rest = &rest[at + part.len()..],
+            None => return false,
+        }
+    }
+    rest.ends_with(last)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn router(pairs: &[(&str, &str)]) -> Router {
+        Router::new(
+            pairs
+                .iter()
+                .map(|(k, v)| (k.to_string(), Route::new(*v)))
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn resolves_exact_keys_in_both_modes() {
+        let router = router(&[
+            ("api", "http://localhost:8080"),
+            ("web", "http://localhost:3000"),
+        ]);
+
+        for mode in [ProxyMode::Path, ProxyMode::Domain] {
+            assert_eq!(
+                router.resolve(&mode, "api").map(|(_, r)| r.target.as_str()),
+                Some("http://localhost:8080")
+            );
+            assert_eq!(
+                router.resolve(&mode, "web").map(|(_, r)| r.target.as_str()),
+                Some("http://localhost:3000")
+            );
+        }
+    }
+
+    #[test]
+    fn unknown_keys_do_not_resolve() {
+        let router = router(&[("api", "http://localhost:8080")]);
+        assert!(router.resolve(&ProxyMode::Path, "apis").is_none());
+        assert!(router.resolve(&ProxyMode::Path, "").is_none());
+        assert!(Router::default()
+            .resolve(&ProxyMode::Domain, "api")
+            .is_none());
+    }
+
+    #[test]
+    fn exact_keys_win_over_globs_in_path_mode() {
+        let routes = [
+            ("api-*", "http://any-api"),
+            ("api-orders", "http://orders"),
+            ("api-o*", "http://o-apis"),
+            ("*-svc", "http://svc"),
+        ]
+        .iter()
+        .map(|(k, v)| (k.to_string(), Route::new(*v)))
+        .collect();
+        let router = Router::for_mode(&ProxyMode::Path, routes).unwrap();
+        let target = |key| {
+            router
+                .resolve(&ProxyMode::Path, key)
+                .map(|(key, route)| (key, route.target.as_str()))
+        };
+
+        assert_eq!(target("api-orders"), Some(("api-orders", "http://orders")));
+        // the more specific glob wins, whatever the config order
+        assert_eq!(target("api-offers"), Some(("api-o*", "http://o-apis")));
+        assert_eq!(target("api-users"), Some(("api-*", "http://any-api")));
+        assert_eq!(target("billing-svc"), Some(("*-svc", "http://svc")));
+        assert_eq!(target("web"), None);
+
+        // other modes take keys literally
+        let router = Router::for_mode(&ProxyMode::Domain, router.routes.clone()).unwrap();
+        assert!(router.resolve(&ProxyMode::Domain, "api-users").is_none());
+    }
+
+    #[test]
+    fn path_mode_globs_match_in_a_domain_profile() {
+        let mut glob = Route::new("http://any-api");
+        glob.mode = Some(ProxyMode::Path);
+        let routes = [
+            ("api-*".to_string(), glob),
+            ("web-*".to_string(), Route::new("http://any-web")),
+        ]
+        .into_iter()
+        .collect();
+        let router = Router::for_mode(&ProxyMode::Domain, routes).unwrap();
+
+        assert_eq!(
+            router
+                .resolve(&ProxyMode::Path, "api-v1")
+                .map(|(key, route)| (key, route.target.as_str())),
+            Some(("api-*", "http://any-api"))
+        );
+        // a host label is never matched against a glob, and the profile's own routes stay literal
+        assert!(router.resolve(&ProxyMode::Domain, "api-v1").is_none());
+        assert!(router.resolve(&ProxyMode::Path, "web-v1").is_none());
+    }
+
+    #[test]
+    fn globs_match_any_run_of_characters() {
+        assert!(glob_matches("api-*", "api-users"));
+        assert!(glob_matches("a*b*c", "a-x-b-y-c"));
+        assert!(glob_matches("*-svc", "billing-svc"));
+        assert!(!glob_matches("api-*", "web-api"));
+        assert!(!glob_matches("a*b*c", "a-c-b"));
+        assert!(!glob_matches("api", "api-users"));
+    }
+
+    #[test]
+    fn resolve_prefix_prefers_longest_key() {
+        let router = router(&[("api", "http://v1"), ("api/v2", "http://v2")]);
+
+        let (key, route) = router.resolve_prefix("api/v2/users").unwrap();
+        assert_eq!((key, route.target.as_str()), ("api/v2", "http://v2"));
+
+        let (key, route) = router.resolve_prefix("api/v3").unwrap();
+        assert_eq!((key, route.target.as_str()), ("api", "http://v1"));
+
+        let (key, _) = router.resolve_prefix("Api/V2/").unwrap();
+        assert_eq!(key, "api/v2");
+
+        assert!(router.resolve_prefix("apiv2").is_none());
+        assert!(router.resolve_prefix("").is_none());
+    }
+
+    #[test]
+    fn resolve_pattern_tries_patterns_in_config_order() {
+        let routes = [
+            (r"^/user-\d+/", "http://users"),
+            (r"^/user-", "http://fallback"),
+        ]
+        .iter()
+        .map(|(k, v)| (k.to_string(), Route::new(*v)))
+        .collect();
+        let router = Router::for_mode(&ProxyMode::Regex, routes).unwrap();
+
+        let (key, route, caps) = router.resolve_pattern("/user-42/profile").unwrap();
+        assert_eq!(key, r"^/user-\d+/");
+        assert_eq!(route.target, "http://users");
+        assert_eq!(&caps[0], "/user-42/");
+
+        let (_, route, _) = router.resolve_pattern("/user-abc/profile").unwrap();
+        assert_eq!(route.target, "http://fallback");
+
+        assert!(router.resolve_pattern("/admin").is_none());
+    }
+
+    #[test]
+    fn record_counts_per_route() {
+        let router = router(&[("api", "http://api"), ("web", "http://web")]);
+        router.record("api", StatusCode::OK);
+        router.record("api", StatusCode::BAD_GATEWAY);
+        router.record("unknown", StatusCode::OK);
+
+        let stats = router.stats(false);
+        assert_eq!(stats.keys().collect::<Vec<_>>(), ["api", "web"]);
+        assert_eq!(stats["api"].total, 2);
+        assert_eq!(stats["api"].success, 1);
+        assert_eq!(stats["api"].server_error, 1);
+        assert_eq!(stats["web"], RouteStats::default());
+    }
+
+    #[test]
+    fn invalid_patterns_fail_to_build() {
+        let routes = [("^/user-(\\d+".to_string(), Route::new("http://users"))]
+            .into_iter()
+            .collect();
+        let err = Router::for_mode(&ProxyMode::Regex, routes).unwrap_err();
+        assert!(err.contains("Invalid route pattern"));
+    }
+
+    #[test]
+    fn reports_size() {
+        assert!(Router::default().is_empty());
+        let router = router(&[("api", "http://localhost:8080")]);
+        assert_eq!(router.len(), 1);
+        assert!(!router.is_empty());
+    }
+}
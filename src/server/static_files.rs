@@ -0,0 +1,206 @@
+use super::proxy::{boxed_full, method_not_allowed, not_found, ProxyBody};
+use hyper::header::{CONTENT_LENGTH, CONTENT_TYPE};
+use hyper::{Method, Response, StatusCode};
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+/// Prefix of a target that serves files from a directory, as in `dir:/srv/site`.
+pub const DIR_PREFIX: &str = "dir:";
+/// The `file://` form of a directory target, as in `file:///srv/site`.
+pub const FILE_PREFIX: &str = "file://";
+
+const INDEX_FILE: &str = "index.html";
+
+/// The directory a static target serves, or `None` if the target is an upstream URL.
+pub fn root(target: &str) -> Option<PathBuf> {
+    let path = target
+        .strip_prefix(DIR_PREFIX)
+        .or_else(|| target.strip_prefix(FILE_PREFIX))?;
+    Some(PathBuf::from(shellexpand::tilde(path).as_ref()))
+}
+
+/// Answers a request for `path` (the request path with the route prefix removed) from the
+/// files under `root`. A directory is answered with its `index.html`. Paths that climb out
+/// of `root`, with `..` or through a symlink, get `403 Forbidden`.
+pub async fn serve(root: &Path, path: &str, method: &Method) -> Response<ProxyBody> {
+    if method != Method::GET && method != Method::HEAD {
+        return method_not_allowed(&["GET".into(), "HEAD".into()]);
+    }
+    let Some(relative) = relative_path(path) else {
+        return forbidden();
+    };
+
+    let mut file = root.join(relative);
+    if tokio::fs::metadata(&file).await.is_ok_and(|m| m.is_dir()) {
+        file.push(INDEX_FILE);
+    }
+    let (Ok(root), Ok(file)) = (
+        tokio::fs::canonicalize(root).await,
+        tokio::fs::canonicalize(&file).await,
+    ) else {
+        return not_found();
+    };
+    if !file.starts_with(&root) {
+        return forbidden();
+    }
+
+    let contents = match tokio::fs::read(&file).await {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == ErrorKind::NotFound => return not_found(),
+        Err(e) if e.kind() == ErrorKind::PermissionDenied => return forbidden(),
+        // e.g. a directory without an index file
+        Err(_) => return not_found(),
+    };
+    let len = contents.len();
+    let body = if method == Method::HEAD {
+        boxed_full("")
+    } else {
+        boxed_full(contents)
+    };
+    Response::builder()
+        .header(CONTENT_TYPE, content_type(&file))
+        .header(CONTENT_LENGTH, len)
+        .body(body)
+        .unwrap()
+}
+
+/// Turns the request path into a path relative to the served directory, decoding
+/// percent-escapes. Returns `None` for paths that would leave the directory.
+fn relative_path(path: &str) -> Option<PathBuf> {
+    let path = path.split(['?', '#']).next().unwrap_or_default();
+    let mut relative = PathBuf::new();
+    for segment in path.split('/') {
+        let segment = percent_decode(segment)?;
+        match segment.as_str() {
+            "" | "." => {}
+            ".." => return None,
+            _ if segment.contains(['/', '\\', '\0']) => return None,
+            _ => relative.push(segment),
+        }
+    }
+    Some(relative)
+}
+
+fn percent_decode(segment: &str) -> Option<String> {
+    let bytes = segment.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = segment.get(i + 1..i + 3)?;
+            decoded.push(u8::from_str_radix(hex, 16).ok()?);
+            i += 3;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(decoded).ok()
+}
+
+/// Guesses the content type from the file extension, falling back to raw bytes.
+fn content_type(file: &Path) -> &'static str {
+    let extension = file
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(str::to_ascii_lowercase);
+    match extension.as_deref() {
+        Some("html" | "htm") => "text/html; charset=utf-8",
+        Some("css") => "text/css; charset=utf-8",
+        Some("js" | "mjs") => "text/javascript; charset=utf-8",
+        Some("json" | "map") => "application/json",
+        Some("txt") => "text/plain; charset=utf-8",
+        Some("xml") => "application/xml",
+        Some("svg") => "image/svg+xml",
+        Some("png") => "image/png",
+        Some("jpg" | "jpeg") => "image/jpeg",
+        Some("gif") => "image/gif",
+        Some("webp") => "image/webp",
+        Some("ico") => "image/x-icon",
+        Some("woff") => "font/woff",
+        Some("woff2") => "font/woff2",
+        Some("wasm") => "application/wasm",
+        Some("pdf") => "application/pdf",
+        _ => "application/octet-stream",
+    }
+}
+
+fn forbidden() -> Response<ProxyBody> {
+    Response::builder()
+        .status(StatusCode::FORBIDDEN)
+        .body(boxed_full("Local Http Proxy: Forbidden"))
+        .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http_body_util::BodyExt;
+    use std::fs;
+    use tempfile::TempDir;
+
+    fn site() -> TempDir {
+        let dir = TempDir::new().unwrap();
+        fs::create_dir(dir.path().join("docs")).unwrap();
+        fs::write(dir.path().join("index.html"), "<h1>home</h1>").unwrap();
+        fs::write(dir.path().join("app.js"), "console.log(1)").unwrap();
+        fs::write(dir.path().join("docs/index.html"), "<h1>docs</h1>").unwrap();
+        dir
+    }
+
+    async fn body(res: Response<ProxyBody>) -> String {
+        let bytes = res.into_body().collect().await.unwrap().to_bytes();
+        String::from_utf8(bytes.to_vec()).unwrap()
+    }
+
+    #[test]
+    fn root_reads_both_target_forms() {
+        assert_eq!(root("dir:/srv/site"), Some(PathBuf::from("/srv/site")));
+        assert_eq!(root("file:///srv/site"), Some(PathBuf::from("/srv/site")));
+        assert_eq!(root("http://localhost:3000"), None);
+    }
+
+    #[tokio::test]
+    async fn serves_files_with_their_content_type() {
+        let site = site();
+
+        let res = serve(site.path(), "/app.js?v=2", &Method::GET).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(
+            res.headers()[CONTENT_TYPE],
+            "text/javascript; charset=utf-8"
+        );
+        assert_eq!(body(res).await, "console.log(1)");
+
+        let res = serve(site.path(), "/", &Method::GET).await;
+        assert_eq!(res.headers()[CONTENT_TYPE], "text/html; charset=utf-8");
+        assert_eq!(body(res).await, "<h1>home</h1>");
+
+        let res = serve(site.path(), "/docs", &Method::HEAD).await;
+        assert_eq!(res.headers()[CONTENT_LENGTH], "13");
+        assert_eq!(body(res).await, "");
+
+        let res = serve(site.path(), "/missing.css", &Method::GET).await;
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+        let res = serve(site.path(), "/app.js", &Method::POST).await;
+        assert_eq!(res.status(), StatusCode::METHOD_NOT_ALLOWED);
+    }
+
+    #[tokio::test]
+    async fn paths_outside_the_root_are_forbidden() {
+        let outer = site();
+        let root = outer.path().join("docs");
+
+        for path in ["/../index.html", "/%2e%2e/index.html", "/..%2findex.html"] {
+            let res = serve(&root, path, &Method::GET).await;
+            assert_eq!(res.status(), StatusCode::FORBIDDEN, "{path}");
+        }
+
+        #[cfg(unix)]
+        {
+            std::os::unix::fs::symlink(outer.path().join("app.js"), root.join("link.js")).unwrap();
+            let res = serve(&root, "/link.js", &Method::GET).await;
+            assert_eq!(res.status(), StatusCode::FORBIDDEN);
+        }
+    }
+}
@@ -0,0 +1,236 @@
+use super::body_log::Direction;
+use super::proxy::ProxyBody;
+use hyper::body::{Body, Bytes, Frame, SizeHint};
+use hyper::StatusCode;
+use indexmap::IndexMap;
+use serde::{Deserialize, Serialize};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::task::{ready, Context, Poll};
+
+/// The path the running proxy serves its request counters on, whatever the mode.
+pub const STATS_PATH: &str = "/__lhp/stats";
+
+/// Request counters for one route.
+/// They live in memory only, so they start from zero every time the server starts.
+#[derive(Debug, Default)]
+pub struct RouteCounters {
+    total: AtomicU64,
+    // indexed by status class: 1xx..5xx
+    by_class: [AtomicU64; 5],
+    // body bytes read from clients and sent to them, when `count_bytes` is on
+    request_bytes: AtomicU64,
+    response_bytes: AtomicU64,
+}
+
+impl RouteCounters {
+    pub fn record(&self, status: StatusCode) {
+        self.total.fetch_add(1, Ordering::Relaxed);
+        let class = usize::from(status.as_u16() / 100);
+        if let Some(counter) = class.checked_sub(1).and_then(|i| self.by_class.get(i)) {
+            counter.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    fn add_bytes(&self, direction: Direction, len: usize) {
+        let counter = match direction {
+            Direction::Request => &self.request_bytes,
+            Direction::Response => &self.response_bytes,
+        };
+        counter.fetch_add(len as u64, Ordering::Relaxed);
+    }
+
+    /// The counters as of now; `bytes` adds the body byte counts.
+    pub fn snapshot(&self, bytes: bool) -> RouteStats {
+        let class = |i: usize| self.by_class[i].load(Ordering::Relaxed);
+        let bytes = |counter: &AtomicU64| bytes.then(|| counter.load(Ordering::Relaxed));
+        RouteStats {
+            total: self.total.load(Ordering::Relaxed),
+            informational: class(0),
+            success: class(1),
+            redirection: class(2),
+            client_error: class(3),
+            server_error: class(4),
+            request_bytes: bytes(&self.request_bytes),
+            response_bytes: bytes(&self.response_bytes),
+        }
+    }
+}
+
+/// A body that adds the bytes passing through it to a route's counters as they are
+/// streamed, without buffering.
+pub struct CountingBody {
+    inner: ProxyBody,
+    counters: Arc<RouteCounters>,
+    direction: Direction,
+}
+
+impl CountingBody {
+    pub fn new(inner: ProxyBody, counters: Arc<RouteCounters>, direction: Direction) -> Self {
+        CountingBody {
+            inner,
+            counters,
+            direction,
+        }
+    }
+}
+
+impl Body for CountingBody {
+    type Data = Bytes;
+    type Error = hyper::Error;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Bytes>, hyper::Error>>> {
+        let frame = ready!(Pin::new(&mut self.inner).poll_frame(cx));
+        if let Some(Ok(frame)) = &frame
+            && let Some(data) = frame.data_ref()
+        {
+            self.counters.add_bytes(self.direction, data.len());
+        }
+        Poll::Ready(frame)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+/// Counters for the client connections the server accepts, kept like `RouteCounters`.
+#[derive(Debug, Default)]
+pub struct ConnectionCounters {
+    accepted: AtomicU64,
+    errors: AtomicU64,
+    open: AtomicU64,
+}
+
+impl ConnectionCounters {
+    pub fn opened(&self) {
+        self.accepted.fetch_add(1, Ordering::Relaxed);
+        self.open.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Counts the end of a connection that `opened` counted, and whether serving it failed.
+    pub fn closed(&self, failed: bool) {
+        self.open.fetch_sub(1, Ordering::Relaxed);
+        if failed {
+            self.errors.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub fn snapshot(&self) -> ConnectionStats {
+        ConnectionStats {
+            accepted: self.accepted.load(Ordering::Relaxed),
+            errors: self.errors.load(Ordering::Relaxed),
+            open: self.open.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// A point-in-time copy of the connection counters, as served on `STATS_PATH`.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq, Eq)]
+pub struct ConnectionStats {
+    pub accepted: u64,
+    pub errors: u64,
+    pub open: u64,
+}
+
+/// A point-in-time copy of a route's counters, as served on `STATS_PATH`.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq, Eq)]
+pub struct RouteStats {
+    pub total: u64,
+    #[serde(rename = "1xx")]
+    pub informational: u64,
+    #[serde(rename = "2xx")]
+    pub success: u64,
+    #[serde(rename = "3xx")]
+    pub redirection: u64,
+    #[serde(rename = "4xx")]
+    pub client_error: u64,
+    #[serde(rename = "5xx")]
+    pub server_error: u64,
+    // body bytes, only counted with `count_bytes`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub request_bytes: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub response_bytes: Option<u64>,
+}
+
+/// The body of a `STATS_PATH` response, with routes in config order.
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct StatsReport {
+    pub routes: IndexMap<String, RouteStats>,
+    // missing from servers older than the counters
+    #[serde(default)]
+    pub connections: ConnectionStats,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_requests_by_status_class() {
+        let counters = RouteCounters::default();
+        for status in [
+            StatusCode::OK,
+            StatusCode::CREATED,
+            StatusCode::NOT_MODIFIED,
+            StatusCode::NOT_FOUND,
+            StatusCode::BAD_GATEWAY,
+            StatusCode::BAD_GATEWAY,
+        ] {
+            counters.record(status);
+        }
+
+        assert_eq!(
+            counters.snapshot(false),
+            RouteStats {
+                total: 6,
+                informational: 0,
+                success: 2,
+                redirection: 1,
+                client_error: 1,
+                server_error: 2,
+                request_bytes: None,
+                response_bytes: None,
+            }
+        );
+    }
+
+    #[test]
+    fn open_connections_go_down_as_they_close() {
+        let counters = ConnectionCounters::default();
+        counters.opened();
+        counters.opened();
+        counters.closed(true);
+
+        assert_eq!(
+            counters.snapshot(),
+            ConnectionStats {
+                accepted: 2,
+                errors: 1,
+                open: 1,
+            }
+        );
+    }
+
+    #[test]
+    fn stats_serialize_with_status_class_names() {
+        let stats = RouteStats {
+            total: 1,
+            success: 1,
+            ..RouteStats::default()
+        };
+        let value = serde_json::to_value(&stats).unwrap();
+        assert_eq!(value["total"], 1);
+        assert_eq!(value["2xx"], 1);
+        assert_eq!(value["5xx"], 0);
+    }
+}
@@ -0,0 +1,159 @@
+use hyper::{HeaderMap, Method, StatusCode};
+use opentelemetry::{
+    global,
+    propagation::TextMapPropagator,
+    trace::{SpanKind, Status, TraceContextExt, Tracer},
+    Context, KeyValue,
+};
+use opentelemetry_http::{HeaderExtractor, HeaderInjector};
+use opentelemetry_otlp::{SpanExporter, WithExportConfig};
+use opentelemetry_sdk::{propagation::TraceContextPropagator, trace::SdkTracerProvider};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+const TRACER_NAME: &str = "local-http-proxy";
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Starts exporting request spans to the OTLP/HTTP collector at `endpoint`.
+/// Spans are batched and sent in the background; call `shutdown` on the returned provider to
+/// send the last batch before exiting.
+pub fn init(endpoint: &str) -> Result<SdkTracerProvider, String> {
+    let exporter = SpanExporter::builder()
+        .with_http()
+        .with_endpoint(endpoint)
+        .build()
+        .map_err(|e| format!("Could not set up the OTLP exporter for {endpoint}.\n  {e}"))?;
+    let provider = SdkTracerProvider::builder()
+        .with_batch_exporter(exporter)
+        .build();
+    install(provider.clone());
+    Ok(provider)
+}
+
+/// Makes `provider` the destination of request spans.
+fn install(provider: SdkTracerProvider) {
+    global::set_tracer_provider(provider);
+    ENABLED.store(true, Ordering::Relaxed);
+}
+
+/// The span covering one proxied request. Does nothing unless tracing was set up with `init`.
+pub struct RequestSpan(Option<Context>);
+
+impl RequestSpan {
+    /// Starts a server span, continuing the trace from an incoming `traceparent` header.
+    pub fn start(method: &Method, path: &str, headers: &HeaderMap) -> Self {
+        if !ENABLED.load(Ordering::Relaxed) {
+            return RequestSpan(None);
+        }
+
+        let parent = TraceContextPropagator::new().extract(&HeaderExtractor(headers));
+        let tracer = global::tracer(TRACER_NAME);
+        let span = tracer
+            .span_builder(method.to_string())
+            .with_kind(SpanKind::Server)
+            .with_attributes([
+                KeyValue::new("http.request.method", method.to_string()),
+                KeyValue::new("url.path", path.to_string()),
+            ])
+            .start_with_context(&tracer, &parent);
+        RequestSpan(Some(parent.with_span(span)))
+    }
+
+    /// Records the route the request matched and the upstream it is forwarded to.
+    pub fn record_route(&self, key: &str, upstream: &str) {
+        if let Some(cx) = &self.0 {
+            let span = cx.span();
+            span.set_attribute(KeyValue::new("lhp.route", key.to_string()));
+            span.set_attribute(KeyValue::new("lhp.upstream", upstream.to_string()));
+        }
+    }
+
+    /// Writes this span's `traceparent` into the upstream request's headers.
+    pub fn inject(&self, headers: &mut HeaderMap) {
+        if let Some(cx) = &self.0 {
+            TraceContextPropagator::new().inject_context(cx, &mut HeaderInjector(headers));
+        }
+    }
+
+    pub fn end(self, status: StatusCode) {
+        if let Some(cx) = self.0 {
+            let span = cx.span();
+            span.set_attribute(KeyValue::new(
+                "http.response.status_code",
+                i64::from(status.as_u16()),
+            ));
+            if status.is_server_error() {
+                span.set_status(Status::error(status.to_string()));
+            }
+            span.end();
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use opentelemetry_sdk::trace::{InMemorySpanExporter, SpanData};
+    use std::sync::OnceLock;
+
+    /// Routes spans from every test into one in-memory exporter; the tracer provider is
+    /// process-wide, so tests should pick out their own spans.
+    pub(crate) fn exporter() -> &'static InMemorySpanExporter {
+        static EXPORTER: OnceLock<InMemorySpanExporter> = OnceLock::new();
+        EXPORTER.get_or_init(|| {
+            let exporter = InMemorySpanExporter::default();
+            install(
+                SdkTracerProvider::builder()
+                    .with_simple_exporter(exporter.clone())
+                    .build(),
+            );
+            exporter
+        })
+    }
+
+    pub(crate) fn finished_span(path: &str) -> Option<SpanData> {
+        exporter()
+            .get_finished_spans()
+            .unwrap()
+            .into_iter()
+            .find(|span| {
+                span.attributes
+                    .iter()
+                    .any(|kv| kv.key.as_str() == "url.path" && kv.value.as_str() == path)
+            })
+    }
+
+    pub(crate) fn attribute(span: &SpanData, key: &str) -> Option<String> {
+        span.attributes
+            .iter()
+            .find(|kv| kv.key.as_str() == key)
+            .map(|kv| kv.value.to_string())
+    }
+
+    #[test]
+    fn span_continues_incoming_trace() {
+        exporter();
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            "traceparent",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01"
+                .parse()
+                .unwrap(),
+        );
+
+        let span = RequestSpan::start(&Method::GET, "/telemetry-unit", &headers);
+        let mut upstream = HeaderMap::new();
+        span.inject(&mut upstream);
+        span.end(StatusCode::OK);
+
+        let recorded = finished_span("/telemetry-unit").expect("span is recorded");
+        assert_eq!(
+            recorded.span_context.trace_id().to_string(),
+            "4bf92f3577b34da6a3ce929d0e0e4736"
+        );
+        assert_eq!(recorded.parent_span_id.to_string(), "00f067aa0ba902b7");
+        let traceparent = upstream["traceparent"].to_str().unwrap();
+        assert!(traceparent.starts_with("00-4bf92f3577b34da6a3ce929d0e0e4736-"));
+        assert!(traceparent.contains(&recorded.span_context.span_id().to_string()));
+    }
+}
@@ -0,0 +1,136 @@
+use http_body_util::{BodyExt, Empty, Full};
+use hyper::body::{Bytes, Incoming};
+use hyper::client::conn::http1 as client;
+use hyper::server::conn::http1;
+use hyper::service::service_fn;
+use hyper::{Request, Response};
+use hyper_util::rt::TokioIo;
+use local_http_proxy::config::{ProxyMode, Route};
+use local_http_proxy::Proxy;
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use tokio::net::{TcpListener, TcpStream};
+
+/// Answers every request with its path, prefixed with `upstream:`.
+async fn spawn_upstream() -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        loop {
+            let (stream, _) = listener.accept().await.unwrap();
+            let service = service_fn(|req: Request<Incoming>| async move {
+                let body = format!("upstream:{}", req.uri().path());
+                Ok::<_, Infallible>(Response::new(Full::new(Bytes::from(body))))
+            });
+            tokio::spawn(http1::Builder::new().serve_connection(TokioIo::new(stream), service));
+        }
+    });
+    addr
+}
+
+/// Answers every request with its body, sent back as `image/png`.
+async fn spawn_echo_upstream() -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        loop {
+            let (stream, _) = listener.accept().await.unwrap();
+            let service = service_fn(|req: Request<Incoming>| async move {
+                let body = req.into_body().collect().await?.to_bytes();
+                let res = Response::builder()
+                    .header("content-type", "image/png")
+                    .body(Full::new(body))
+                    .unwrap();
+                Ok::<_, hyper::Error>(res)
+            });
+            tokio::spawn(http1::Builder::new().serve_connection(TokioIo::new(stream), service));
+        }
+    });
+    addr
+}
+
+async fn get(addr: SocketAddr, path: &str) -> hyper::Result<(u16, String)> {
+    let stream = TcpStream::connect(addr).await.unwrap();
+    let (mut sender, conn) = client::handshake(TokioIo::new(stream)).await?;
+    tokio::spawn(conn);
+    let req = Request::builder()
+        .uri(path)
+        .header("host", addr.to_string())
+        .body(Empty::<Bytes>::new())
+        .unwrap();
+    let res = sender.send_request(req).await?;
+    let status = res.status().as_u16();
+    let body = res.into_body().collect().await?.to_bytes();
+    Ok((status, String::from_utf8(body.to_vec()).unwrap()))
+}
+
+#[tokio::test]
+async fn embedded_proxy_forwards_and_shuts_down() {
+    let upstream = spawn_upstream().await;
+    let proxy = Proxy::builder()
+        .mode(ProxyMode::Path)
+        .route("api", Route::new(format!("http://{upstream}")))
+        .serve()
+        .await
+        .unwrap();
+    let addr = proxy.addr();
+    assert_ne!(addr.port(), 0);
+
+    assert_eq!(
+        get(addr, "/api/users").await.unwrap(),
+        (200, "upstream:/users".to_string())
+    );
+    assert_eq!(get(addr, "/missing").await.unwrap().0, 404);
+
+    proxy.shutdown().await.unwrap();
+    assert!(TcpStream::connect(addr).await.is_err());
+}
+
+#[tokio::test]
+async fn binary_bodies_pass_through_byte_for_byte() {
+    let upstream = spawn_echo_upstream().await;
+    let mut logged = Route::new(format!("http://{upstream}"));
+    logged.log_bodies = true;
+    let proxy = Proxy::builder()
+        .mode(ProxyMode::Path)
+        .route("raw", Route::new(format!("http://{upstream}")))
+        .route("logged", logged)
+        .serve()
+        .await
+        .unwrap();
+    let addr = proxy.addr();
+    // every byte value, including invalid UTF-8 and NULs, over several body frames
+    let payload: Vec<u8> = (0..=255u8).cycle().take(256 * 1024 + 7).collect();
+
+    for path in ["/raw/upload", "/logged/upload"] {
+        let stream = TcpStream::connect(addr).await.unwrap();
+        let (mut sender, conn) = client::handshake(TokioIo::new(stream)).await.unwrap();
+        tokio::spawn(conn);
+        let req = Request::builder()
+            .method("POST")
+            .uri(path)
+            .header("host", addr.to_string())
+            .header("content-type", "application/octet-stream")
+            .body(Full::new(Bytes::from(payload.clone())))
+            .unwrap();
+        let res = sender.send_request(req).await.unwrap();
+        assert_eq!(res.status(), 200, "{path}");
+        assert_eq!(res.headers()["content-type"], "image/png");
+        let body = res.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(body.len(), payload.len(), "{path}");
+        assert!(body == payload, "{path} changed the body");
+    }
+
+    proxy.shutdown().await.unwrap();
+}
+
+#[tokio::test]
+async fn invalid_routes_fail_to_serve() {
+    let err = Proxy::builder()
+        .mode(ProxyMode::Regex)
+        .route("(", Route::new("http://localhost:8080"))
+        .serve()
+        .await
+        .unwrap_err();
+    assert!(err.to_string().contains("Invalid route pattern"));
+}